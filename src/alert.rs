@@ -0,0 +1,263 @@
+//! Throttled alerts for events that carry labels, tags, or Sigma rule IDs
+//!
+//! Alerts are meant for a low-volume channel such as a webhook or
+//! syslog, next to the bulk audit log. For every rule, duplicates are
+//! suppressed within a time window and the number of alerts per
+//! window is limited.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use linux_audit_parser::{Body, Key, MessageType, Value};
+use serde_json::Value as JValue;
+
+use crate::clock::{Clock, SystemClock};
+use crate::config::AlertRule;
+use crate::types::{Event, EventValues};
+
+/// Per-rule throttling state
+#[derive(Debug)]
+struct State {
+    rule: AlertRule,
+    /// Start of the current window
+    window_start: Option<Instant>,
+    /// Alerts sent in the current window
+    sent: u64,
+    /// Dedup keys of alerts that have been sent, with the time
+    seen: HashMap<String, Instant>,
+}
+
+/// Decides which events are sent as alerts
+#[derive(Debug)]
+pub struct Alerter {
+    rules: Vec<State>,
+    suppressed: u64,
+    clock: Arc<dyn Clock>,
+}
+
+impl Alerter {
+    pub fn new(rules: &[AlertRule]) -> Self {
+        Alerter {
+            rules: rules
+                .iter()
+                .map(|rule| State {
+                    rule: rule.clone(),
+                    window_start: None,
+                    sent: 0,
+                    seen: HashMap::new(),
+                })
+                .collect(),
+            suppressed: 0,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Use `clock` instead of the system's monotonic clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Returns the names of the rules for which `ev` is sent as an
+    /// alert.
+    ///
+    /// Windows are measured by the monotonic clock rather than by
+    /// event timestamps, which may jump or differ between nodes.
+    pub fn check(&mut self, ev: &Event) -> Vec<String> {
+        let labels = syscall_strings(ev, "LABELS");
+        let mut tags = syscall_strings(ev, "TAGS");
+        tags.extend(syscall_strings(ev, "SIGMA"));
+        if labels.is_empty() && tags.is_empty() {
+            return vec![];
+        }
+
+        let now = self.clock.now();
+        let mut json: Option<JValue> = None;
+        let mut names = vec![];
+        for state in self.rules.iter_mut() {
+            let rule = &state.rule;
+            if !(rule.labels.iter().any(|l| labels.contains(l))
+                || rule.tags.iter().any(|t| tags.contains(t)))
+            {
+                continue;
+            }
+
+            let window = Duration::from_secs(rule.window);
+            if state.window_start.map_or(true, |t| now >= t + window) {
+                state.window_start = Some(now);
+                state.sent = 0;
+                state.seen.retain(|_, t| now < *t + window);
+            }
+
+            let key = if rule.dedup_fields.is_empty() {
+                None
+            } else {
+                let json =
+                    json.get_or_insert_with(|| serde_json::to_value(ev).unwrap_or(JValue::Null));
+                let mut values = vec![];
+                for path in &rule.dedup_fields {
+                    lookup(json, path.elements(), &mut values);
+                }
+                Some(JValue::from(values).to_string())
+            };
+            if key.as_ref().is_some_and(|k| state.seen.contains_key(k)) || state.sent >= rule.burst
+            {
+                self.suppressed += 1;
+                continue;
+            }
+
+            state.sent += 1;
+            if let Some(key) = key {
+                state.seen.insert(key, now);
+            }
+            names.push(rule.name.clone());
+        }
+        names
+    }
+
+    /// Number of alerts that have been suppressed as duplicates or
+    /// because of the burst limit
+    pub fn suppressed(&self) -> u64 {
+        self.suppressed
+    }
+}
+
+/// Adds a `LAUREL_ALERT` record that contains the names of the
+/// matching rules
+pub fn add_alert_record(ev: &mut Event, rules: Vec<String>) {
+    let mut rv = Body::default();
+    rv.push((
+        Key::Literal("rules"),
+        Value::List(rules.into_iter().map(Value::from).collect()),
+    ));
+    ev.laurel.insert("LAUREL_ALERT", EventValues::Single(rv));
+}
+
+/// Returns the string list `key` from the SYSCALL record
+fn syscall_strings(ev: &Event, key: &str) -> Vec<String> {
+    let Some(EventValues::Single(rv)) = ev.body.get(&MessageType::SYSCALL) else {
+        return vec![];
+    };
+    match rv.get(key).map(serde_json::to_value) {
+        Some(Ok(JValue::Array(vs))) => vs
+            .into_iter()
+            .filter_map(|v| match v {
+                JValue::String(s) => Some(s),
+                _ => None,
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Collects the values at `path`, descending into lists
+fn lookup(value: &JValue, path: &[String], out: &mut Vec<JValue>) {
+    match (path, value) {
+        ([], v) => out.push(v.clone()),
+        (_, JValue::Array(vs)) => vs.iter().for_each(|v| lookup(v, path, out)),
+        ([first, rest @ ..], JValue::Object(map)) => {
+            if let Some(v) = map.get(first) {
+                lookup(v, rest, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::config::Alerts;
+    use linux_audit_parser::EventID;
+
+    fn event(labels: &[&str], tags: &[&str], exe: &str) -> Event<'static> {
+        let mut ev = Event::new(
+            None,
+            EventID {
+                timestamp: 1000,
+                sequence: 1,
+            },
+        );
+        let mut rv = Body::default();
+        rv.push((Key::Literal("exe"), Value::from(exe.to_string())));
+        let list = |l: &[&str]| Value::List(l.iter().map(|s| Value::from(s.to_string())).collect());
+        if !labels.is_empty() {
+            rv.push((Key::Literal("LABELS"), list(labels)));
+        }
+        if !tags.is_empty() {
+            rv.push((Key::Literal("TAGS"), list(tags)));
+        }
+        ev.body
+            .insert(MessageType::SYSCALL, EventValues::Single(rv));
+        ev
+    }
+
+    #[test]
+    fn alerter() {
+        let alerts: Alerts = toml::from_str(
+            r#"
+            [[rules]]
+            name = "shell"
+            labels = ["reverse-shell"]
+            window = 60
+            dedup-fields = ["SYSCALL.exe"]
+
+            [[rules]]
+            name = "cron"
+            tags = ["T1053.003"]
+            window = 10
+            burst = 2
+            "#,
+        )
+        .unwrap();
+        let clock = Arc::new(ManualClock::default());
+        let mut a = Alerter::new(&alerts.rules).with_clock(clock.clone());
+
+        assert!(a.check(&event(&[], &[], "/bin/sh")).is_empty());
+        assert!(a.check(&event(&["other"], &[], "/bin/sh")).is_empty());
+        assert_eq!(a.suppressed(), 0);
+
+        // Duplicates are suppressed until the window has passed.
+        assert_eq!(
+            a.check(&event(&["reverse-shell"], &[], "/bin/sh")),
+            ["shell"]
+        );
+        clock.advance(1000);
+        assert!(a
+            .check(&event(&["reverse-shell"], &[], "/bin/sh"))
+            .is_empty());
+        clock.advance(1000);
+        assert_eq!(
+            a.check(&event(&["reverse-shell"], &[], "/bin/bash")),
+            ["shell"]
+        );
+        clock.advance(58000);
+        assert_eq!(
+            a.check(&event(&["reverse-shell"], &[], "/bin/sh")),
+            ["shell"]
+        );
+        assert_eq!(a.suppressed(), 1);
+
+        // No more than `burst` alerts per window
+        for _ in 0..2 {
+            clock.advance(1000);
+            assert_eq!(a.check(&event(&[], &["T1053.003"], "/bin/x")), ["cron"]);
+        }
+        clock.advance(1000);
+        assert!(a.check(&event(&[], &["T1053.003"], "/bin/x")).is_empty());
+        clock.advance(8000);
+        assert_eq!(a.check(&event(&[], &["T1053.003"], "/bin/x")), ["cron"]);
+        assert_eq!(a.suppressed(), 2);
+
+        clock.advance(1000);
+        let mut ev = event(&["reverse-shell"], &["T1053.003"], "/bin/zsh");
+        let names = a.check(&ev);
+        assert_eq!(names, ["shell", "cron"]);
+        add_alert_record(&mut ev, names);
+        assert!(serde_json::to_string(&ev)
+            .unwrap()
+            .contains(r#""LAUREL_ALERT":{"rules":["shell","cron"]}"#));
+    }
+}
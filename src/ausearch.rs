@@ -0,0 +1,149 @@
+//! Output of events in a format similar to `ausearch -i`
+
+use std::io::{self, Write};
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::json::{to_value, SpecialFormatter};
+use crate::rfc5424::parse_timestamp;
+
+/// Serialize message using formatter, write in `ausearch -i` format.
+pub fn write<W, T>(w: &mut W, message: &T, formatter: SpecialFormatter) -> io::Result<()>
+where
+    W: ?Sized + Write,
+    T: ?Sized + Serialize,
+{
+    write_value(w, &to_value(message, formatter)?)
+}
+
+/// Writes already-serialized message in a format similar to the
+/// interpreted output of `ausearch -i`:
+///
+/// - Every event starts with a `----` line.
+/// - Records are written in reverse order, one per line, as
+///   `type=<TYPE> msg=audit(<date> <time>:<seq>) : field=value …`.
+///   The time is UTC.
+/// - For fields that have been translated (e.g. `uid` → `UID`,
+///   `syscall` → `SYSCALL`), the translation is written instead of
+///   the raw value.
+/// - Argument lists are written as `a0`, `a1`, … fields, the
+///   `PROCTITLE` argument list as `proctitle`.
+/// - Other information added by laurel (`PID`, `LABELS`, …) is left
+///   out.
+pub fn write_value<W: ?Sized + Write>(w: &mut W, value: &Value) -> io::Result<()> {
+    let Value::Object(map) = value else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "message is not an object",
+        ));
+    };
+    let id = map.get("ID").and_then(Value::as_str).unwrap_or_default();
+    let msg = match (parse_timestamp(id), id.split_once(':')) {
+        (Some(t), Some((_, seq))) => format!(
+            "{:02}/{:02}/{:04} {:02}:{:02}:{:02}.{:03}:{seq}",
+            t.month, t.day, t.year, t.hour, t.min, t.sec, t.msec
+        ),
+        _ => id.into(),
+    };
+    let node = match map.get("NODE").and_then(Value::as_str) {
+        Some(node) => format!("node={node} "),
+        None => String::new(),
+    };
+    write!(w, "----")?;
+    for (ty, v) in map.iter().rev() {
+        let records = match v {
+            Value::Object(rec) => vec![rec],
+            Value::Array(vs) => vs.iter().rev().filter_map(Value::as_object).collect(),
+            _ => continue,
+        };
+        for rec in records {
+            write!(w, "\n{node}type={ty} msg=audit({msg}) :")?;
+            write_fields(w, ty, rec)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_fields<W: ?Sized + Write>(
+    w: &mut W,
+    ty: &str,
+    rec: &Map<String, Value>,
+) -> io::Result<()> {
+    for (k, v) in rec {
+        match (k.as_str(), v) {
+            ("ARGV", Value::Array(argv)) if ty == "PROCTITLE" => {
+                let args: Vec<String> = argv.iter().map(scalar).collect();
+                write!(w, " proctitle={}", args.join(" "))?;
+            }
+            // Syscall arguments follow the exit code.
+            ("ARGV", _) if ty == "SYSCALL" => {}
+            ("ARGV", Value::Array(argv)) => write_args(w, argv)?,
+            (k, _) if k.chars().any(|c| c.is_ascii_uppercase()) => {}
+            (k, Value::Object(nested)) => {
+                write!(w, " {k}='")?;
+                let mut buf = vec![];
+                write_fields(&mut buf, ty, nested)?;
+                w.write_all(buf.strip_prefix(b" ").unwrap_or(&buf))?;
+                write!(w, "'")?;
+            }
+            (k, v) => {
+                let v = match rec.get(&k.to_ascii_uppercase()) {
+                    Some(t @ Value::String(_)) => t,
+                    _ => v,
+                };
+                write!(w, " {k}={}", scalar(v))?;
+                if let ("SYSCALL", "exit", Some(Value::Array(argv))) = (ty, k, rec.get("ARGV")) {
+                    write_args(w, argv)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_args<W: ?Sized + Write>(w: &mut W, args: &[Value]) -> io::Result<()> {
+    for (n, arg) in args.iter().enumerate() {
+        write!(w, " a{n}={}", scalar(arg))?;
+    }
+    Ok(())
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => "(null)".into(),
+        Value::Array(vs) => vs.iter().map(scalar).collect::<Vec<_>>().join(","),
+        v => v.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ausearch() {
+        let event: Value =
+            serde_json::from_slice(include_bytes!("testdata/record-execve.json")).unwrap();
+        let mut buf = vec![];
+        write_value(&mut buf, &event).unwrap();
+        let output = String::from_utf8(buf).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[0], "----");
+        assert_eq!(
+            lines[1],
+            "node=work type=PROCTITLE msg=audit(03/07/2021 10:50:32.375:15558) : proctitle=whoami"
+        );
+        assert!(lines[2].starts_with("node=work type=PATH msg=audit(03/07/2021 10:50:32.375:15558) : item=1 name=/lib64/ld-linux-x86-64.so.2 "), "{}", lines[2]);
+        assert!(lines[2].ends_with(" ouid=root ogid=root rdev=00:00 nametype=NORMAL cap_fp=0x0 cap_fi=0x0 cap_fe=0 cap_fver=0x0"), "{}", lines[2]);
+        assert!(lines[3].contains(" : item=0 name=/usr/bin/whoami "));
+        assert!(lines[4].ends_with(" : cwd=/home/user/tmp"));
+        assert!(lines[5].ends_with(" : argc=1 a0=whoami"));
+        assert_eq!(
+            lines[6],
+            "node=work type=SYSCALL msg=audit(03/07/2021 10:50:32.375:15558) : arch=x86_64 syscall=execve success=yes exit=0 a0=0x63b29337fd18 a1=0x63b293387d58 a2=0x63b293375640 a3=0xfffffffffffff000 items=2 ppid=10883 pid=10884 auid=user uid=root gid=root euid=root suid=root fsuid=root egid=root sgid=root fsgid=root tty=pts1 ses=1 comm=whoami exe=/usr/bin/whoami key=(null)"
+        );
+        assert_eq!(lines.len(), 7);
+    }
+}
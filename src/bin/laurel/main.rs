@@ -2,40 +2,74 @@
 //! the Linux Audit daemon and reformats events as JSON Lines.
 
 use getopts::Options;
+use std::cell::{Cell, RefCell};
+#[cfg(feature = "signing")]
+use std::collections::HashMap;
 use std::env;
 use std::fs;
-use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Read, Write};
 use std::ops::AddAssign;
+use std::os::unix::fs::FileTypeExt;
 use std::os::unix::fs::PermissionsExt;
-use std::os::unix::io::FromRawFd;
-use std::os::unix::net::UnixStream;
+use std::os::unix::io::{AsRawFd, BorrowedFd, FromRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::sync::{
-    atomic::{AtomicBool, Ordering},
+    atomic::{AtomicBool, AtomicU64, Ordering},
     Arc,
 };
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::{anyhow, Context};
 
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
 use nix::sys::{
     signal::{sigprocmask, SigSet, SigmaskHow::*, Signal::*},
+    stat::{umask, Mode},
     sysinfo::sysinfo,
 };
-use nix::unistd::{chown, execve, Group, Uid, User};
+use nix::unistd::{chdir, chown, chroot, execve, getpid, Group, Uid, User};
 #[cfg(target_os = "linux")]
-use nix::unistd::{setresgid, setresuid};
+use nix::unistd::{daemon, getsid, setresgid, setresuid};
 
 #[cfg(target_os = "linux")]
 use caps::{securebits::set_keepcaps, CapSet, Capability};
 
 use serde::{Deserialize, Serialize};
 
+use linux_audit_parser::EventID;
+
+use laurel::alert::{self, Alerter};
+use laurel::ausearch;
 use laurel::coalesce::{self, Coalesce};
-use laurel::config::{Config, Input, Logfile};
-use laurel::json;
+use laurel::config::{Config, Input, Logfile, OutputFormat};
+use laurel::control::{self, EventRing, Query};
+#[cfg(feature = "encryption")]
+use laurel::encrypt::{self, FileEncryptor};
+use laurel::entity::Entity;
+use laurel::follow::{Checkpoint, FollowFile};
+use laurel::forward::Forwarder;
+use laurel::generate;
+use laurel::goaudit;
+use laurel::json::{self, Escape, Framing, InvalidUtf8, SpecialFormatter};
 use laurel::logger;
+use laurel::lookup::LookupTable;
+use laurel::otlp::{self, HttpExporter, Otlp};
+use laurel::projection::Projection;
+use laurel::rename::Rename;
+use laurel::rewrite::Rewrite;
+use laurel::rfc5424::{self, Rfc5424};
 use laurel::rotate::FileRotate;
+#[cfg(feature = "sigma")]
+use laurel::sigma::Sigma;
+#[cfg(feature = "signing")]
+use laurel::sign::{self, LineSigner};
+use laurel::spool::{write_partial, Spool};
+use laurel::strtab::{self, StringTable};
+use laurel::text;
+use laurel::translation::TranslatedValues;
 use laurel::types::Event;
 
 const fn build_id() -> &'static str {
@@ -109,6 +143,58 @@ fn drop_privileges(runas_user: &User) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Remove all capabilities that are not needed for processing events
+/// from the bounding set, so they can't be regained later.
+#[cfg(target_os = "linux")]
+fn restrict_bounding_set() -> anyhow::Result<()> {
+    #[allow(unused_mut)]
+    let mut keep = caps::CapsHashSet::new();
+    #[cfg(feature = "procfs")]
+    keep.extend([Capability::CAP_SYS_PTRACE, Capability::CAP_DAC_READ_SEARCH]);
+    for cap in caps::read(None, CapSet::Bounding).context("read bounding set")? {
+        if !keep.contains(&cap) {
+            caps::drop(None, CapSet::Bounding, cap)
+                .with_context(|| format!("drop {cap} from bounding set"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Environment variable that passes the listening control socket
+/// to the new process image on restart.
+const CONTROL_FD_ENV: &str = "LAUREL_CONTROL_FD";
+
+/// Set or clear the close-on-exec flag on a file descriptor.
+fn set_cloexec(fd: RawFd, on: bool) -> anyhow::Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error()).context("get descriptor flags");
+    }
+    let flags = match on {
+        true => flags | libc::FD_CLOEXEC,
+        false => flags & !libc::FD_CLOEXEC,
+    };
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, flags) } < 0 {
+        return Err(io::Error::last_os_error()).context("set descriptor flags");
+    }
+    Ok(())
+}
+
+/// Create the control socket, replacing a stale socket left over
+/// from a previous run.
+fn create_control_socket(path: &Path) -> anyhow::Result<UnixListener> {
+    if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+        fs::remove_file(path)
+            .with_context(|| format!("can't remove stale control socket {}", path.display()))?;
+    }
+    // The umask is set so that the socket is never
+    // accessible to others, not even briefly.
+    let old_umask = umask(Mode::from_bits_truncate(0o177));
+    let listener = UnixListener::bind(path);
+    umask(old_umask);
+    listener.with_context(|| format!("can't create control socket {}", path.display()))
+}
+
 /// Wrapper around UnixStream that attempts to reconnect up to
 /// `retries` times on error, using an exponential backoff algorithm,
 /// starting with 100ms.
@@ -171,23 +257,328 @@ impl Write for ReconnectableStream {
     }
 }
 
+/// Output format with format-specific settings
+enum Format {
+    Json,
+    Rfc5424(Rfc5424),
+    Text,
+    Ausearch,
+    GoAudit,
+    Otlp(Otlp),
+}
+
+fn make_format(def: &Logfile) -> Format {
+    match def.format {
+        OutputFormat::Json => Format::Json,
+        OutputFormat::Rfc5424 => Format::Rfc5424(Rfc5424::new(
+            def.sd_enterprise_id
+                .unwrap_or(rfc5424::DEFAULT_ENTERPRISE_ID),
+        )),
+        OutputFormat::Text => Format::Text,
+        OutputFormat::Ausearch => Format::Ausearch,
+        OutputFormat::GoAudit => Format::GoAudit,
+        OutputFormat::Otlp => Format::Otlp(Otlp::new()),
+    }
+}
+
+fn make_string_table(def: &Logfile) -> Option<StringTable> {
+    def.string_table.as_deref().map(|fields| {
+        StringTable::new(
+            fields,
+            def.string_table_size.unwrap_or(strtab::DEFAULT_SIZE),
+        )
+    })
+}
+
+fn make_rename(def: &Logfile) -> Option<Rename> {
+    (!def.rename.is_empty()).then(|| Rename::new(&def.rename))
+}
+
+#[cfg(feature = "signing")]
+fn make_signer(def: &Logfile, dir: &Path) -> anyhow::Result<Option<LineSigner>> {
+    let Some(path) = &def.sign_key else {
+        return Ok(None);
+    };
+    if def.format != OutputFormat::Json {
+        return Err(anyhow!("sign-key requires format = \"json\""));
+    }
+    LineSigner::load(&dir.join(path))
+        .map(Some)
+        .map_err(|e| anyhow!("can't read signing key: {e}"))
+}
+
+fn make_rewrite(def: &Logfile) -> anyhow::Result<Option<Rewrite>> {
+    if def.rewrite.is_empty() {
+        return Ok(None);
+    }
+    Rewrite::new(&def.rewrite)
+        .map(Some)
+        .map_err(|e| anyhow!("invalid rewrite rule: {e}"))
+}
+
+/// Builds the settings from the configuration and loads Sigma rules
+#[cfg_attr(not(feature = "sigma"), allow(unused_variables, unused_mut))]
+fn make_coalesce_settings(config: &Config, dir: &Path) -> anyhow::Result<coalesce::Settings> {
+    let mut settings = config.make_coalesce_settings();
+    #[cfg(feature = "sigma")]
+    if let Some(def) = &config.analyze.sigma {
+        let sigma = Sigma {
+            rules: def.rules.iter().map(|p| dir.join(p)).collect(),
+        };
+        let (rules, errors) = sigma.load().context("can't load Sigma rules")?;
+        for e in errors {
+            log::warn!("Skipping Sigma rule: {e}");
+        }
+        log::info!("Loaded {} Sigma rules", rules.len());
+        settings.analyze_sigma = rules;
+    }
+    Ok(settings)
+}
+
+fn make_lookup_tables(def: &Logfile, dir: &Path) -> anyhow::Result<Vec<LookupTable>> {
+    def.lookup
+        .iter()
+        .map(|l| {
+            LookupTable::new(&dir.join(&l.file), &l.field, &l.name)
+                .map_err(|e| anyhow!("can't read lookup table: {e}"))
+        })
+        .collect()
+}
+
 struct Logger {
     prefix: Option<String>,
+    formatter: SpecialFormatter,
+    format: Format,
+    lookups: Vec<LookupTable>,
+    rewrite: Option<Rewrite>,
+    translated_values: TranslatedValues,
+    projection: Option<Projection>,
+    string_table: Option<StringTable>,
+    rename: Option<Rename>,
+    spool: Option<Spool>,
+    #[cfg(feature = "signing")]
+    signer: Option<LineSigner>,
+    framing: Framing,
     output: BufWriter<Box<dyn Write>>,
+    /// Set when the output file has been rotated
+    rotated: Option<Arc<AtomicBool>>,
 }
 
 impl Logger {
     fn log<S: Serialize>(&mut self, message: S) -> std::io::Result<()> {
+        self.log_and_keep(message, None)
+    }
+
+    /// Like [`Logger::log`]. If the message is rendered as plain
+    /// JSON, the line is also copied to `keep`, so that it can be
+    /// reused without serializing the message again.
+    fn log_and_keep<S: Serialize>(
+        &mut self,
+        message: S,
+        keep: Option<&mut Vec<u8>>,
+    ) -> std::io::Result<()> {
+        let mut buf = Vec::new();
+        self.render(&mut buf, message)?;
+        if let (Some(keep), true) = (keep, self.is_plain_json()) {
+            keep.extend_from_slice(buf.strip_suffix(b"\n").unwrap_or(&buf));
+        }
+        let buf = self.finish(buf)?;
+        self.write_out(buf)
+    }
+
+    /// Returns true if messages are rendered without any
+    /// transformations
+    fn is_untransformed(&self) -> bool {
+        self.lookups.is_empty()
+            && self.rewrite.is_none()
+            && self.translated_values == TranslatedValues::Both
+            && self.projection.is_none()
+            && self.string_table.is_none()
+            && self.rename.is_none()
+    }
+
+    /// Returns true if messages are rendered like [`json::to_writer`]
+    /// does
+    fn is_plain_json(&self) -> bool {
+        matches!(self.format, Format::Json)
+            && self.prefix.is_none()
+            && self.formatter.escape == Escape::default()
+            && self.formatter.invalid_utf8 == InvalidUtf8::default()
+            && self.is_untransformed()
+    }
+
+    /// Apply transformations to the rendered lines that have to
+    /// happen last: signing, framing
+    fn finish(&mut self, buf: Vec<u8>) -> std::io::Result<Vec<u8>> {
+        #[cfg(feature = "signing")]
+        let buf = match &mut self.signer {
+            Some(signer) => signer
+                .sign(&buf)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            None => buf,
+        };
+        Ok(self.framing.apply(buf))
+    }
+
+    fn render<S: Serialize>(&mut self, w: &mut Vec<u8>, message: S) -> std::io::Result<()> {
+        if self.is_untransformed() {
+            if let Some(prefix) = &self.prefix {
+                w.write_all(prefix.as_bytes())?;
+            }
+            match &self.format {
+                Format::Json => json::to_writer_escaped(&mut *w, &message, self.formatter.clone())?,
+                Format::Rfc5424(r) => r.write(w, &message, self.formatter.clone())?,
+                Format::Text => text::write(w, &message, self.formatter.clone())?,
+                Format::Ausearch => ausearch::write(w, &message, self.formatter.clone())?,
+                Format::GoAudit => goaudit::write(w, &message, self.formatter.clone())?,
+                Format::Otlp(o) => o.write(w, &message, self.formatter.clone())?,
+            }
+            w.write_all(b"\n")?;
+        } else {
+            let mut value = json::to_value(&message, self.formatter.clone())?;
+            for t in &mut self.lookups {
+                t.apply(&mut value);
+            }
+            if let Some(r) = &self.rewrite {
+                r.apply(&mut value);
+            }
+            self.translated_values.apply(&mut value);
+            if let Some(p) = &self.projection {
+                p.apply(&mut value);
+            }
+            if let Some(t) = &mut self.string_table {
+                // Readers of the new file need all definitions.
+                if self
+                    .rotated
+                    .as_ref()
+                    .is_some_and(|r| r.swap(false, Ordering::Relaxed))
+                {
+                    t.clear();
+                }
+            }
+            if let Some(strings) = self.string_table.as_mut().and_then(|t| t.apply(&mut value)) {
+                self.write_value(w, &strings)?;
+            }
+            if let Some(r) = &self.rename {
+                r.apply(&mut value);
+            }
+            self.write_value(w, &value)?;
+        }
+        Ok(())
+    }
+
+    fn write_value(&self, w: &mut Vec<u8>, value: &serde_json::Value) -> std::io::Result<()> {
         if let Some(prefix) = &self.prefix {
-            self.output.write_all(prefix.as_bytes())?;
+            w.write_all(prefix.as_bytes())?;
+        }
+        match &self.format {
+            Format::Json => serde_json::to_writer(&mut *w, value)?,
+            Format::Rfc5424(r) => r.write_value(w, value)?,
+            Format::Text => text::write_value(w, value)?,
+            Format::Ausearch => ausearch::write_value(w, value)?,
+            Format::GoAudit => goaudit::write_value(w, value)?,
+            Format::Otlp(o) => o.write_value(w, value)?,
         }
-        laurel::json::to_writer(&mut self.output, &message)?;
-        self.output.write_all(b"\n")?;
-        self.output.flush()
+        w.write_all(b"\n")
+    }
+
+    /// Write rendered event to output. If a spool has been
+    /// configured, events that can't be written are kept and written
+    /// once the output works again.
+    fn write_out(&mut self, buf: Vec<u8>) -> std::io::Result<()> {
+        let Some(mut spool) = self.spool.take() else {
+            self.output.write_all(&buf)?;
+            return self.output.flush();
+        };
+        // The output is flushed after every event, so the BufWriter
+        // can be bypassed. This avoids writing partial data twice.
+        let mut lines = vec![];
+        if !spool.is_empty() {
+            let len = spool.len();
+            match spool.replay(self.output.get_mut()) {
+                Ok(0) => log::info!("Output works again, wrote {len} spooled events"),
+                Ok(dropped) => {
+                    log::warn!("Output works again, wrote {len} spooled events, lost {dropped}");
+                    let mut gap = Vec::new();
+                    let id = EventID {
+                        timestamp: SystemTime::now()
+                            .duration_since(SystemTime::UNIX_EPOCH)
+                            .map(|d| d.as_millis() as u64)
+                            .unwrap_or_default(),
+                        sequence: 0,
+                    };
+                    if self
+                        .render(
+                            &mut gap,
+                            serde_json::json!({
+                                "ID": id.to_string(),
+                                "LAUREL_GAP": {"dropped": dropped},
+                            }),
+                        )
+                        .is_ok()
+                    {
+                        if let Ok(gap) = self.finish(gap) {
+                            lines.push(gap);
+                        }
+                    }
+                }
+                Err(_) => {
+                    spool.push(buf);
+                    self.spool = Some(spool);
+                    return Ok(());
+                }
+            }
+        }
+        lines.push(buf);
+        let output = self.output.get_mut();
+        for line in lines {
+            if !spool.is_empty() {
+                spool.push(line);
+            } else if let Err((n, e)) = write_partial(output, &line) {
+                log::warn!("Error writing output: {e}, spooling events");
+                // Don't repeat the part that has been written.
+                if n < line.len() {
+                    spool.push_rest(line[n..].to_vec());
+                }
+            }
+        }
+        self.spool = Some(spool);
+        Ok(())
+    }
+
+    /// Create logger that writes to `output` instead of the
+    /// destination given in `def`
+    fn with_output(def: &Logfile, dir: &Path, output: Box<dyn Write>) -> anyhow::Result<Self> {
+        Ok(Logger {
+            prefix: def.line_prefix.clone(),
+            formatter: def.into(),
+            format: make_format(def),
+            lookups: make_lookup_tables(def, dir)?,
+            rewrite: make_rewrite(def)?,
+            translated_values: def.translated_values,
+            projection: def.fields.as_deref().map(Projection::new),
+            string_table: make_string_table(def),
+            rename: make_rename(def),
+            spool: def.spool_size.map(Spool::new),
+            framing: def.framing,
+            #[cfg(feature = "signing")]
+            signer: make_signer(def, dir)?,
+            output: BufWriter::new(output),
+            rotated: None,
+        })
     }
 
     fn new(def: &Logfile, dir: &Path) -> anyhow::Result<Self> {
-        match &def.file {
+        let file = def.file.to_str().unwrap();
+        if def.encrypt_to.is_some()
+            && (file == "-"
+                || ["|", "unix:", "http://", "https://"]
+                    .iter()
+                    .any(|p| file.starts_with(p)))
+        {
+            return Err(anyhow!("encrypt-to only works for files"));
+        }
+        let output: Box<dyn Write> = match &def.file {
             p if p.to_str().unwrap().starts_with('|') => {
                 let command = &p.to_str().unwrap()[1..].trim_start();
                 let mut child = std::process::Command::new(command)
@@ -198,10 +589,7 @@ impl Logger {
                     .stdin
                     .take()
                     .ok_or_else(|| anyhow!("failed to open stdin"))?;
-                Ok(Logger {
-                    prefix: def.line_prefix.clone(),
-                    output: BufWriter::new(Box::new(stdin)),
-                })
+                Box::new(stdin)
             }
             p if p.to_str().unwrap().starts_with("unix:") => {
                 let mut path = PathBuf::from(p.to_str().unwrap().strip_prefix("unix:").unwrap());
@@ -210,20 +598,22 @@ impl Logger {
                     filename.push(&path);
                     path = filename;
                 }
-                Ok(Logger {
-                    prefix: def.line_prefix.clone(),
-                    output: BufWriter::new(Box::new(ReconnectableStream::new(path, 7))),
-                })
+                Box::new(ReconnectableStream::new(path, 7))
+            }
+            p if ["http://", "https://"]
+                .iter()
+                .any(|prefix| p.to_str().unwrap().starts_with(prefix)) =>
+            {
+                Box::new(HttpExporter::new(p.to_str().unwrap(), 7)?)
+            }
+            p if p.as_os_str() == "-" => Box::new(io::stdout()),
+            p if p.has_root() && p.parent().is_none() => {
+                return Err(anyhow!(
+                    "invalid file directory={} file={}",
+                    dir.to_string_lossy(),
+                    p.to_string_lossy()
+                ))
             }
-            p if p.as_os_str() == "-" => Ok(Logger {
-                prefix: def.line_prefix.clone(),
-                output: BufWriter::new(Box::new(io::stdout())),
-            }),
-            p if p.has_root() && p.parent().is_none() => Err(anyhow!(
-                "invalid file directory={} file={}",
-                dir.to_string_lossy(),
-                p.to_string_lossy()
-            )),
             p => {
                 let mut filename = dir.to_path_buf();
                 filename.push(p);
@@ -246,10 +636,80 @@ impl Logger {
                 if let Some(filesize) = &def.size {
                     rot = rot.with_filesize(*filesize);
                 }
-                Ok(Logger {
-                    prefix: def.line_prefix.clone(),
-                    output: BufWriter::new(Box::new(rot)),
-                })
+                #[cfg(feature = "encryption")]
+                if let Some(key) = &def.encrypt_to {
+                    rot = rot.with_encryption(FileEncryptor::new(encrypt::parse_public_key(key)?));
+                }
+                let rotated = rot.rotated();
+                let mut logger = Self::with_output(def, dir, Box::new(rot))?;
+                logger.rotated = Some(rotated);
+                return Ok(logger);
+            }
+        };
+        Self::with_output(def, dir, output)
+    }
+}
+
+/// Writes events that match alert rules to a separate log, subject
+/// to deduplication and burst limits
+struct AlertLogger {
+    alerter: Alerter,
+    logger: Logger,
+    /// Shared with the main loop for status reports and heartbeats
+    suppressed: Arc<AtomicU64>,
+    /// Alerts that could not be written, shared like `suppressed`
+    undelivered: Arc<AtomicU64>,
+}
+
+impl AlertLogger {
+    /// Writes `e` as alert if it matches. Errors are logged and
+    /// counted instead of stopping laurel, since alert destinations
+    /// are typically remote.
+    fn log(&mut self, e: &Event) {
+        let rules = self.alerter.check(e);
+        self.suppressed
+            .store(self.alerter.suppressed(), Ordering::Relaxed);
+        if rules.is_empty() {
+            return;
+        }
+        let mut e = e.clone();
+        alert::add_alert_record(&mut e, rules);
+        if let Err(err) = self.logger.log(&e) {
+            log::warn!("Error writing to alert log: {err}");
+            self.undelivered.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Outputs for events that have not been filtered
+struct EventOutputs {
+    logger: Logger,
+    slim_logger: Option<Logger>,
+    alert_logger: Option<AlertLogger>,
+    ring: Option<EventRing>,
+}
+
+impl EventOutputs {
+    fn log(&mut self, e: &Event) {
+        let mut line = vec![];
+        self.logger
+            .log_and_keep(e, self.ring.as_ref().map(|_| &mut line))
+            .map_err(|e| anyhow!("Error writing to audit log: {e}"))
+            .unwrap();
+        if let Some(slim_logger) = &mut self.slim_logger {
+            slim_logger
+                .log(e)
+                .map_err(|e| anyhow!("Error writing to slim log: {e}"))
+                .unwrap();
+        }
+        if let Some(alert_logger) = &mut self.alert_logger {
+            alert_logger.log(e);
+        }
+        if let Some(ring) = &self.ring {
+            // The event only needs to be serialized again if the
+            // audit log is not plain JSON.
+            if !line.is_empty() || json::to_writer(&mut line, e).is_ok() {
+                ring.push(line);
             }
         }
     }
@@ -347,18 +807,493 @@ fn write_state(path: &Path, state: &coalesce::State) {
     }
 }
 
+/// Records the position in the input file before the `unprocessed`
+/// bytes that have been read but not processed yet.
+fn write_checkpoint(path: &Path, position: &Cell<Checkpoint>, unprocessed: usize) {
+    let mut c = position.get();
+    c.offset = c.offset.saturating_sub(unprocessed as u64);
+    if let Err(e) = c.store(path) {
+        log::error!("Error writing input checkpoint {}: {e}", path.display());
+    }
+}
+
+/// Render events from the audit log (or the specified file) for humans
+fn run_tail(config: &Config, args: &[String]) -> Result<(), anyhow::Error> {
+    let mut opts = Options::new();
+    opts.optopt("n", "lines", "Number of events to show at start", "NUM");
+    opts.optflag("", "no-follow", "Exit after reaching end of file");
+    let matches = opts.parse(args)?;
+
+    let path = match matches.free.first() {
+        Some(f) => PathBuf::from(f),
+        None => {
+            let file = &config.auditlog.file;
+            match file.to_str().unwrap_or_default() {
+                f if f == "-" || f.starts_with('|') || f.starts_with("unix:") => {
+                    return Err(anyhow!("audit log {f} is not a file"));
+                }
+                _ => {}
+            }
+            let mut path = config
+                .directory
+                .clone()
+                .unwrap_or_else(|| Path::new(".").to_path_buf());
+            path.push(file);
+            path
+        }
+    };
+
+    let settings = laurel::tail::Tail {
+        lines: match matches.opt_str("n") {
+            Some(n) => n.parse().with_context(|| format!("invalid number {n}"))?,
+            None => 10,
+        },
+        follow: !matches.opt_present("no-follow"),
+        color: io::stdout().is_terminal(),
+    };
+    laurel::tail::run(&path, &settings, &mut io::stdout().lock())
+        .with_context(|| format!("Error reading {}", path.to_string_lossy()))
+}
+
+/// Counters that are reported in `LAUREL_HEARTBEAT` and
+/// `LAUREL_SHUTDOWN` events
+fn event_counters(
+    uptime: Duration,
+    overall_stats: &Stats,
+    stats: &Stats,
+    dropped_records: u64,
+    forwarder: Option<&Forwarder>,
+    alerts_suppressed: &AtomicU64,
+    alerts_undelivered: &AtomicU64,
+) -> Vec<(&'static str, u64)> {
+    vec![
+        ("uptime", uptime.as_secs()),
+        ("lines", overall_stats.lines + stats.lines),
+        ("errors", overall_stats.errors + stats.errors),
+        ("dropped_records", dropped_records),
+        ("forward_dropped", forwarder.map_or(0, Forwarder::dropped)),
+        ("http_dropped", otlp::dropped()),
+        (
+            "alerts_suppressed",
+            alerts_suppressed.load(Ordering::Relaxed),
+        ),
+        (
+            "alerts_undelivered",
+            alerts_undelivered.load(Ordering::Relaxed),
+        ),
+    ]
+}
+
+/// Print recent events that match conditions, retrieved through the
+/// control socket
+fn run_query(config: &Config, args: &[String]) -> Result<(), anyhow::Error> {
+    let Some(path) = &config.control.socket else {
+        return Err(anyhow!("control socket is not configured"));
+    };
+    let query = match args.join(" ") {
+        q if q.trim().is_empty() => "*".to_string(),
+        q => q,
+    };
+    query.parse::<Query>().map_err(|e| anyhow!(e))?;
+    let mut stream = UnixStream::connect(path)
+        .with_context(|| format!("Error connecting to {}", path.to_string_lossy()))?;
+    writeln!(stream, "{query}")?;
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct Error {
+        error: String,
+    }
+    let mut stdout = io::stdout().lock();
+    // A complete response is terminated by an empty line.
+    for line in BufReader::new(stream).split(b'\n') {
+        let line = line?;
+        if line.is_empty() {
+            return Ok(());
+        }
+        if let Ok(Error { error }) = serde_json::from_slice(&line) {
+            return Err(anyhow!(error));
+        }
+        stdout.write_all(&line)?;
+        stdout.write_all(b"\n")?;
+    }
+    Err(anyhow!("response truncated"))
+}
+
+/// Interval at which control socket clients are served while input
+/// is pending
+const CONTROL_SERVE_PERIOD: Duration = Duration::from_millis(100);
+
+/// Raw audit inputs used by `selftest` if the golden directory does
+/// not contain any inputs
+const SELFTEST_CORPUS: &[(&str, &[u8])] = &[
+    ("execve", include_bytes!("../../testdata/record-execve.txt")),
+    (
+        "execve-long",
+        include_bytes!("../../testdata/record-execve-long.txt"),
+    ),
+    (
+        "connect",
+        include_bytes!("../../testdata/record-connect.txt"),
+    ),
+    (
+        "bind-ipv4-bigendian",
+        include_bytes!("../../testdata/record-bind-ipv4-bigendian.txt"),
+    ),
+    ("ptrace", include_bytes!("../../testdata/record-ptrace.txt")),
+    (
+        "uringop",
+        include_bytes!("../../testdata/record-uringop.txt"),
+    ),
+    ("login", include_bytes!("../../testdata/record-login.txt")),
+    (
+        "avc-apparmor",
+        include_bytes!("../../testdata/record-avc-apparmor.txt"),
+    ),
+    (
+        "syscall-key",
+        include_bytes!("../../testdata/record-syscall-key.txt"),
+    ),
+    (
+        "perl-reverse-shell",
+        include_bytes!("../../testdata/record-perl-reverse-shell.txt"),
+    ),
+    (
+        "shell-proc-trace",
+        include_bytes!("../../testdata/shell-proc-trace.txt"),
+    ),
+];
+
+/// Writer that appends to a buffer that is shared with the caller
+struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Run raw audit input through the processing pipeline, return what
+/// would be written to the audit log
+fn selftest_process(config: &Config, dir: &Path, input: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let buf = Rc::new(RefCell::new(Vec::new()));
+    let mut logger =
+        Logger::with_output(&config.auditlog, dir, Box::new(SharedBuf(Rc::clone(&buf))))?;
+    let mut coalesce = Coalesce::new(move |e: &Event| {
+        if !e.is_filtered {
+            logger
+                .log(e)
+                .map_err(|e| anyhow!("Error writing output: {e}"))
+                .unwrap();
+        }
+    })
+    .with_settings(make_coalesce_settings(config, dir)?)
+    .offline();
+    for (n, line) in input.split_inclusive(|c| *c == b'\n').enumerate() {
+        // Allow for annotated inputs
+        if line.starts_with(b"#") || line.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+        coalesce
+            .process_line(line)
+            .with_context(|| format!("line {}", n + 1))?;
+    }
+    coalesce.flush();
+    drop(coalesce);
+    let output = buf.take();
+    Ok(output)
+}
+
+/// Compare output for raw audit inputs against golden files
+///
+/// Inputs are read from `DIR/*.txt` (or the bundled corpus), expected
+/// outputs from `DIR/*.out`.
+fn run_selftest(config: &Config, args: &[String]) -> Result<(), anyhow::Error> {
+    let mut opts = Options::new();
+    opts.optflag("", "update", "Write golden files instead of comparing");
+    let matches = opts.parse(args)?;
+    let golden_dir = PathBuf::from(
+        matches
+            .free
+            .first()
+            .ok_or_else(|| anyhow!("no golden directory specified"))?,
+    );
+    let update = matches.opt_present("update");
+    let dir = config
+        .directory
+        .clone()
+        .unwrap_or_else(|| Path::new(".").to_path_buf());
+
+    let mut inputs = vec![];
+    if golden_dir.is_dir() {
+        for entry in fs::read_dir(&golden_dir)
+            .with_context(|| format!("read_dir: {}", golden_dir.to_string_lossy()))?
+        {
+            let path = entry?.path();
+            if let (Some(name), Some("txt")) = (
+                path.file_stem().and_then(|s| s.to_str()),
+                path.extension().and_then(|s| s.to_str()),
+            ) {
+                let input =
+                    fs::read(&path).with_context(|| format!("read: {}", path.to_string_lossy()))?;
+                inputs.push((name.to_string(), input));
+            }
+        }
+    } else if update {
+        fs::create_dir_all(&golden_dir)
+            .with_context(|| format!("create_dir: {}", golden_dir.to_string_lossy()))?;
+    }
+    if inputs.is_empty() {
+        inputs = SELFTEST_CORPUS
+            .iter()
+            .map(|(name, input)| (name.to_string(), input.to_vec()))
+            .collect();
+    }
+    inputs.sort();
+
+    let mut failed = 0;
+    for (name, input) in &inputs {
+        let output = selftest_process(config, &dir, input).with_context(|| name.clone())?;
+        let golden = golden_dir.join(format!("{name}.out"));
+        if update {
+            fs::write(&golden, &output)
+                .with_context(|| format!("write: {}", golden.to_string_lossy()))?;
+            println!("updated {name}");
+            continue;
+        }
+        let expected = match fs::read(&golden) {
+            Ok(expected) => expected,
+            Err(e) => {
+                println!("FAIL {name}: {}: {e}", golden.to_string_lossy());
+                failed += 1;
+                continue;
+            }
+        };
+        let mismatch = output
+            .split(|c| *c == b'\n')
+            .zip(expected.split(|c| *c == b'\n'))
+            .enumerate()
+            .find(|(_, (o, e))| o != e);
+        match mismatch {
+            None if output.len() == expected.len() => println!("ok {name}"),
+            None => {
+                println!("FAIL {name}: output length differs");
+                failed += 1;
+            }
+            Some((n, (o, e))) => {
+                println!("FAIL {name}: line {} differs", n + 1);
+                println!("  expected: {}", String::from_utf8_lossy(e));
+                println!("  actual:   {}", String::from_utf8_lossy(o));
+                failed += 1;
+            }
+        }
+    }
+    match failed {
+        0 => Ok(()),
+        n => Err(anyhow!("{n} of {} tests failed", inputs.len())),
+    }
+}
+
+/// Create a private key for signing output lines or decrypting
+/// output files and print the public key
+#[cfg_attr(
+    not(all(feature = "signing", feature = "encryption")),
+    allow(unused_variables, unreachable_code)
+)]
+fn run_keygen(args: &[String]) -> Result<(), anyhow::Error> {
+    let mut opts = Options::new();
+    opts.optflag("", "encryption", "Create key for encrypted output files");
+    let matches = opts.parse(args)?;
+    let [path] = matches.free.as_slice() else {
+        return Err(anyhow!("usage: keygen [--encryption] FILE"));
+    };
+    if matches.opt_present("encryption") {
+        #[cfg(feature = "encryption")]
+        println!("{}", encrypt::generate_key(Path::new(path))?);
+        #[cfg(not(feature = "encryption"))]
+        return Err(anyhow!("built without encryption support"));
+    } else {
+        #[cfg(feature = "signing")]
+        println!("{}", sign::generate_key(Path::new(path))?.public_key());
+        #[cfg(not(feature = "signing"))]
+        return Err(anyhow!("built without signing support"));
+    }
+    Ok(())
+}
+
+/// Decrypt output files and write the contents to stdout
+#[cfg(feature = "encryption")]
+fn run_decrypt(args: &[String]) -> Result<(), anyhow::Error> {
+    let mut opts = Options::new();
+    opts.reqopt("k", "key", "Private key file", "FILE");
+    let matches = opts.parse(args)?;
+    let key = encrypt::read_secret_key(Path::new(&matches.opt_str("k").unwrap()))?;
+
+    let mut stdout = io::stdout().lock();
+    let mut failed = 0;
+    let mut report = |name: &str, errors: Vec<encrypt::EncryptError>| {
+        for e in errors {
+            eprintln!("{name}: {e}");
+            // Files that are still being written have not been
+            // finished yet.
+            if !matches!(e, encrypt::EncryptError::Unfinished(_)) {
+                failed += 1;
+            }
+        }
+    };
+    if matches.free.is_empty() {
+        report(
+            "-",
+            encrypt::decrypt(&key, io::stdin().lock(), &mut stdout)?,
+        );
+    }
+    for f in &matches.free {
+        let file = fs::File::open(f).with_context(|| format!("Error opening {f}"))?;
+        let errors = encrypt::decrypt(&key, BufReader::new(file), &mut stdout)
+            .with_context(|| format!("Error decrypting {f}"))?;
+        report(f, errors);
+    }
+    match failed {
+        0 => Ok(()),
+        n => Err(anyhow!("{n} damaged parts skipped")),
+    }
+}
+
+/// Check signatures of lines from the specified file or stdin
+#[cfg(feature = "signing")]
+fn run_verify(args: &[String]) -> Result<(), anyhow::Error> {
+    let mut opts = Options::new();
+    opts.optmulti("k", "key", "Accept only signatures made with KEY", "KEY");
+    let matches = opts.parse(args)?;
+    let keys = matches.opt_strs("k");
+
+    let input: Box<dyn BufRead> = match matches.free.first().map(String::as_str) {
+        None | Some("-") => Box::new(io::stdin().lock()),
+        Some(f) => Box::new(BufReader::new(
+            fs::File::open(f).with_context(|| format!("Error opening {f}"))?,
+        )),
+    };
+    let (mut good, mut bad) = (0, 0);
+    // Next expected sequence number per key and session
+    let mut next_seq: HashMap<(String, String), u64> = HashMap::new();
+    for (n, line) in input.split(b'\n').enumerate() {
+        let line = line?;
+        match sign::verify(&line) {
+            Ok(v) if !keys.is_empty() && !keys.contains(&v.key) => {
+                eprintln!("line {}: untrusted key {}", n + 1, v.key);
+                bad += 1;
+            }
+            Ok(v) => {
+                let next = next_seq.entry((v.key, v.session)).or_insert(v.seq);
+                if v.seq == *next {
+                    good += 1;
+                } else {
+                    eprintln!(
+                        "line {}: expected sequence number {next}, got {}",
+                        n + 1,
+                        v.seq
+                    );
+                    bad += 1;
+                }
+                *next = (*next).max(v.seq + 1);
+            }
+            Err(e) => {
+                eprintln!("line {}: {e}", n + 1);
+                bad += 1;
+            }
+        }
+    }
+    match bad {
+        0 => {
+            println!("{good} lines ok");
+            Ok(())
+        }
+        n => Err(anyhow!("{n} of {} lines failed verification", good + bad)),
+    }
+}
+
+/// Write a synthetic audit stream to stdout
+fn run_generate(args: &[String]) -> Result<(), anyhow::Error> {
+    let mut opts = Options::new();
+    opts.optopt("n", "count", "Number of events (default: unlimited)", "NUM");
+    opts.optopt("r", "rate", "Events per second (default: unlimited)", "NUM");
+    opts.optopt("", "mix", "Relative frequencies of event types", "MIX");
+    opts.optopt("", "seed", "Seed for reproducible streams", "NUM");
+    let matches = opts.parse(args)?;
+    let count: Option<u64> = match matches.opt_str("n") {
+        Some(n) => Some(n.parse().with_context(|| format!("invalid number {n}"))?),
+        None => None,
+    };
+    let rate: Option<f64> = match matches.opt_str("r") {
+        Some(r) => match r.parse::<f64>() {
+            Ok(v) if v.is_finite() && v > 0.0 => Some(v),
+            _ => return Err(anyhow!("invalid rate {r}")),
+        },
+        None => None,
+    };
+    let mix = match matches.opt_str("mix") {
+        Some(m) => m.parse()?,
+        None => generate::Mix::default(),
+    };
+    let now_ms = || {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default()
+    };
+    let seed = match matches.opt_str("seed") {
+        Some(s) => s.parse().with_context(|| format!("invalid seed {s}"))?,
+        None => now_ms(),
+    };
+
+    let mut generator = generate::Generator::new(mix, seed);
+    let mut out = BufWriter::new(io::stdout().lock());
+    let start = Instant::now();
+    let mut n = 0;
+    while count.map_or(true, |c| n < c) {
+        if let Some(rate) = rate {
+            let due = start + Duration::from_secs_f64(n as f64 / rate);
+            if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                out.flush()?;
+                std::thread::sleep(wait);
+            }
+        }
+        generator.write_event(&mut out, now_ms())?;
+        n += 1;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn run_rules() -> Result<(), anyhow::Error> {
+    for rule in laurel::rules::list_rules().context("Error reading audit rules")? {
+        println!("{rule}");
+    }
+    Ok(())
+}
+
 fn run_app() -> Result<(), anyhow::Error> {
     let args: Vec<String> = env::args().collect();
 
     let mut opts = Options::new();
     opts.optopt("c", "config", "Configuration file", "FILE");
+    opts.optmulti("", "set", "Override configuration value", "KEY=VALUE");
     opts.optflag("d", "dry-run", "Only parse configuration and exit");
+    opts.optflag("D", "daemon", "Detach and run in the background");
     opts.optflag("h", "help", "Print short help text and exit");
     opts.optflag("v", "version", "Print version and exit");
+    opts.parsing_style(getopts::ParsingStyle::StopAtFirstFree);
 
     let matches = opts.parse(&args[1..])?;
     if matches.opt_present("h") {
         println!("{}", opts.usage(&args[0]));
+        println!(
+            "Commands:\n    tail [-n NUM] [--no-follow] [FILE]\n    query [CONDITION...]\n    rules\n    selftest [--update] DIR\n    keygen [--encryption] FILE\n    verify [--key KEY...] [FILE]\n    decrypt --key FILE [FILE...]"
+        );
         return Ok(());
     }
 
@@ -367,34 +1302,48 @@ fn run_app() -> Result<(), anyhow::Error> {
         return Ok(());
     }
 
-    let config: Config = match matches.opt_str("c") {
-        Some(f) => {
-            if fs::metadata(&f)
-                .with_context(|| format!("stat {f}"))?
-                .permissions()
-                .mode()
-                & 0o002
-                != 0
-            {
-                return Err(anyhow!("Config file {f} must not be world-writable"));
-            }
-            let lines = fs::read(&f).with_context(|| format!("Error reading {f}"))?;
-            toml::from_str(&String::from_utf8(lines).with_context(|| format!("Error parsing {f}"))?)
-                .with_context(|| format!("Error parsing {f}"))?
+    let mut overrides = laurel::config::env_overrides(env::vars());
+    for s in matches.opt_strs("set") {
+        match s.split_once('=') {
+            Some((k, v)) => overrides.push((k.into(), v.into())),
+            None => return Err(anyhow!("Invalid --set argument {s}, expected KEY=VALUE")),
         }
-        None => Config::default(),
-    };
+    }
+    let config = Config::load(matches.opt_str("c").as_deref().map(Path::new), &overrides)?;
+
+    let daemon_mode = matches.opt_present("D");
+    if daemon_mode && matches!(config.input, Input::Stdin) {
+        return Err(anyhow!(
+            "Daemon mode requires a unix socket or file as input"
+        ));
+    }
 
     // Set up input before dropping privileges.
+    let mut input_fd: Option<RawFd> = Some(0);
+    let mut input_position = None;
     let raw_input: Box<dyn Read> = match &config.input {
         // safety: File descriptor 0 is readable. (If it isn't, the
         // first read will cause the appropriate error.) We don't use
         // file descriptor 0 anywhere else.
         Input::Stdin => Box::new(unsafe { std::fs::File::from_raw_fd(0) }),
-        Input::Unix(path) => Box::new(
-            UnixStream::connect(path)
-                .with_context(|| format!("Error connecting to {}", path.to_string_lossy()))?,
-        ),
+        Input::Unix(path) => {
+            let stream = UnixStream::connect(path)
+                .with_context(|| format!("Error connecting to {}", path.to_string_lossy()))?;
+            input_fd = Some(stream.as_raw_fd());
+            Box::new(stream)
+        }
+        Input::File(path) => {
+            let checkpoint = config
+                .directory
+                .as_deref()
+                .unwrap_or(Path::new("."))
+                .join(&config.state.input_checkpoint);
+            let file = FollowFile::open(path, Checkpoint::load(&checkpoint).ok())
+                .with_context(|| format!("Error opening {}", path.to_string_lossy()))?;
+            input_fd = None;
+            input_position = Some(file.position());
+            Box::new(file)
+        }
     };
 
     // std::io::Stdin's buffer is only 8KB, so we construct our own.
@@ -416,6 +1365,22 @@ fn run_app() -> Result<(), anyhow::Error> {
         return Ok(());
     }
 
+    match matches.free.first().map(String::as_str) {
+        Some("tail") => return run_tail(&config, &matches.free[1..]),
+        Some("selftest") => return run_selftest(&config, &matches.free[1..]),
+        Some("query") => return run_query(&config, &matches.free[1..]),
+        Some("keygen") => return run_keygen(&matches.free[1..]),
+        #[cfg(feature = "signing")]
+        Some("verify") => return run_verify(&matches.free[1..]),
+        #[cfg(feature = "encryption")]
+        Some("decrypt") => return run_decrypt(&matches.free[1..]),
+        Some("generate") => return run_generate(&matches.free[1..]),
+        #[cfg(target_os = "linux")]
+        Some("rules") => return run_rules(),
+        Some(cmd) => return Err(anyhow!("unknown command {cmd}")),
+        None => {}
+    }
+
     let dir = config
         .directory
         .clone()
@@ -446,7 +1411,31 @@ fn run_app() -> Result<(), anyhow::Error> {
     fs::set_permissions(&dir, PermissionsExt::from_mode(0o755))
         .with_context(|| format!("chmod: {}", dir.to_string_lossy()))?;
 
+    // A session leader has already been detached, e.g. before
+    // restarting on SIGHUP.
+    #[cfg(target_os = "linux")]
+    if daemon_mode && getsid(None)? != getpid() {
+        daemon(true, false).context("daemon")?;
+    }
+
+    if let Some(pidfile) = &config.daemon.pidfile {
+        let pid = getpid().to_string();
+        if fs::read_to_string(pidfile).ok().as_deref().map(str::trim) != Some(pid.as_str()) {
+            fs::write(pidfile, format!("{pid}\n"))
+                .with_context(|| format!("write pidfile {}", pidfile.to_string_lossy()))?;
+        }
+    }
+
+    let dir = if config.daemon.chroot {
+        chroot(&dir).with_context(|| format!("chroot: {}", dir.to_string_lossy()))?;
+        chdir("/").context("chdir: /")?;
+        PathBuf::from("/")
+    } else {
+        dir
+    };
+
     let statefile_path = config.state.file.as_ref().map(|f| dir.join(f));
+    let checkpoint_path = dir.join(&config.state.input_checkpoint);
 
     let mut error_logger = if let Some(def) = &config.debug.parse_error_log {
         let mut filename = dir.clone();
@@ -467,6 +1456,65 @@ fn run_app() -> Result<(), anyhow::Error> {
         None
     };
 
+    // Reading audit rules requires CAP_AUDIT_CONTROL.
+    #[cfg(target_os = "linux")]
+    let audit_rules = if (config.audit_rules.report
+        || config.audit_rules.check_exec
+        || config.audit_rules.check_features)
+        && Uid::effective().is_root()
+    {
+        laurel::rules::list_rules()
+            .map_err(|e| log::warn!("Could not read audit rules: {e}"))
+            .ok()
+    } else {
+        None
+    };
+
+    // Subscribing to process events requires CAP_NET_ADMIN.
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    let proc_watcher = if config.enrich.cgroup_prefetch || config.enrich.process_events {
+        laurel::procwatch::ProcWatcher::new()
+            .map_err(|e| log::warn!("Could not subscribe to process events: {e}"))
+            .ok()
+    } else {
+        None
+    };
+
+    // The control socket is created before privileges are dropped
+    // and is only accessible to root. After a restart on SIGHUP, the
+    // privileges needed to replace it are gone, so the listening
+    // socket is inherited instead.
+    let inherited_control_fd = env::var(CONTROL_FD_ENV)
+        .ok()
+        .and_then(|fd| fd.parse::<RawFd>().ok());
+    env::remove_var(CONTROL_FD_ENV);
+    let control_socket = match (&config.control.socket, inherited_control_fd) {
+        (Some(_), Some(fd)) => {
+            let listener = unsafe { UnixListener::from_raw_fd(fd) };
+            set_cloexec(fd, true)?;
+            Some(listener)
+        }
+        (Some(path), None) => match create_control_socket(path) {
+            Ok(listener) => Some(listener),
+            Err(e) => {
+                log::warn!("Not serving queries: {e:#}");
+                None
+            }
+        },
+        (None, _) => None,
+    };
+    let control_fd = control_socket.as_ref().map(UnixListener::as_raw_fd);
+
+    // Programs that records are forwarded to are started with full
+    // privileges.
+    let forwarder = (!config.forward.destinations.is_empty())
+        .then(|| Forwarder::new(&config.forward.destinations, config.forward.queue_size));
+
+    #[cfg(target_os = "linux")]
+    if daemon_mode && Uid::effective().is_root() {
+        restrict_bounding_set()?;
+    }
+
     if !Uid::effective().is_root() {
         log::warn!("Not dropping privileges -- not running as root");
     } else if runas_user.uid.is_root() {
@@ -501,37 +1549,113 @@ fn run_app() -> Result<(), anyhow::Error> {
     let emit_fn_drop;
     let emit_fn_log;
 
-    let mut logger = Logger::new(&config.auditlog, &dir).context("can't create audit logger")?;
+    let logger = Logger::new(&config.auditlog, &dir).context("can't create audit logger")?;
+    let slim_logger = match &config.slimlog {
+        Some(def) => Some(Logger::new(def, &dir).context("can't create slim logger")?),
+        None => None,
+    };
+
+    let alerts_suppressed = Arc::new(AtomicU64::new(0));
+    let alerts_undelivered = Arc::new(AtomicU64::new(0));
+    let alert_logger = match &config.alerts.output {
+        Some(def) if !config.alerts.rules.is_empty() => Some(AlertLogger {
+            alerter: Alerter::new(&config.alerts.rules),
+            logger: Logger::new(def, &dir).context("can't create alert logger")?,
+            suppressed: Arc::clone(&alerts_suppressed),
+            undelivered: Arc::clone(&alerts_undelivered),
+        }),
+        _ => None,
+    };
+
+    let (ring, mut control_server) = match control_socket {
+        Some(listener) => {
+            let ring = EventRing::new(config.control.events);
+            let server = control::Server::new(listener, ring.clone())
+                .context("can't set up control socket")?;
+            // Events are not rendered for an empty ring.
+            (
+                Some(ring).filter(|_| config.control.events > 0),
+                Some(server),
+            )
+        }
+        None => (None, None),
+    };
+
+    // Output is suspended on SIGUSR1 and resumed on SIGUSR2. Input
+    // is still processed so that process tracking remains accurate.
+    // Events that are not written in the meantime are counted.
+    let suspended = Arc::new(AtomicBool::new(false));
+    let suspended_events = Arc::new(AtomicU64::new(0));
+
+    let mut outputs = EventOutputs {
+        logger,
+        slim_logger,
+        alert_logger,
+        ring,
+    };
 
     if let laurel::config::FilterAction::Log = config.filter.filter_action {
         log::info!("Logging filtered audit records");
         let mut filter_logger =
             Logger::new(&config.filterlog, &dir).context("can't create filterlog logger")?;
+        let suspended = Arc::clone(&suspended);
+        let suspended_events = Arc::clone(&suspended_events);
         emit_fn_log = move |e: &Event| {
+            if suspended.load(Ordering::Relaxed) {
+                if !e.is_filtered {
+                    suspended_events.fetch_add(1, Ordering::Relaxed);
+                }
+                return;
+            }
             if e.is_filtered {
                 filter_logger
                     .log(e)
                     .map_err(|e| anyhow!("Error writing to filter log: {e}"))
                     .unwrap();
             } else {
-                logger
-                    .log(e)
-                    .map_err(|e| anyhow!("Error writing to audit log: {e}"))
-                    .unwrap();
+                outputs.log(e);
             }
         };
         coalesce = Coalesce::new(emit_fn_log);
     } else {
         log::info!("Dropping filtered audit records");
+        let suspended = Arc::clone(&suspended);
+        let suspended_events = Arc::clone(&suspended_events);
         emit_fn_drop = move |e: &Event| {
-            if !e.is_filtered {
+            if !e.is_filtered && suspended.load(Ordering::Relaxed) {
+                suspended_events.fetch_add(1, Ordering::Relaxed);
+            } else if !e.is_filtered {
+                outputs.log(e);
+            }
+        };
+        coalesce = Coalesce::new(emit_fn_drop);
+    }
+
+    let mut process_logger = match &config.entities.processes {
+        Some(def) => Some(Logger::new(def, &dir).context("can't create process entity logger")?),
+        None => None,
+    };
+    let mut file_logger = match &config.entities.files {
+        Some(def) => Some(Logger::new(def, &dir).context("can't create file entity logger")?),
+        None => None,
+    };
+    if process_logger.is_some() || file_logger.is_some() {
+        let suspended = Arc::clone(&suspended);
+        coalesce = coalesce.with_entity_fn(move |e: &Entity| {
+            if suspended.load(Ordering::Relaxed) {
+                return;
+            }
+            let logger = match e {
+                Entity::Process(_) => &mut process_logger,
+                Entity::File(_) => &mut file_logger,
+            };
+            if let Some(logger) = logger {
                 logger
                     .log(e)
-                    .map_err(|e| anyhow!("Error writing to audit log: {e}"))
+                    .map_err(|e| anyhow!("Error writing to entity log: {e}"))
                     .unwrap();
             }
-        };
-        coalesce = Coalesce::new(emit_fn_drop);
+        });
     }
 
     let mut inputlog = if let Some(ref w) = config.debug.inputlog {
@@ -540,7 +1664,7 @@ fn run_app() -> Result<(), anyhow::Error> {
         None
     };
 
-    coalesce = coalesce.with_settings(config.make_coalesce_settings());
+    coalesce = coalesce.with_settings(make_coalesce_settings(&config, &dir)?);
 
     if let Some(state) = statefile_path
         .as_ref()
@@ -553,6 +1677,48 @@ fn run_app() -> Result<(), anyhow::Error> {
         coalesce.initialize().context("Failed to initialize")?;
     }
 
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    if let Some(watcher) = proc_watcher {
+        let cgroups = config
+            .enrich
+            .cgroup_prefetch
+            .then(laurel::procwatch::CgroupCache::default);
+        if let Some(cgroups) = &cgroups {
+            coalesce = coalesce.with_cgroup_cache(cgroups.clone());
+        }
+        let events = if config.enrich.process_events {
+            let (tx, rx) = std::sync::mpsc::sync_channel(laurel::procwatch::EVENT_QUEUE_SIZE);
+            coalesce = coalesce.with_proc_events(rx);
+            Some(tx)
+        } else {
+            None
+        };
+        watcher.spawn(cgroups, events);
+    }
+
+    #[cfg(target_os = "linux")]
+    if let Some(rules) = &audit_rules {
+        if config.audit_rules.check_exec && !rules.iter().any(laurel::rules::Rule::is_exec) {
+            log::warn!("No audit rules for execve syscalls are loaded");
+        }
+        if config.audit_rules.check_features {
+            let unmet: Vec<_> = config
+                .rule_requirements()
+                .into_iter()
+                .filter(|(_, req)| !req.is_met(rules))
+                .collect();
+            for (feature, req) in &unmet {
+                log::warn!("{feature} is configured, but no {req} are loaded");
+            }
+            if !unmet.is_empty() {
+                coalesce.emit_rules_check(&unmet);
+            }
+        }
+        if config.audit_rules.report {
+            coalesce.emit_rules(rules);
+        }
+    }
+
     let mut line: Vec<u8> = Vec::new();
     #[cfg(feature = "procfs")]
     let mut cpu_stats = CPUStats::default();
@@ -562,14 +1728,68 @@ fn run_app() -> Result<(), anyhow::Error> {
     let statusreport_period = config.statusreport_period.map(Duration::from_secs);
     let mut statusreport_last_t = SystemTime::now();
 
+    let heartbeat_period = config
+        .heartbeat_period
+        .filter(|p| *p > 0)
+        .map(Duration::from_secs);
+    let mut heartbeat_last_t = SystemTime::now();
+    let start_t = SystemTime::now();
+
     let write_state_period = config.state.write_state_period.map(Duration::from_secs);
     let mut write_state_last_t = SystemTime::now();
 
-    sigprocmask(SIG_UNBLOCK, Some(&SigSet::from_iter([SIGHUP])), None)?;
+    let mut clock_skew = None;
+
+    let mut control_served_t = Instant::now();
+
+    sigprocmask(
+        SIG_UNBLOCK,
+        Some(&SigSet::from_iter([SIGHUP, SIGTERM, SIGUSR1, SIGUSR2])),
+        None,
+    )?;
     let hup = Arc::new(AtomicBool::new(false));
     signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&hup))?;
+    let term = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(signal_hook::consts::SIGTERM, Arc::clone(&term))?;
+    let suspend = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(signal_hook::consts::SIGUSR1, Arc::clone(&suspend))?;
+    let resume = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(signal_hook::consts::SIGUSR2, Arc::clone(&resume))?;
+
+    let shutdown_grace_period = Duration::from_secs(config.shutdown_grace_period);
+    let mut shutdown_t: Option<SystemTime> = None;
 
     loop {
+        if suspend.swap(false, Ordering::Relaxed) && !suspended.swap(true, Ordering::Relaxed) {
+            log::info!("Got SIGUSR1, suspending output.");
+        }
+        if resume.swap(false, Ordering::Relaxed) && suspended.swap(false, Ordering::Relaxed) {
+            let dropped = suspended_events.swap(0, Ordering::Relaxed);
+            log::info!("Got SIGUSR2, resuming output, {dropped} events have not been written.");
+            coalesce.emit_gap(dropped);
+        }
+        // On SIGTERM, keep reading input until auditd closes it or
+        // the grace period has passed. In-flight events are flushed
+        // below.
+        if term.load(Ordering::Relaxed) && shutdown_t.is_none() {
+            log::info!("Got SIGTERM, shutting down.");
+            shutdown_t = Some(SystemTime::now());
+        }
+        let mut timeout = match shutdown_t {
+            Some(t) => match shutdown_grace_period.saturating_sub(t.elapsed()?) {
+                d if d.is_zero() => {
+                    log::warn!("Shutdown grace period has passed, not waiting for more input.");
+                    break;
+                }
+                d => Some(d),
+            },
+            None => None,
+        };
+
+        if hup.load(Ordering::Relaxed) && config.daemon.chroot {
+            log::warn!("Got SIGHUP, ignoring: Can't restart within chroot.");
+            hup.store(false, Ordering::Relaxed);
+        }
         if hup.load(Ordering::Relaxed) {
             let buf = input.buffer();
             let lines = buf.split_inclusive(|c| *c == b'\n');
@@ -589,11 +1809,18 @@ fn run_app() -> Result<(), anyhow::Error> {
             if let Some(p) = statefile_path.as_ref() {
                 write_state(p, coalesce.state());
             }
+            if let Some(pos) = &input_position {
+                write_checkpoint(&checkpoint_path, pos, 0);
+            }
             coalesce.flush();
 
             log::info!("Restarting...");
             use std::ffi::CString;
             let argv: Vec<CString> = env::args().map(|a| CString::new(a).unwrap()).collect();
+            if let Some(fd) = control_fd {
+                set_cloexec(fd, false)?;
+                env::set_var(CONTROL_FD_ENV, fd.to_string());
+            }
             let env: Vec<CString> = env::vars()
                 .map(|(k, v)| CString::new(format!("{k}={v}")).unwrap())
                 .collect();
@@ -609,35 +1836,23 @@ fn run_app() -> Result<(), anyhow::Error> {
             execve(&argv[0], &argv, &env)?;
         }
 
-        line.clear();
-        if input
-            .read_until(b'\n', &mut line)
-            .context("read from stdin")?
-            == 0
-        {
-            break;
-        }
-
-        if let Some(ref mut l) = inputlog {
-            l.output.write_all(&line)?;
-            l.output.flush()?;
-        }
-
-        stats.lines += 1;
-        match coalesce.process_line(&line) {
-            Ok(()) => (),
-            Err(e) => {
-                stats.errors += 1;
-                if let Some(ref mut l) = error_logger {
-                    l.write_all(&line)
-                        .and_then(|_| l.flush())
-                        .context("write log")?;
-                }
-                let line = String::from_utf8_lossy(&line).replace('\n', "");
-                log::error!("Error {e} processing msg: {line}");
-                continue;
+        if let Some(heartbeat_period_t) = heartbeat_period {
+            if heartbeat_last_t.elapsed()? >= heartbeat_period_t {
+                let counters = event_counters(
+                    start_t.elapsed()?,
+                    &overall_stats,
+                    &stats,
+                    coalesce.dropped_records(),
+                    forwarder.as_ref(),
+                    &alerts_suppressed,
+                    &alerts_undelivered,
+                );
+                coalesce.emit_heartbeat(&counters);
+                heartbeat_last_t = SystemTime::now();
             }
-        };
+            let t = heartbeat_period_t.saturating_sub(heartbeat_last_t.elapsed()?);
+            timeout = Some(timeout.map_or(t, |s| s.min(t)));
+        }
 
         // Output status information about Laurel every "statusreport_period_t" time (configurable)
         if let Some(statusreport_period_t) = statusreport_period {
@@ -648,6 +1863,45 @@ fn run_app() -> Result<(), anyhow::Error> {
                 log::info!(
                     "Parsing stats (until now): processed {} lines {} events with {} errors in total",
                     &stats.lines, &stats.events, &stats.errors );
+                if coalesce.dropped_records() > 0 {
+                    log::info!(
+                        "Filter stats (until now): {} records dropped by type",
+                        coalesce.dropped_records()
+                    );
+                }
+                for (name, n) in coalesce.preset_filtered() {
+                    log::info!("Filter stats (until now): {n} events filtered by preset {name}");
+                }
+                if let Some(f) = forwarder.as_ref().filter(|f| f.dropped() > 0) {
+                    log::info!(
+                        "Forward stats (until now): {} records could not be forwarded",
+                        f.dropped()
+                    );
+                }
+                if otlp::dropped() > 0 {
+                    log::info!(
+                        "HTTP stats (until now): {} events could not be sent",
+                        otlp::dropped()
+                    );
+                }
+                if alerts_suppressed.load(Ordering::Relaxed) > 0 {
+                    log::info!(
+                        "Alert stats (until now): {} alerts suppressed",
+                        alerts_suppressed.load(Ordering::Relaxed)
+                    );
+                }
+                if alerts_undelivered.load(Ordering::Relaxed) > 0 {
+                    log::info!(
+                        "Alert stats (until now): {} alerts could not be written",
+                        alerts_undelivered.load(Ordering::Relaxed)
+                    );
+                }
+                if coalesce.analyzer_hits() > 0 {
+                    log::info!(
+                        "Analyzer stats (until now): {} labels and tags attached",
+                        coalesce.analyzer_hits()
+                    );
+                }
                 log::info!(
                     "Running with EUID {} using config {}",
                     Uid::effective().as_raw(),
@@ -678,19 +1932,165 @@ fn run_app() -> Result<(), anyhow::Error> {
                 stats = Stats::default();
                 statusreport_last_t = SystemTime::now();
             }
+            if statusreport_period_t.as_secs() > 0 {
+                let t = statusreport_period_t.saturating_sub(statusreport_last_t.elapsed()?);
+                timeout = Some(timeout.map_or(t, |s| s.min(t)));
+            }
         }
 
         if let (Some(statefile), Some(p)) = (&config.state.file, &write_state_period) {
             if write_state_last_t.elapsed()? >= *p {
                 write_state(statefile, coalesce.state());
+                if let Some(pos) = &input_position {
+                    let partial = if line.ends_with(b"\n") { 0 } else { line.len() };
+                    write_checkpoint(&checkpoint_path, pos, input.buffer().len() + partial);
+                }
                 write_state_last_t = SystemTime::now();
             }
+            let t = p.saturating_sub(write_state_last_t.elapsed()?);
+            timeout = Some(timeout.map_or(t, |s| s.min(t)));
+        }
+
+        // Events in the reorder window are emitted once the window
+        // has passed, even if no more input arrives.
+        coalesce.expire_idle();
+        if let Some(t) = coalesce.idle_timeout() {
+            timeout = Some(timeout.map_or(t, |s| s.min(t)));
+        }
+
+        // Control socket clients are served while waiting for input
+        // below. While input is pending, they are served periodically
+        // so that queries are answered under load, too.
+        if let Some(server) = &mut control_server {
+            if let Some(t) = server.timeout() {
+                timeout = Some(timeout.map_or(t, |s| s.min(t)));
+            }
+            if !input.buffer().is_empty() && control_served_t.elapsed() >= CONTROL_SERVE_PERIOD {
+                server.serve();
+                control_served_t = Instant::now();
+            }
+        }
+
+        // Don't block on reading input beyond the next periodic task
+        // or the end of the shutdown grace period. Unlike read(2),
+        // poll(2) is interrupted by signals.
+        if input.buffer().is_empty() {
+            // safety: input_fd is owned by input which outlives fd.
+            let input_fd = input_fd.map(|fd| unsafe { BorrowedFd::borrow_raw(fd) });
+            let mut fds: Vec<PollFd> = input_fd
+                .iter()
+                .map(|fd| PollFd::new(*fd, PollFlags::POLLIN))
+                .collect();
+            if let Some(server) = &control_server {
+                fds.extend(server.poll_fds());
+            }
+            // A followed file does its own waiting in read_until.
+            let timeout = match input_fd {
+                Some(_) => timeout.map_or(PollTimeout::NONE, |t| {
+                    PollTimeout::try_from(t).unwrap_or(PollTimeout::MAX)
+                }),
+                None => PollTimeout::ZERO,
+            };
+            let ready = match poll(&mut fds, timeout) {
+                Ok(_) => fds
+                    .iter()
+                    .map(|fd| fd.revents().is_some_and(|r| !r.is_empty()))
+                    .collect::<Vec<_>>(),
+                Err(Errno::EINTR) => continue,
+                Err(e) => return Err(e).context("poll"),
+            };
+            let input_ready = input_fd.is_none() || ready[0];
+            // Clients that have timed out are disconnected, too.
+            if let Some(server) = &mut control_server {
+                server.serve();
+                control_served_t = Instant::now();
+            }
+            if !input_ready {
+                continue;
+            }
+        }
+
+        // Incomplete lines from a followed file are kept until the
+        // rest has been written.
+        if line.ends_with(b"\n") {
+            line.clear();
+        }
+        match input.read_until(b'\n', &mut line) {
+            Ok(0) => break,
+            Ok(_) => {}
+            // A followed file has no end, stop once all available
+            // input has been read.
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock && shutdown_t.is_some() => break,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(e) => return Err(e).context("read from stdin"),
+        }
+        if !line.ends_with(b"\n") && input_position.is_some() {
+            continue;
+        }
+
+        if let Some(ref mut l) = inputlog {
+            l.output.write_all(&line)?;
+            l.output.flush()?;
+        }
+
+        if let Some(f) = &forwarder {
+            f.forward(&line);
         }
+
+        stats.lines += 1;
+        match coalesce.process_line(&line) {
+            Ok(()) => {
+                let skew = coalesce.clock_skew();
+                if skew.is_some() != clock_skew.is_some() {
+                    match skew {
+                        Some(skew) => log::warn!(
+                            "Audit event timestamps differ from system clock by {skew} ms"
+                        ),
+                        None => log::info!("Audit event timestamps match system clock again"),
+                    }
+                }
+                clock_skew = skew;
+            }
+            Err(e) => {
+                stats.errors += 1;
+                if let Some(ref mut l) = error_logger {
+                    l.write_all(&line)
+                        .and_then(|_| l.flush())
+                        .context("write log")?;
+                }
+                if config.debug.error_events {
+                    coalesce.emit_error(&e, &line);
+                }
+                let line = String::from_utf8_lossy(&line).replace('\n', "");
+                log::error!("Error {e} processing msg: {line}");
+                continue;
+            }
+        };
     }
 
+    // Emit incomplete events before writing the state so that they
+    // are not emitted again after a restart.
+    let counters = event_counters(
+        start_t.elapsed()?,
+        &overall_stats,
+        &stats,
+        coalesce.dropped_records(),
+        forwarder.as_ref(),
+        &alerts_suppressed,
+        &alerts_undelivered,
+    );
+    coalesce.shutdown(&counters);
     if let Some(p) = statefile_path.as_ref() {
         write_state(p, coalesce.state());
     }
+    if let Some(pos) = &input_position {
+        let partial = if line.ends_with(b"\n") { 0 } else { line.len() };
+        write_checkpoint(&checkpoint_path, pos, input.buffer().len() + partial);
+    }
+    // Close (and sync) all outputs
+    drop(coalesce);
+    // Write out records that are still queued for forwarding
+    drop(forwarder);
 
     // If periodical reports were enabled, stats only contains temporary statistics.
     if let Some(statusreport_period_t) = statusreport_period {
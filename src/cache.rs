@@ -0,0 +1,193 @@
+//! Bounded caches for enrichment lookups
+//!
+//! Lookups that fail can be cached as well, by using `Option<T>` as
+//! value type. This keeps expensive lookups for unknown keys (e.g.
+//! files that have vanished) from being repeated for every event.
+
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use indexmap::IndexMap;
+
+use crate::clock::{Clock, SystemClock};
+
+/// LRU cache with a fixed number of entries and optional expiry
+#[derive(Debug)]
+pub struct LruCache<K, V> {
+    max_entries: usize,
+    ttl: Option<Duration>,
+    clock: Arc<dyn Clock>,
+    entries: IndexMap<K, (Instant, V)>,
+}
+
+impl<K: Hash + Eq, V> LruCache<K, V> {
+    pub fn new(max_entries: usize) -> Self {
+        LruCache {
+            max_entries,
+            ttl: None,
+            clock: Arc::new(SystemClock),
+            entries: IndexMap::new(),
+        }
+    }
+
+    /// Entries are looked up again after `ttl` has passed since they
+    /// were inserted.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Use `clock` instead of the system clock for expiry
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Like [`LruCache::get`], without copying the value
+    pub fn get_ref(&mut self, key: &K) -> Option<&V> {
+        let idx = self.entries.get_index_of(key)?;
+        if self.is_expired(&self.entries[idx].0) {
+            self.entries.shift_remove_index(idx);
+            return None;
+        }
+        let last = self.entries.len() - 1;
+        self.entries.move_index(idx, last);
+        self.entries.get_index(last).map(|(_, (_, v))| v)
+    }
+
+    /// Returns the value for `key` without marking it as recently
+    /// used
+    pub fn peek(&self, key: &K) -> Option<&V> {
+        self.entries
+            .get(key)
+            .filter(|(t, _)| !self.is_expired(t))
+            .map(|(_, v)| v)
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.max_entries == 0 {
+            return;
+        }
+        if self.entries.shift_remove(&key).is_none() && self.entries.len() >= self.max_entries {
+            self.purge_expired();
+            if self.entries.len() >= self.max_entries {
+                self.entries.shift_remove_index(0);
+            }
+        }
+        self.entries.insert(key, (self.clock.now(), value));
+    }
+
+    fn is_expired(&self, t: &Instant) -> bool {
+        self.ttl
+            .is_some_and(|ttl| self.clock.now().saturating_duration_since(*t) >= ttl)
+    }
+
+    /// Removes all entries whose time to live has passed. This also
+    /// happens when an entry would have to be evicted to make room
+    /// for a new one.
+    pub fn purge_expired(&mut self) {
+        if let Some(ttl) = self.ttl {
+            let now = self.clock.now();
+            self.entries
+                .retain(|_, (t, _)| now.saturating_duration_since(*t) < ttl);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<K: Hash + Eq, V: Clone> LruCache<K, V> {
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        self.get_ref(key).cloned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    #[test]
+    fn lru() {
+        let mut cache = LruCache::new(3);
+        assert_eq!(cache.len(), 0);
+        cache.insert((0, 0, 0), [0; 32]);
+        assert_eq!(cache.len(), 1);
+        cache.insert((1, 1, 1), [1; 32]);
+        assert_eq!(cache.len(), 2);
+        cache.insert((2, 2, 2), [2; 32]);
+        assert_eq!(cache.len(), 3);
+        // Overflow the table,
+        cache.insert((3, 3, 3), [3; 32]);
+        assert_eq!(cache.len(), 3);
+        assert_eq!(
+            cache.get(&(0, 0, 0)),
+            None,
+            "(0,0,0) should have been evicted"
+        );
+        assert_ne!(
+            cache.get(&(1, 1, 1)),
+            None,
+            "(1,1,1) should NOT have been evicted"
+        );
+        // insert another, evict another, testing LRU mechanism.
+        cache.insert((4, 4, 4), [4; 32]);
+        assert_ne!(
+            cache.get(&(1, 1, 1)),
+            None,
+            "(1,1,1) should still NOT have been evicted"
+        );
+        assert_ne!(cache.get(&(4, 4, 4)), None);
+        assert_ne!(cache.get(&(3, 3, 3)), None);
+        assert_eq!(cache.get(&(2, 2, 2)), None);
+    }
+
+    #[test]
+    fn ttl() {
+        let clock = Arc::new(ManualClock::default());
+        let mut cache = LruCache::new(10)
+            .with_ttl(Duration::from_millis(50))
+            .with_clock(clock.clone());
+        cache.insert("found", Some(1));
+        // negative entry
+        cache.insert("missing", None::<i32>);
+        assert_eq!(cache.get(&"found"), Some(Some(1)));
+        assert_eq!(cache.get(&"missing"), Some(None));
+        assert_eq!(cache.get(&"unknown"), None);
+        clock.advance(49);
+        assert_eq!(cache.peek(&"found"), Some(&Some(1)));
+        clock.advance(1);
+        assert_eq!(cache.peek(&"found"), None);
+        assert_eq!(cache.get(&"found"), None);
+        assert_eq!(cache.get(&"missing"), None);
+        assert!(cache.is_empty(), "expired entries should have been removed");
+    }
+
+    #[test]
+    fn purge_expired() {
+        let clock = Arc::new(ManualClock::default());
+        let mut cache = LruCache::new(2)
+            .with_ttl(Duration::from_millis(50))
+            .with_clock(clock.clone());
+        cache.insert(1, 1);
+        clock.advance(60);
+        cache.insert(2, 2);
+        cache.purge_expired();
+        assert_eq!(cache.len(), 1);
+        cache.insert(1, 1);
+        clock.advance(60);
+        cache.insert(3, 3);
+        // Expired entries make room before live ones are evicted.
+        cache.insert(4, 4);
+        assert_eq!(cache.get(&3), Some(3));
+        assert_eq!(cache.get(&4), Some(4));
+        assert_eq!(cache.len(), 2);
+    }
+}
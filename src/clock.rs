@@ -0,0 +1,76 @@
+//! Clocks for synthetic events, for comparisons with audit event
+//! timestamps, and for enrichment deadlines
+//!
+//! [`ManualClock`] makes such comparisons deterministic in tests.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// Returns the current time in milliseconds since the epoch
+    fn now_ms(&self) -> u64;
+    /// Returns the current monotonic time, used for deadlines
+    fn now(&self) -> Instant;
+}
+
+/// The system's real-time and monotonic clocks
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_ms(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default()
+    }
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Clock that only moves when it is set or advanced
+///
+/// Setting the time only affects the real-time clock, advancing it
+/// affects both.
+#[derive(Debug)]
+pub struct ManualClock {
+    ms: AtomicU64,
+    start: Instant,
+    elapsed: AtomicU64,
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock::new(0)
+    }
+}
+
+impl ManualClock {
+    pub fn new(ms: u64) -> Self {
+        ManualClock {
+            ms: AtomicU64::new(ms),
+            start: Instant::now(),
+            elapsed: AtomicU64::new(0),
+        }
+    }
+
+    pub fn set(&self, ms: u64) {
+        self.ms.store(ms, Ordering::Relaxed);
+    }
+
+    pub fn advance(&self, ms: u64) {
+        self.ms.fetch_add(ms, Ordering::Relaxed);
+        self.elapsed.fetch_add(ms, Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_ms(&self) -> u64 {
+        self.ms.load(Ordering::Relaxed)
+    }
+    fn now(&self) -> Instant {
+        self.start + Duration::from_millis(self.elapsed.load(Ordering::Relaxed))
+    }
+}
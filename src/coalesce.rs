@@ -1,30 +1,60 @@
-use std::collections::{BTreeMap, HashSet};
+#[cfg(feature = "sigma")]
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt::{self, Display};
 use std::io::Read;
 use std::os::unix::ffi::OsStrExt;
 #[cfg(all(feature = "procfs", target_os = "linux"))]
 use std::os::unix::fs::MetadataExt;
 use std::str::FromStr;
-
 #[cfg(all(feature = "procfs", target_os = "linux"))]
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
 use faster_hex::hex_string;
 
+use indexmap::IndexMap;
+
 use linux_audit_parser::*;
 
 use serde::{Deserialize, Serialize};
 use serde_with::{DeserializeFromStr, SerializeDisplay};
 
-use crate::constants::{ARCH_NAMES, SYSCALL_NAMES, URING_OPS};
+use crate::analyze::{AttackRule, AuditConfig, AuditConfigState, ExecFromTmp, SuspiciousShell};
+use crate::cache::LruCache;
+use crate::clock::{Clock, SystemClock};
+use crate::config::{
+    CommandGroups, HostMetadata, PasswdField, PropagatePolicy, Propagation, SampleRule,
+};
+#[cfg(all(feature = "procfs", target_os = "linux"))]
+use crate::constants::KERNEL_TAINT_FLAGS;
+use crate::constants::{
+    demux_syscall, signal_name, ARCH_NAMES, KEY_SEPARATOR, NTP_STATUS_FLAGS, SYSCALL_NAMES,
+    URING_OPS,
+};
+use crate::entity::{Entity, EntityTracker};
 #[cfg(all(feature = "procfs", target_os = "linux"))]
 use crate::hash::Sha256Writer;
 use crate::label_matcher::LabelMatcher;
-use crate::proc::{self, ContainerInfo, ProcTable, Process, ProcessKey};
+#[cfg(all(feature = "procfs", target_os = "linux"))]
+use crate::pool::{Cancel, Task, WorkerPool};
+use crate::presets::FilterPreset;
+use crate::proc::{self, ContainerInfo, ProcTable, Process, ProcessKey, SharedBytes};
 #[cfg(all(feature = "procfs", target_os = "linux"))]
 use crate::procfs;
+#[cfg(all(feature = "procfs", target_os = "linux"))]
+use crate::procwatch::{CgroupCache, ProcEvent};
+use crate::rules::{Requirement, Rule};
+#[cfg(feature = "sigma")]
+use crate::sigma::{self, SigmaRule};
 #[cfg(target_os = "linux")]
 use crate::sockaddr::{SocketAddr, SocketAddrMatcher};
+use crate::summary::{self, Summary};
 use crate::types::*;
-use crate::userdb::UserDB;
+use crate::userdb::{self, UserDB};
+#[cfg(target_os = "linux")]
+use crate::utmp::{SessionTable, UTMP_PATH};
 
 use tinyvec::TinyVec;
 
@@ -61,6 +91,9 @@ impl FromStr for EventKey {
     }
 }
 
+/// Key/value pairs of a user-defined section
+pub type SectionFields = Vec<(Vec<u8>, Vec<u8>)>;
+
 #[derive(Clone)]
 pub struct Settings {
     /// Generate ARGV and ARGV_STR from EXECVE
@@ -69,25 +102,94 @@ pub struct Settings {
 
     pub execve_env_exact: HashSet<Vec<u8>>,
     pub execve_env_prefix: Vec<Vec<u8>>,
+    /// Upper-case substrings of environment variable names whose
+    /// values are redacted
+    pub execve_env_redact: Vec<Vec<u8>>,
 
     pub execve_argv_limit_bytes: Option<usize>,
+    /// Generate ARGV0_BASE, CMDLINE, ARGV_FLAGS from EXECVE
+    pub execve_argv0_base: bool,
+    pub execve_cmdline: bool,
+    pub execve_argv_flags: bool,
+
+    /// Only keep PATH records with these nametypes
+    pub path_nametypes: Option<HashSet<Vec<u8>>>,
+    /// Write SYSCALL.key as list of keys
+    pub key_list: bool,
+
     pub enrich_container: bool,
     pub enrich_container_info: bool,
+    pub enrich_container_events: bool,
     pub enrich_systemd: bool,
+    pub enrich_systemd_unit: bool,
+    /// Add process IDs from the innermost pid namespace
+    pub enrich_ns_pid: bool,
+    pub enrich_file_action: bool,
+    pub enrich_written_by: bool,
+    pub enrich_written_by_cache_entries: usize,
+    pub enrich_parent_stubs: bool,
+    /// Add the process that ptrace, process_vm_writev etc. are
+    /// directed at
+    pub enrich_target_process: bool,
+    /// Add paths as seen from laurel's mount namespace to PATH
+    /// records
+    pub enrich_host_path: bool,
+    /// Add file system type, source, and mount options to PATH
+    /// records
+    pub enrich_mount: bool,
+    /// Add module file and kernel taint status to module loading and
+    /// kexec syscalls
+    pub enrich_kernel_module: bool,
+    pub enrich_kernel_module_hash: bool,
+    /// Where to add `LAUREL_HOST` records
+    pub enrich_host_metadata: HostMetadata,
+    /// Add the utmp login session for the terminal
+    pub enrich_login_session: bool,
     pub enrich_pid: bool,
+    /// Fetch ancestors of processes that have not been seen in
+    /// audit events from /proc
+    pub enrich_ancestors: bool,
+    /// Add `ENTITY_ID` to process information, see
+    /// `ProcessKey::entity_id`
+    pub enrich_entity_id: bool,
+    /// Add a hash over the process and its ancestors as
+    /// `SYSCALL.LINEAGE`
+    pub enrich_lineage: bool,
     pub enrich_script: bool,
     pub enrich_uid_groups: bool,
+    /// Only add `UID_GROUPS` if the process runs with privileges
+    /// that differ from the uid or as root
+    pub enrich_uid_groups_privileged: bool,
+    /// Fields from the user database that are added for the audit
+    /// uid in SYSCALL and authentication records
+    pub enrich_auid_passwd: Vec<PasswdField>,
     pub enrich_exe_hash: bool,
     pub enrich_exe_hash_size_limit: u64,
+    pub enrich_kernel_module_hash_size_limit: u64,
     pub enrich_exe_hash_cache_entries: usize,
+    /// Number of worker threads that compute exe hashes; if 0, they
+    /// are computed while the SYSCALL record is processed.
+    pub enrich_workers: usize,
     pub enrich_prefix: Option<String>,
+    /// Time in milliseconds after which slow enrichments (/proc
+    /// lookups, hashing) are skipped for an event
+    pub enrich_deadline: Option<u64>,
 
     pub proc_label_keys: HashSet<Vec<u8>>,
     pub proc_propagate_labels: HashSet<Vec<u8>>,
+    pub proc_propagate_policy: HashMap<Vec<u8>, PropagatePolicy>,
 
     pub translate_universal: bool,
     pub translate_userdb: bool,
+    /// Seconds after which cached users and groups are looked up
+    /// again
+    pub translate_userdb_refresh: u64,
     pub drop_translated: bool,
+    pub translate_skip_universal: HashSet<MessageType>,
+    pub translate_skip_userdb: HashSet<MessageType>,
+    /// Decode SELinux contexts and extended attribute syscall
+    /// arguments
+    pub translate_selinux: bool,
 
     pub label_exe: Option<LabelMatcher>,
     pub unlabel_exe: Option<LabelMatcher>,
@@ -97,13 +199,73 @@ pub struct Settings {
     pub label_argv_count: usize,
     pub label_script: Option<LabelMatcher>,
     pub unlabel_script: Option<LabelMatcher>,
+    /// Labels applied based on the systemd unit of new processes
+    pub label_unit: Option<LabelMatcher>,
+    /// Labels applied based on the cgroup path of new processes
+    pub label_cgroup: Option<LabelMatcher>,
 
     pub filter_keys: HashSet<Vec<u8>>,
     pub filter_labels: HashSet<Vec<u8>>,
     pub filter_null_keys: bool,
+    /// Syscalls whose events are filtered
+    pub filter_syscalls: HashSet<String>,
+    /// If non-empty, syscalls whose events are not filtered
+    pub filter_allow_syscalls: HashSet<String>,
     pub filter_sockaddr: Vec<SocketAddrMatcher>,
     pub filter_raw_lines: regex::bytes::RegexSet,
+    /// Built-in presets, matched against raw lines
+    pub filter_presets: Vec<(FilterPreset, regex::bytes::RegexSet)>,
     pub filter_first_per_process: bool,
+    pub filter_sample: Vec<SampleRule>,
+    /// Record types that are dropped before parsing
+    pub drop_record_types: HashSet<MessageType>,
+
+    pub analyze_suspicious_shell: Option<SuspiciousShell>,
+    pub analyze_exec_from_tmp: Option<ExecFromTmp>,
+    pub analyze_audit_config: Option<AuditConfig>,
+    /// Rules for tagging events with ATT&CK techniques
+    pub analyze_attack: Option<Vec<AttackRule>>,
+    /// Sigma rules, see [`sigma::Sigma::load`]
+    #[cfg(feature = "sigma")]
+    pub analyze_sigma: Vec<SigmaRule>,
+
+    pub command_groups: Option<CommandGroups>,
+
+    /// Name of the local node; events from other nodes are
+    /// associated with separate process tables.
+    pub local_node: Option<Vec<u8>>,
+
+    /// Difference between event timestamps and the system clock, in
+    /// milliseconds, above which clock skew is reported
+    pub clock_skew_threshold: Option<u64>,
+
+    /// Time in milliseconds that events are kept after their EOE
+    /// marker, so that records arriving out of order are still
+    /// merged
+    pub reorder_window: u64,
+
+    /// Emit events strictly in order of their IDs. Completed events
+    /// are held back until no earlier event can be emitted anymore.
+    pub ordered_output: bool,
+
+    /// Track processes and file paths for entity records
+    pub entity_processes: bool,
+    pub entity_files: bool,
+    /// Time in milliseconds after which entity records are written
+    /// for processes and files that have not been seen since
+    pub entity_idle_timeout: u64,
+
+    /// Interval in milliseconds at which `LAUREL_SUMMARY` events are
+    /// written
+    pub summary_period: Option<u64>,
+    /// Number of entries per list in `LAUREL_SUMMARY` events
+    pub summary_max_entries: usize,
+    /// Share of events or bytes in percent above which a warning is
+    /// logged for a key when a summary is written
+    pub summary_key_share_warning: Option<u64>,
+
+    /// User-defined sections that are added to every event
+    pub sections: Vec<(String, SectionFields)>,
 }
 
 impl Default for Settings {
@@ -113,22 +275,58 @@ impl Default for Settings {
             execve_argv_string: false,
             execve_env_exact: HashSet::new(),
             execve_env_prefix: vec![],
+            execve_env_redact: ENV_REDACT_DEFAULT
+                .iter()
+                .map(|s| s.as_bytes().to_vec())
+                .collect(),
             execve_argv_limit_bytes: None,
+            execve_argv0_base: false,
+            execve_cmdline: false,
+            execve_argv_flags: false,
+            path_nametypes: None,
+            key_list: false,
             enrich_container: false,
             enrich_container_info: false,
+            enrich_container_events: false,
             enrich_systemd: false,
+            enrich_systemd_unit: false,
+            enrich_ns_pid: false,
+            enrich_file_action: false,
+            enrich_written_by: false,
+            enrich_written_by_cache_entries: 1024,
+            enrich_parent_stubs: false,
+            enrich_target_process: false,
+            enrich_host_path: false,
+            enrich_mount: false,
+            enrich_kernel_module: false,
+            enrich_kernel_module_hash: false,
+            enrich_host_metadata: HostMetadata::Off,
+            enrich_login_session: false,
             enrich_pid: true,
+            enrich_ancestors: false,
+            enrich_entity_id: false,
+            enrich_lineage: false,
             enrich_script: true,
             enrich_uid_groups: true,
+            enrich_uid_groups_privileged: false,
+            enrich_auid_passwd: vec![],
             enrich_exe_hash: false,
             enrich_exe_hash_size_limit: 10_000_000,
+            enrich_kernel_module_hash_size_limit: 100_000_000,
             enrich_exe_hash_cache_entries: 1024,
+            enrich_workers: 0,
             enrich_prefix: None,
+            enrich_deadline: None,
             proc_label_keys: HashSet::new(),
             proc_propagate_labels: HashSet::new(),
+            proc_propagate_policy: HashMap::new(),
             translate_universal: false,
             translate_userdb: false,
+            translate_userdb_refresh: userdb::DEFAULT_REFRESH,
             drop_translated: false,
+            translate_skip_universal: HashSet::new(),
+            translate_skip_userdb: HashSet::new(),
+            translate_selinux: false,
             label_exe: None,
             unlabel_exe: None,
             label_argv: None,
@@ -137,26 +335,85 @@ impl Default for Settings {
             label_argv_count: 32,
             label_script: None,
             unlabel_script: None,
+            label_unit: None,
+            label_cgroup: None,
             filter_keys: HashSet::new(),
             filter_labels: HashSet::new(),
             filter_null_keys: false,
+            filter_syscalls: HashSet::new(),
+            filter_allow_syscalls: HashSet::new(),
             filter_sockaddr: vec![],
             filter_raw_lines: regex::bytes::RegexSet::empty(),
+            filter_presets: vec![],
             filter_first_per_process: false,
+            filter_sample: vec![],
+            drop_record_types: HashSet::new(),
+            analyze_suspicious_shell: None,
+            analyze_exec_from_tmp: None,
+            analyze_audit_config: None,
+            analyze_attack: None,
+            #[cfg(feature = "sigma")]
+            analyze_sigma: vec![],
+            command_groups: None,
+            local_node: None,
+            clock_skew_threshold: None,
+            reorder_window: 0,
+            ordered_output: false,
+            entity_processes: false,
+            entity_files: false,
+            entity_idle_timeout: 60_000,
+            summary_period: None,
+            summary_max_entries: 10,
+            summary_key_share_warning: None,
+            sections: vec![],
         }
     }
 }
 
 #[derive(Debug, Error)]
 pub enum CoalesceError {
-    #[error("{0}")]
-    Parse(ParseError),
+    #[error("{error}{}", .offset.map(|o| format!(" at offset {o}")).unwrap_or_default())]
+    Parse {
+        error: ParseError,
+        /// Position in the input line where parsing failed
+        offset: Option<usize>,
+    },
     #[error("duplicate event id {0}")]
     DuplicateEvent(EventID),
     #[error("Event id {0} for EOE marker not found")]
     SpuriousEOE(EventID),
 }
 
+impl CoalesceError {
+    fn parse(line: &[u8], error: ParseError) -> Self {
+        let offset = match &error {
+            ParseError::MalformedHeader(rest)
+            | ParseError::MalformedBody(rest)
+            | ParseError::TrailingGarbage(rest) => {
+                let line = line.strip_suffix(b"\n").unwrap_or(line);
+                let rest = rest.strip_suffix(b"\n").unwrap_or(rest);
+                line.ends_with(rest).then(|| line.len() - rest.len())
+            }
+            ParseError::HexDecodeError { .. } => None,
+        };
+        CoalesceError::Parse { error, offset }
+    }
+
+    /// Returns a short identifier for the class of error
+    pub fn code(&self) -> &'static str {
+        match self {
+            CoalesceError::Parse { error, .. } => match error {
+                ParseError::MalformedHeader(_) => "malformed-header",
+                ParseError::MalformedBody(_) => "malformed-body",
+                ParseError::TrailingGarbage(_) => "trailing-garbage",
+                ParseError::HexDecodeError { .. } => "hex-decode",
+            },
+            CoalesceError::DuplicateEvent(_) => "duplicate-event",
+            CoalesceError::SpuriousEOE(_) => "spurious-eoe",
+        }
+    }
+}
+
 #[derive(Default, Clone, Serialize, Deserialize)]
 pub struct State<'ev> {
     /// Events that are being collected/processed
@@ -165,43 +422,69 @@ pub struct State<'ev> {
     pub done: HashSet<EventKey>,
     /// Process table built from observing process-related events
     pub processes: ProcTable,
+    /// Process tables for remote nodes
+    #[serde(default, with = "node_map")]
+    pub node_processes: BTreeMap<Vec<u8>, ProcTable>,
     /// Creadential cache
     userdb: UserDB,
+    /// Audit configuration of the local host
+    #[serde(default)]
+    pub audit_config: AuditConfigState,
+}
+
+/// Node names are used as map keys, which must be strings in JSON.
+mod node_map {
+    use std::collections::BTreeMap;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::proc::ProcTable;
+
+    pub fn serialize<S: Serializer>(
+        value: &BTreeMap<Vec<u8>, ProcTable>,
+        s: S,
+    ) -> Result<S::Ok, S::Error> {
+        value
+            .iter()
+            .map(|(k, v)| (String::from_utf8_lossy(k), v))
+            .collect::<BTreeMap<_, _>>()
+            .serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        d: D,
+    ) -> Result<BTreeMap<Vec<u8>, ProcTable>, D::Error> {
+        Ok(BTreeMap::<String, ProcTable>::deserialize(d)?
+            .into_iter()
+            .map(|(k, v)| (k.into_bytes(), v))
+            .collect())
+    }
 }
 
 #[cfg(all(feature = "procfs", target_os = "linux"))]
 /// LRU cache for exe hashes, keyed by (dev, inode, mtime_nsec)
-struct ExeHashCache {
-    max_entries: usize,
-    entries: indexmap::IndexMap<(u64, u64, i64), [u8; 32]>,
-}
+type ExeHashCache = LruCache<(u64, u64, i64), [u8; 32]>;
 
 #[cfg(all(feature = "procfs", target_os = "linux"))]
-impl ExeHashCache {
-    fn new(max_entries: usize) -> Self {
-        ExeHashCache {
-            max_entries,
-            entries: indexmap::IndexMap::new(),
-        }
-    }
+/// Exe hash that is being computed by a worker, with its cache key
+type PendingExeHash = ((u64, u64, i64), Task<Option<[u8; 32]>>);
 
-    fn get(&mut self, key: &(u64, u64, i64)) -> Option<[u8; 32]> {
-        let idx = self.entries.get_index_of(key)?;
-        let (k, v) = self.entries.shift_remove_index(idx).unwrap();
-        let hash = v;
-        self.entries.insert(k, v);
-        Some(hash)
-    }
+/// LRU cache for processes that have written to files, keyed by
+/// (dev, inode)
+type WrittenByCache = LruCache<(Vec<u8>, u64), Process>;
 
-    fn insert(&mut self, key: (u64, u64, i64), hash: [u8; 32]) {
-        if self.max_entries == 0 {
-            return;
-        }
-        if self.entries.len() >= self.max_entries {
-            self.entries.shift_remove_index(0);
-        }
-        self.entries.insert(key, hash);
-    }
+/// LRU cache for processes that have recently been started via
+/// execve, keyed by pid
+type ExecHistory = LruCache<u32, Process>;
+
+/// Function that takes entity records
+type EntityFn<'a> = Box<dyn 'a + FnMut(&Entity)>;
+
+/// Exec events of commands that have been started by the same shell
+struct CommandGroup<'ev> {
+    /// Timestamp of the first event
+    first: u64,
+    events: Vec<Event<'ev>>,
 }
 
 /// Coalesce collects Audit Records from individual lines and assembles them to Events
@@ -212,9 +495,95 @@ pub struct Coalesce<'a, 'ev> {
     next_expire: Option<u64>,
     /// Output function
     emit_fn: Box<dyn 'a + FnMut(&Event<'ev>)>,
+    /// Function that takes entity records
+    entity_fn: Option<EntityFn<'a>>,
+    /// Processes and files that entity records are written for
+    entities: EntityTracker,
+    /// Event counts for the current summary period
+    summary: Summary,
     /// Cache for exe hashes
     #[cfg(all(feature = "procfs", target_os = "linux"))]
     exe_hash_cache: Option<ExeHashCache>,
+    /// Workers for exe hashing
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    workers: Option<WorkerPool>,
+    /// Exe hashes that are being computed, by event
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    pending_exe_hashes: HashMap<EventKey, PendingExeHash>,
+    /// Static information about the host for `LAUREL_HOST` records
+    host_metadata: Vec<(&'static str, Vec<u8>)>,
+    /// Login sessions for terminals
+    #[cfg(target_os = "linux")]
+    login_sessions: Option<SessionTable>,
+    /// Cache for processes that have written to files
+    written_by_cache: Option<WrittenByCache>,
+    /// Recently exec'd processes, used for parent stubs
+    exec_history: Option<ExecHistory>,
+    /// Mount tables used for PATH enrichment
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    mount_cache: Option<procfs::MountCache>,
+    /// Cgroup paths captured from process events
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    cgroup_cache: Option<CgroupCache>,
+    /// Process events used to keep the process table up to date
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    proc_events: Option<Receiver<ProcEvent>>,
+    /// Timestamp for next /proc scan if process events are used
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    next_proc_scan: Option<u64>,
+    /// Number of labels and tags attached by built-in analyzers
+    analyzer_hits: u64,
+    /// Number of records dropped based on their type
+    dropped_records: u64,
+    /// Number of events filtered by each preset
+    preset_filtered: IndexMap<&'static str, u64>,
+    /// ID of the last audit record that has been processed
+    last_id: Option<EventID>,
+    /// Newest timestamp of audit records seen so far
+    latest_timestamp: u64,
+    /// System clock time at which the last audit record has been
+    /// processed
+    last_record_t: u64,
+    /// Complete events that are kept in the reorder window, by
+    /// timestamp
+    reorder_pending: BTreeSet<(u64, EventKey)>,
+    /// Events that are held back for ordered output, by ID and node,
+    /// with a counter that keeps events with the same ID and node in
+    /// order
+    ordered: BTreeMap<(EventID, Option<Vec<u8>>, u64), Event<'ev>>,
+    ordered_count: u64,
+    /// Difference between the system clock and the timestamp of the
+    /// last audit record in milliseconds, if it is above the
+    /// threshold
+    clock_skew: Option<i64>,
+    /// Pending command groups, keyed by shell process
+    command_groups: BTreeMap<ProcessKey, CommandGroup<'ev>>,
+    /// Containers that have been reported as started, with the ID
+    /// of the event in which they were first seen
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    containers: BTreeMap<Vec<u8>, EventID>,
+    /// Point in time after which slow enrichments are skipped for
+    /// the current record or event
+    enrich_deadline: Option<Instant>,
+    /// Enrichments that have been skipped for the current record or
+    /// event
+    enrich_skipped: Vec<&'static str>,
+    /// Ratio of the sampling rule that matched the current SYSCALL
+    /// record
+    sample_ratio: Option<u32>,
+    /// Architecture of the current SYSCALL record
+    arch: Option<&'static str>,
+    /// Node name for entity IDs in the current event
+    entity_node: Vec<u8>,
+    /// Local host name, used for entity IDs if events have no node
+    /// name
+    hostname: Option<Vec<u8>>,
+    /// Translated user and group names for the current event
+    id_names: IdNames,
+    /// Set if the current event comes from a node other than
+    /// `local_node`; the local user database does not apply.
+    remote_node: bool,
+    clock: Arc<dyn Clock>,
 
     pub settings: Settings,
 }
@@ -222,6 +591,38 @@ pub struct Coalesce<'a, 'ev> {
 const EXPIRE_PERIOD: u64 = 1_000;
 const EXPIRE_INFLIGHT_TIMEOUT: u64 = 5_000;
 const EXPIRE_DONE_TIMEOUT: u64 = 120_000;
+const EXEC_HISTORY_ENTRIES: usize = 4096;
+const COMMAND_GROUP_MAX_EVENTS: usize = 256;
+const NODE_PROCESSES_MAX: usize = 16384;
+/// Added to the `ppid` entry if the parent process could not be
+/// determined because its pid has been reused
+const PID_REUSE_DETECTED: &str = "pid_reuse_detected";
+/// User space records about authentication and sessions
+const AUTH_TYPES: [MessageType; 12] = [
+    MessageType::USER_AUTH,
+    MessageType::USER_ACCT,
+    MessageType::USER_LOGIN,
+    MessageType::USER_LOGOUT,
+    MessageType::USER_START,
+    MessageType::USER_END,
+    MessageType::USER_CHAUTHTOK,
+    MessageType::USER_ERR,
+    MessageType::USER_CMD,
+    MessageType::CRED_ACQ,
+    MessageType::CRED_DISP,
+    MessageType::CRED_REFR,
+];
+/// Number of queued jobs per worker thread
+#[cfg(all(feature = "procfs", target_os = "linux"))]
+const WORKER_QUEUE_PER_THREAD: usize = 64;
+/// Maximum time to wait for an exe hash computed by a worker if no
+/// enrichment deadline has been configured
+#[cfg(all(feature = "procfs", target_os = "linux"))]
+const EXE_HASH_WAIT_MAX: Duration = Duration::from_secs(1);
+/// Interval for /proc scans if process events are used, to catch up
+/// with events that have been lost
+#[cfg(all(feature = "procfs", target_os = "linux"))]
+const PROC_SCAN_PERIOD: u64 = 60_000;
 
 /// generate translation of SocketAddr enum to a format similar to
 /// what auditd log_format=ENRICHED produces
@@ -293,6 +694,57 @@ fn add_translated_socketaddr(rv: &mut Body, sa: SocketAddr) {
     rv.push(("SADDR".into(), Value::Map(m)));
 }
 
+/// Adds the components of SELinux contexts found in `subj`, `obj`,
+/// `scontext`, `tcontext` fields as `SUBJ`, `OBJ`, … maps.
+fn add_selinux_contexts(rv: &mut Body) {
+    let mut contexts = vec![];
+    for name in ["subj", "obj", "scontext", "tcontext"] {
+        let Some(Value::Str(s, _)) = rv.get(name) else {
+            continue;
+        };
+        // user:role:type[:level], the MLS level may contain colons.
+        let parts: Vec<&[u8]> = s.splitn(4, |c| *c == b':').collect();
+        if parts.len() < 3 {
+            continue;
+        }
+        let m = ["user", "role", "type", "level"]
+            .into_iter()
+            .zip(parts)
+            .map(|(k, v)| {
+                (
+                    Key::Literal(k),
+                    String::from_utf8_lossy(v).into_owned().into(),
+                )
+            })
+            .collect();
+        contexts.push((Key::NameTranslated(name.as_bytes().into()), Value::Map(m)));
+    }
+    contexts.into_iter().for_each(|c| rv.push(c));
+}
+
+/// Adds the size argument of extended attribute syscalls as `XATTR`
+/// map. The attribute name and value are not part of audit records,
+/// neither is the flags argument of the set*xattr syscalls (`a4`).
+fn add_xattr_args(rv: &mut Body, syscall: &str) {
+    let size_arg = match syscall {
+        "setxattr" | "lsetxattr" | "fsetxattr" | "getxattr" | "lgetxattr" | "fgetxattr" => 3,
+        "listxattr" | "llistxattr" | "flistxattr" => 2,
+        _ => return,
+    };
+    let Some(Value::List(args)) = rv.get("ARGV") else {
+        return;
+    };
+    let arg = |n: usize| match args.get(n) {
+        Some(Value::Number(Number::Hex(v))) => Some(*v),
+        _ => None,
+    };
+    let mut m = vec![];
+    if let Some(size) = arg(size_arg) {
+        m.push((Key::Literal("size"), (size as i64).into()));
+    }
+    rv.push((Key::Literal("XATTR"), Value::Map(m)));
+}
+
 #[derive(Default)]
 struct UserGroupIDs {
     uid: Option<u32>, // should not need this
@@ -307,29 +759,79 @@ impl UserGroupIDs {
             self.ids.push((name.into(), id));
         }
     }
-    fn get_translated<'a>(
-        &'a self,
-        userdb: &'a mut UserDB,
-    ) -> impl Iterator<Item = (&'a [u8], String)> {
+    fn get(&self, name: &[u8]) -> Option<u32> {
+        match name {
+            b"uid" => self.uid,
+            _ => self
+                .ids
+                .iter()
+                .find(|(n, _)| n.as_slice() == name)
+                .map(|(_, id)| *id),
+        }
+    }
+    fn iter(&self) -> impl Iterator<Item = (&[u8], u32)> {
         let uid = self.uid.iter().map(|id| (b"uid".as_slice(), *id));
         let ids = self.ids.iter().map(|(name, id)| (name.as_slice(), *id));
+        uid.chain(ids)
+    }
+}
 
-        uid.chain(ids).map(move |(name, id)| {
-            let translated = if id == 0xffffffff {
-                "unset".to_string()
-            } else {
-                if name.ends_with(b"uid") {
-                    userdb.get_user(id)
-                } else if name.ends_with(b"gid") {
-                    userdb.get_group(id)
-                } else {
-                    None
-                }
-                .unwrap_or(format!("unknown({id})"))
-            };
-            (name, translated)
+/// Translated user and group names
+///
+/// The cache is cleared for every event, so that IDs that occur in
+/// several fields and records (uid, euid, suid, fsuid, …) are only
+/// looked up and formatted once.
+#[derive(Default)]
+struct IdNames(HashMap<(u8, u32), Vec<u8>>);
+
+impl IdNames {
+    fn get(&mut self, userdb: &mut UserDB, name: &[u8], id: u32) -> &[u8] {
+        let kind = match name {
+            _ if id == 0xffffffff => 0,
+            n if n.ends_with(b"uid") => b'u',
+            n if n.ends_with(b"gid") => b'g',
+            _ => b'?',
+        };
+        self.0.entry((kind, id)).or_insert_with(|| {
+            match kind {
+                0 => return b"unset".to_vec(),
+                b'u' => userdb.get_user(id),
+                b'g' => userdb.get_group(id),
+                _ => None,
+            }
+            .map(String::into_bytes)
+            .unwrap_or_else(|| format!("unknown({id})").into_bytes())
         })
     }
+
+    fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+/// Returns the working directory from the CWD record of `ev`
+#[cfg(all(feature = "procfs", target_os = "linux"))]
+fn event_cwd(ev: &Event) -> Option<Vec<u8>> {
+    match ev.body.get(&MessageType::CWD) {
+        Some(EventValues::Single(r)) => match r.get("cwd") {
+            Some(Value::Str(cwd, _)) => Some(cwd.to_vec()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Returns `PATH.name`, resolved relative to `cwd` if necessary
+#[cfg(all(feature = "procfs", target_os = "linux"))]
+fn absolute_path(path: &Body, cwd: Option<&[u8]>) -> Option<Vec<u8>> {
+    let Some(Value::Str(name, _)) = path.get("name") else {
+        return None;
+    };
+    match cwd {
+        _ if name.starts_with(b"/") => Some(name.to_vec()),
+        Some(cwd) => Some([cwd.strip_suffix(b"/").unwrap_or(cwd), b"/", name].concat()),
+        None => None,
+    }
 }
 
 /// Returns a script name from path if exe's dev / inode don't match
@@ -426,6 +928,200 @@ fn path_script_name(path: &Body, pid: u32, ppid: u32, cwd: &[u8], exe: &[u8]) ->
     }
 }
 
+/// Deterministically selects one in `ratio` events, based on the
+/// event ID, so that the same events are kept when logs are processed
+/// again.
+fn is_sampled(id: &EventID, ratio: u32) -> bool {
+    if ratio <= 1 {
+        return true;
+    }
+    // splitmix64 finalizer
+    let mut x = id.timestamp.rotate_left(32) ^ id.sequence as u64;
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^= x >> 31;
+    x % ratio as u64 == 0
+}
+
+/// Parts of environment variable names that indicate that the value
+/// is a secret
+pub const ENV_REDACT_DEFAULT: &[&str] = &[
+    "PASSWORD",
+    "PASSWD",
+    "SECRET",
+    "TOKEN",
+    "KEY",
+    "CREDENTIAL",
+    "AUTH",
+    "PRIVATE",
+    "COOKIE",
+    "SESSION",
+];
+
+/// Returns true if the environment variable name contains one of the
+/// (upper-case) `patterns`, ignoring case
+#[cfg(all(feature = "procfs", target_os = "linux"))]
+fn is_secret(name: &[u8], patterns: &[Vec<u8>]) -> bool {
+    let name = name.to_ascii_uppercase();
+    patterns
+        .iter()
+        .any(|p| !p.is_empty() && name.windows(p.len()).any(|w| w == p.as_slice()))
+}
+
+/// Determines the type of a raw audit record without parsing it,
+/// from a `[node=… ]type=…` prefix
+fn record_type(line: &[u8]) -> Option<MessageType> {
+    let line = match line.strip_prefix(b"node=") {
+        Some(rest) => &rest[rest.iter().position(|c| *c == b' ')? + 1..],
+        None => line,
+    };
+    let ty = line.strip_prefix(b"type=")?;
+    let ty = &ty[..ty.iter().position(|c| *c == b' ')?];
+    std::str::from_utf8(ty).ok()?.parse().ok()
+}
+
+const O_ACCMODE: u64 = 0o3;
+const O_TRUNC: u64 = 0o1000;
+
+/// Derive what happened to the file referenced by a `PATH` record
+/// from its `nametype`, the syscall and its `open` flags.
+/// `first_delete` is set for the first `DELETE` record of the event:
+/// For `rename` calls, this is the source, subsequent `DELETE` records
+/// refer to replaced targets.
+fn file_action(
+    syscall: &str,
+    flags: Option<u64>,
+    nametype: &[u8],
+    first_delete: bool,
+) -> Option<&'static str> {
+    let is_rename = syscall.starts_with("rename");
+    match (nametype, syscall) {
+        (b"CREATE", _) if is_rename => Some("renamed_to"),
+        (b"DELETE", _) if is_rename && first_delete => Some("renamed_from"),
+        (b"CREATE", _) => Some("created"),
+        (b"DELETE", _) => Some("deleted"),
+        (b"NORMAL", "creat" | "truncate") => Some("truncated"),
+        (b"NORMAL", _) => match flags {
+            Some(flags) if flags & O_TRUNC != 0 => Some("truncated"),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Append argument to a command line, using single quotes for
+/// anything that a POSIX shell would split or interpret.
+fn push_shell_quoted(buf: &mut Vec<u8>, arg: &[u8]) {
+    let safe = !arg.is_empty()
+        && arg
+            .iter()
+            .all(|c| c.is_ascii_alphanumeric() || b"%+,-./:=@_^".contains(c));
+    if safe {
+        buf.extend(arg);
+    } else {
+        buf.push(b'\'');
+        for c in arg {
+            match c {
+                b'\'' => buf.extend(br#"'\''"#),
+                _ => buf.push(*c),
+            }
+        }
+        buf.push(b'\'');
+    }
+}
+
+/// Collect option flags from a command line: Short options (`-x`,
+/// including clusters such as `-xvf`) and long options (`--foo`,
+/// `--foo=bar`) up to a terminating `--`. Values are not included,
+/// duplicates are removed.
+fn argv_flags(argv: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    let mut flags: Vec<Vec<u8>> = Vec::new();
+    let mut add = |flag: Vec<u8>| {
+        if !flags.contains(&flag) {
+            flags.push(flag);
+        }
+    };
+    for arg in argv.iter().skip(1) {
+        if arg == b"--" {
+            break;
+        } else if let Some(long) = arg.strip_prefix(b"--") {
+            let name = long.split(|&c| c == b'=').next().unwrap_or_default();
+            add([&b"--"[..], name].concat());
+        } else if let Some(short) = arg.strip_prefix(b"-") {
+            for c in short.iter().take_while(|c| c.is_ascii_alphanumeric()) {
+                add(vec![b'-', *c]);
+            }
+        }
+    }
+    flags
+}
+
+/// Computes the SHA256 hash of the first `len` bytes of `file`,
+/// giving up once `cancel` has been set.
+#[cfg(all(feature = "procfs", target_os = "linux"))]
+fn hash_exe(file: std::fs::File, len: u64, cancel: &Cancel) -> Option<[u8; 32]> {
+    use std::io::Write;
+    let mut hasher = Sha256Writer::default();
+    // Ensure that we don't "accidentally" read more than the
+    // configured limit allows.
+    let mut limited_fd = file.take(len);
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        if cancel.is_cancelled() {
+            return None;
+        }
+        match limited_fd.read(&mut buf) {
+            Ok(0) => return Some(hasher.finalize()),
+            Ok(n) => hasher.write_all(&buf[..n]).ok()?,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) => return None,
+        }
+    }
+}
+
+/// Collects static information about the host: hostname, machine
+/// ID, boot ID, kernel release and laurel version
+fn host_metadata() -> Vec<(&'static str, Vec<u8>)> {
+    let mut md = vec![];
+    if let Ok(name) = nix::unistd::gethostname() {
+        md.push(("hostname", name.as_bytes().to_vec()));
+    }
+    if let Ok(id) = std::fs::read_to_string("/etc/machine-id") {
+        md.push(("machine_id", id.trim().as_bytes().to_vec()));
+    }
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    {
+        if let Ok(id) = procfs::get_boot_id() {
+            md.push(("boot_id", id.into_bytes()));
+        }
+        if let Ok(release) = procfs::get_kernel_release() {
+            md.push(("kernel", release.into_bytes()));
+        }
+    }
+    md.push(("version", crate::VERSION.as_bytes().to_vec()));
+    md
+}
+
+/// Adds enrichments that have been skipped to the
+/// `LAUREL_ENRICHMENT_INCOMPLETE` record of `ev`.
+fn add_enrichment_incomplete(ev: &mut Event, skipped: Vec<&'static str>) {
+    if skipped.is_empty() {
+        return;
+    }
+    let mut list = match ev.laurel.get("LAUREL_ENRICHMENT_INCOMPLETE") {
+        Some(EventValues::Single(rv)) => match rv.get("skipped") {
+            Some(Value::List(l)) => l.clone(),
+            _ => vec![],
+        },
+        _ => vec![],
+    };
+    list.extend(skipped.into_iter().map(Value::Literal));
+    let mut rv = Body::default();
+    rv.push((Key::Literal("skipped"), Value::List(list)));
+    ev.laurel
+        .insert("LAUREL_ENRICHMENT_INCOMPLETE", EventValues::Single(rv));
+}
+
 impl<'a, 'ev> Coalesce<'a, 'ev> {
     /// Creates a `Coalsesce`. `emit_fn` is the function that takes
     /// completed events.
@@ -434,8 +1130,50 @@ impl<'a, 'ev> Coalesce<'a, 'ev> {
             state: State::default(),
             next_expire: None,
             emit_fn: Box::new(emit_fn),
+            entity_fn: None,
+            entities: EntityTracker::default(),
+            summary: Summary::default(),
             #[cfg(all(feature = "procfs", target_os = "linux"))]
             exe_hash_cache: None,
+            #[cfg(all(feature = "procfs", target_os = "linux"))]
+            workers: None,
+            #[cfg(all(feature = "procfs", target_os = "linux"))]
+            pending_exe_hashes: HashMap::new(),
+            host_metadata: vec![],
+            #[cfg(target_os = "linux")]
+            login_sessions: None,
+            written_by_cache: None,
+            exec_history: None,
+            #[cfg(all(feature = "procfs", target_os = "linux"))]
+            mount_cache: None,
+            #[cfg(all(feature = "procfs", target_os = "linux"))]
+            cgroup_cache: None,
+            #[cfg(all(feature = "procfs", target_os = "linux"))]
+            proc_events: None,
+            #[cfg(all(feature = "procfs", target_os = "linux"))]
+            next_proc_scan: None,
+            analyzer_hits: 0,
+            dropped_records: 0,
+            preset_filtered: IndexMap::new(),
+            last_id: None,
+            latest_timestamp: 0,
+            last_record_t: 0,
+            reorder_pending: BTreeSet::new(),
+            ordered: BTreeMap::new(),
+            ordered_count: 0,
+            clock_skew: None,
+            command_groups: BTreeMap::new(),
+            #[cfg(all(feature = "procfs", target_os = "linux"))]
+            containers: BTreeMap::new(),
+            enrich_deadline: None,
+            enrich_skipped: vec![],
+            sample_ratio: None,
+            arch: None,
+            entity_node: vec![],
+            remote_node: false,
+            hostname: None,
+            id_names: IdNames::default(),
+            clock: Arc::new(SystemClock),
             // let max = self.settings.enrich_exe_hash_cache_entries;
             settings: Settings::default(),
         }
@@ -443,12 +1181,43 @@ impl<'a, 'ev> Coalesce<'a, 'ev> {
 
     pub fn with_settings(mut self, settings: Settings) -> Self {
         self.settings = settings;
+        self.state.processes.ns_pid = self.settings.enrich_ns_pid;
+        self.state
+            .userdb
+            .set_refresh(self.settings.translate_userdb_refresh);
         #[cfg(all(feature = "procfs", target_os = "linux"))]
         if self.settings.enrich_exe_hash_cache_entries > 0 {
             self.exe_hash_cache = Some(ExeHashCache::new(
                 self.settings.enrich_exe_hash_cache_entries,
             ));
         }
+        #[cfg(all(feature = "procfs", target_os = "linux"))]
+        if self.settings.enrich_mount || self.settings.enrich_host_path {
+            self.mount_cache = Some(procfs::MountCache::new(Arc::clone(&self.clock)));
+        }
+        #[cfg(all(feature = "procfs", target_os = "linux"))]
+        if self.settings.enrich_workers > 0 {
+            let n = self.settings.enrich_workers;
+            match WorkerPool::new(n, n * WORKER_QUEUE_PER_THREAD) {
+                Ok(pool) => self.workers = Some(pool),
+                Err(e) => log::warn!("Could not start enrichment workers: {e}"),
+            }
+        }
+        if self.settings.enrich_host_metadata != HostMetadata::Off {
+            self.host_metadata = host_metadata();
+        }
+        #[cfg(target_os = "linux")]
+        if self.settings.enrich_login_session {
+            self.login_sessions = Some(SessionTable::new(UTMP_PATH));
+        }
+        if self.settings.enrich_written_by {
+            self.written_by_cache = Some(WrittenByCache::new(
+                self.settings.enrich_written_by_cache_entries,
+            ));
+        }
+        if self.settings.enrich_parent_stubs {
+            self.exec_history = Some(ExecHistory::new(EXEC_HISTORY_ENTRIES));
+        }
         self
     }
 
@@ -467,13 +1236,53 @@ impl<'a, 'ev> Coalesce<'a, 'ev> {
     }
 
     /// Add labels from `proc` to `labels`, according to
-    /// `self.settings.proc_propagate_labels`.
-    fn propagate_labels(&self, proc: &Process, labels: &mut HashSet<Vec<u8>>) {
-        labels.extend(
-            proc.labels
-                .intersection(&self.settings.proc_propagate_labels)
-                .cloned(),
-        );
+    /// `self.settings.proc_propagate_labels` and
+    /// `self.settings.proc_propagate_policy`.
+    ///
+    /// `exe` is the executable of the process that receives the labels.
+    fn propagate_labels(
+        &self,
+        proc: &Process,
+        how: Propagation,
+        exe: Option<&[u8]>,
+        labels: &mut HashSet<Vec<u8>>,
+    ) {
+        for label in proc
+            .labels
+            .intersection(&self.settings.proc_propagate_labels)
+        {
+            if let Some(policy) = self.settings.proc_propagate_policy.get(label) {
+                if !policy.across.contains(&how) {
+                    continue;
+                }
+                if exe.is_some_and(|exe| policy.stop_exe.is_match(exe)) {
+                    continue;
+                }
+                if let (Propagation::Fork, Some(max_depth)) = (how, policy.max_depth) {
+                    if self.label_depth(proc, label, max_depth) >= max_depth {
+                        continue;
+                    }
+                }
+            }
+            labels.insert(label.clone());
+        }
+    }
+
+    /// Count the generations of ancestors of `proc` that carry
+    /// `label`, up to `limit`.
+    fn label_depth(&self, proc: &Process, label: &[u8], limit: u32) -> u32 {
+        let mut depth = 0;
+        let mut proc = proc;
+        while depth < limit {
+            match proc.parent.and_then(|pk| self.state.processes.get_key(&pk)) {
+                Some(parent) if parent.labels.contains(label) => {
+                    depth += 1;
+                    proc = parent;
+                }
+                _ => break,
+            }
+        }
+        depth
     }
 
     /// Apply exe-specific labels to all processes in the process table
@@ -511,1630 +1320,5311 @@ impl<'a, 'ev> Coalesce<'a, 'ev> {
                 let mut labels = proc.labels.clone();
 
                 if let Some(parent) = proc.parent.and_then(|pk| self.state.processes.get_key(&pk)) {
-                    self.propagate_labels(parent, &mut labels);
+                    self.propagate_labels(
+                        parent,
+                        Propagation::Fork,
+                        proc.exe.as_deref(),
+                        &mut labels,
+                    );
                 }
 
                 if let Some(exe) = &proc.exe {
                     self.label_exe(exe, &mut labels);
                 }
+                #[cfg(all(feature = "procfs", target_os = "linux"))]
+                if let (Some(label_unit), Some(unit)) =
+                    (&self.settings.label_unit, &proc.systemd_unit)
+                {
+                    labels.extend(label_unit.matches(unit).map(Vec::from));
+                }
                 self.state.processes.get_key_mut(pk).unwrap().labels = labels;
             }
         }
     }
 
+    /// Don't retrieve process information from /proc, as for remote
+    /// nodes, so that output only depends on the input.
+    pub fn offline(mut self) -> Self {
+        self.state.processes = ProcTable::remote();
+        self
+    }
+
     pub fn with_state(mut self, state: State<'ev>) -> Self {
         self.state = state;
+        self.state.userdb.upgrade();
+        self.state
+            .userdb
+            .set_refresh(self.settings.translate_userdb_refresh);
+        self.state.processes.intern_all();
+        self.state.processes.ns_pid = self.settings.enrich_ns_pid;
+        self.state
+            .node_processes
+            .values_mut()
+            .for_each(ProcTable::intern_all);
         self.label_processes();
         self
     }
 
-    pub fn state(&self) -> &State<'_> {
-        &self.state
+    /// Use cgroup paths of new processes that have been captured
+    /// outside of the audit event stream, see
+    /// [`crate::procwatch::ProcWatcher`].
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    pub fn with_cgroup_cache(mut self, cache: CgroupCache) -> Self {
+        self.cgroup_cache = Some(cache);
+        self
     }
 
-    pub fn initialize(&mut self) -> Result<(), proc::ProcError> {
-        if self.settings.translate_userdb {
-            self.state.userdb.populate();
-        }
-        self.state.processes = ProcTable::from_proc(
-            self.settings.label_exe.clone(),
-            &self.settings.proc_propagate_labels,
-        )?;
-        self.label_processes();
+    /// Use `clock` instead of the system clock for synthetic events
+    /// and comparisons with event timestamps
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
 
-        Ok(())
+    /// Retrieve process information from `source` instead of /proc
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    pub fn with_proc_source(mut self, source: Arc<dyn procfs::ProcSource>) -> Self {
+        self.state.processes.source = Some(source);
+        self
     }
 
-    /// Flush out events
-    ///
-    /// Called every EXPIRE_PERIOD ms and when Coalesce is destroyed.
-    fn expire_inflight(&mut self, now: u64) {
-        let mut events = vec![];
-        self.state.inflight.retain(|EventKey(_, id), event| {
-            let emit = id.timestamp + EXPIRE_INFLIGHT_TIMEOUT < now;
-            emit.then(|| events.push(event.clone()));
-            !emit
-        });
-        events.into_iter().for_each(|event| self.emit_event(event));
+    /// Keep the process table up to date using fork and exit events,
+    /// see [`crate::procwatch::ProcWatcher`]. /proc is then scanned
+    /// less often.
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    pub fn with_proc_events(mut self, events: Receiver<ProcEvent>) -> Self {
+        self.proc_events = Some(events);
+        self
     }
 
-    fn expire_done(&mut self, now: u64) {
-        self.state
-            .done
-            .retain(|EventKey(_, id)| id.timestamp + EXPIRE_DONE_TIMEOUT >= now);
+    /// Write entity records for processes and files (see
+    /// `entity_processes`, `entity_files` settings) using `entity_fn`
+    pub fn with_entity_fn<F: 'a + FnMut(&Entity)>(mut self, entity_fn: F) -> Self {
+        self.entity_fn = Some(Box::new(entity_fn));
+        self
     }
 
-    /// Create an enriched pid entry in rv.
-    fn add_record_procinfo(&self, rec: &mut Body, name: &[u8], proc: &Process) {
-        let mut m: Vec<(Key, Value)> = Vec::with_capacity(4);
-        match &proc.key {
-            ProcessKey::Event(id) => {
-                m.push(("EVENT_ID".into(), format!("{id}").into()));
-            }
-            ProcessKey::Observed { time, pid: _ } => {
-                let (sec, msec) = (time / 1000, time % 1000);
-                m.push(("START_TIME".into(), format!("{sec}.{msec:03}").into()));
+    /// Apply pending process events to the process table
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    fn handle_proc_events(&mut self) {
+        let events: Vec<ProcEvent> = match &self.proc_events {
+            Some(rx) => rx.try_iter().collect(),
+            None => return,
+        };
+        for event in events {
+            match event {
+                ProcEvent::Fork { pid, ppid, time } => {
+                    // The audit event for an exec in the new process
+                    // may have been seen first.
+                    if self
+                        .state
+                        .processes
+                        .get_pid(pid)
+                        .is_some_and(|p| p.ppid == ppid)
+                    {
+                        continue;
+                    }
+                    let Some(parent) = self.state.processes.get_or_retrieve(ppid).cloned() else {
+                        continue;
+                    };
+                    let mut labels = HashSet::new();
+                    self.propagate_labels(
+                        &parent,
+                        Propagation::Fork,
+                        parent.exe.as_deref(),
+                        &mut labels,
+                    );
+                    self.state.processes.insert(Process {
+                        key: ProcessKey::Observed { time, pid },
+                        parent: Some(parent.key),
+                        pid,
+                        ppid,
+                        labels,
+                        ..parent
+                    });
+                }
+                ProcEvent::Exit { pid } => self.state.processes.exit(pid),
+                ProcEvent::Exec { .. } => {}
             }
         }
-        if name != b"pid" {
-            if let Some(comm) = &proc.comm {
-                m.push(("comm".into(), Value::from(comm.as_slice())));
-            }
-            if let Some(exe) = &proc.exe {
-                m.push(("exe".into(), Value::from(exe.as_slice())));
-            }
-            if proc.ppid != 0 {
-                m.push(("ppid".into(), Value::from(proc.ppid as i64)));
+    }
+
+    /// Remove processes that are no longer running. If process events
+    /// are used, /proc is only scanned every PROC_SCAN_PERIOD.
+    #[cfg_attr(
+        not(all(feature = "procfs", target_os = "linux")),
+        allow(unused_variables)
+    )]
+    fn expire_processes(&mut self, now: u64) {
+        #[cfg(all(feature = "procfs", target_os = "linux"))]
+        if self.proc_events.is_some() {
+            self.handle_proc_events();
+            if self.next_proc_scan.is_some_and(|t| now < t) {
+                self.state.processes.expire_exited();
+                return;
             }
-        } else {
-            #[cfg(all(feature = "procfs", target_os = "linux"))]
-            {
-                if let (true, Some(container_info)) =
-                    (self.settings.enrich_container, &proc.container_info)
-                {
-                    let id = hex_string(&container_info.id);
-                    m.push((
-                        "container".into(),
-                        Value::Map(vec![("id".into(), id.into())]),
-                    ));
-                }
+            self.next_proc_scan = Some(now + PROC_SCAN_PERIOD);
+        }
+        self.state.processes.expire();
+    }
 
-                if let (true, Some(systemd_service)) =
-                    (self.settings.enrich_systemd, &proc.systemd_service)
-                {
-                    m.push((
-                        "systemd_service".into(),
-                        Value::List(
-                            systemd_service
-                                .iter()
-                                .map(|v| Value::from(v.as_slice()))
-                                .collect(),
-                        ),
-                    ));
-                }
-            }
-        }
-
-        let key = match &self.settings.enrich_prefix {
-            Some(s) => Key::Name(NVec::from_iter(s.bytes().chain(name.iter().cloned()))),
-            None => Key::NameTranslated(name.into()),
+    /// Emits a `CONTAINER_START` event if the container `proc` is
+    /// running in has not been seen before.
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    fn observe_container(&mut self, id: EventID, proc: &Process) {
+        let Some(ContainerInfo { id: container_id }) = &proc.container_info else {
+            return;
         };
-        rec.push((key, Value::Map(m)));
-    }
-
-    /// Translates UID, GID and variants, e.g.:
-    /// - auid=1000 -> AUID="user"
-    /// - ogid=1000 -> OGID="user"
-    ///
-    /// IDs that can't be resolved are translated into "unknown(n)".
-    /// `(uint32)-1` is translated into "unset".
-    fn add_record_userdb(&mut self, body: &mut Body, ids: &UserGroupIDs) {
-        for (name, translated) in ids.get_translated(&mut self.state.userdb) {
-            let key = match &self.settings.enrich_prefix {
-                Some(s) => Key::Name(NVec::from_iter(s.bytes().chain((*name).iter().cloned()))),
-                None => Key::NameTranslated((*name).into()),
-            };
-            body.push((key, Value::from(translated.as_bytes())));
+        if !self.settings.enrich_container_events || self.containers.contains_key(container_id) {
+            return;
+        }
+        self.containers.insert(container_id.clone(), id);
+        let mut rv = Body::default();
+        rv.push((Key::Literal("id"), hex_string(container_id).into()));
+        rv.push((Key::Literal("event"), id.to_string().into()));
+        rv.push((Key::Literal("pid"), (proc.pid as i64).into()));
+        if let Some(exe) = &proc.exe {
+            rv.push((Key::Literal("exe"), exe.to_vec().into()));
         }
+        if let Some(unit) = &proc.systemd_unit {
+            rv.push((Key::Literal("unit"), unit.clone().into()));
+        }
+        let mut ev = self.synthetic_event();
+        ev.laurel.insert("CONTAINER_START", EventValues::Single(rv));
+        self.output(&ev)
     }
 
-    /// Enrich "pid" entries using `ppid`, `exe`, `ID` (generating
-    /// event id) from the shadow process table
-    fn enrich_pid(&mut self, rv: &mut Body, k: &Key, v: &Value) {
-        if !self.settings.enrich_pid {
+    /// Emits `CONTAINER_STOP` events for containers that no process
+    /// in the process table belongs to anymore.
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    fn expire_containers(&mut self) {
+        if self.containers.is_empty() {
             return;
         }
-        let name = match &k {
-            Key::Common(Common::Pid) => &b"pid"[..],
-            Key::Common(Common::PPid) => &b"ppid"[..],
-            Key::Name(r) if r.ends_with(b"pid") => r.as_ref(),
-            _ => return,
-        };
-        if let Value::Number(Number::Dec(pid)) = v {
-            if let Some(proc) = self.state.processes.get_pid(*pid as _) {
-                self.add_record_procinfo(rv, name, proc);
-            } else if let Some(proc) = self.state.processes.get_or_retrieve(*pid as _).cloned() {
-                self.add_record_procinfo(rv, name, &proc)
-            }
+        let live: HashSet<&[u8]> = self
+            .state
+            .processes
+            .processes
+            .values()
+            .filter_map(|p| p.container_info.as_ref())
+            .map(|c| &c.id[..])
+            .collect();
+        let gone: Vec<Vec<u8>> = self
+            .containers
+            .keys()
+            .filter(|id| !live.contains(&id[..]))
+            .cloned()
+            .collect();
+        for container_id in gone {
+            let first = self.containers.remove(&container_id).unwrap();
+            let mut rv = Body::default();
+            rv.push((Key::Literal("id"), hex_string(&container_id).into()));
+            rv.push((Key::Literal("first_event"), first.to_string().into()));
+            let mut ev = self.synthetic_event();
+            ev.laurel.insert("CONTAINER_STOP", EventValues::Single(rv));
+            self.output(&ev)
         }
     }
 
-    /// Apply uid, gid, pid enrichment to generic records
-    fn enrich_generic(&mut self, body: &mut Body) {
-        let mut nrv = Body::default();
-        let mut ids = UserGroupIDs::default();
-        body.retain(|(k, v)| {
-            match (k, v) {
-                (Key::NameUID(name), Value::Number(Number::Dec(n)))
-                | (Key::NameGID(name), Value::Number(Number::Dec(n))) => {
-                    ids.push(name, *n as _);
-                    if self.settings.drop_translated {
-                        return false;
-                    }
+    /// Returns the difference between the system clock and the
+    /// timestamp of the last audit record in milliseconds if it is
+    /// above `clock_skew_threshold`.
+    pub fn clock_skew(&self) -> Option<i64> {
+        self.clock_skew
+    }
+
+    pub fn state(&self) -> &State<'_> {
+        &self.state
+    }
+
+    /// Number of labels and tags that have been attached by built-in
+    /// analyzers
+    pub fn analyzer_hits(&self) -> u64 {
+        self.analyzer_hits
+    }
+
+    /// Number of records that have been dropped based on their type
+    pub fn dropped_records(&self) -> u64 {
+        self.dropped_records
+    }
+
+    /// Number of events that have been filtered, by preset name
+    pub fn preset_filtered(&self) -> &IndexMap<&'static str, u64> {
+        &self.preset_filtered
+    }
+
+    /// Creates an empty event for records that are generated by
+    /// laurel itself, using the current time.
+    fn synthetic_event(&self) -> Event<'ev> {
+        Event::new(
+            None,
+            EventID {
+                timestamp: self.clock.now_ms(),
+                sequence: 0,
+            },
+        )
+    }
+
+    /// Emits the audit rules that are loaded into the kernel as
+    /// `LAUREL_RULES` event.
+    pub fn emit_rules(&mut self, rules: &[Rule]) {
+        let mut ev = self.synthetic_event();
+        let rvs = rules
+            .iter()
+            .map(|rule| {
+                let mut rv = Body::default();
+                rv.push((Key::Literal("rule"), rule.to_string().into()));
+                if let Some(key) = rule.key() {
+                    rv.push((Key::Literal("key"), Value::Str(key, Quote::None)));
                 }
-                _ => self.enrich_pid(&mut nrv, k, v),
-            };
-            true
-        });
-        body.extend(nrv);
-        if self.settings.translate_userdb {
-            self.add_record_userdb(body, &ids);
+                rv
+            })
+            .collect();
+        ev.laurel.insert("LAUREL_RULES", EventValues::Multi(rvs));
+        self.output(&ev)
+    }
+
+    /// Emits a `LAUREL_RULES_CHECK` event listing configured features
+    /// that won't work because the audit rules they need are not
+    /// loaded
+    pub fn emit_rules_check(&mut self, unmet: &[(&str, Requirement)]) {
+        let mut ev = self.synthetic_event();
+        let rvs = unmet
+            .iter()
+            .map(|(feature, req)| {
+                let mut rv = Body::default();
+                rv.push((Key::Literal("feature"), feature.to_string().into()));
+                rv.push((Key::Literal("missing"), req.to_string().into()));
+                rv
+            })
+            .collect();
+        ev.laurel
+            .insert("LAUREL_RULES_CHECK", EventValues::Multi(rvs));
+        self.output(&ev)
+    }
+
+    /// Emits a `LAUREL_ERROR` event describing an error that occurred
+    /// while processing `line`.
+    pub fn emit_error(&mut self, err: &CoalesceError, line: &[u8]) {
+        let mut ev = self.synthetic_event();
+        let mut rv = Body::default();
+        rv.push((Key::Literal("code"), Value::Literal(err.code())));
+        rv.push((Key::Literal("message"), err.to_string().into()));
+        if let CoalesceError::Parse {
+            offset: Some(offset),
+            ..
+        } = err
+        {
+            rv.push((Key::Literal("offset"), (*offset as i64).into()));
         }
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        rv.push((Key::Literal("line"), line.to_vec().into()));
+        ev.laurel.insert("LAUREL_ERROR", EventValues::Single(rv));
+        self.output(&ev)
     }
 
-    /// Transform PROCTITLE record
-    ///
-    /// The flat proctitle field is turned into a list.
-    fn transform_proctitle(&mut self, rv: &mut Body) {
-        let mut argv = vec![];
-        rv.retain(|(k, v)| {
-            match (k, v) {
-                (k, Value::Str(r, _)) if k == "proctitle" => {
-                    argv = r
-                        .split(|c| *c == 0)
-                        .map(|arg| {
-                            // (assumed) safety:
-                            // We are adding references to the
-                            // same memory regions back to rv.
-                            let arg = unsafe {
-                                &*std::ptr::slice_from_raw_parts(arg.as_ptr(), arg.len())
-                            };
-                            Value::Str(arg, Quote::None)
-                        })
-                        .collect();
-                    false
-                }
-                _ => true,
-            }
-        });
-        if !argv.is_empty() {
-            rv.push(("ARGV".into(), Value::List(argv)));
+    /// Emits a `LAUREL_GAP` event containing the number of events
+    /// that have not been written, e.g. while output was suspended.
+    pub fn emit_gap(&mut self, dropped: u64) {
+        let mut ev = self.synthetic_event();
+        let mut rv = Body::default();
+        rv.push((Key::Literal("dropped"), (dropped as i64).into()));
+        ev.laurel.insert("LAUREL_GAP", EventValues::Single(rv));
+        self.output(&ev)
+    }
+
+    /// Emits a `LAUREL_HEARTBEAT` event containing the caller's
+    /// `counters`, the size of the process table and the ID of the
+    /// last audit record that has been processed.
+    pub fn emit_heartbeat(&mut self, counters: &[(&'static str, u64)]) {
+        let mut ev = self.synthetic_event();
+        let mut rv = Body::default();
+        rv.push((Key::Literal("version"), Value::Literal(crate::VERSION)));
+        for (name, value) in counters {
+            rv.push((Key::Literal(name), (*value as i64).into()));
         }
+        rv.push((
+            Key::Literal("processes"),
+            (self.state.processes.processes.len() as i64).into(),
+        ));
+        if let Some(id) = self.last_id {
+            rv.push((Key::Literal("last_event"), id.to_string().into()));
+        }
+        if let Some(skew) = self.clock_skew {
+            rv.push((Key::Literal("clock_skew"), skew.into()));
+        }
+        if !self.preset_filtered.is_empty() {
+            let m = self
+                .preset_filtered
+                .iter()
+                .map(|(name, n)| (Key::Literal(name), (*n as i64).into()))
+                .collect();
+            rv.push((Key::Literal("preset_filtered"), Value::Map(m)));
+        }
+        ev.laurel
+            .insert("LAUREL_HEARTBEAT", EventValues::Single(rv));
+        if self.settings.enrich_host_metadata != HostMetadata::Off {
+            self.add_host_metadata(&mut ev);
+        }
+        self.output(&ev)
     }
 
-    /// Enrich SOCKADDR record
-    ///
-    /// This function also determines whether the record should be filtered
-    fn enrich_sockaddr(&mut self, rv: &mut Body, is_filtered: &mut bool) {
-        let mut nrv = Body::default();
-        rv.retain(|(k, v)| match (k, v) {
-            (k, Value::Str(vr, _q)) => {
-                if k == "saddr" {
-                    #[cfg(target_os = "linux")]
-                    if let Ok(sa) = SocketAddr::parse(vr) {
-                        // There's no need to enrich saddr entries
-                        // that will be dropped, but the raw data
-                        // should be kept in case filter.filter-action
-                        // is set to "log".
-                        if *is_filtered {
-                            return true;
-                        } else if self.settings.filter_sockaddr.iter().any(|f| f.matches(&sa)) {
-                            *is_filtered = true;
-                            return true;
-                        }
-                        if self.settings.translate_universal {
-                            add_translated_socketaddr(&mut nrv, sa);
-                            return false;
-                        } else {
-                            return true;
-                        }
-                    }
-                } else if k == "SADDR" && self.settings.translate_universal || *is_filtered {
-                    // If we do our own enrichment, drop pre-existing
-                    // enriched SOCKADDR.saddr enrichment.
-                    return false;
-                }
-                true
+    /// Adds a `LAUREL_HOST` record to `ev`.
+    fn add_host_metadata(&self, ev: &mut Event) {
+        let mut rv = Body::default();
+        for (k, v) in &self.host_metadata {
+            rv.push((Key::Literal(k), Value::Owned(v.clone())));
+        }
+        ev.laurel.insert("LAUREL_HOST", EventValues::Single(rv));
+    }
+
+    /// Returns the deadline for an event whose first record is seen
+    /// now.
+    fn new_enrich_deadline(&self) -> Option<Instant> {
+        self.settings
+            .enrich_deadline
+            .map(|d| self.clock.now() + Duration::from_millis(d))
+    }
+
+    /// Uses the time budget `deadline` of an event for enriching
+    /// its records. There is one budget per event, shared by
+    /// [`Coalesce::handle_syscall`] and [`Coalesce::emit_event`].
+    fn start_enrichment(&mut self, deadline: Option<Instant>) {
+        self.enrich_deadline = deadline;
+        self.enrich_skipped.clear();
+    }
+
+    /// Returns whether `enrichment` can still be carried out within
+    /// the time budget. Otherwise, it is recorded as skipped.
+    fn within_deadline(&mut self, enrichment: &'static str) -> bool {
+        match self.enrich_deadline {
+            Some(t) if self.clock.now() >= t => {
+                self.skip_enrichment(enrichment);
+                false
             }
             _ => true,
-        });
-        rv.extend(nrv);
+        }
     }
 
-    /// Enrich URINGOP record
-    fn enrich_uringop(&mut self, body: &mut Body) {
-        let mut nrv = Body::default();
+    fn skip_enrichment(&mut self, enrichment: &'static str) {
+        if !self.enrich_skipped.contains(&enrichment) {
+            self.enrich_skipped.push(enrichment);
+        }
+    }
 
-        let mut ids = UserGroupIDs::default();
+    /// Adds `HOST_PATH` to `PATH` records of processes that run in a
+    /// different mount namespace, e.g. in containers.
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    fn enrich_host_path(&mut self, ev: &mut Event, pid: u32) {
+        let Some(cache) = &mut self.mount_cache else {
+            return;
+        };
+        let cwd = event_cwd(ev);
+        let Some(EventValues::Multi(paths)) = ev.body.get_mut(&MessageType::PATH) else {
+            return;
+        };
+        let key = match &self.settings.enrich_prefix {
+            Some(s) => Key::Name(NVec::from_iter(
+                s.bytes().chain(b"host_path".iter().cloned()),
+            )),
+            None => Key::Literal("HOST_PATH"),
+        };
+        for path in paths {
+            let Some(name) = absolute_path(path, cwd.as_deref()) else {
+                continue;
+            };
+            match cache.host_path(self.state.processes.source(), pid, &name) {
+                Ok(Some(host_path)) => path.push((key.clone(), Value::from(host_path))),
+                Ok(None) => continue,
+                // The process has already exited.
+                Err(_) => return,
+            }
+        }
+    }
 
-        body.retain(|(k, v)| {
-            match (k, v) {
-                (Key::NameUID(name), Value::Number(Number::Dec(n)))
-                | (Key::NameGID(name), Value::Number(Number::Dec(n))) => {
-                    ids.push(name, *n as _);
-                    if self.settings.drop_translated {
-                        return false;
-                    }
-                }
-                (Key::Name(name), Value::Number(Number::Dec(op)))
-                    if self.settings.translate_universal && k == "uring_op" =>
-                {
-                    if let Some(Some(op_name)) = URING_OPS.get(*op as usize) {
-                        nrv.push((Key::NameTranslated(name.clone()), Value::from(*op_name)));
-                    }
+    /// Adds `MOUNT` with the file system type, source, and mount
+    /// options of the file system that files in `PATH` records
+    /// reside on.
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    fn enrich_mount(&mut self, ev: &mut Event, pid: u32) {
+        let Some(cache) = &mut self.mount_cache else {
+            return;
+        };
+        let cwd = event_cwd(ev);
+        let Some(EventValues::Multi(paths)) = ev.body.get_mut(&MessageType::PATH) else {
+            return;
+        };
+        let key = match &self.settings.enrich_prefix {
+            Some(s) => Key::Name(NVec::from_iter(s.bytes().chain(b"mount".iter().cloned()))),
+            None => Key::Literal("MOUNT"),
+        };
+        for path in paths {
+            let Some(name) = absolute_path(path, cwd.as_deref()) else {
+                continue;
+            };
+            match cache.lookup(self.state.processes.source(), pid, &name) {
+                Ok(Some(mount)) => {
+                    let m = vec![
+                        (Key::Literal("fstype"), Value::from(mount.fstype.clone())),
+                        (Key::Literal("source"), Value::from(mount.source.clone())),
+                        (Key::Literal("options"), Value::from(mount.options.clone())),
+                    ];
+                    path.push((key.clone(), Value::Map(m)));
                 }
-                _ => {}
+                Ok(None) => continue,
+                // The process has already exited.
+                Err(_) => return,
             }
-            true
-        });
+        }
+    }
 
-        body.extend(nrv);
+    /// Adds the file that a kernel module or kexec image has been
+    /// loaded from (based on PATH records) as `MODULE_FILE`,
+    /// optionally its hash as `MODULE_HASH`, and the kernel's taint
+    /// mask as `KERNEL_TAINTED` and `KERNEL_TAINT_FLAGS` to the
+    /// SYSCALL record of `init_module`, `finit_module`,
+    /// `delete_module`, `kexec_load`, and `kexec_file_load` events.
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    fn enrich_kernel_module(&mut self, ev: &mut Event) {
+        if !matches!(
+            ev.syscall,
+            Some(
+                "init_module" | "finit_module" | "delete_module" | "kexec_load" | "kexec_file_load"
+            )
+        ) {
+            return;
+        }
+        let cwd = event_cwd(ev);
+        let file = match ev.body.get(&MessageType::PATH) {
+            Some(EventValues::Multi(paths)) => paths
+                .iter()
+                .find(|p| matches!(p.get("nametype"), Some(Value::Str(b"NORMAL", _))))
+                .and_then(|p| absolute_path(p, cwd.as_deref())),
+            _ => None,
+        };
+        let hash = match &file {
+            Some(file) if self.settings.enrich_kernel_module_hash => {
+                self.kernel_module_hash(ev.process_key, file)
+            }
+            _ => None,
+        };
+        let tainted = procfs::get_kernel_tainted().ok();
 
-        if self.settings.translate_userdb {
-            self.add_record_userdb(body, &ids);
+        let Some(EventValues::Single(rv)) = ev.body.get_mut(&MessageType::SYSCALL) else {
+            return;
+        };
+        if let Some(file) = file {
+            rv.push((Key::Literal("MODULE_FILE"), Value::from(file)));
+        }
+        if let Some(hash) = hash {
+            rv.push((Key::Literal("MODULE_HASH"), hex_string(&hash).into()));
+        }
+        if let Some(tainted) = tainted {
+            let flags: Vec<u8> = KERNEL_TAINT_FLAGS
+                .iter()
+                .enumerate()
+                .filter(|(bit, _)| tainted & (1 << bit) != 0)
+                .map(|(_, c)| *c)
+                .collect();
+            rv.push((Key::Literal("KERNEL_TAINTED"), Value::from(tainted as i64)));
+            rv.push((Key::Literal("KERNEL_TAINT_FLAGS"), Value::from(flags)));
         }
     }
 
-    /// Enrich SYSCALL record
-    ///
-    /// Add ARCH, SYSCALL, PID, PPID, SCRIPT, LABELS if appropriate
-    fn enrich_syscall(
+    /// Hashes the module file as seen by the loading process. Hashing
+    /// is done by the worker pool if there is one, waiting no longer
+    /// than the enrichment deadline.
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    fn kernel_module_hash(
         &mut self,
-        rv: &mut Body,
         process_key: Option<ProcessKey>,
-        script: &Option<NVec>,
-        container_info: &mut Option<Body>,
-    ) {
-        #[cfg(all(feature = "procfs", target_os = "linux"))]
-        if let (true, Some(script)) = (self.settings.enrich_script, &script) {
-            rv.push((
-                Key::Literal("SCRIPT"),
-                Value::Str(script.as_slice(), Quote::None),
-            ));
+        file: &[u8],
+    ) -> Option<[u8; 32]> {
+        if !self.within_deadline("module_hash") {
+            return None;
         }
-
-        if let Some(proc) = process_key.and_then(|k| self.state.processes.get_key(&k)) {
-            #[cfg(all(feature = "procfs", target_os = "linux"))]
-            if let (true, Some(c)) = (self.settings.enrich_container, &proc.container_info) {
-                let mut ci = Body::default();
-                ci.push((
-                    Key::Literal("ID"),
-                    Value::Str(hex_string(&c.id).as_bytes(), Quote::None),
-                ));
-                *container_info = Some(ci);
+        let pid = process_key
+            .and_then(|k| self.state.processes.get_key(&k))
+            .map(|p| p.pid)?;
+        let f = self
+            .state
+            .processes
+            .source()
+            .pid_path_open(pid, file)
+            .ok()?;
+        let len = f.metadata().ok()?.len();
+        if len > self.settings.enrich_kernel_module_hash_size_limit {
+            return None;
+        }
+        let Some(workers) = &self.workers else {
+            return hash_exe(f, len, &Cancel::default());
+        };
+        let Some(task) = workers.submit(move |cancel| hash_exe(f, len, cancel)) else {
+            self.skip_enrichment("module_hash");
+            return None;
+        };
+        let deadline = self
+            .enrich_deadline
+            .unwrap_or_else(|| self.clock.now() + EXE_HASH_WAIT_MAX);
+        match task.wait(Some(deadline)) {
+            Some(h) => h,
+            None => {
+                self.skip_enrichment("module_hash");
+                None
             }
+        }
+    }
 
-            if !proc.labels.is_empty() {
-                let labels = proc
-                    .labels
-                    .iter()
-                    .map(|l| Value::Str(l, Quote::None))
-                    .collect::<Vec<_>>();
-                rv.push((Key::Literal("LABELS"), Value::List(labels)));
-            }
+    /// Adds the utmp login session for the terminal of the SYSCALL
+    /// record as `LOGIN_SESSION`.
+    ///
+    /// utmp does not record audit session IDs, so the session is
+    /// looked up by terminal name. If the terminal has been reused
+    /// since the event was generated, the newer session is reported.
+    #[cfg(target_os = "linux")]
+    fn enrich_login_session(&mut self, ev: &mut Event) {
+        let key = match &self.settings.enrich_prefix {
+            Some(s) => Key::Name(NVec::from_iter(
+                s.bytes().chain(b"login_session".iter().cloned()),
+            )),
+            None => Key::Literal("LOGIN_SESSION"),
+        };
+        let Some(sessions) = &mut self.login_sessions else {
+            return;
+        };
+        let Some(EventValues::Single(rv)) = ev.body.get_mut(&MessageType::SYSCALL) else {
+            return;
+        };
+        let session = match rv.get("tty") {
+            Some(Value::Str(tty, _)) if *tty != b"(none)" => sessions.lookup(tty),
+            _ => None,
+        };
+        let Some(session) = session else {
+            return;
+        };
+        let m = vec![
+            ("USER".into(), Value::from(session.user.clone())),
+            ("HOST".into(), Value::from(session.host.clone())),
+            ("ORIGIN".into(), Value::Literal(session.origin())),
+            ("PID".into(), Value::from(session.pid as i64)),
+        ];
+        rv.push((key, Value::Map(m)));
+    }
+
+    /// Adds the process that a `ptrace`, `process_vm_readv`,
+    /// `process_vm_writev`, `pidfd_getfd`, `kill`, `tkill`, or
+    /// `tgkill` syscall was directed at as `TARGET_PROCESS`. For
+    /// signals, the signal name is added as `SIGNAL`.
+    fn enrich_target_process(&mut self, ev: &mut Event) {
+        let Some(EventValues::Single(rv)) = ev.body.get_mut(&MessageType::SYSCALL) else {
+            return;
+        };
+        let arg = |n: usize| match rv.get("ARGV") {
+            Some(Value::List(args)) => match args.get(n) {
+                Some(Value::Number(Number::Hex(v))) => Some(*v),
+                _ => None,
+            },
+            _ => None,
+        };
+        let signal = match ev.syscall {
+            Some("kill" | "tkill") => arg(1),
+            Some("tgkill") => arg(2),
+            _ => None,
+        };
+        let pid = match ev.syscall {
+            // PTRACE_TRACEME
+            Some("ptrace") if arg(0) == Some(0) => None,
+            Some("ptrace") => arg(1),
+            // Negative values and 0 refer to process groups. tgkill
+            // names the thread group, tkill only the thread.
+            Some("kill" | "tgkill") => arg(0),
+            Some("tkill") => arg(0).map(|tid| self.tid_tgid(tid)),
+            Some("process_vm_readv" | "process_vm_writev") => arg(0),
+            // The target can only be determined while the pidfd is
+            // still open.
+            #[cfg(all(feature = "procfs", target_os = "linux"))]
+            Some("pidfd_getfd") if !self.state.processes.remote => ev
+                .process_key
+                .and_then(|k| self.state.processes.get_key(&k))
+                .zip(arg(0))
+                .and_then(|(proc, fd)| {
+                    self.state
+                        .processes
+                        .source()
+                        .pidfd_pid(proc.pid, fd as u32)
+                        .ok()
+                })
+                .map(u64::from),
+            _ => None,
+        };
+        if let Some(name) = ev.arch.zip(signal).and_then(|(a, s)| signal_name(a, s)) {
+            rv.push((Key::Literal("SIGNAL"), Value::Literal(name)));
         }
+        let Some(pid) = pid.filter(|&p| p != 0 && p <= i32::MAX as u64) else {
+            return;
+        };
+        let Some(proc) = self.state.processes.get_or_retrieve(pid as u32).cloned() else {
+            return;
+        };
+        let m = self.target_procinfo(&proc);
+        rv.push((self.procinfo_key(b"target_process"), Value::Map(m)));
     }
 
-    fn transform_execve(&mut self, rv: &mut Body, process_key: Option<ProcessKey>) {
-        let mut argv: Vec<Value> = Vec::with_capacity(rv.len() - 1);
-        rv.retain(|(k, v)| {
-            match k {
-                Key::ArgLen(_) => false,
-                Key::Arg(i, None) => {
-                    let idx = *i as usize;
-                    if argv.len() <= idx {
-                        argv.resize(idx + 1, Value::Empty);
-                    };
-                    argv[idx] = v.clone();
-                    false
-                }
-                Key::Arg(i, Some(f)) => {
-                    let idx = *i as usize;
-                    if argv.len() <= idx {
-                        argv.resize(idx + 1, Value::Empty);
-                        argv[idx] = Value::Segments(Vec::new());
-                    }
-                    if let Some(Value::Segments(vs)) = argv.get_mut(idx) {
-                        let frag = *f as usize;
-                        let r = match v {
-                            Value::Str(r, _) => r,
-                            _ => todo!(),
-                        };
-                        if vs.len() <= frag {
-                            vs.resize(frag + 1, &[]);
-                            let ptr = std::ptr::slice_from_raw_parts(r.as_ptr(), r.len());
-                            // (assumed) safety: vs[frag] is only added back to rv
-                            vs[frag] = unsafe { &*ptr };
-                        }
-                    }
-                    false
-                }
-                _ => true,
+    /// Returns the ID of the process that thread `tid` belongs to
+    fn tid_tgid(&self, tid: u64) -> u64 {
+        #[cfg(all(feature = "procfs", target_os = "linux"))]
+        if !self.state.processes.remote
+            && (1..=i32::MAX as u64).contains(&tid)
+            && self.state.processes.get_pid(tid as u32).is_none()
+        {
+            if let Ok(tgid) = self.state.processes.source().tid_tgid(tid as u32) {
+                return tgid.into();
             }
-        });
+        }
+        tid
+    }
 
-        if let Some(process_key) = process_key {
-            if self.settings.label_argv_count > 0
-                && self.settings.label_argv_bytes > 0
-                && (self.settings.label_argv.is_some() || self.settings.unlabel_argv.is_some())
-            {
-                let mut buf: Vec<u8> = Vec::with_capacity(self.settings.label_argv_bytes);
+    /// Information about processes that are the target of signals,
+    /// ptrace etc.
+    fn target_procinfo<'p>(&self, proc: &'p Process) -> Vec<(Key, Value<'p>)> {
+        let mut m = self.procinfo(b"target_process", proc);
+        m.insert(0, ("pid".into(), (proc.pid as i64).into()));
+        #[cfg(all(feature = "procfs", target_os = "linux"))]
+        if let (true, Some(container_info)) = (self.settings.enrich_container, &proc.container_info)
+        {
+            let id = hex_string(&container_info.id);
+            m.push((
+                "container".into(),
+                Value::Map(vec![("id".into(), id.into())]),
+            ));
+        }
+        if !proc.labels.is_empty() {
+            let mut labels: Vec<&Vec<u8>> = proc.labels.iter().collect();
+            labels.sort();
+            let labels = labels.into_iter().map(|l| Value::from(l.clone())).collect();
+            m.push((Key::Literal("LABELS"), Value::List(labels)));
+        }
+        m
+    }
 
-                for arg in argv.iter().take(self.settings.label_argv_count) {
-                    if !buf.is_empty() {
-                        buf.push(b' ');
-                    }
-                    // FIXME TryFrom<&Value> needs to be implemented in linux-audit-parser
-                    let b: Vec<u8> = match arg.clone().try_into() {
-                        Ok(b) => b,
-                        Err(_) => continue,
+    /// Emits a `LAUREL_SUMMARY` event containing event counts since
+    /// the start of the summary period and starts a new period at
+    /// `now`.
+    fn emit_summary(&mut self, now: u64) {
+        let summary = std::mem::take(&mut self.summary);
+        self.summary.start = Some(now);
+        let Some(start) = summary.start else {
+            return;
+        };
+        let max = self.settings.summary_max_entries;
+        let translate = self.settings.translate_userdb;
+        let ts = |t: u64| format!("{}.{:03}", t / 1000, t % 1000);
+
+        let mut rv = Body::default();
+        rv.push((Key::Literal("start"), ts(start).into()));
+        rv.push((Key::Literal("end"), ts(now).into()));
+        rv.push((Key::Literal("events"), (summary.events as i64).into()));
+        rv.push((Key::Literal("bytes"), (summary.bytes as i64).into()));
+        for (name, map) in [
+            ("auid", &summary.auid),
+            ("uid", &summary.uid),
+            ("gid", &summary.gid),
+        ] {
+            let entries = summary::top(map, max)
+                .into_iter()
+                .map(|(id, n)| {
+                    let value: Value = match (*id, translate) {
+                        (0xffffffff, _) => "unset".into(),
+                        (id, true) if name == "gid" => self
+                            .state
+                            .userdb
+                            .get_group(id)
+                            .unwrap_or(format!("#{id}"))
+                            .into(),
+                        (id, true) => self
+                            .state
+                            .userdb
+                            .get_user(id)
+                            .unwrap_or(format!("#{id}"))
+                            .into(),
+                        (id, false) => (id as i64).into(),
                     };
-                    if buf.len() + b.len() >= self.settings.label_argv_bytes {
-                        break;
-                    }
-                    buf.extend(b);
-                }
-
-                if let Some(ref mut proc) = self.state.processes.get_key_mut(&process_key) {
-                    if let Some(ref m) = self.settings.label_argv {
-                        for label in m.matches(&buf) {
-                            proc.labels.insert(label.into());
-                        }
-                    }
+                    Value::Map(vec![
+                        (Key::Literal("value"), value),
+                        (Key::Literal("events"), (n as i64).into()),
+                    ])
+                })
+                .collect();
+            rv.push((Key::Literal(name), Value::List(entries)));
+        }
+        let entries = summary::top(&summary.exe, max)
+            .into_iter()
+            .map(|(s, n)| {
+                Value::Map(vec![
+                    (
+                        Key::Literal("value"),
+                        String::from_utf8_lossy(s).into_owned().into(),
+                    ),
+                    (Key::Literal("events"), (n as i64).into()),
+                ])
+            })
+            .collect();
+        rv.push((Key::Literal("exe"), Value::List(entries)));
+        let entries = summary::top(&summary.key, max)
+            .into_iter()
+            .map(|(s, n)| {
+                let bytes = summary.key_bytes.get(s).copied().unwrap_or_default();
+                Value::Map(vec![
+                    (
+                        Key::Literal("value"),
+                        String::from_utf8_lossy(s).into_owned().into(),
+                    ),
+                    (Key::Literal("events"), (n as i64).into()),
+                    (Key::Literal("bytes"), (bytes as i64).into()),
+                ])
+            })
+            .collect();
+        rv.push((Key::Literal("key"), Value::List(entries)));
 
-                    if let Some(ref m) = self.settings.unlabel_argv {
-                        for label in m.matches(&buf) {
-                            proc.labels.remove(label);
-                        }
-                    }
+        if let Some(threshold) = self.settings.summary_key_share_warning {
+            let share = |n: u64, total: u64| match total {
+                0 => 0,
+                total => n * 100 / total,
+            };
+            for (key, n) in &summary.key {
+                let bytes = summary.key_bytes.get(key).copied().unwrap_or_default();
+                let (events_pct, bytes_pct) =
+                    (share(*n, summary.events), share(bytes, summary.bytes));
+                if events_pct > threshold || bytes_pct > threshold {
+                    log::warn!(
+                        "Key {} accounts for {events_pct}% of syscall events and {bytes_pct}% of input bytes",
+                        String::from_utf8_lossy(key)
+                    );
                 }
             }
         }
 
-        // Strip data from the middle of excessively long ARGV
-        if let Some(argv_max) = self.settings.execve_argv_limit_bytes {
-            let argv_size: usize = argv.iter().map(|v| 1 + v.str_len()).sum();
-            if argv_size > argv_max {
-                let diff = argv_size - argv_max;
-                let skip_range = (argv_size - diff) / 2..(argv_size + diff) / 2;
-                argv = {
-                    let mut filtered = Vec::new();
-                    let mut start = 0;
-                    let mut skipped: Option<(usize, usize)> = None;
-                    for arg in argv.iter() {
-                        let end = start + arg.str_len();
-                        if skip_range.contains(&start) || skip_range.contains(&end) {
-                            skipped = match skipped {
-                                None => Some((1, end - start)),
-                                Some((args, bytes)) => Some((args + 1, 1 + bytes + (end - start))),
-                            };
-                        } else {
-                            if let Some((args, bytes)) = skipped {
-                                filtered.push(Value::Skipped((args, bytes)));
-                                skipped = None;
-                            }
-                            filtered.push(arg.clone());
-                        }
-                        start = end + 1;
-                    }
-                    filtered
-                };
-            }
+        let mut ev = self.synthetic_event();
+        ev.laurel.insert("LAUREL_SUMMARY", EventValues::Single(rv));
+        self.output(&ev)
+    }
+
+    /// Flushes all in-flight event data and emits a `LAUREL_SHUTDOWN`
+    /// event containing `counters`. Events that were still incomplete
+    /// are marked with a `LAUREL_FORCED_FLUSH` record.
+    pub fn shutdown(&mut self, counters: &[(&'static str, u64)]) {
+        // Events that are only kept for the reorder window are
+        // complete.
+        self.expire_reordered(u64::MAX);
+        let inflight = std::mem::take(&mut self.state.inflight);
+        let flushed = inflight.len();
+        for (_, mut event) in inflight {
+            let mut rv = Body::default();
+            rv.push((Key::Literal("reason"), Value::Literal("shutdown")));
+            event
+                .laurel
+                .insert("LAUREL_FORCED_FLUSH", EventValues::Single(rv));
+            self.emit_event(event);
         }
+        self.flush();
 
-        // ARGV
-        if self.settings.execve_argv_list {
-            rv.push((Key::Literal("ARGV"), Value::List(argv.clone())));
+        let mut ev = self.synthetic_event();
+        let mut rv = Body::default();
+        rv.push((Key::Literal("version"), Value::Literal(crate::VERSION)));
+        for (name, value) in counters {
+            rv.push((Key::Literal(name), (*value as i64).into()));
         }
-        // ARGV_STR
-        if self.settings.execve_argv_string {
-            rv.push((
-                Key::Literal("ARGV_STR"),
-                Value::StringifiedList(argv.clone()),
-            ));
+        rv.push((Key::Literal("flushed"), (flushed as i64).into()));
+        rv.push((
+            Key::Literal("processes"),
+            (self.state.processes.processes.len() as i64).into(),
+        ));
+        if let Some(id) = self.last_id {
+            rv.push((Key::Literal("last_event"), id.to_string().into()));
         }
+        ev.laurel.insert("LAUREL_SHUTDOWN", EventValues::Single(rv));
+        self.output(&ev);
+        self.expire_ordered(u64::MAX);
+    }
 
-        // ENV
-        #[cfg(all(feature = "procfs", target_os = "linux"))]
-        if let (Some(proc), false) = (
-            process_key.and_then(|k| self.state.processes.get_key(&k)),
-            self.settings.execve_env_exact.is_empty() && self.settings.execve_env_prefix.is_empty(),
-        ) {
-            if let Ok(vars) = procfs::get_environ(proc.pid, |k| {
-                self.settings.execve_env_exact.contains(k)
-                    || self
-                        .settings
-                        .execve_env_prefix
-                        .iter()
-                        .any(|p| k.starts_with(p))
-            }) {
-                let map = vars
-                    .iter()
-                    .map(|(k, v)| {
-                        (
-                            Key::Name(NVec::from(k.as_slice())),
-                            Value::Str(v, Quote::None),
-                        )
-                    })
-                    .collect();
-                rv.push((Key::Literal("ENV"), Value::Map(map)));
-            }
+    pub fn initialize(&mut self) -> Result<(), proc::ProcError> {
+        if self.settings.translate_userdb {
+            self.state.userdb.populate();
         }
+        let mut processes = ProcTable {
+            #[cfg(all(feature = "procfs", target_os = "linux"))]
+            source: self.state.processes.source.clone(),
+            ns_pid: self.settings.enrich_ns_pid,
+            ..ProcTable::default()
+        };
+        processes.populate(
+            self.settings.label_exe.clone(),
+            &self.settings.proc_propagate_labels,
+        )?;
+        self.state.processes = processes;
+        self.label_processes();
+
+        Ok(())
     }
 
-    /// Rewrite event to normal form
+    /// Flush out events
     ///
-    /// This function
-    /// - turns SYSCALL/a* fields into a single an ARGV list
-    /// - turns EXECVE/a* and EXECVE/a*[*] fields into an ARGV list
-    /// - turns PROCTITLE/proctitle into a (abbreviated) ARGV list
-    /// - translates *uid, *gid, syscall, arch, sockaddr if configured to do so.
-    /// - enriches PID and container enrichment if configured to do so.
-    /// - collects environment variables for EXECVE events
-    /// - registers process in shadow process table for EXECVE events
-    fn transform_event(&mut self, ev: &mut Event) {
-        #[cfg(all(feature = "procfs", target_os = "linux"))]
-        let mut proc = ev
-            .process_key
-            .as_ref()
-            .and_then(|p| self.state.processes.get_key(p).cloned());
-
-        if let Some(EventValues::Single(rv)) = ev.body.get_mut(&MessageType::EXECVE) {
-            self.transform_execve(rv, ev.process_key);
+    /// Called every EXPIRE_PERIOD ms and when Coalesce is destroyed.
+    fn expire_inflight(&mut self, now: u64) {
+        let timeout = EXPIRE_INFLIGHT_TIMEOUT + self.settings.reorder_window;
+        let mut events = vec![];
+        self.state.inflight.retain(|EventKey(_, id), event| {
+            let emit = id.timestamp.saturating_add(timeout) < now;
+            emit.then(|| events.push(event.clone()));
+            !emit
+        });
+        for event in events {
+            let key = EventKey(event.node.clone(), event.id);
+            self.reorder_pending.remove(&(event.id.timestamp, key));
+            self.emit_event(event);
         }
+    }
 
-        // Handle script enrichment
-        // TODO: Look up process per key.
-        #[cfg(all(feature = "procfs", target_os = "linux"))]
-        let script: Option<NVec> = match (self.settings.enrich_script, &self.settings.label_script)
-        {
-            (false, None) => None,
-            _ => match (&proc, ev.body.get(&MessageType::PATH), ev.is_exec) {
-                (Some(proc), Some(EventValues::Multi(paths)), true) => {
-                    let mut cwd = &b"/"[..];
-                    if let Some(EventValues::Single(r)) = ev.body.get(&MessageType::CWD) {
-                        if let Some(Value::Str(rv, _)) = r.get("cwd") {
-                            cwd = rv;
-                        }
-                    };
-                    path_script_name(
-                        &paths[0],
-                        proc.pid,
-                        proc.ppid,
-                        cwd,
-                        &proc.exe.clone().unwrap_or_default(),
-                    )
-                }
-                _ => None,
-            },
-        };
-        #[cfg(not(all(feature = "procfs", target_os = "linux")))]
-        let script = None;
-
-        #[cfg(all(feature = "procfs", target_os = "linux"))]
-        if let (Some(ref mut proc), Some(script)) = (&mut proc, &script) {
-            if let Some(label_script) = &self.settings.label_script {
-                for label in label_script.matches(script.as_ref()) {
-                    proc.labels.insert(label.into());
-                }
+    /// Emit complete events that have been kept for the reorder
+    /// window
+    fn expire_reordered(&mut self, now: u64) {
+        while let Some((ts, _)) = self.reorder_pending.first() {
+            if ts.saturating_add(self.settings.reorder_window) >= now {
+                break;
             }
-            if let Some(unlabel_script) = &self.settings.unlabel_script {
-                for label in unlabel_script.matches(script.as_ref()) {
-                    proc.labels.remove(label);
-                }
+            let (_, key) = self.reorder_pending.pop_first().unwrap();
+            if let Some(ev) = self.state.inflight.remove(&key) {
+                self.emit_event(ev);
             }
         }
+    }
 
-        if let Some(EventValues::Multi(ref mut rvs)) = ev.body.get_mut(&MessageType::SOCKADDR) {
-            for rv in rvs {
-                self.enrich_sockaddr(rv, &mut ev.is_filtered)
+    /// Emit complete events from the reorder window and events held
+    /// back for ordered output once no audit records have been
+    /// received for the rest of the respective window, as measured by
+    /// the system clock. This is meant to be called periodically
+    /// while waiting for input.
+    pub fn expire_idle(&mut self) {
+        if self.reorder_pending.is_empty() && self.ordered.is_empty() {
+            return;
+        }
+        let idle = self.clock.now_ms().saturating_sub(self.last_record_t);
+        let now = self.latest_timestamp.saturating_add(idle);
+        self.expire_reordered(now);
+        self.expire_ordered(now);
+    }
+
+    /// Returns the time after which [`Coalesce::expire_idle`] will
+    /// emit the next event from the reorder window or the events held
+    /// back for ordered output if no more audit records are received.
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        let reordered = self
+            .reorder_pending
+            .first()
+            .map(|(ts, _)| ts + self.settings.reorder_window);
+        let ordered = self
+            .ordered
+            .first_key_value()
+            .map(|((id, _, _), _)| id.timestamp + self.ordered_delay());
+        let next = match (reordered, ordered) {
+            (Some(a), Some(b)) => a.min(b),
+            (a, b) => a.or(b)?,
+        };
+        let idle = self.clock.now_ms().saturating_sub(self.last_record_t);
+        let remaining = (next + 1).saturating_sub(self.latest_timestamp.saturating_add(idle));
+        Some(Duration::from_millis(remaining))
+    }
+
+    /// Time in milliseconds for which events are held back for
+    /// ordered output
+    fn ordered_delay(&self) -> u64 {
+        let window = match &self.settings.command_groups {
+            Some(settings) => settings.window,
+            None => 0,
+        };
+        (EXPIRE_INFLIGHT_TIMEOUT + self.settings.reorder_window).max(window)
+    }
+
+    /// Emit events that have been held back for ordered output once
+    /// all earlier events have been completed or expired
+    fn expire_ordered(&mut self, now: u64) {
+        let limit = now.saturating_sub(self.ordered_delay());
+        while let Some(entry) = self.ordered.first_entry() {
+            if entry.key().0.timestamp >= limit {
+                break;
             }
+            let ev = entry.remove();
+            (self.emit_fn)(&ev);
         }
+    }
 
-        if ev.is_filtered {
+    /// Passes `ev` to the emit function, or holds it back if output
+    /// is ordered
+    fn output(&mut self, ev: &Event<'ev>) {
+        if !self.settings.ordered_output {
+            (self.emit_fn)(ev);
             return;
         }
+        self.ordered
+            .insert((ev.id, ev.node.clone(), self.ordered_count), ev.clone());
+        self.ordered_count += 1;
+    }
 
-        let mut container_info: Option<Body> = None;
+    fn expire_done(&mut self, now: u64) {
+        self.state
+            .done
+            .retain(|EventKey(_, id)| id.timestamp + EXPIRE_DONE_TIMEOUT >= now);
+    }
 
-        for tv in ev.body.iter_mut() {
-            match tv {
-                (&MessageType::SYSCALL, EventValues::Single(rv)) => {
-                    self.enrich_syscall(rv, ev.process_key, &script, &mut container_info)
-                }
-                (&MessageType::EXECVE, EventValues::Single(_)) => {}
-                (&MessageType::PROCTITLE, EventValues::Single(rv)) => self.transform_proctitle(rv),
-                (&MessageType::URINGOP, EventValues::Multi(rvs)) => {
-                    rvs.iter_mut().for_each(|rv| self.enrich_uringop(rv))
+    /// Create an enriched pid entry in rv.
+    fn add_record_procinfo(&self, rec: &mut Body, name: &[u8], proc: &Process) {
+        let m = self.procinfo(name, proc);
+        rec.push((self.procinfo_key(name), Value::Map(m)));
+    }
+
+    fn procinfo_key(&self, name: &[u8]) -> Key {
+        match &self.settings.enrich_prefix {
+            Some(s) => Key::Name(NVec::from_iter(s.bytes().chain(name.iter().cloned()))),
+            None => Key::NameTranslated(name.into()),
+        }
+    }
+
+    fn procinfo<'p>(&self, name: &[u8], proc: &'p Process) -> Vec<(Key, Value<'p>)> {
+        let mut m: Vec<(Key, Value)> = Vec::with_capacity(4);
+        match &proc.key {
+            ProcessKey::Event(id) => {
+                m.push(("EVENT_ID".into(), format!("{id}").into()));
+            }
+            ProcessKey::Observed { time, pid: _ } => {
+                let (sec, msec) = (time / 1000, time % 1000);
+                m.push(("START_TIME".into(), format!("{sec}.{msec:03}").into()));
+            }
+        }
+        if self.settings.enrich_entity_id {
+            m.push((
+                "ENTITY_ID".into(),
+                proc.key.entity_id(&self.entity_node).into(),
+            ));
+        }
+        if name != b"pid" {
+            if let Some(comm) = &proc.comm {
+                m.push(("comm".into(), Value::from(&comm[..])));
+            }
+            if let Some(exe) = &proc.exe {
+                m.push(("exe".into(), Value::from(&exe[..])));
+            }
+            if proc.ppid != 0 {
+                m.push(("ppid".into(), Value::from(proc.ppid as i64)));
+            }
+        } else {
+            #[cfg(all(feature = "procfs", target_os = "linux"))]
+            {
+                if let (true, Some(container_info)) =
+                    (self.settings.enrich_container, &proc.container_info)
+                {
+                    let id = hex_string(&container_info.id);
+                    m.push((
+                        "container".into(),
+                        Value::Map(vec![("id".into(), id.into())]),
+                    ));
                 }
-                (_, EventValues::Single(rv)) => self.enrich_generic(rv),
-                (_, EventValues::Multi(rvs)) => {
-                    rvs.iter_mut().for_each(|rv| self.enrich_generic(rv))
+
+                if let (true, Some(systemd_service)) =
+                    (self.settings.enrich_systemd, &proc.systemd_service)
+                {
+                    m.push((
+                        "systemd_service".into(),
+                        Value::List(
+                            systemd_service
+                                .iter()
+                                .map(|v| Value::from(v.as_slice()))
+                                .collect(),
+                        ),
+                    ));
                 }
             }
         }
+        m
+    }
 
-        if self.settings.enrich_container_info {
-            ev.container_info = container_info;
-        }
+    /// Returns whether "universal" translations are performed for
+    /// records of type `ty`
+    fn translate_universal(&self, ty: MessageType) -> bool {
+        self.settings.translate_universal && !self.settings.translate_skip_universal.contains(&ty)
     }
 
-    /// Do bookkeeping on event, transform, emit it via the provided
-    /// output function.
-    fn emit_event(&mut self, mut ev: Event<'ev>) {
-        self.state.done.insert(EventKey(ev.node.clone(), ev.id));
+    /// Returns whether UID, GID values in records of type `ty` are
+    /// translated
+    fn translate_userdb(&self, ty: MessageType) -> bool {
+        self.settings.translate_userdb
+            && !self.remote_node
+            && !self.settings.translate_skip_userdb.contains(&ty)
+    }
 
-        self.transform_event(&mut ev);
-        (self.emit_fn)(&ev)
+    /// Returns whether raw UID, GID values in records of type `ty`
+    /// are dropped
+    fn drop_ids(&self, ty: MessageType) -> bool {
+        self.settings.drop_translated
+            && !self.remote_node
+            && !self.settings.translate_skip_userdb.contains(&ty)
     }
 
-    /// Early handling of SYSCALL events
+    /// Translates UID, GID and variants, e.g.:
+    /// - auid=1000 -> AUID="user"
+    /// - ogid=1000 -> OGID="user"
     ///
-    /// This involves:
-    /// - deciding whether the process is known / updating the process table
-    ///   - determining a process key for new events
-    /// - early handling of process labels based on key, exe
-    /// - deciding whether the event should be filtered, avoiding unnecessary
-    ///   work for enrichment/transformation
-    pub fn handle_syscall(
-        &mut self,
-        id: EventID,
-        body: &mut Body,
-        filter_event: &mut bool,
-        is_exec: &mut bool,
-        process_key: &mut Option<ProcessKey>,
-    ) {
-        let mut arch: Option<u32> = None;
-        let mut syscall: Option<u32> = None;
-
-        let mut pid = 0;
-        let mut ppid = 0;
-
-        let mut comm: Option<&[u8]> = None;
-        let mut exe: Option<&[u8]> = None;
-        let mut key: Option<&[u8]> = None;
+    /// IDs that can't be resolved are translated into "unknown(n)".
+    /// `(uint32)-1` is translated into "unset".
+    fn add_record_userdb(&mut self, body: &mut Body, ids: &UserGroupIDs) {
+        for (name, id) in ids.iter() {
+            let translated = self.id_names.get(&mut self.state.userdb, name, id);
+            let key = match &self.settings.enrich_prefix {
+                Some(s) => Key::Name(NVec::from_iter(s.bytes().chain(name.iter().cloned()))),
+                None => Key::NameTranslated(name.into()),
+            };
+            body.push((key, Value::from(translated)));
+        }
+    }
 
-        let mut argv = Vec::with_capacity(4);
+    /// Enrich "pid" entries using `ppid`, `exe`, `ID` (generating
+    /// event id) from the shadow process table
+    fn enrich_pid(&mut self, rv: &mut Body, k: &Key, v: &Value) {
+        if !self.settings.enrich_pid {
+            return;
+        }
+        let name = match &k {
+            Key::Common(Common::Pid) => &b"pid"[..],
+            Key::Common(Common::PPid) => &b"ppid"[..],
+            Key::Name(r) if r.ends_with(b"pid") => r.as_ref(),
+            _ => return,
+        };
+        if let Value::Number(Number::Dec(pid)) = v {
+            // OBJ_PID records refer to targets of signals.
+            if name == b"opid" && self.settings.enrich_target_process {
+                if !self.within_deadline("target_process") {
+                    return;
+                }
+                if let Some(proc) = self.state.processes.get_or_retrieve(*pid as _).cloned() {
+                    let m = self.target_procinfo(&proc);
+                    rv.push((self.procinfo_key(name), Value::Map(m)));
+                }
+            } else if let Some(proc) = self.state.processes.get_pid(*pid as _) {
+                self.add_record_procinfo(rv, name, proc);
+            } else if let Some(proc) = self.state.processes.get_or_retrieve(*pid as _).cloned() {
+                // Not subject to the enrichment deadline, this keeps
+                // the process table up to date.
+                self.add_record_procinfo(rv, name, &proc)
+            }
+        }
+    }
 
+    /// Apply uid, gid, pid enrichment to generic records
+    fn enrich_generic(&mut self, ty: MessageType, body: &mut Body) {
+        let mut nrv = Body::default();
         let mut ids = UserGroupIDs::default();
-
-        // Filter / collect
+        let drop_ids = self.drop_ids(ty);
         body.retain(|(k, v)| {
             match (k, v) {
-                (Key::Arg(_, None), v) => {
-                    argv.push(v.clone());
-                    return false;
-                }
-                (Key::ArgLen(_), _) => return false,
-                (Key::Common(Common::Arch), Value::Number(Number::Hex(n))) => {
-                    arch = Some(*n as u32);
-                    return !(self.settings.translate_universal && self.settings.drop_translated);
-                }
-                (Key::Common(Common::Syscall), Value::Number(Number::Dec(n))) => {
-                    syscall = Some(*n as u32);
-                    return !(self.settings.translate_universal && self.settings.drop_translated);
-                }
-                (Key::Common(Common::Pid), Value::Number(Number::Dec(n))) => {
-                    pid = *n as u32;
-                }
-                (Key::Common(Common::PPid), Value::Number(Number::Dec(n))) => {
-                    ppid = *n as u32;
-                }
-                (Key::Common(Common::Comm), Value::Str(s, _)) => comm = Some(*s),
-                (Key::Common(Common::Exe), Value::Str(s, _)) => exe = Some(*s),
-                (Key::Common(Common::Key), Value::Str(s, _)) => key = Some(*s),
                 (Key::NameUID(name), Value::Number(Number::Dec(n)))
                 | (Key::NameGID(name), Value::Number(Number::Dec(n))) => {
                     ids.push(name, *n as _);
-                    if self.settings.drop_translated {
+                    if drop_ids {
                         return false;
                     }
                 }
-                (Key::Name(name), Value::Str(_, _)) => {
-                    match name.as_ref() {
-                        b"ARCH" | b"SYSCALL" if self.settings.translate_universal => return false,
-                        _ => (),
-                    };
-                }
-                _ => {}
-            }
+                _ => self.enrich_pid(&mut nrv, k, v),
+            };
             true
         });
-        body.push((Key::Literal("ARGV"), Value::List(argv)));
-
-        // Determine syscall.
-        let mut arch_name = None;
-        let mut syscall_name = None;
-        if let (Some(arch), Some(syscall)) = (arch, syscall) {
-            arch_name = ARCH_NAMES.get(&arch);
-            if let Some(arch_name) = arch_name {
-                syscall_name = SYSCALL_NAMES
-                    .get(*arch_name)
-                    .and_then(|syscall_tbl| syscall_tbl.get(&syscall));
-                if let Some(syscall_name) = syscall_name {
-                    if syscall_name.starts_with("execve") {
-                        *is_exec = true;
-                    }
-                }
-            }
+        body.extend(nrv);
+        if self.translate_userdb(ty) {
+            self.add_record_userdb(body, &ids);
         }
+        if AUTH_TYPES.contains(&ty) {
+            self.add_auid_passwd(body, &ids);
+        }
+    }
 
-        let mut labels: HashSet<Vec<u8>> = HashSet::default();
-
-        if let Some(key) = key {
-            if self.settings.filter_keys.contains(key) {
-                *filter_event = true;
-            }
-            if self.settings.proc_label_keys.contains(key) {
-                labels.insert(key.to_vec());
+    /// Adds the configured user database fields for the audit uid
+    fn add_auid_passwd(&mut self, body: &mut Body, ids: &UserGroupIDs) {
+        if self.settings.enrich_auid_passwd.is_empty() || self.remote_node {
+            return;
+        }
+        let Some(auid) = ids.get(b"auid").filter(|id| *id != 0xffffffff) else {
+            return;
+        };
+        let mut values = vec![];
+        for field in &self.settings.enrich_auid_passwd {
+            let key = match field {
+                PasswdField::Shell => "shell",
+                PasswdField::Home => "home",
+                PasswdField::Gecos => "gecos",
+            };
+            match self.state.userdb.get_passwd_field(auid, *field) {
+                Some(v) => values.push((key, v)),
+                None => return,
             }
-        } else if self.settings.filter_null_keys {
-            *filter_event = true;
         }
+        let m = values
+            .iter()
+            .map(|(k, v)| (Key::Literal(k), Value::from(v.as_bytes())))
+            .collect();
+        body.push((Key::Literal("AUID_PASSWD"), Value::Map(m)));
+    }
 
-        let (first_per_process, proc) = match (
-            *is_exec,
-            self.state
-                .processes
-                .get_pid(pid)
-                .filter(|p| p.pid == pid && p.ppid == ppid && p.exe.as_deref() == exe),
-        ) {
-            (false, Some(proc)) => (false, proc.clone()),
-            (_, proc) => {
-                let is_first = *is_exec || proc.is_none();
-
-                if let Some(pre_exec_proc) = self
-                    .state
-                    .processes
-                    .get_pid(pid)
-                    .and_then(|p| (ppid == p.ppid).then_some(p))
-                {
-                    self.propagate_labels(pre_exec_proc, &mut labels);
+    /// Transform PROCTITLE record
+    ///
+    /// The flat proctitle field is turned into a list.
+    fn transform_proctitle(&mut self, rv: &mut Body) {
+        let mut argv = vec![];
+        rv.retain(|(k, v)| {
+            match (k, v) {
+                (k, Value::Str(r, _)) if k == "proctitle" => {
+                    argv = r
+                        .split(|c| *c == 0)
+                        .map(|arg| {
+                            // (assumed) safety:
+                            // We are adding references to the
+                            // same memory regions back to rv.
+                            let arg = unsafe {
+                                &*std::ptr::slice_from_raw_parts(arg.as_ptr(), arg.len())
+                            };
+                            Value::Str(arg, Quote::None)
+                        })
+                        .collect();
+                    false
                 }
+                _ => true,
+            }
+        });
+        if !argv.is_empty() {
+            rv.push(("ARGV".into(), Value::List(argv)));
+        }
+    }
 
-                let parent_proc = self.state.processes.get_or_retrieve(ppid).cloned();
-                let parent = parent_proc.as_ref().map(|p| p.key);
-
-                if let Some(ref p) = parent_proc {
-                    self.propagate_labels(p, &mut labels)
-                }
-                if let Some(exe) = exe {
-                    self.label_exe(exe, &mut labels)
+    /// Enrich SOCKADDR record
+    ///
+    /// This function also determines whether the record should be filtered
+    fn enrich_sockaddr(&mut self, rv: &mut Body, is_filtered: &mut bool) {
+        let mut nrv = Body::default();
+        let translate = self.translate_universal(MessageType::SOCKADDR);
+        let drop_raw = self.settings.drop_translated;
+        rv.retain(|(k, v)| match (k, v) {
+            (k, Value::Str(vr, _q)) => {
+                if k == "saddr" {
+                    #[cfg(target_os = "linux")]
+                    if let Ok(sa) = SocketAddr::parse(vr) {
+                        // There's no need to enrich saddr entries
+                        // that will be dropped, but the raw data
+                        // should be kept in case filter.filter-action
+                        // is set to "log".
+                        if *is_filtered {
+                            return true;
+                        } else if self.settings.filter_sockaddr.iter().any(|f| f.matches(&sa)) {
+                            *is_filtered = true;
+                            return true;
+                        }
+                        if translate {
+                            add_translated_socketaddr(&mut nrv, sa);
+                            // Like other raw values, saddr is kept
+                            // next to its translation unless
+                            // translate.drop-raw is set.
+                            return !drop_raw;
+                        } else {
+                            return true;
+                        }
+                    }
+                } else if k == "SADDR" && translate || *is_filtered {
+                    // If we do our own enrichment, drop pre-existing
+                    // enriched SOCKADDR.saddr enrichment.
+                    return false;
                 }
+                true
+            }
+            _ => true,
+        });
+        rv.extend(nrv);
+    }
 
-                let mut new_proc = Process {
-                    key: ProcessKey::Event(id),
-                    parent,
-                    pid,
-                    ppid,
-                    labels,
-                    exe: exe.map(Vec::from),
-                    comm: comm.map(Vec::from),
-                    ..Process::default()
-                };
+    /// Enrich URINGOP record
+    fn enrich_uringop(&mut self, body: &mut Body) {
+        let mut nrv = Body::default();
 
-                #[cfg(all(feature = "procfs", target_os = "linux"))]
-                if self.settings.enrich_container || self.settings.enrich_systemd {
-                    let mut container_info: Option<ContainerInfo> = None;
-                    let mut systemd_service: Option<Vec<Vec<u8>>> = None;
-                    let cgroup = procfs::parse_proc_pid_cgroup(pid).ok().flatten();
-                    if self.settings.enrich_container {
-                        container_info = match cgroup {
-                            Some(ref path) => {
-                                proc::try_extract_container_id(path).map(|id| ContainerInfo { id })
-                            }
-                            _ => parent_proc.as_ref().and_then(|p| p.container_info.clone()),
-                        };
+        let mut ids = UserGroupIDs::default();
+        let translate = self.translate_universal(MessageType::URINGOP);
+        let drop_ids = self.drop_ids(MessageType::URINGOP);
+
+        body.retain(|(k, v)| {
+            match (k, v) {
+                (Key::NameUID(name), Value::Number(Number::Dec(n)))
+                | (Key::NameGID(name), Value::Number(Number::Dec(n))) => {
+                    ids.push(name, *n as _);
+                    if drop_ids {
+                        return false;
                     }
-                    if self.settings.enrich_systemd {
-                        systemd_service = match cgroup {
-                            Some(ref path) => proc::try_extract_systemd_service(path),
-                            _ => parent_proc.as_ref().and_then(|p| p.systemd_service.clone()),
-                        };
+                }
+                (Key::Name(name), Value::Number(Number::Dec(op)))
+                    if translate && k == "uring_op" =>
+                {
+                    if let Some(Some(op_name)) = URING_OPS.get(*op as usize) {
+                        nrv.push((Key::NameTranslated(name.clone()), Value::from(*op_name)));
                     }
-                    new_proc.container_info = container_info;
-                    new_proc.systemd_service = systemd_service;
                 }
-
-                self.state.processes.insert(new_proc.clone());
-                (is_first, new_proc)
+                _ => {}
             }
-        };
+            true
+        });
 
-        if proc
-            .labels
-            .intersection(&self.settings.filter_labels)
-            .next()
-            .is_some()
-        {
-            *filter_event = true;
-        }
+        body.extend(nrv);
 
-        // TODO: This logic needs to be split.
-        if first_per_process && !self.settings.filter_first_per_process {
-            *filter_event = false;
+        if self.translate_userdb(MessageType::URINGOP) {
+            self.add_record_userdb(body, &ids);
         }
+    }
 
-        *process_key = Some(proc.key);
+    /// Translate response, rule number, and trust values of FANOTIFY
+    /// records that are written for permission events answered by
+    /// fanotify listeners
+    fn enrich_fanotify(&mut self, body: &mut Body) {
+        if !self.translate_universal(MessageType::FANOTIFY) {
+            return;
+        }
+        let mut nrv = Body::default();
+        body.retain(|(k, v)| {
+            let Key::Name(name) = k else {
+                return true;
+            };
+            let translated = match (&name[..], v) {
+                // FAN_AUDIT and FAN_INFO may be set in addition to
+                // FAN_ALLOW or FAN_DENY.
+                (b"resp", Value::Number(Number::Dec(n))) => match n & 0x3 {
+                    1 => Value::Literal("allow"),
+                    2 => Value::Literal("deny"),
+                    _ => return true,
+                },
+                (b"fan_type", Value::Number(Number::Dec(n))) => match n {
+                    0 => Value::Literal("none"),
+                    1 => Value::Literal("audit_rule"),
+                    _ => return true,
+                },
+                // The rule number is written in hexadecimal, without
+                // prefix.
+                (b"fan_info", Value::Number(Number::Dec(n))) => {
+                    match i64::from_str_radix(&n.to_string(), 16) {
+                        Ok(n) => Value::from(n),
+                        Err(_) => return true,
+                    }
+                }
+                (b"fan_info", Value::Number(Number::Hex(n))) => Value::from(*n as i64),
+                (b"fan_info", Value::Str(s, _)) => {
+                    match std::str::from_utf8(s).map(|s| i64::from_str_radix(s, 16)) {
+                        Ok(Ok(n)) => Value::from(n),
+                        _ => return true,
+                    }
+                }
+                (b"subj_trust" | b"obj_trust", Value::Number(Number::Dec(n))) => match n {
+                    0 => Value::Literal("no"),
+                    1 => Value::Literal("yes"),
+                    2 => Value::Literal("unknown"),
+                    _ => return true,
+                },
+                _ => return true,
+            };
+            nrv.push((Key::NameTranslated(name.clone()), translated));
+            true
+        });
+        body.extend(nrv);
+    }
 
-        // No point in adding translations / enrichments to record if
-        // we are going to filter anyway.
-        if *filter_event {
+    /// Decode TIME_INJOFFSET and TIME_ADJNTPVAL records that are
+    /// written when the system clock is adjusted
+    ///
+    /// Adds the affected clock, the injected offset in nanoseconds,
+    /// and the difference between old and new NTP values. Frequency
+    /// values are converted from the kernel's scaled representation
+    /// to parts per billion, changes to the status word are listed as
+    /// flags.
+    fn enrich_time_change(&mut self, ty: MessageType, body: &mut Body) {
+        if !self.translate_universal(ty) {
             return;
         }
+        // Negative values are not recognized as numbers by the parser.
+        let num = |name: &str| match body.get(name) {
+            Some(Value::Number(Number::Dec(n))) => Some(*n),
+            Some(Value::Str(s, _)) => std::str::from_utf8(s).ok()?.parse::<i64>().ok(),
+            _ => None,
+        };
+        let flags = |bits: i64| {
+            NTP_STATUS_FLAGS
+                .iter()
+                .filter(|(bit, _)| bits & bit != 0)
+                .map(|(_, name)| Value::Literal(name))
+                .collect::<Vec<_>>()
+        };
+        let mut nrv = Body::default();
+        nrv.push((Key::Literal("CLOCK"), Value::Literal("realtime")));
+        if ty == MessageType::TIME_INJOFFSET {
+            if let (Some(sec), Some(nsec)) = (num("sec"), num("nsec")) {
+                let offset = sec.saturating_mul(1_000_000_000).saturating_add(nsec);
+                nrv.push((Key::Literal("OFFSET_NS"), Value::from(offset)));
+            }
+        } else if let (Some(Value::Str(op, _)), Some(old), Some(new)) =
+            (body.get("op"), num("old"), num("new"))
+        {
+            nrv.push((Key::Literal("DELTA"), Value::from(new.wrapping_sub(old))));
+            match *op {
+                // time_freq is kept in ns/s, shifted by NTP_SCALE_SHIFT.
+                b"freq" => {
+                    nrv.push((Key::Literal("OLD_PPB"), Value::from(old >> 32)));
+                    nrv.push((Key::Literal("NEW_PPB"), Value::from(new >> 32)));
+                }
+                b"status" => {
+                    nrv.push((Key::Literal("STATUS_SET"), Value::List(flags(new & !old))));
+                    nrv.push((
+                        Key::Literal("STATUS_CLEARED"),
+                        Value::List(flags(old & !new)),
+                    ));
+                }
+                _ => {}
+            }
+        }
+        body.extend(nrv);
+    }
 
-        if let (Some(arch_name), true) = (arch_name, self.settings.translate_universal) {
-            let key = match &self.settings.enrich_prefix {
-                Some(s) => Key::Name(NVec::from_iter(s.bytes().chain(b"arch".iter().cloned()))),
-                None => Key::Literal("ARCH"),
+    /// Apply built-in heuristics to exec events, label process.
+    fn analyze_exec(&mut self, ev: &Event) {
+        let Some(proc) = ev
+            .process_key
+            .and_then(|k| self.state.processes.get_key(&k))
+        else {
+            return;
+        };
+        let mut labels = vec![];
+
+        if let Some(s) = &self.settings.analyze_suspicious_shell {
+            let tty = match ev.body.get(&MessageType::SYSCALL) {
+                Some(EventValues::Single(rv)) => match rv.get("tty") {
+                    Some(Value::Str(tty, _)) => Some(*tty),
+                    _ => None,
+                },
+                _ => None,
             };
-            body.push((key, Value::Literal(arch_name)));
-        }
-        if let (Some(syscall_name), true) = (syscall_name, self.settings.translate_universal) {
-            let key = match &self.settings.enrich_prefix {
-                Some(s) => Key::Name(NVec::from_iter(s.bytes().chain(b"syscall".iter().cloned()))),
-                None => Key::Literal("SYSCALL"),
+            let argv: Vec<Vec<u8>> = match ev.body.get(&MessageType::EXECVE) {
+                Some(EventValues::Single(rv)) => match rv.get("ARGV") {
+                    Some(Value::List(args)) => args
+                        .iter()
+                        .map(|v| v.clone().try_into().unwrap_or_default())
+                        .collect(),
+                    _ => vec![],
+                },
+                _ => vec![],
             };
-            body.push((key, Value::Literal(syscall_name)));
+            let parent = proc.parent.and_then(|k| self.state.processes.get_key(&k));
+            if let (Some(exe), Some(parent)) = (&proc.exe, parent) {
+                if s.matches(exe, tty, &argv, parent) {
+                    labels.push(s.label.as_bytes().to_vec());
+                }
+            }
         }
 
-        if self.settings.enrich_pid {
-            self.add_record_procinfo(body, b"pid", &proc);
-            if let Some(parent_process) = proc
-                .parent
-                .and_then(|key| self.state.processes.get_key(&key))
-            {
-                self.add_record_procinfo(body, b"ppid", parent_process);
+        if let (Some(e), Some(exe)) = (&self.settings.analyze_exec_from_tmp, &proc.exe) {
+            let modes: Vec<u64> = match ev.body.get(&MessageType::PATH) {
+                Some(EventValues::Multi(paths)) => paths
+                    .iter()
+                    .filter_map(|p| match p.get("mode") {
+                        Some(Value::Number(Number::Oct(m))) => Some(*m),
+                        _ => None,
+                    })
+                    .collect(),
+                _ => vec![],
+            };
+            if e.matches(exe, &modes) {
+                labels.push(e.label.as_bytes().to_vec());
             }
         }
 
-        if self.settings.translate_userdb {
-            self.add_record_userdb(body, &ids);
+        self.analyzer_hits += labels.len() as u64;
+        if let Some(proc) = ev
+            .process_key
+            .and_then(|k| self.state.processes.get_key_mut(&k))
+        {
+            proc.labels.extend(labels);
         }
+    }
 
-        if self.settings.enrich_uid_groups {
-            if let Some(names) = ids
-                .uid
-                .and_then(|uid| self.state.userdb.get_user_groups(uid))
-            {
-                body.push((
-                    Key::Literal("UID_GROUPS"),
-                    Value::List(names.iter().map(|n| Value::from(n.as_bytes())).collect()),
-                ));
+    /// Update the audit configuration state from `auditd` lifecycle
+    /// and `CONFIG_CHANGE` records, add it as `LAUREL_AUDIT_CONFIG`,
+    /// and label the process that has changed the configuration.
+    fn analyze_audit_config(&mut self, ev: &mut Event) {
+        let mut updated = false;
+        for (ty, values) in &ev.body {
+            match values {
+                EventValues::Single(rv) => updated |= self.state.audit_config.update(*ty, rv),
+                EventValues::Multi(rvs) => {
+                    for rv in rvs {
+                        updated |= self.state.audit_config.update(*ty, rv);
+                    }
+                }
             }
         }
-
-        #[cfg(all(feature = "procfs", target_os = "linux"))]
-        if let Some(exe) = exe {
-            self.enrich_exe_hash(body, pid, exe);
+        if !updated {
+            return;
+        }
+        ev.laurel.insert(
+            "LAUREL_AUDIT_CONFIG",
+            EventValues::Single(self.state.audit_config.to_body()),
+        );
+        if !ev.body.contains_key(&MessageType::CONFIG_CHANGE) {
+            return;
+        }
+        let Some(ac) = &self.settings.analyze_audit_config else {
+            return;
+        };
+        if let Some(proc) = ev
+            .process_key
+            .and_then(|k| self.state.processes.get_key_mut(&k))
+        {
+            if proc.labels.insert(ac.label.as_bytes().to_vec()) {
+                self.analyzer_hits += 1;
+            }
         }
     }
 
-    #[cfg(all(feature = "procfs", target_os = "linux"))]
-    fn enrich_exe_hash(&mut self, rv: &mut Body, pid: u32, exe: &[u8]) {
-        let Some(ref mut cache) = self.exe_hash_cache else {
+    /// Add ATT&CK technique IDs of all matching rules as
+    /// `SYSCALL.TAGS`, IDs of matching Sigma rules as `SYSCALL.SIGMA`.
+    fn analyze_tags(&mut self, ev: &mut Event) {
+        let Some(EventValues::Single(rv)) = ev.body.get(&MessageType::SYSCALL) else {
             return;
         };
-        let Ok(fd) = procfs::open_pid_exe_meta(pid) else {
-            return;
+        let exe = match rv.get("exe") {
+            Some(Value::Str(exe, _)) => Some(*exe),
+            _ => None,
         };
-        let Ok(path) = procfs::get_pid_exe_link(pid) else {
-            return;
+        let argv: Option<Vec<u8>> = match ev.body.get(&MessageType::EXECVE) {
+            Some(EventValues::Single(rv)) => match rv.get("ARGV") {
+                Some(Value::List(args)) => Some(
+                    args.iter()
+                        .map(|v| v.clone().try_into().unwrap_or_default())
+                        .collect::<Vec<Vec<u8>>>()
+                        .join(&b' '),
+                ),
+                _ => None,
+            },
+            _ => None,
         };
-        let Ok(meta) = fd.metadata() else {
-            return;
+        let paths: Vec<&[u8]> = match ev.body.get(&MessageType::PATH) {
+            Some(EventValues::Multi(paths)) => paths
+                .iter()
+                .filter_map(|p| match p.get("name") {
+                    Some(Value::Str(name, _)) => Some(*name),
+                    _ => None,
+                })
+                .collect(),
+            _ => vec![],
         };
-        if path.as_os_str().as_bytes() != exe {
-            return;
+        #[cfg(feature = "sigma")]
+        let (categories, fields) = match self.settings.analyze_sigma.is_empty() {
+            true => (vec![], vec![]),
+            false => self.sigma_fields(ev, rv, exe, argv.as_deref()),
         };
-        if meta.len() > self.settings.enrich_exe_hash_size_limit {
-            return;
+        let mut techniques: Vec<&str> = vec![];
+        for rule in self.settings.analyze_attack.iter().flatten() {
+            if !techniques.contains(&rule.technique.as_str())
+                && rule.matches(ev.syscall, exe, argv.as_deref(), &paths)
+            {
+                techniques.push(&rule.technique);
+            }
         }
-
-        let cache_key = (meta.dev(), meta.ino(), meta.mtime_nsec());
-        let hash = match cache.get(&cache_key) {
-            Some(h) => h,
-            None => {
-                let mut hasher = Sha256Writer::default();
-                // Ensure that we don't "accidentally" read more than
-                // the configured limit allows.
-                let mut limited_fd = fd.take(meta.len());
-                let Ok(_) = std::io::copy(&mut limited_fd, &mut hasher) else {
-                    return;
-                };
-                let h = hasher.finalize();
-                cache.insert(cache_key, h);
-                h
+        #[cfg(feature = "sigma")]
+        let sigma = self.match_sigma(&categories, &fields, &mut techniques);
+        #[cfg(not(feature = "sigma"))]
+        let sigma: Vec<&str> = vec![];
+        let hits = (techniques.len() + sigma.len()) as u64;
+        let tags: Vec<Value> = techniques
+            .into_iter()
+            .map(|t| Value::from(t.to_string()))
+            .collect();
+        let sigma: Vec<Value> = sigma
+            .into_iter()
+            .map(|s| Value::from(s.to_string()))
+            .collect();
+        self.analyzer_hits += hits;
+        if let Some(EventValues::Single(rv)) = ev.body.get_mut(&MessageType::SYSCALL) {
+            if !tags.is_empty() {
+                rv.push((Key::Literal("TAGS"), Value::List(tags)));
             }
-        };
-        rv.push((Key::Literal("EXE_HASH"), hex_string(&hash).into()));
+            if !sigma.is_empty() {
+                rv.push((Key::Literal("SIGMA"), Value::List(sigma)));
+            }
+        }
     }
 
-    /// Ingest a log line and add it to the coalesce object.
-    ///
-    /// Simple one-liner events are emitted immediately.
-    ///
-    /// For complex multi-line events (SYSCALL + additional
-    /// information), corresponding records are collected. The entire
-    /// event is emitted only when an EOE ("end of event") line for
-    /// the event is encountered.
-    pub fn process_line(&mut self, line: &[u8]) -> Result<(), CoalesceError> {
-        let mut do_filter = self.settings.filter_raw_lines.is_match(line);
-
-        let skip_enriched = self.settings.translate_universal && self.settings.translate_userdb;
-        let mut msg = parse(line, skip_enriched).map_err(CoalesceError::Parse)?;
-        let event_key = EventKey(msg.node.clone(), msg.id);
-
-        // clean out state every EXPIRE_PERIOD
-        match self.next_expire {
-            Some(t) if t < msg.id.timestamp => {
-                self.expire_inflight(msg.id.timestamp);
-                self.expire_done(msg.id.timestamp);
-                self.state.processes.expire();
-                self.next_expire = Some(msg.id.timestamp + EXPIRE_PERIOD)
+    /// Returns the IDs of the Sigma rules that match, adds their
+    /// ATT&CK techniques to `techniques`
+    #[cfg(feature = "sigma")]
+    fn match_sigma<'s>(
+        &'s self,
+        categories: &[sigma::Category],
+        fields: &sigma::Fields,
+        techniques: &mut Vec<&'s str>,
+    ) -> Vec<&'s str> {
+        let mut sigma = vec![];
+        for rule in &self.settings.analyze_sigma {
+            if categories.iter().any(|c| rule.matches(*c, fields)) {
+                sigma.push(rule.id.as_str());
+                for t in &rule.techniques {
+                    if !techniques.contains(&t.as_str()) {
+                        techniques.push(t);
+                    }
+                }
             }
-            None => self.next_expire = Some(msg.id.timestamp + EXPIRE_PERIOD),
-            _ => (),
-        };
-
-        let mut is_exec = false;
-        let mut process_key = None;
-        if msg.ty == MessageType::SYSCALL {
-            self.handle_syscall(
-                msg.id,
-                &mut msg.body,
-                &mut do_filter,
-                &mut is_exec,
-                &mut process_key,
-            );
         }
+        sigma
+    }
 
-        if msg.ty == MessageType::EOE {
-            if self.state.done.contains(&event_key) {
-                return Err(CoalesceError::DuplicateEvent(msg.id));
-            }
-            let ev = self
-                .state
-                .inflight
-                .remove(&event_key)
-                .ok_or(CoalesceError::SpuriousEOE(msg.id))?;
-            self.emit_event(ev);
-        } else if msg.ty.is_multipart() {
-            // kernel-level messages
-            if !self.state.inflight.contains_key(&event_key) {
-                self.state
-                    .inflight
-                    .insert(event_key.clone(), Event::new(msg.node, msg.id));
+    /// Returns the Sigma categories of `ev` and the values of the
+    /// supported fields
+    #[cfg(feature = "sigma")]
+    fn sigma_fields<'f>(
+        &mut self,
+        ev: &'f Event,
+        rv: &'f Body,
+        exe: Option<&'f [u8]>,
+        argv: Option<&'f [u8]>,
+    ) -> (Vec<sigma::Category>, sigma::Fields<'f>) {
+        let mut categories = vec![];
+        let mut fields: sigma::Fields = vec![];
+        let num = |name: &str| match rv.get(name) {
+            Some(Value::Number(Number::Dec(n))) => Some(*n),
+            _ => None,
+        };
+        if let Some(exe) = exe {
+            fields.push(("Image", exe.into()));
+        }
+        if let Some(argv) = argv {
+            fields.push(("CommandLine", argv.into()));
+        }
+        for (name, field) in [("pid", "ProcessId"), ("ppid", "ParentProcessId")] {
+            if let Some(n) = num(name) {
+                fields.push((field, n.to_string().into_bytes().into()));
             }
-            let ev = self.state.inflight.get_mut(&event_key).unwrap();
-            ev.is_filtered |= do_filter;
-            ev.is_exec |= is_exec;
-            if process_key.is_some() {
-                ev.process_key = process_key;
+        }
+        if let Some(user) = num("uid").and_then(|uid| self.state.userdb.get_user(uid as u32)) {
+            fields.push(("User", user.into_bytes().into()));
+        }
+        let parent = ev
+            .process_key
+            .and_then(|k| self.state.processes.get_key(&k))
+            .and_then(|p| p.parent)
+            .and_then(|k| self.state.processes.get_key(&k))
+            .and_then(|p| p.exe.clone());
+        if let Some(exe) = parent {
+            fields.push(("ParentImage", exe.as_ref().to_vec().into()));
+        }
+        let cwd = match ev.body.get(&MessageType::CWD) {
+            Some(EventValues::Single(rv)) => match rv.get("cwd") {
+                Some(Value::Str(cwd, _)) => Some(*cwd),
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some(cwd) = cwd {
+            fields.push(("CurrentDirectory", cwd.into()));
+        }
+        if ev.is_exec {
+            categories.push(sigma::Category::ProcessCreation);
+        }
+        if let Some(EventValues::Multi(paths)) = ev.body.get(&MessageType::PATH) {
+            for p in paths {
+                if !matches!(p.get("nametype"), Some(Value::Str(b"CREATE", _))) {
+                    continue;
+                }
+                let Some(Value::Str(name, _)) = p.get("name") else {
+                    continue;
+                };
+                let name: Cow<[u8]> = match cwd {
+                    Some(cwd) if !name.starts_with(b"/") => [cwd, b"/", *name].concat().into(),
+                    _ => (*name).into(),
+                };
+                fields.push(("TargetFilename", name));
+            }
+            if fields.iter().any(|(k, _)| *k == "TargetFilename") {
+                categories.push(sigma::Category::FileEvent);
             }
+        }
+        (categories, fields)
+    }
 
-            match ev.body.get_mut(&msg.ty) {
-                Some(EventValues::Single(v)) => v.extend(msg.body),
-                Some(EventValues::Multi(v)) => v.push(msg.body),
-                None => match msg.ty {
-                    MessageType::SYSCALL => {
-                        ev.body.insert(msg.ty, EventValues::Single(msg.body));
+    /// Enrich SYSCALL record
+    ///
+    /// Add ARCH, SYSCALL, PID, PPID, SCRIPT, NS_PID, NS_PPID, LABELS if
+    /// appropriate
+    fn enrich_syscall(
+        &mut self,
+        rv: &mut Body,
+        process_key: Option<ProcessKey>,
+        script: &Option<NVec>,
+        container_info: &mut Option<Body>,
+    ) {
+        #[cfg(all(feature = "procfs", target_os = "linux"))]
+        if let (true, Some(script)) = (self.settings.enrich_script, &script) {
+            rv.push((
+                Key::Literal("SCRIPT"),
+                Value::Str(script.as_slice(), Quote::None),
+            ));
+        }
+
+        if let Some(proc) = process_key.and_then(|k| self.state.processes.get_key(&k)) {
+            #[cfg(all(feature = "procfs", target_os = "linux"))]
+            if let (true, Some(c)) = (self.settings.enrich_container, &proc.container_info) {
+                let mut ci = Body::default();
+                ci.push((
+                    Key::Literal("ID"),
+                    Value::Str(hex_string(&c.id).as_bytes(), Quote::None),
+                ));
+                *container_info = Some(ci);
+            }
+
+            #[cfg(all(feature = "procfs", target_os = "linux"))]
+            if let (true, Some(unit)) = (self.settings.enrich_systemd_unit, &proc.systemd_unit) {
+                rv.push((Key::Literal("UNIT"), Value::Str(unit, Quote::None)));
+            }
+
+            #[cfg(all(feature = "procfs", target_os = "linux"))]
+            if let (true, Some(ns_pid)) = (self.settings.enrich_ns_pid, proc.ns_pid) {
+                rv.push((Key::Literal("NS_PID"), Value::from(ns_pid.pid as i64)));
+                // The parent's pid is only meaningful within the
+                // same namespace.
+                if let Some(ns_ppid) = proc
+                    .parent
+                    .and_then(|k| self.state.processes.get_key(&k))
+                    .and_then(|p| p.ns_pid)
+                    .filter(|p| p.ns == ns_pid.ns)
+                {
+                    rv.push((Key::Literal("NS_PPID"), Value::from(ns_ppid.pid as i64)));
+                }
+            }
+
+            if !proc.labels.is_empty() {
+                let labels = proc
+                    .labels
+                    .iter()
+                    .map(|l| Value::Str(l, Quote::None))
+                    .collect::<Vec<_>>();
+                rv.push((Key::Literal("LABELS"), Value::List(labels)));
+            }
+        }
+
+        if let (true, Some(lineage)) = (
+            self.settings.enrich_lineage,
+            process_key.and_then(|k| self.state.processes.lineage(&k)),
+        ) {
+            rv.push((Key::Literal("LINEAGE"), Value::from(hex_string(&lineage))));
+        }
+    }
+
+    fn transform_execve(&mut self, rv: &mut Body, process_key: Option<ProcessKey>) {
+        let mut argv: Vec<Value> = Vec::with_capacity(rv.len() - 1);
+        rv.retain(|(k, v)| {
+            match k {
+                Key::ArgLen(_) => false,
+                Key::Arg(i, None) => {
+                    let idx = *i as usize;
+                    if argv.len() <= idx {
+                        argv.resize(idx + 1, Value::Empty);
+                    };
+                    argv[idx] = v.clone();
+                    false
+                }
+                Key::Arg(i, Some(f)) => {
+                    let idx = *i as usize;
+                    if argv.len() <= idx {
+                        argv.resize(idx + 1, Value::Empty);
+                        argv[idx] = Value::Segments(Vec::new());
                     }
-                    MessageType::EXECVE | MessageType::PROCTITLE | MessageType::CWD => {
-                        ev.body.insert(msg.ty, EventValues::Single(msg.body));
+                    if let Some(Value::Segments(vs)) = argv.get_mut(idx) {
+                        let frag = *f as usize;
+                        let r = match v {
+                            Value::Str(r, _) => r,
+                            _ => todo!(),
+                        };
+                        if vs.len() <= frag {
+                            vs.resize(frag + 1, &[]);
+                            let ptr = std::ptr::slice_from_raw_parts(r.as_ptr(), r.len());
+                            // (assumed) safety: vs[frag] is only added back to rv
+                            vs[frag] = unsafe { &*ptr };
+                        }
+                    }
+                    false
+                }
+                _ => true,
+            }
+        });
+
+        if let Some(process_key) = process_key {
+            if self.settings.label_argv_count > 0
+                && self.settings.label_argv_bytes > 0
+                && (self.settings.label_argv.is_some() || self.settings.unlabel_argv.is_some())
+            {
+                let mut buf: Vec<u8> = Vec::with_capacity(self.settings.label_argv_bytes);
+
+                for arg in argv.iter().take(self.settings.label_argv_count) {
+                    if !buf.is_empty() {
+                        buf.push(b' ');
+                    }
+                    // FIXME TryFrom<&Value> needs to be implemented in linux-audit-parser
+                    let b: Vec<u8> = match arg.clone().try_into() {
+                        Ok(b) => b,
+                        Err(_) => continue,
+                    };
+                    if buf.len() + b.len() >= self.settings.label_argv_bytes {
+                        break;
+                    }
+                    buf.extend(b);
+                }
+
+                if let Some(ref mut proc) = self.state.processes.get_key_mut(&process_key) {
+                    if let Some(ref m) = self.settings.label_argv {
+                        for label in m.matches(&buf) {
+                            proc.labels.insert(label.into());
+                        }
+                    }
+
+                    if let Some(ref m) = self.settings.unlabel_argv {
+                        for label in m.matches(&buf) {
+                            proc.labels.remove(label);
+                        }
+                    }
+                }
+            }
+        }
+
+        // Derived fields are computed from the complete argument list.
+        let mut derived: Vec<(Key, Value)> = Vec::new();
+        if self.settings.execve_argv0_base || self.settings.execve_argv_flags {
+            let args: Vec<Vec<u8>> = argv
+                .iter()
+                .map(|v| v.clone().try_into().unwrap_or_default())
+                .collect();
+            if let (true, Some(arg0)) = (self.settings.execve_argv0_base, args.first()) {
+                let base = arg0.rsplit(|&c| c == b'/').next().unwrap_or_default();
+                derived.push((Key::Literal("ARGV0_BASE"), Value::Owned(base.to_vec())));
+            }
+            if self.settings.execve_argv_flags {
+                let flags = argv_flags(&args).into_iter().map(Value::Owned).collect();
+                derived.push((Key::Literal("ARGV_FLAGS"), Value::List(flags)));
+            }
+        }
+
+        // Strip data from the middle of excessively long ARGV
+        if let Some(argv_max) = self.settings.execve_argv_limit_bytes {
+            let argv_size: usize = argv.iter().map(|v| 1 + v.str_len()).sum();
+            if argv_size > argv_max {
+                let diff = argv_size - argv_max;
+                let skip_range = (argv_size - diff) / 2..(argv_size + diff) / 2;
+                argv = {
+                    let mut filtered = Vec::new();
+                    let mut start = 0;
+                    let mut skipped: Option<(usize, usize)> = None;
+                    for arg in argv.iter() {
+                        let end = start + arg.str_len();
+                        if skip_range.contains(&start) || skip_range.contains(&end) {
+                            skipped = match skipped {
+                                None => Some((1, end - start)),
+                                Some((args, bytes)) => Some((args + 1, 1 + bytes + (end - start))),
+                            };
+                        } else {
+                            if let Some((args, bytes)) = skipped {
+                                filtered.push(Value::Skipped((args, bytes)));
+                                skipped = None;
+                            }
+                            filtered.push(arg.clone());
+                        }
+                        start = end + 1;
+                    }
+                    filtered
+                };
+            }
+        }
+
+        // ARGV
+        if self.settings.execve_argv_list {
+            rv.push((Key::Literal("ARGV"), Value::List(argv.clone())));
+        }
+        // ARGV_STR
+        if self.settings.execve_argv_string {
+            rv.push((
+                Key::Literal("ARGV_STR"),
+                Value::StringifiedList(argv.clone()),
+            ));
+        }
+        // CMDLINE
+        if self.settings.execve_cmdline {
+            let mut buf: Vec<u8> = Vec::new();
+            for arg in argv.iter() {
+                if !buf.is_empty() {
+                    buf.push(b' ');
+                }
+                match arg {
+                    Value::Skipped((args, bytes)) => {
+                        buf.extend(format!("<<< Skipped: args={args}, bytes={bytes} >>>").bytes())
                     }
                     _ => {
-                        ev.body.insert(msg.ty, EventValues::Multi(vec![msg.body]));
+                        push_shell_quoted(&mut buf, &Vec::try_from(arg.clone()).unwrap_or_default())
                     }
-                },
-            };
-        } else {
-            // user-space messages
-            if self.state.done.contains(&event_key) {
-                return Err(CoalesceError::DuplicateEvent(msg.id));
+                }
+            }
+            rv.push((Key::Literal("CMDLINE"), Value::Owned(buf)));
+        }
+        for kv in derived {
+            rv.push(kv);
+        }
+
+        // ENV
+        #[cfg(all(feature = "procfs", target_os = "linux"))]
+        if let (Some(proc), false) = (
+            process_key.and_then(|k| self.state.processes.get_key(&k)),
+            self.settings.execve_env_exact.is_empty() && self.settings.execve_env_prefix.is_empty(),
+        ) {
+            if let Ok(vars) = self.state.processes.source().pid_environ(proc.pid, &|k| {
+                self.settings.execve_env_exact.contains(k)
+                    || self
+                        .settings
+                        .execve_env_prefix
+                        .iter()
+                        .any(|p| k.starts_with(p))
+            }) {
+                let map = vars
+                    .iter()
+                    .map(|(k, v)| {
+                        let v = match is_secret(k, &self.settings.execve_env_redact) {
+                            true => Value::Literal("[REDACTED]"),
+                            false => Value::Str(v, Quote::None),
+                        };
+                        (Key::Name(NVec::from(k.as_slice())), v)
+                    })
+                    .collect();
+                rv.push((Key::Literal("ENV"), Value::Map(map)));
             }
-            let mut ev = Event::new(msg.node, msg.id);
-            ev.is_filtered |= do_filter;
-            ev.body.insert(msg.ty, EventValues::Single(msg.body));
-            self.emit_event(ev);
         }
+    }
+
+    /// Rewrite event to normal form
+    ///
+    /// This function
+    /// - turns SYSCALL/a* fields into a single an ARGV list
+    /// - turns EXECVE/a* and EXECVE/a*[*] fields into an ARGV list
+    /// - turns PROCTITLE/proctitle into a (abbreviated) ARGV list
+    /// - translates *uid, *gid, syscall, arch, sockaddr if configured to do so.
+    /// - enriches PID and container enrichment if configured to do so.
+    /// - collects environment variables for EXECVE events
+    /// - registers process in shadow process table for EXECVE events
+    fn transform_event(&mut self, ev: &mut Event) {
+        #[cfg(all(feature = "procfs", target_os = "linux"))]
+        let mut proc = ev
+            .process_key
+            .as_ref()
+            .and_then(|p| self.state.processes.get_key(p).cloned());
+
+        if let Some(EventValues::Single(rv)) = ev.body.get_mut(&MessageType::EXECVE) {
+            self.transform_execve(rv, ev.process_key);
+        }
+
+        if ev.is_exec {
+            self.analyze_exec(ev);
+        }
+
+        if self.settings.analyze_audit_config.is_some() && !self.state.processes.remote {
+            self.analyze_audit_config(ev);
+        }
+
+        // PATH records are usually, but not necessarily, ordered by item
+        if let Some(EventValues::Multi(paths)) = ev.body.get_mut(&MessageType::PATH) {
+            paths.sort_by_key(|p| match p.get("item") {
+                Some(Value::Number(Number::Dec(n))) => *n,
+                _ => i64::MAX,
+            });
+        }
+
+        // Handle script enrichment
+        // TODO: Look up process per key.
+        #[cfg(all(feature = "procfs", target_os = "linux"))]
+        let script_in_time = !ev.is_exec
+            || !(self.settings.enrich_script || self.settings.label_script.is_some())
+            || self.within_deadline("script");
+        #[cfg(all(feature = "procfs", target_os = "linux"))]
+        let script: Option<NVec> = match (self.settings.enrich_script, &self.settings.label_script)
+        {
+            (false, None) => None,
+            _ if !script_in_time => None,
+            _ => match (&proc, ev.body.get(&MessageType::PATH), ev.is_exec) {
+                (Some(proc), Some(EventValues::Multi(paths)), true)
+                    if !self.state.processes.remote =>
+                {
+                    let mut cwd = &b"/"[..];
+                    if let Some(EventValues::Single(r)) = ev.body.get(&MessageType::CWD) {
+                        if let Some(Value::Str(rv, _)) = r.get("cwd") {
+                            cwd = rv;
+                        }
+                    };
+                    path_script_name(
+                        &paths[0],
+                        proc.pid,
+                        proc.ppid,
+                        cwd,
+                        &proc.exe.clone().unwrap_or_default(),
+                    )
+                }
+                _ => None,
+            },
+        };
+        #[cfg(not(all(feature = "procfs", target_os = "linux")))]
+        let script = None;
+
+        #[cfg(all(feature = "procfs", target_os = "linux"))]
+        if let (Some(ref mut proc), Some(script)) = (&mut proc, &script) {
+            if let Some(label_script) = &self.settings.label_script {
+                for label in label_script.matches(script.as_ref()) {
+                    proc.labels.insert(label.into());
+                }
+            }
+            if let Some(unlabel_script) = &self.settings.unlabel_script {
+                for label in unlabel_script.matches(script.as_ref()) {
+                    proc.labels.remove(label);
+                }
+            }
+        }
+
+        if let Some(EventValues::Multi(ref mut rvs)) = ev.body.get_mut(&MessageType::SOCKADDR) {
+            for rv in rvs {
+                self.enrich_sockaddr(rv, &mut ev.is_filtered)
+            }
+        }
+
+        if ev.is_filtered {
+            return;
+        }
+
+        if let Some(ratio) = ev.sample_ratio {
+            if !is_sampled(&ev.id, ratio) {
+                ev.is_filtered = true;
+                return;
+            }
+            if let Some(EventValues::Single(rv)) = ev.body.get_mut(&MessageType::SYSCALL) {
+                rv.push((Key::Literal("SAMPLE_RATIO"), (ratio as i64).into()));
+            }
+        }
+
+        if let (true, Some(syscall)) = (
+            self.settings.enrich_file_action || self.written_by_cache.is_some(),
+            ev.syscall,
+        ) {
+            let flags_arg = match syscall {
+                "open" => Some(1),
+                "openat" => Some(2),
+                _ => None,
+            };
+            let flags = match (flags_arg, ev.body.get(&MessageType::SYSCALL)) {
+                (Some(n), Some(EventValues::Single(rv))) => match rv.get("ARGV") {
+                    Some(Value::List(args)) => match args.get(n) {
+                        Some(Value::Number(Number::Hex(flags))) => Some(*flags),
+                        _ => None,
+                    },
+                    _ => None,
+                },
+                _ => None,
+            };
+            let writer = match &self.written_by_cache {
+                Some(_) => ev
+                    .process_key
+                    .and_then(|k| self.state.processes.get_key(&k).cloned()),
+                None => None,
+            };
+            if let Some(EventValues::Multi(paths)) = ev.body.get_mut(&MessageType::PATH) {
+                let mut first_delete = true;
+                for path in paths {
+                    let Some(Value::Str(nametype, _)) = path.get("nametype") else {
+                        continue;
+                    };
+                    let nametype = *nametype;
+                    let action = file_action(syscall, flags, nametype, first_delete);
+                    if nametype == b"DELETE" {
+                        first_delete = false;
+                    }
+                    if let (Some(Value::Str(dev, _)), Some(Value::Number(Number::Dec(inode)))) =
+                        (path.get("dev"), path.get("inode"))
+                    {
+                        let file = (dev.to_vec(), *inode as u64);
+                        let is_write = matches!(action, Some("created" | "truncated"))
+                            || (nametype == b"NORMAL" && flags.is_some_and(|f| f & O_ACCMODE != 0));
+                        match (is_write, &writer, &mut self.written_by_cache) {
+                            (true, Some(writer), Some(cache)) => cache.insert(file, writer.clone()),
+                            (false, _, Some(cache)) => {
+                                if let Some(writer) = cache.get(&file) {
+                                    self.add_record_procinfo(path, b"written_by", &writer);
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                    if let (true, Some(action)) = (self.settings.enrich_file_action, action) {
+                        let key = match &self.settings.enrich_prefix {
+                            Some(s) => Key::Name(NVec::from_iter(
+                                s.bytes().chain(b"file_action".iter().cloned()),
+                            )),
+                            None => Key::Literal("FILE_ACTION"),
+                        };
+                        path.push((key, Value::Literal(action)));
+                    }
+                }
+            }
+        }
+
+        #[cfg(all(feature = "procfs", target_os = "linux"))]
+        if let (true, false, Some(proc)) = (
+            self.settings.enrich_host_path,
+            self.state.processes.remote,
+            &proc,
+        ) {
+            if self.within_deadline("host_path") {
+                self.enrich_host_path(ev, proc.pid);
+            }
+        }
+
+        #[cfg(all(feature = "procfs", target_os = "linux"))]
+        if let (false, Some(proc)) = (self.state.processes.remote, &proc) {
+            if !self.settings.enrich_mount || self.within_deadline("mount") {
+                self.enrich_mount(ev, proc.pid);
+            }
+        }
+
+        if self.settings.enrich_target_process && self.within_deadline("target_process") {
+            self.enrich_target_process(ev);
+        }
+
+        #[cfg(all(feature = "procfs", target_os = "linux"))]
+        if self.settings.enrich_kernel_module && !self.state.processes.remote {
+            self.enrich_kernel_module(ev);
+        }
+
+        #[cfg(target_os = "linux")]
+        if !self.state.processes.remote {
+            self.enrich_login_session(ev);
+        }
+
+        if self.settings.translate_selinux {
+            for values in ev.body.values_mut() {
+                match values {
+                    EventValues::Single(rv) => add_selinux_contexts(rv),
+                    EventValues::Multi(rvs) => rvs.iter_mut().for_each(add_selinux_contexts),
+                }
+            }
+            if let (Some(syscall), Some(EventValues::Single(rv))) =
+                (ev.syscall, ev.body.get_mut(&MessageType::SYSCALL))
+            {
+                add_xattr_args(rv, syscall);
+            }
+        }
+
+        if let Some(nametypes) = &self.settings.path_nametypes {
+            if let Some(EventValues::Multi(paths)) = ev.body.get_mut(&MessageType::PATH) {
+                paths.retain(|p| {
+                    matches!(p.get("nametype"), Some(Value::Str(t, _)) if nametypes.contains(*t))
+                });
+                if paths.is_empty() {
+                    ev.body.shift_remove(&MessageType::PATH);
+                }
+            }
+        }
+
+        let mut container_info: Option<Body> = None;
+
+        for tv in ev.body.iter_mut() {
+            match tv {
+                (&MessageType::SYSCALL, EventValues::Single(rv)) => {
+                    self.enrich_syscall(rv, ev.process_key, &script, &mut container_info)
+                }
+                (&MessageType::EXECVE, EventValues::Single(_)) => {}
+                (&MessageType::PROCTITLE, EventValues::Single(rv)) => self.transform_proctitle(rv),
+                (&MessageType::URINGOP, EventValues::Multi(rvs)) => {
+                    rvs.iter_mut().for_each(|rv| self.enrich_uringop(rv))
+                }
+                (&MessageType::FANOTIFY, EventValues::Multi(rvs)) => {
+                    rvs.iter_mut().for_each(|rv| self.enrich_fanotify(rv))
+                }
+                (ty @ (&MessageType::TIME_ADJNTPVAL | &MessageType::TIME_INJOFFSET), values) => {
+                    let ty = *ty;
+                    match values {
+                        EventValues::Single(rv) => self.enrich_time_change(ty, rv),
+                        EventValues::Multi(rvs) => rvs
+                            .iter_mut()
+                            .for_each(|rv| self.enrich_time_change(ty, rv)),
+                    }
+                }
+                (ty, EventValues::Single(rv)) => self.enrich_generic(*ty, rv),
+                (ty, EventValues::Multi(rvs)) => {
+                    rvs.iter_mut().for_each(|rv| self.enrich_generic(*ty, rv))
+                }
+            }
+        }
+
+        if self.settings.enrich_container_info {
+            ev.container_info = container_info;
+        }
+
+        #[cfg(feature = "sigma")]
+        let sigma = !self.settings.analyze_sigma.is_empty();
+        #[cfg(not(feature = "sigma"))]
+        let sigma = false;
+        if self.settings.analyze_attack.is_some() || sigma {
+            self.analyze_tags(ev);
+        }
+    }
+
+    /// Do bookkeeping on event, transform, emit it via the provided
+    /// output function.
+    fn emit_event(&mut self, mut ev: Event<'ev>) {
+        self.state.done.insert(EventKey(ev.node.clone(), ev.id));
+
+        if let Some(preset) = ev.filter_preset.filter(|_| ev.is_filtered) {
+            *self.preset_filtered.entry(preset.name()).or_default() += 1;
+        }
+
+        if self.settings.summary_period.is_some() {
+            let keys: Vec<&[u8]> = match ev.body.get(&MessageType::SYSCALL) {
+                Some(EventValues::Single(rv)) => match rv.get("key") {
+                    Some(Value::Str(s, _)) => s.split(|c| *c == KEY_SEPARATOR).collect(),
+                    Some(Value::List(l)) => l
+                        .iter()
+                        .filter_map(|v| match v {
+                            Value::Str(s, _) => Some(*s),
+                            _ => None,
+                        })
+                        .collect(),
+                    _ => vec![],
+                },
+                _ => vec![],
+            };
+            self.summary.add_size(&keys, ev.size as u64);
+        }
+
+        if let Some(skew) = ev.clock_skew {
+            let mut rv = Body::default();
+            rv.push((Key::Literal("offset"), skew.into()));
+            ev.laurel
+                .insert("LAUREL_CLOCK_SKEW", EventValues::Single(rv));
+        }
+
+        for (name, fields) in &self.settings.sections {
+            let mut rv = Body::default();
+            for (k, v) in fields {
+                rv.push((Key::Name(NVec::from(k.as_slice())), Value::Owned(v.clone())));
+            }
+            ev.sections.insert(name.clone(), rv);
+        }
+
+        if self.settings.enrich_host_metadata == HostMetadata::All
+            && !self.is_remote_node(ev.node.as_deref())
+        {
+            self.add_host_metadata(&mut ev);
+        }
+
+        let node = ev.node.clone();
+        let group = self.with_node_processes(node.as_deref(), |c| {
+            if c.settings.enrich_entity_id {
+                c.entity_node = match ev.node.as_ref().or(c.settings.local_node.as_ref()) {
+                    Some(node) => node.clone(),
+                    None => c
+                        .hostname
+                        .get_or_insert_with(|| {
+                            nix::unistd::gethostname()
+                                .map(|h| h.as_bytes().to_vec())
+                                .unwrap_or_default()
+                        })
+                        .clone(),
+                };
+            }
+            c.id_names.clear();
+            c.remote_node = c.is_remote_node(ev.node.as_deref());
+            c.start_enrichment(ev.enrich_deadline.or_else(|| c.new_enrich_deadline()));
+            #[cfg(all(feature = "procfs", target_os = "linux"))]
+            c.resolve_exe_hash(&mut ev);
+            c.transform_event(&mut ev);
+            add_enrichment_incomplete(&mut ev, std::mem::take(&mut c.enrich_skipped));
+            c.track_entities(&ev);
+            c.command_group_key(&ev)
+        });
+        match group {
+            Some(key) => self.add_to_command_group(key, ev),
+            None => self.output(&ev),
+        }
+    }
+
+    /// Records the process and the files of `ev` for entity records
+    fn track_entities(&mut self, ev: &Event) {
+        if self.entity_fn.is_none() || ev.is_filtered {
+            return;
+        }
+        let node = ev.node.as_deref();
+        if self.settings.entity_processes {
+            if let Some(proc) = ev
+                .process_key
+                .and_then(|k| self.state.processes.get_key(&k))
+            {
+                self.entities.process(node, proc, ev.id);
+            }
+        }
+        if !self.settings.entity_files {
+            return;
+        }
+        let Some(EventValues::Multi(paths)) = ev.body.get(&MessageType::PATH) else {
+            return;
+        };
+        let cwd = match ev.body.get(&MessageType::CWD) {
+            Some(EventValues::Single(r)) => match r.get("cwd") {
+                Some(Value::Str(cwd, _)) => Some(*cwd),
+                _ => None,
+            },
+            _ => None,
+        };
+        for path in paths {
+            let Some(Value::Str(name, _)) = path.get("name") else {
+                continue;
+            };
+            match cwd {
+                Some(cwd) if !name.starts_with(b"/") => {
+                    let mut abs = cwd.to_vec();
+                    if !abs.ends_with(b"/") {
+                        abs.push(b'/');
+                    }
+                    abs.extend(*name);
+                    self.entities.file(node, &abs, ev.id);
+                }
+                _ => self.entities.file(node, name, ev.id),
+            }
+        }
+    }
+
+    /// Writes entity records for processes and files that have not
+    /// been seen since `entity_idle_timeout`.
+    fn expire_entities(&mut self, now: u64) {
+        let Some(entity_fn) = &mut self.entity_fn else {
+            return;
+        };
+        let before = now.saturating_sub(self.settings.entity_idle_timeout);
+        for entity in self.entities.expire(before) {
+            entity_fn(&entity);
+        }
+    }
+
+    /// Returns the key of the shell process that has started the
+    /// command of an exec event, if command groups are enabled.
+    fn command_group_key(&self, ev: &Event) -> Option<ProcessKey> {
+        let settings = self.settings.command_groups.as_ref()?;
+        if !ev.is_exec || ev.is_filtered || self.state.processes.remote {
+            return None;
+        }
+        let proc = self.state.processes.get_key(ev.process_key.as_ref()?)?;
+        let parent = self.state.processes.get_key(proc.parent.as_ref()?)?;
+        let base = parent.exe.as_deref()?.rsplit(|&c| c == b'/').next()?;
+        settings
+            .shells
+            .iter()
+            .any(|s| s.as_bytes() == base)
+            .then_some(parent.key)
+    }
+
+    fn add_to_command_group(&mut self, key: ProcessKey, ev: Event<'ev>) {
+        let group = self.command_groups.entry(key).or_insert(CommandGroup {
+            first: ev.id.timestamp,
+            events: vec![],
+        });
+        group.events.push(ev);
+        if group.events.len() >= COMMAND_GROUP_MAX_EVENTS {
+            let group = self.command_groups.remove(&key).unwrap();
+            self.emit_command_group(key, group);
+        }
+    }
+
+    /// Emits command groups whose time window has passed.
+    fn expire_command_groups(&mut self, now: u64) {
+        let window = match &self.settings.command_groups {
+            Some(settings) => settings.window,
+            None => 0,
+        };
+        let expired: Vec<ProcessKey> = self
+            .command_groups
+            .iter()
+            .filter(|(_, group)| group.first.saturating_add(window) <= now)
+            .map(|(key, _)| *key)
+            .collect();
+        for key in expired {
+            let group = self.command_groups.remove(&key).unwrap();
+            self.emit_command_group(key, group);
+        }
+    }
+
+    /// Emits a `LAUREL_COMMAND` event that lists all commands of the
+    /// group. The individual exec events are emitted as filtered
+    /// events. Groups that consist of a single command are emitted
+    /// unchanged.
+    fn emit_command_group(&mut self, key: ProcessKey, group: CommandGroup<'ev>) {
+        let mut events = group.events;
+        if events.len() == 1 {
+            self.output(&events[0]);
+            return;
+        }
+
+        let shell = match self.state.processes.get_key(&key) {
+            Some(proc) => self.procinfo(b"shell", proc),
+            None => vec![],
+        };
+        let commands = events
+            .iter()
+            .map(|ev| {
+                let mut m = vec![(Key::Literal("EVENT_ID"), ev.id.to_string().into())];
+                if let Some(EventValues::Single(rv)) = ev.body.get(&MessageType::SYSCALL) {
+                    if let Some(exe) = rv.get("exe") {
+                        m.push((Key::Literal("exe"), exe.clone()));
+                    }
+                }
+                if let Some(EventValues::Single(rv)) = ev.body.get(&MessageType::EXECVE) {
+                    for k in ["ARGV", "ARGV_STR"] {
+                        if let Some(v) = rv.get(k) {
+                            m.push((Key::Literal(k), v.clone()));
+                        }
+                    }
+                }
+                Value::Map(m)
+            })
+            .collect();
+        let mut rv = Body::default();
+        rv.push((Key::Literal("SHELL"), Value::Map(shell)));
+        rv.push((Key::Literal("COMMANDS"), Value::List(commands)));
+
+        // The composite event takes the ID of the first grouped
+        // event. A timestamp with sequence number 0 would not be
+        // unique if several groups started within the same
+        // millisecond.
+        let mut composite = Event::new(events[0].node.clone(), events[0].id);
+        composite
+            .laurel
+            .insert("LAUREL_COMMAND", EventValues::Single(rv));
+
+        for ev in events.iter_mut() {
+            ev.is_filtered = true;
+            self.output(ev);
+        }
+        self.output(&composite);
+    }
+
+    /// Returns true if `node` names a node other than the configured
+    /// `local_node`. Without `local_node`, all events are considered
+    /// local.
+    fn is_remote_node(&self, node: Option<&[u8]>) -> bool {
+        match (node, &self.settings.local_node) {
+            (Some(node), Some(local)) => node != local.as_slice(),
+            _ => false,
+        }
+    }
+
+    /// Runs `f` with the process table of `node` in place of the
+    /// local one. The remote table is taken out of
+    /// `state.node_processes` for the duration of the call and put
+    /// back afterwards, so each table has exactly one owner.
+    fn with_node_processes<R>(&mut self, node: Option<&[u8]>, f: impl FnOnce(&mut Self) -> R) -> R {
+        let Some(node) = node.filter(|_| self.is_remote_node(node)) else {
+            return f(self);
+        };
+        let table = self
+            .state
+            .node_processes
+            .remove(node)
+            .unwrap_or_else(ProcTable::remote);
+        let local = std::mem::replace(&mut self.state.processes, table);
+        let rv = f(self);
+        let table = std::mem::replace(&mut self.state.processes, local);
+        self.state.node_processes.insert(node.to_vec(), table);
+        rv
+    }
+
+    /// Early handling of SYSCALL events
+    ///
+    /// This involves:
+    /// - deciding whether the process is known / updating the process table
+    ///   - determining a process key for new events
+    /// - early handling of process labels based on key, exe
+    /// - deciding whether the event should be filtered, avoiding unnecessary
+    ///   work for enrichment/transformation
+    pub fn handle_syscall(
+        &mut self,
+        event_key: &EventKey,
+        body: &mut Body,
+        filter_event: &mut bool,
+        is_exec: &mut bool,
+        syscall_name_out: &mut Option<&'static str>,
+        process_key: &mut Option<ProcessKey>,
+    ) {
+        let id = event_key.1;
+        let mut arch: Option<u32> = None;
+        let mut syscall: Option<u32> = None;
+
+        let mut pid = 0;
+        let mut ppid = 0;
+
+        let mut comm: Option<&[u8]> = None;
+        let mut exe: Option<&[u8]> = None;
+        let mut key: Option<&[u8]> = None;
+
+        let mut argv = Vec::with_capacity(4);
+
+        let mut ids = UserGroupIDs::default();
+
+        let translate = self.translate_universal(MessageType::SYSCALL);
+        let drop_ids = self.drop_ids(MessageType::SYSCALL);
+
+        // Filter / collect
+        body.retain(|(k, v)| {
+            match (k, v) {
+                (Key::Arg(_, None), v) => {
+                    argv.push(v.clone());
+                    return false;
+                }
+                (Key::ArgLen(_), _) => return false,
+                (Key::Common(Common::Arch), Value::Number(Number::Hex(n))) => {
+                    arch = Some(*n as u32);
+                    return !(translate && self.settings.drop_translated);
+                }
+                (Key::Common(Common::Syscall), Value::Number(Number::Dec(n))) => {
+                    syscall = Some(*n as u32);
+                    return !(translate && self.settings.drop_translated);
+                }
+                (Key::Common(Common::Pid), Value::Number(Number::Dec(n))) => {
+                    pid = *n as u32;
+                }
+                (Key::Common(Common::PPid), Value::Number(Number::Dec(n))) => {
+                    ppid = *n as u32;
+                }
+                (Key::Common(Common::Comm), Value::Str(s, _)) => comm = Some(*s),
+                (Key::Common(Common::Exe), Value::Str(s, _)) => exe = Some(*s),
+                (Key::Common(Common::Key), Value::Str(s, _)) => {
+                    key = Some(*s);
+                    return !self.settings.key_list;
+                }
+                (Key::NameUID(name), Value::Number(Number::Dec(n)))
+                | (Key::NameGID(name), Value::Number(Number::Dec(n))) => {
+                    ids.push(name, *n as _);
+                    if drop_ids {
+                        return false;
+                    }
+                }
+                (Key::Name(name), Value::Str(_, _)) => {
+                    match name.as_ref() {
+                        b"ARCH" | b"SYSCALL" if translate => return false,
+                        _ => (),
+                    };
+                }
+                _ => {}
+            }
+            true
+        });
+        if let (true, Some(key)) = (self.settings.key_list, key) {
+            let keys = key
+                .split(|c| *c == KEY_SEPARATOR)
+                .map(|k| Value::Str(k, Quote::None))
+                .collect();
+            body.push((Key::Literal("key"), Value::List(keys)));
+        }
+        let a0 = match argv.first() {
+            Some(Value::Number(Number::Hex(n))) => Some(*n),
+            _ => None,
+        };
+        body.push((Key::Literal("ARGV"), Value::List(argv)));
+
+        // Determine syscall. For multiplexed syscalls (socketcall,
+        // ipc), use the name of the operation.
+        let mut arch_name = None;
+        let mut syscall_name = None;
+        if let (Some(arch), Some(syscall)) = (arch, syscall) {
+            arch_name = ARCH_NAMES.get(&arch);
+            self.arch = arch_name.copied();
+            if let Some(arch_name) = arch_name {
+                syscall_name = SYSCALL_NAMES
+                    .get(*arch_name)
+                    .and_then(|syscall_tbl| syscall_tbl.get(&syscall))
+                    .map(|name| a0.and_then(|a0| demux_syscall(name, a0)).unwrap_or(name));
+                if let Some(syscall_name) = syscall_name {
+                    if syscall_name.starts_with("execve") {
+                        *is_exec = true;
+                    }
+                    *syscall_name_out = Some(syscall_name);
+                }
+            }
+        }
+
+        // Events for filtered syscalls are dropped even if they are
+        // the first ones seen for a process.
+        let syscall_filtered = syscall_name
+            .is_some_and(|s| self.settings.filter_syscalls.contains(s))
+            || !self.settings.filter_allow_syscalls.is_empty()
+                && !syscall_name.is_some_and(|s| self.settings.filter_allow_syscalls.contains(s));
+        *filter_event |= syscall_filtered;
+
+        if self.settings.summary_period.is_some() {
+            self.summary
+                .add(ids.get(b"auid"), ids.uid, ids.get(b"gid"), exe);
+        }
+
+        let mut labels: HashSet<Vec<u8>> = HashSet::default();
+
+        if let Some(key) = key {
+            for key in key.split(|c| *c == KEY_SEPARATOR) {
+                if self.settings.filter_keys.contains(key) {
+                    *filter_event = true;
+                }
+                if self.settings.proc_label_keys.contains(key) {
+                    labels.insert(key.to_vec());
+                }
+            }
+        } else if self.settings.filter_null_keys {
+            *filter_event = true;
+        }
+
+        let mut pid_reuse_detected = false;
+        let (first_per_process, proc) = match (
+            *is_exec,
+            self.state
+                .processes
+                .get_pid(pid)
+                .filter(|p| p.pid == pid && p.ppid == ppid && p.exe.as_deref() == exe),
+        ) {
+            (false, Some(proc)) => (false, proc.clone()),
+            (_, proc) => {
+                let is_first = *is_exec || proc.is_none();
+
+                if let Some(pre_exec_proc) = self
+                    .state
+                    .processes
+                    .get_pid(pid)
+                    .and_then(|p| (ppid == p.ppid).then_some(p))
+                {
+                    self.propagate_labels(pre_exec_proc, Propagation::Exec, exe, &mut labels);
+                }
+
+                let (parent_proc, reused) =
+                    self.state.processes.get_or_retrieve_at(ppid, id.timestamp);
+                let parent_proc = parent_proc.cloned();
+                let parent = parent_proc.as_ref().map(|p| p.key);
+                pid_reuse_detected = reused;
+
+                #[cfg(all(feature = "procfs", target_os = "linux"))]
+                if let (true, Some(parent)) = (self.settings.enrich_ancestors, &parent) {
+                    self.state.processes.link_ancestors(parent);
+                }
+
+                if let Some(ref p) = parent_proc {
+                    self.propagate_labels(p, Propagation::Fork, exe, &mut labels)
+                }
+                if let Some(exe) = exe {
+                    self.label_exe(exe, &mut labels)
+                }
+
+                let mut new_proc = Process {
+                    key: ProcessKey::Event(id),
+                    parent,
+                    pid,
+                    ppid,
+                    labels,
+                    exe: exe.map(SharedBytes::from),
+                    comm: comm.map(SharedBytes::from),
+                    ..Process::default()
+                };
+
+                #[cfg(all(feature = "procfs", target_os = "linux"))]
+                if self.settings.enrich_container
+                    || self.settings.enrich_systemd
+                    || self.settings.enrich_systemd_unit
+                    || self.settings.label_unit.is_some()
+                    || self.settings.label_cgroup.is_some()
+                {
+                    let mut container_info: Option<ContainerInfo> = None;
+                    let mut systemd_service: Option<Vec<Vec<u8>>> = None;
+                    let mut systemd_unit: Option<Vec<u8>> = None;
+                    let cgroup = match self.state.processes.remote {
+                        false => self
+                            .cgroup_cache
+                            .as_ref()
+                            .and_then(|c| c.get(pid, id.timestamp))
+                            .or_else(|| {
+                                self.state.processes.source().pid_cgroup(pid).ok().flatten()
+                            }),
+                        true => None,
+                    };
+                    if self.settings.enrich_container {
+                        container_info = match cgroup {
+                            Some(ref path) => {
+                                proc::try_extract_container_id(path).map(|id| ContainerInfo { id })
+                            }
+                            _ => parent_proc.as_ref().and_then(|p| p.container_info.clone()),
+                        };
+                    }
+                    if self.settings.enrich_systemd {
+                        systemd_service = match cgroup {
+                            Some(ref path) => proc::try_extract_systemd_service(path),
+                            _ => parent_proc.as_ref().and_then(|p| p.systemd_service.clone()),
+                        };
+                    }
+                    if self.settings.enrich_systemd_unit || self.settings.label_unit.is_some() {
+                        systemd_unit = match cgroup {
+                            Some(ref path) => proc::try_extract_systemd_unit(path),
+                            _ => parent_proc.as_ref().and_then(|p| p.systemd_unit.clone()),
+                        };
+                    }
+                    if let (Some(label_unit), Some(unit)) =
+                        (&self.settings.label_unit, &systemd_unit)
+                    {
+                        new_proc
+                            .labels
+                            .extend(label_unit.matches(unit).map(Vec::from));
+                    }
+                    if let (Some(label_cgroup), Some(path)) = (&self.settings.label_cgroup, &cgroup)
+                    {
+                        new_proc
+                            .labels
+                            .extend(label_cgroup.matches(path).map(Vec::from));
+                    }
+                    new_proc.container_info = container_info;
+                    new_proc.systemd_service = systemd_service;
+                    new_proc.systemd_unit = systemd_unit;
+                }
+
+                #[cfg(all(feature = "procfs", target_os = "linux"))]
+                {
+                    new_proc.ns_pid = self.state.processes.retrieve_ns_pid(pid);
+                }
+
+                self.state.processes.insert(new_proc.clone());
+                if let (true, Some(history)) = (*is_exec, &mut self.exec_history) {
+                    history.insert(pid, new_proc.clone());
+                }
+                (is_first, new_proc)
+            }
+        };
+
+        #[cfg(all(feature = "procfs", target_os = "linux"))]
+        if !self.state.processes.remote {
+            self.observe_container(id, &proc);
+        }
+
+        if proc
+            .labels
+            .intersection(&self.settings.filter_labels)
+            .next()
+            .is_some()
+        {
+            *filter_event = true;
+        }
+
+        // TODO: This logic needs to be split.
+        let keep_first =
+            first_per_process && !self.settings.filter_first_per_process && !syscall_filtered;
+        if keep_first {
+            *filter_event = false;
+        }
+
+        // Sampling is only decided once all filters have been
+        // applied to the event, see transform_event.
+        self.sample_ratio = match keep_first {
+            true => None,
+            false => self
+                .settings
+                .filter_sample
+                .iter()
+                .find(|r| r.matches(key, syscall_name))
+                .map(|r| r.ratio),
+        };
+
+        *process_key = Some(proc.key);
+
+        // No point in adding translations / enrichments to record if
+        // we are going to filter anyway.
+        if *filter_event {
+            return;
+        }
+
+        if let (Some(arch_name), true) = (arch_name, translate) {
+            let key = match &self.settings.enrich_prefix {
+                Some(s) => Key::Name(NVec::from_iter(s.bytes().chain(b"arch".iter().cloned()))),
+                None => Key::Literal("ARCH"),
+            };
+            body.push((key, Value::Literal(arch_name)));
+        }
+        if let (Some(syscall_name), true) = (syscall_name, translate) {
+            let key = match &self.settings.enrich_prefix {
+                Some(s) => Key::Name(NVec::from_iter(s.bytes().chain(b"syscall".iter().cloned()))),
+                None => Key::Literal("SYSCALL"),
+            };
+            body.push((key, Value::Literal(syscall_name)));
+        }
+
+        if self.settings.enrich_pid {
+            self.add_record_procinfo(body, b"pid", &proc);
+            if let Some(parent_process) = proc
+                .parent
+                .and_then(|key| self.state.processes.get_key(&key))
+            {
+                let mut m = self.procinfo(b"ppid", parent_process);
+                if pid_reuse_detected {
+                    m.push((PID_REUSE_DETECTED.into(), Value::Literal("true")));
+                }
+                body.push((self.procinfo_key(b"ppid"), Value::Map(m)));
+            } else if let Some(stub) = self
+                .exec_history
+                .as_mut()
+                .filter(|_| ppid != 0)
+                .and_then(|history| history.get(&ppid))
+                .filter(|stub| stub.key < proc.key)
+            {
+                // The parent has already exited and is no longer
+                // known. It is merely likely that the most recently
+                // exec'd process with the same pid is the parent.
+                let mut m = self.procinfo(b"ppid", &stub);
+                m.push(("CONFIDENCE".into(), "low".into()));
+                if pid_reuse_detected {
+                    m.push((PID_REUSE_DETECTED.into(), Value::Literal("true")));
+                }
+                body.push((self.procinfo_key(b"ppid"), Value::Map(m)));
+            } else if pid_reuse_detected {
+                let m = vec![(PID_REUSE_DETECTED.into(), Value::Literal("true"))];
+                body.push((self.procinfo_key(b"ppid"), Value::Map(m)));
+            }
+        }
+
+        if self.translate_userdb(MessageType::SYSCALL) {
+            self.add_record_userdb(body, &ids);
+        }
+
+        let privileged = ids
+            .get(b"euid")
+            .is_some_and(|euid| euid == 0 || Some(euid) != ids.uid);
+        if self.settings.enrich_uid_groups
+            && !self.remote_node
+            && (privileged || !self.settings.enrich_uid_groups_privileged)
+        {
+            if let Some(names) = ids
+                .uid
+                .and_then(|uid| self.state.userdb.get_user_groups(uid))
+            {
+                body.push((
+                    Key::Literal("UID_GROUPS"),
+                    Value::List(names.iter().map(|n| Value::from(n.as_bytes())).collect()),
+                ));
+            }
+        }
+
+        self.add_auid_passwd(body, &ids);
+
+        #[cfg(all(feature = "procfs", target_os = "linux"))]
+        if let (false, Some(exe)) = (self.state.processes.remote, exe) {
+            if self.exe_hash_cache.is_none() || self.within_deadline("exe_hash") {
+                self.enrich_exe_hash(event_key, body, pid, exe);
+            }
+        }
+    }
+
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    fn enrich_exe_hash(&mut self, event_key: &EventKey, rv: &mut Body, pid: u32, exe: &[u8]) {
+        let Some(ref mut cache) = self.exe_hash_cache else {
+            return;
+        };
+        let Ok((fd, path)) = self.state.processes.source().pid_exe(pid) else {
+            return;
+        };
+        let Ok(meta) = fd.metadata() else {
+            return;
+        };
+        if path.as_os_str().as_bytes() != exe {
+            return;
+        };
+        if meta.len() > self.settings.enrich_exe_hash_size_limit {
+            return;
+        }
+
+        let cache_key = (meta.dev(), meta.ino(), meta.mtime_nsec());
+        let len = meta.len();
+        let hash = match (cache.get(&cache_key), &self.workers) {
+            (Some(h), _) => h,
+            (None, Some(workers)) => {
+                // The result is picked up when the event is written.
+                match workers.submit(move |cancel| hash_exe(fd, len, cancel)) {
+                    Some(task) => {
+                        self.pending_exe_hashes
+                            .insert(event_key.clone(), (cache_key, task));
+                    }
+                    None => self.skip_enrichment("exe_hash"),
+                }
+                return;
+            }
+            (None, None) => {
+                let Some(h) = hash_exe(fd, len, &Cancel::default()) else {
+                    return;
+                };
+                cache.insert(cache_key, h);
+                h
+            }
+        };
+        rv.push((Key::Literal("EXE_HASH"), hex_string(&hash).into()));
+    }
+
+    /// Adds the `EXE_HASH` that has been computed by a worker to the
+    /// SYSCALL record.
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    fn resolve_exe_hash(&mut self, ev: &mut Event) {
+        let event_key = EventKey(ev.node.clone(), ev.id);
+        let Some((cache_key, task)) = self.pending_exe_hashes.remove(&event_key) else {
+            return;
+        };
+        // Without a deadline, don't wait for slow hashing forever.
+        let deadline = self
+            .enrich_deadline
+            .unwrap_or_else(|| self.clock.now() + EXE_HASH_WAIT_MAX);
+        let hash = match task.wait(Some(deadline)) {
+            Some(Some(h)) => h,
+            Some(None) => return,
+            None => {
+                self.skip_enrichment("exe_hash");
+                return;
+            }
+        };
+        if let Some(cache) = &mut self.exe_hash_cache {
+            cache.insert(cache_key, hash);
+        }
+        if let Some(EventValues::Single(rv)) = ev.body.get_mut(&MessageType::SYSCALL) {
+            rv.push((Key::Literal("EXE_HASH"), hex_string(&hash).into()));
+        }
+    }
+
+    /// Ingest a log line and add it to the coalesce object.
+    ///
+    /// Simple one-liner events are emitted immediately.
+    ///
+    /// For complex multi-line events (SYSCALL + additional
+    /// information), corresponding records are collected. The entire
+    /// event is emitted only when an EOE ("end of event") line for
+    /// the event is encountered.
+    pub fn process_line(&mut self, line: &[u8]) -> Result<(), CoalesceError> {
+        if !self.settings.drop_record_types.is_empty() {
+            if let Some(ty) = record_type(line) {
+                // EOE records are needed to complete events.
+                if ty != MessageType::EOE && self.settings.drop_record_types.contains(&ty) {
+                    self.dropped_records += 1;
+                    return Ok(());
+                }
+            }
+        }
+
+        let mut do_filter = self.settings.filter_raw_lines.is_match(line);
+        let mut preset = None;
+        if !do_filter {
+            preset = self
+                .settings
+                .filter_presets
+                .iter()
+                .find(|(_, re)| re.is_match(line))
+                .map(|(p, _)| *p);
+            do_filter = preset.is_some();
+        }
+
+        let skip_enriched = self.settings.translate_universal && self.settings.translate_userdb;
+        let mut msg = parse(line, skip_enriched).map_err(|e| CoalesceError::parse(line, e))?;
+        let event_key = EventKey(msg.node.clone(), msg.id);
+        self.last_id = Some(msg.id);
+        self.latest_timestamp = self.latest_timestamp.max(msg.id.timestamp);
+        self.last_record_t = self.clock.now_ms();
+        self.expire_reordered(self.latest_timestamp);
+
+        // The skew is determined per record so that events are
+        // annotated based on their own timestamps.
+        let clock_skew = self.settings.clock_skew_threshold.and_then(|threshold| {
+            let skew = self.clock.now_ms() as i64 - msg.id.timestamp as i64;
+            (skew.unsigned_abs() > threshold).then_some(skew)
+        });
+        self.clock_skew = clock_skew;
+
+        #[cfg(all(feature = "procfs", target_os = "linux"))]
+        self.handle_proc_events();
+
+        // clean out state every EXPIRE_PERIOD
+        match self.next_expire {
+            Some(t) if t < msg.id.timestamp => {
+                self.expire_inflight(msg.id.timestamp);
+                self.expire_command_groups(msg.id.timestamp);
+                self.expire_ordered(msg.id.timestamp);
+                self.expire_entities(msg.id.timestamp);
+                self.expire_done(msg.id.timestamp);
+                self.expire_processes(msg.id.timestamp);
+                #[cfg(all(feature = "procfs", target_os = "linux"))]
+                self.expire_containers();
+                for table in self.state.node_processes.values_mut() {
+                    table.truncate(NODE_PROCESSES_MAX);
+                }
+                self.next_expire = Some(msg.id.timestamp + EXPIRE_PERIOD)
+            }
+            None => self.next_expire = Some(msg.id.timestamp + EXPIRE_PERIOD),
+            _ => (),
+        };
+
+        if let Some(period) = self.settings.summary_period {
+            match self.summary.start {
+                Some(t) if t + period <= msg.id.timestamp => self.emit_summary(msg.id.timestamp),
+                None => self.summary.start = Some(msg.id.timestamp),
+                _ => (),
+            }
+        }
+
+        let mut is_exec = false;
+        let mut syscall = None;
+        let mut process_key = None;
+        if msg.ty == MessageType::SYSCALL {
+            let deadline = match self.state.inflight.get(&event_key) {
+                Some(ev) => ev.enrich_deadline,
+                None => self.new_enrich_deadline(),
+            };
+            self.start_enrichment(deadline);
+            self.with_node_processes(msg.node.as_deref(), |c| {
+                c.handle_syscall(
+                    &event_key,
+                    &mut msg.body,
+                    &mut do_filter,
+                    &mut is_exec,
+                    &mut syscall,
+                    &mut process_key,
+                )
+            });
+        }
+
+        if msg.ty == MessageType::EOE {
+            let pending = (msg.id.timestamp, event_key);
+            if self.state.done.contains(&pending.1) || self.reorder_pending.contains(&pending) {
+                return Err(CoalesceError::DuplicateEvent(msg.id));
+            }
+            if self.settings.reorder_window > 0 {
+                let ev = self
+                    .state
+                    .inflight
+                    .get_mut(&pending.1)
+                    .ok_or(CoalesceError::SpuriousEOE(msg.id))?;
+                ev.size += line.len();
+                self.reorder_pending.insert(pending);
+            } else {
+                let mut ev = self
+                    .state
+                    .inflight
+                    .remove(&pending.1)
+                    .ok_or(CoalesceError::SpuriousEOE(msg.id))?;
+                ev.size += line.len();
+                self.emit_event(ev);
+            }
+        } else if msg.ty.is_multipart() {
+            // kernel-level messages
+            if !self.state.inflight.contains_key(&event_key) {
+                let mut ev = Event::new(msg.node, msg.id);
+                ev.clock_skew = clock_skew;
+                ev.enrich_deadline = match msg.ty {
+                    MessageType::SYSCALL => self.enrich_deadline,
+                    _ => self.new_enrich_deadline(),
+                };
+                self.state.inflight.insert(event_key.clone(), ev);
+            }
+            let ev = self.state.inflight.get_mut(&event_key).unwrap();
+            add_enrichment_incomplete(ev, std::mem::take(&mut self.enrich_skipped));
+            ev.size += line.len();
+            ev.is_filtered |= do_filter;
+            ev.filter_preset = ev.filter_preset.or(preset);
+            ev.is_exec |= is_exec;
+            if syscall.is_some() {
+                ev.syscall = syscall;
+            }
+            if process_key.is_some() {
+                ev.process_key = process_key;
+            }
+            if msg.ty == MessageType::SYSCALL {
+                ev.sample_ratio = self.sample_ratio.take();
+                ev.arch = self.arch.take();
+            }
+
+            match ev.body.get_mut(&msg.ty) {
+                Some(EventValues::Single(v)) => v.extend(msg.body),
+                Some(EventValues::Multi(v)) => v.push(msg.body),
+                None => match msg.ty {
+                    MessageType::SYSCALL => {
+                        ev.body.insert(msg.ty, EventValues::Single(msg.body));
+                    }
+                    MessageType::EXECVE | MessageType::PROCTITLE | MessageType::CWD => {
+                        ev.body.insert(msg.ty, EventValues::Single(msg.body));
+                    }
+                    _ => {
+                        ev.body.insert(msg.ty, EventValues::Multi(vec![msg.body]));
+                    }
+                },
+            };
+        } else {
+            // user-space messages
+            if self.state.done.contains(&event_key) {
+                return Err(CoalesceError::DuplicateEvent(msg.id));
+            }
+            let mut ev = Event::new(msg.node, msg.id);
+            ev.clock_skew = clock_skew;
+            ev.size = line.len();
+            ev.is_filtered |= do_filter;
+            ev.filter_preset = preset;
+            ev.body.insert(msg.ty, EventValues::Single(msg.body));
+            self.emit_event(ev);
+        }
+        Ok(())
+    }
+
+    /// Flush all in-flight event data, including partial events
+    pub fn flush(&mut self) {
+        self.expire_inflight(u64::MAX);
+        self.expire_command_groups(u64::MAX);
+        self.expire_ordered(u64::MAX);
+        self.expire_entities(u64::MAX);
+    }
+}
+
+impl Drop for Coalesce<'_, '_> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::ManualClock;
+    use std::cell::RefCell;
+    use std::error::Error;
+    use std::io::{BufRead, BufReader};
+    use std::rc::Rc;
+
+    fn event_to_json(e: &Event) -> String {
+        let mut out = vec![];
+        crate::json::to_writer(&mut out, e).unwrap();
+        String::from_utf8_lossy(&out).to_string()
+    }
+
+    fn find_event<'a>(events: &'a [Event], id: &str) -> Option<Event<'a>> {
+        events.iter().find(|e| &e.id == id).cloned()
+    }
+
+    fn strip_enriched<T>(text: T) -> Vec<u8>
+    where
+        T: AsRef<[u8]>,
+    {
+        let mut out = vec![];
+        for line in BufReader::new(text.as_ref()).lines() {
+            let line = line.unwrap().clone();
+            for c in line.as_bytes() {
+                match *c as char {
+                    '\x1d' => break,
+                    _ => out.push(*c),
+                };
+            }
+            out.push(b'\n');
+        }
+        out
+    }
+
+    fn process_record<T>(c: &mut Coalesce, text: T) -> Result<(), Box<dyn Error>>
+    where
+        T: AsRef<[u8]>,
+    {
+        for line in BufReader::new(text.as_ref())
+            .lines()
+            .filter(|line| match line {
+                Ok(l) if l.is_empty() => false,
+                Ok(l) if l.starts_with("#") => false,
+                _ => true,
+            })
+        {
+            let mut line = line.unwrap().clone();
+            line.push('\n');
+            c.process_line(line.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn coalesce() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(Vec::new()));
+        let mut c = Coalesce::new(mk_emit_vec(&ec));
+
+        process_record(&mut c, include_bytes!("testdata/line-user-acct.txt"))?;
+        assert_eq!(
+            ec.borrow().last().unwrap().id,
+            EventID {
+                timestamp: 1615113648981,
+                sequence: 15220
+            }
+        );
+
+        if process_record(&mut c, include_bytes!("testdata/line-user-acct.txt")).is_ok() {
+            panic!("failed to detect duplicate entries");
+        };
+
+        process_record(&mut c, include_bytes!("testdata/record-execve.txt"))?;
+        assert_eq!(
+            ec.borrow().last().unwrap().id,
+            EventID {
+                timestamp: 1615114232375,
+                sequence: 15558
+            }
+        );
+
+        process_record(&mut c, include_bytes!("testdata/record-execve-long.txt"))?;
+        assert_eq!(
+            ec.borrow().last().unwrap().id,
+            EventID {
+                timestamp: 1615150974493,
+                sequence: 21028
+            }
+        );
+
+        process_record(
+            &mut c,
+            include_bytes!("testdata/record-anom-promiscuous.txt"),
+        )?;
+        let output = event_to_json(ec.borrow().last().unwrap());
+        assert!(
+            output.contains(r#""saddr":"%10%00%00%00%00%00%00%00%00%00%00%00""#),
+            "SOCKADDR.saddr blob is encoded correctly"
+        );
+
+        // recordds do not begin with SYSCALL.
+        process_record(&mut c, include_bytes!("testdata/record-login.txt"))?;
+        process_record(&mut c, include_bytes!("testdata/record-adjntpval.txt"))?;
+        process_record(&mut c, include_bytes!("testdata/record-avc-apparmor.txt"))?;
+
+        let mut c = Coalesce::new(mk_emit_vec(&ec));
+        c.settings.translate_userdb = true;
+        c.settings.drop_translated = true;
+        process_record(
+            &mut c,
+            strip_enriched(include_bytes!("testdata/record-execve.txt")),
+        )?;
+        let gid0name = nix::unistd::Group::from_gid(0.into())
+            .unwrap()
+            .unwrap()
+            .name;
+        let output = event_to_json(ec.borrow().last().unwrap());
+        println!("{output}");
+        assert!(
+            output.contains(r#""UID":"root","#),
+            "output contains translated UID"
+        );
+        assert!(
+            output.contains(&format!(r#""EGID":"{gid0name}","#)),
+            "output contains translated EGID"
+        );
+        assert!(
+            !output.contains(r#""uid":0,"#),
+            "output does not contain raw uid"
+        );
+        assert!(
+            !output.contains(r#""egid":0,"#),
+            "output does not contain raw egid"
+        );
+        assert!(
+            output.contains(r#"NODE":"work","#),
+            "node name is encoded correctly."
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_uids() {
+        let ec = Rc::new(RefCell::new(None));
+
+        let mut c = Coalesce::new(mk_emit(&ec));
+        c.settings.enrich_uid_groups = false;
+        c.settings.enrich_pid = false;
+        c.settings.translate_userdb = true;
+        c.settings.translate_universal = true;
+        process_record(&mut c, include_bytes!("testdata/record-login.txt")).unwrap();
+        if let EventValues::Multi(records) =
+            &ec.borrow().as_ref().unwrap().body[&MessageType::LOGIN]
+        {
+            // Check for: pid uid subj old-auid auid tty old-ses ses res UID OLD-AUID AUID
+            let l = records[0].len();
+            assert!(
+                l == 12,
+                "expected 12 fields, got {l}: {:?}",
+                records[0].clone().into_iter().collect::<Vec<_>>()
+            );
+        } else {
+            panic!("expected EventValues::Multi");
+        };
+    }
+
+    #[test]
+    fn keep_enriched_syscalls() {
+        let ec = Rc::new(RefCell::new(None));
+
+        let mut c = Coalesce::new(mk_emit(&ec));
+        process_record(&mut c, include_bytes!("testdata/record-execve.txt")).unwrap();
+        assert!(event_to_json(ec.borrow().as_ref().unwrap()).contains(r#""ARCH":"x86_64""#));
+        assert!(event_to_json(ec.borrow().as_ref().unwrap()).contains(r#""SYSCALL":"execve""#));
+    }
+
+    #[test]
+    fn translate_sockaddr() {
+        let ec = Rc::new(RefCell::new(None));
+
+        let mut c = Coalesce::new(mk_emit(&ec));
+        c.settings.translate_universal = true;
+        process_record(
+            &mut c,
+            strip_enriched(include_bytes!("testdata/record-nscd.txt")),
+        )
+        .unwrap();
+        let output = event_to_json(ec.borrow().as_ref().unwrap());
+        assert!(
+            output.contains(r#""saddr":"%01%00/var/run/nscd/socket"#),
+            "{output}"
+        );
+        assert!(output.contains(r#""SADDR":{"#), "{output}");
+
+        let mut c = Coalesce::new(mk_emit(&ec));
+        c.settings.translate_universal = true;
+        c.settings.drop_translated = true;
+        process_record(
+            &mut c,
+            strip_enriched(include_bytes!("testdata/record-nscd.txt")),
+        )
+        .unwrap();
+        let output = event_to_json(ec.borrow().as_ref().unwrap());
+        assert!(!output.contains(r#""saddr""#), "{output}");
+        assert!(output.contains(r#""SADDR":{"#), "{output}");
+    }
+
+    #[test]
+    fn translate_skip() {
+        let ec = Rc::new(RefCell::new(None));
+
+        let mut c = Coalesce::new(mk_emit(&ec));
+        c.settings.translate_userdb = true;
+        c.settings.translate_universal = true;
+        c.settings.drop_translated = true;
+        c.settings.translate_skip_userdb = [MessageType::PATH].into();
+        c.settings.translate_skip_universal = [MessageType::SOCKADDR].into();
+
+        process_record(
+            &mut c,
+            strip_enriched(include_bytes!("testdata/record-execve.txt")),
+        )
+        .unwrap();
+        let output = event_to_json(ec.borrow().as_ref().unwrap());
+        assert!(output.contains(r#""UID":"root""#), "{output}");
+        assert!(!output.contains(r#""uid":0"#), "{output}");
+        assert!(output.contains(r#""ouid":0,"ogid":0"#), "{output}");
+        assert!(!output.contains(r#""OUID""#), "{output}");
+        assert!(output.contains(r#""SYSCALL":"execve""#), "{output}");
+
+        process_record(
+            &mut c,
+            strip_enriched(include_bytes!("testdata/record-nscd.txt")),
+        )
+        .unwrap();
+        let output = event_to_json(ec.borrow().as_ref().unwrap());
+        assert!(
+            output.contains(r#""saddr":"%01%00/var/run/nscd/socket"#),
+            "{output}"
+        );
+        assert!(!output.contains(r#""SADDR""#), "{output}");
+        assert!(output.contains(r#""SYSCALL":"connect""#), "{output}");
+    }
+
+    #[test]
+    fn translate_uids() {
+        let ec = Rc::new(RefCell::new(None));
+
+        let gid0name = nix::unistd::Group::from_gid(0.into())
+            .unwrap()
+            .unwrap()
+            .name;
+
+        let mut c = Coalesce::new(|e: &Event| *ec.borrow_mut() = Some(e.clone()));
+        c.settings.translate_userdb = true;
+        c.settings.translate_universal = true;
+        process_record(
+            &mut c,
+            strip_enriched(include_bytes!("testdata/record-login.txt")),
+        )
+        .unwrap();
+
+        if let EventValues::Single(record) =
+            &ec.borrow().as_ref().unwrap().body[&MessageType::SYSCALL]
+        {
+            let mut uids = 0;
+            let mut gids = 0;
+            for (k, v) in record {
+                if k.to_string().ends_with("UID") {
+                    uids += 1;
+                    assert!(v == "root", "Got {k}={v:?}, expected root");
+                }
+                if k.to_string().ends_with("GID") {
+                    gids += 1;
+                    assert!(v == gid0name.as_str(), "Got {k}={v:?}, expected root");
+                }
+            }
+            assert!(
+                uids == 5 && gids == 4,
+                "Got {uids} uids/{gids} gids, expected 5/4",
+            );
+        }
+
+        if let EventValues::Multi(records) =
+            &ec.borrow().as_ref().unwrap().body[&MessageType::LOGIN]
+        {
+            let mut uid = false;
+            let mut old_auid = false;
+            let mut auid = false;
+            // UID="root" OLD-AUID="unset" AUID="root"
+            for (k, v) in &records[0] {
+                if k == "UID" && v == "root" {
+                    uid = true;
+                }
+                if k == "OLD-AUID" && v == "unset" {
+                    old_auid = true;
+                }
+                if k == "AUID" && v == "root" {
+                    auid = true;
+                }
+            }
+            assert!(
+                uid,
+                "missing UID: {:?}",
+                records[0].clone().into_iter().collect::<Vec<_>>()
+            );
+            assert!(
+                old_auid,
+                "missing OLD-AUID: {:?}",
+                records[0].clone().into_iter().collect::<Vec<_>>()
+            );
+            assert!(
+                auid,
+                "missing AUID: {:?}",
+                records[0].clone().into_iter().collect::<Vec<_>>()
+            );
+        } else {
+            panic!("expected EventValues::Multi");
+        };
+    }
+
+    #[test]
+    fn translate_userdb_execve() {
+        let ec = Rc::new(RefCell::new(None));
+
+        let gid0name = nix::unistd::Group::from_gid(0.into())
+            .unwrap()
+            .unwrap()
+            .name;
+
+        let mut c = Coalesce::new(|e: &Event| *ec.borrow_mut() = Some(e.clone()));
+        c.settings.translate_userdb = true;
+        c.settings.translate_universal = true;
+
+        process_record(
+            &mut c,
+            strip_enriched(include_bytes!("testdata/record-execve.txt")),
+        )
+        .unwrap();
+
+        let j = event_to_json(ec.borrow().as_ref().unwrap());
+        println!("{j}");
+        assert!(j.contains(r#""OUID":"root""#));
+        assert!(j.contains(&format!(r#""OGID":"{gid0name}""#)));
+    }
+
+    #[test]
+    fn translate_userdb_remote_node() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&ec));
+        c.settings.local_node = Some(b"local".to_vec());
+        c.settings.translate_userdb = true;
+        c.settings.enrich_auid_passwd = vec![PasswdField::Shell];
+        for (node, seq) in [("local", 1), ("remote", 2)] {
+            process_record(
+                &mut c,
+                format!(
+                    r#"node={node} type=SYSCALL msg=audit(1615114233.001:{seq}): arch=c000003e syscall=0 success=yes exit=1 a0=0 a1=7ffd2c1e5f3a a2=1 a3=0 items=0 ppid=1 pid=3999999 auid=0 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=pts1 ses=1 comm="cat" exe="/usr/bin/cat" key=(null)
+node={node} type=EOE msg=audit(1615114233.001:{seq}): 
+"#
+                ),
+            )?;
+        }
+        let events = ec.borrow();
+        let local = event_to_json(&find_event(&events, "1615114233.001:1").unwrap());
+        assert!(local.contains(r#""UID":"root""#), "{local}");
+        assert!(local.contains("AUID_PASSWD"), "{local}");
+        let remote = event_to_json(&find_event(&events, "1615114233.001:2").unwrap());
+        assert!(!remote.contains(r#""UID":"#), "{remote}");
+        assert!(!remote.contains("UID_GROUPS"), "{remote}");
+        assert!(!remote.contains("AUID_PASSWD"), "{remote}");
+        Ok(())
+    }
+
+    #[test]
+    fn translate_userdb_ptrace() {
+        let ec = Rc::new(RefCell::new(None));
+
+        let mut c = Coalesce::new(|e: &Event| *ec.borrow_mut() = Some(e.clone()));
+        c.settings.translate_userdb = true;
+        c.settings.translate_universal = true;
+
+        process_record(
+            &mut c,
+            strip_enriched(include_bytes!("testdata/record-ptrace.txt")),
+        )
+        .unwrap();
+
+        let j = event_to_json(ec.borrow().as_ref().unwrap());
+        println!("{j}");
+        for u in &[
+            "AUID", "UID", "UID", "EUID", "SUID", "FSUID", "EUID", "SUID", "FSUID", "OAUID", "OUID",
+        ] {
+            assert!(
+                j.contains(&format!(r#""{u}":"root"#)),
+                "record does not contain {u}"
+            );
+        }
+    }
+
+    #[test]
+    fn enrich_uid_groups() {
+        let ec: Rc<RefCell<Option<Event>>> = Rc::new(RefCell::new(None));
+
+        let mut c = Coalesce::new(mk_emit(&ec));
+        c.settings.translate_userdb = false;
+        c.settings.enrich_uid_groups = true;
+
+        process_record(&mut c, include_bytes!("testdata/record-execve.txt")).unwrap();
+
+        assert!(
+            event_to_json(ec.borrow().as_ref().unwrap()).contains(r#""UID_GROUPS":["#),
+            "enrich.uid_groups is performed regardless of translate.userdb"
+        );
+
+        // euid=0
+        let ec: Rc<RefCell<Option<Event>>> = Rc::new(RefCell::new(None));
+        let mut c = Coalesce::new(mk_emit(&ec));
+        c.settings.enrich_uid_groups_privileged = true;
+        process_record(&mut c, include_bytes!("testdata/record-execve.txt")).unwrap();
+        assert!(event_to_json(ec.borrow().as_ref().unwrap()).contains(r#""UID_GROUPS":["#));
+
+        let unprivileged = String::from_utf8_lossy(include_bytes!("testdata/record-execve.txt"))
+            .replace(" uid=0 ", " uid=1000 ")
+            .replace(" euid=0 ", " euid=1000 ");
+        let ec: Rc<RefCell<Option<Event>>> = Rc::new(RefCell::new(None));
+        let mut c = Coalesce::new(mk_emit(&ec));
+        c.settings.enrich_uid_groups_privileged = true;
+        process_record(&mut c, unprivileged.as_bytes()).unwrap();
+        assert!(!event_to_json(ec.borrow().as_ref().unwrap()).contains("UID_GROUPS"));
+    }
+
+    #[test]
+    fn enrich_auid_passwd() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&ec));
+        c.settings.enrich_auid_passwd = vec![PasswdField::Shell, PasswdField::Home];
+        let root = nix::unistd::User::from_uid(0.into())?.unwrap();
+
+        process_record(
+            &mut c,
+            r#"type=USER_AUTH msg=audit(1615114233.000:3): pid=1 uid=0 auid=0 ses=1 msg='op=PAM:authentication acct="root" exe="/bin/login" hostname=? addr=? terminal=tty1 res=success'
+type=USER_AUTH msg=audit(1615114233.000:4): pid=1 uid=0 auid=4294967295 ses=4294967295 msg='op=PAM:authentication acct="root" exe="/bin/login" hostname=? addr=? terminal=tty1 res=success'
+"#,
+        )?;
+        let events = ec.borrow();
+        let output = event_to_json(&events[0]);
+        println!("{output}");
+        assert!(output.contains(&format!(
+            r#""AUID_PASSWD":{{"shell":"{}","home":"{}"}}"#,
+            root.shell.display(),
+            root.dir.display()
+        )));
+        assert!(!event_to_json(&events[1]).contains("AUID_PASSWD"));
+        Ok(())
+    }
+
+    #[test]
+    fn enrich_uringop() {
+        let ec: Rc<RefCell<Option<Event>>> = Rc::new(RefCell::new(None));
+
+        let mut c = Coalesce::new(mk_emit(&ec));
+        c.settings.translate_userdb = true;
+        c.settings.translate_universal = true;
+
+        process_record(&mut c, include_bytes!("testdata/record-uringop.txt")).unwrap();
+
+        let output = event_to_json(ec.borrow().as_ref().unwrap());
+        println!("{output}");
+
+        assert!(
+            output.contains(r#""URING_OP":"openat""#),
+            "uring operations should be translated."
+        );
+        assert!(
+            output.contains(r#""UID":"root""#)
+                && output.contains(r#""GID":"root""#)
+                && output.contains(r#""EUID":"root""#)
+                && output.contains(r#""SUID":"root""#)
+                && output.contains(r#""FSUID":"root""#)
+                && output.contains(r#""EGID":"root""#)
+                && output.contains(r#""SGID":"root""#)
+                && output.contains(r#""FSGID":"root""#),
+            "*uid, *gid should be translated"
+        );
+
+        // todo: pid, ppid
+    }
+
+    #[test]
+    fn enrich_time_change() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&ec));
+        c.settings.translate_universal = true;
+
+        process_record(&mut c, include_bytes!("testdata/record-adjntpval.txt"))?;
+        process_record(
+            &mut c,
+            r#"type=TIME_INJOFFSET msg=audit(1640024916.001:4238): sec=-16 nsec=124887145
+type=TIME_ADJNTPVAL msg=audit(1640024916.001:4238): op=status old=8193 new=8257
+type=SYSCALL msg=audit(1640024916.001:4238): arch=c000003e syscall=159 success=yes exit=5 a0=7ffeaf991370 a1=0 a2=2710 a3=0 items=0 ppid=1 pid=4000 auid=1000 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=pts0 ses=2 comm="date" exe="/usr/bin/date" key=(null)
+type=EOE msg=audit(1640024916.001:4238): 
+"#,
+        )?;
+
+        let events = ec.borrow();
+        let output = event_to_json(&events[0]);
+        println!("{output}");
+        assert!(
+            output.contains(r#""TIME_ADJNTPVAL":[{"op":"freq","old":"-37201248256000","new":"-37237817344000","CLOCK":"realtime","DELTA":-36569088000,"OLD_PPB":-8662,"NEW_PPB":-8671}]"#),
+            "{output}"
+        );
+        let output = event_to_json(&events[1]);
+        println!("{output}");
+        assert!(output.contains(r#""OFFSET_NS":-15875112855"#), "{output}");
+        assert!(
+            output.contains(r#""STATUS_SET":["UNSYNC"],"STATUS_CLEARED":[]"#),
+            "{output}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn enrich_fanotify() {
+        let ec: Rc<RefCell<Option<Event>>> = Rc::new(RefCell::new(None));
+
+        let mut c = Coalesce::new(mk_emit(&ec));
+        c.settings.translate_universal = true;
+
+        process_record(&mut c, include_bytes!("testdata/record-fanotify.txt")).unwrap();
+
+        let output = event_to_json(ec.borrow().as_ref().unwrap());
+        println!("{output}");
+
+        assert!(
+            output.contains(r#""RESP":"deny""#)
+                && output.contains(r#""FAN_TYPE":"audit_rule""#)
+                && output.contains(r#""FAN_INFO":26"#)
+                && output.contains(r#""SUBJ_TRUST":"unknown""#)
+                && output.contains(r#""OBJ_TRUST":"no""#),
+            "FANOTIFY fields should be translated"
+        );
+        assert!(
+            output.contains(r#""SYSCALL":"openat""#),
+            "FANOTIFY record should be part of the syscall event"
+        );
+    }
+
+    #[test]
+    fn key_label() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Option<Event>>> = Rc::new(RefCell::new(None));
+
+        let mut c = Coalesce::new(mk_emit(&ec));
+        c.settings
+            .proc_label_keys
+            .insert(Vec::from(&b"software_mgmt"[..]));
+        c.settings
+            .proc_propagate_labels
+            .insert(Vec::from(&b"software_mgmt"[..]));
+        process_record(&mut c, include_bytes!("testdata/tree/00.txt"))?;
+        {
+            assert!(
+                event_to_json(ec.borrow().as_ref().unwrap())
+                    .contains(r#""LABELS":["software_mgmt"]"#),
+                "process gets 'software_mgmt' label from key"
+            );
+        }
+
+        process_record(&mut c, include_bytes!("testdata/tree/01.txt"))?;
+        {
+            assert!(
+                event_to_json(ec.borrow().as_ref().unwrap())
+                    .contains(r#""LABELS":["software_mgmt"]"#),
+                "child process inherits 'software_mgmt' label"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn label_exe() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Option<Event>>> = Rc::new(RefCell::new(None));
+        let lm = LabelMatcher::new(&[("whoami", "recon")])?;
+
+        let mut c = Coalesce::new(mk_emit(&ec));
+        c.settings.label_exe = Some(lm.clone());
+        process_record(&mut c, include_bytes!("testdata/record-execve.txt"))?;
+        drop(c);
+        assert!(event_to_json(ec.borrow().as_ref().unwrap()).contains(r#"LABELS":["recon"]"#));
+
+        let mut c = Coalesce::new(mk_emit(&ec));
+        c.settings.label_exe = Some(lm);
+        process_record(
+            &mut c,
+            strip_enriched(include_bytes!("testdata/record-execve.txt")),
+        )?;
+        drop(c);
+        assert!(event_to_json(ec.borrow().as_ref().unwrap()).contains(r#"LABELS":["recon"]"#));
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    fn label_unit_cgroup() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Option<Event>>> = Rc::new(RefCell::new(None));
+        let cache = CgroupCache::default();
+        cache.insert(10884, 1615114232000, b"/system.slice/cron.service".to_vec());
+
+        let mut c = Coalesce::new(mk_emit(&ec)).with_cgroup_cache(cache);
+        c.settings.local_node = Some(b"work".to_vec());
+        c.settings.label_unit = Some(LabelMatcher::new(&[("^cron\\.service$", "cron")])?);
+        c.settings.label_cgroup = Some(LabelMatcher::new(&[("^/system\\.slice/", "system")])?);
+        process_record(&mut c, include_bytes!("testdata/record-execve.txt"))?;
+        drop(c);
+        let output = event_to_json(ec.borrow().as_ref().unwrap());
+        assert!(output.contains(r#""cron""#), "{output}");
+        assert!(output.contains(r#""system""#), "{output}");
+        assert!(!output.contains(r#""UNIT""#), "{output}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn label_argv() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Option<Event>>> = Rc::new(RefCell::new(None));
+
+        let mut c = Coalesce::new(mk_emit(&ec));
+        c.settings.label_argv = Some(LabelMatcher::new(&[(
+            r#"^\S*java .* -Dweblogic"#,
+            "weblogic",
+        )])?);
+
+        process_record(&mut c, include_bytes!("testdata/record-weblogic.txt"))?;
+
+        assert!(event_to_json(ec.borrow().as_ref().unwrap()).contains(r#"LABELS":["weblogic"]"#));
+
+        // Ensure this does not crash with long command lines
+        // TODO: check matcher behavior
+        let mut c = Coalesce::new(mk_emit(&ec));
+        c.settings.label_argv = Some(LabelMatcher::new(&[(
+            r#"/opt/app/redacted/to/protect/the/guilty/"#,
+            "protect-the-guilty",
+        )])?);
+        let buf = gen_long_find_execve();
+        process_record(&mut c, buf)?;
+        assert!(event_to_json(ec.borrow().as_ref().unwrap())
+            .contains(r#"LABELS":["protect-the-guilty"]"#));
+
+        let mut c = Coalesce::new(mk_emit(&ec));
+        c.settings.label_argv = Some(LabelMatcher::new(&[
+            (r#"^/bin/echo "#, "echo"), // this should match.
+            (r#"aaaaaaaaaa"#, "aaaa"),  // this shouldn't. argv[1] is too long for the buffer.
+        ])?);
+        process_record(&mut c, include_bytes!("testdata/record-execve-long.txt"))?;
+        assert!(event_to_json(ec.borrow().as_ref().unwrap()).contains(r#"LABELS":["echo"]"#));
+
+        Ok(())
+    }
+
+    // Returns an emitter function that puts the event into an Option
+    fn mk_emit<'c, 'ev: 'c>(
+        ec: &'c Rc<RefCell<Option<Event<'ev>>>>,
+    ) -> impl FnMut(&Event<'ev>) + 'c {
+        |ev: &Event| {
+            if !ev.is_filtered {
+                *ec.borrow_mut() = Some(ev.clone());
+            }
+        }
+    }
+
+    // Returns an emitter function that appends the event onto a Vec
+    fn mk_emit_vec<'c, 'ev>(ec: &'c Rc<RefCell<Vec<Event<'ev>>>>) -> impl FnMut(&Event<'ev>) + 'c {
+        |ev: &Event| {
+            if !ev.is_filtered {
+                ec.borrow_mut().push(ev.clone());
+            }
+        }
+    }
+
+    #[test]
+    fn key_list() -> Result<(), Box<dyn Error>> {
+        let events: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&events));
+        c.settings.key_list = true;
+        c.settings.filter_keys.insert(b"skip".to_vec());
+        // keys: (null), "exec\x01skip", "exec\x01net"
+        process_record(&mut c, br#"type=SYSCALL msg=audit(1615114233.001:1): arch=c000003e syscall=59 success=yes exit=0 a0=55d3ee2c2a50 a1=55d3ee2c2a70 a2=55d3ee2c2a80 a3=0 items=0 ppid=1 pid=4000020 auid=0 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=(none) ses=1 comm="cat" exe="/usr/bin/cat" key=(null)
+type=EOE msg=audit(1615114233.001:1): 
+type=SYSCALL msg=audit(1615114233.002:2): arch=c000003e syscall=2 success=yes exit=3 a0=7ffd2c1e5f3a a1=0 a2=0 a3=0 items=0 ppid=1 pid=4000020 auid=0 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=(none) ses=1 comm="cat" exe="/usr/bin/cat" key=6578656301736B6970
+type=EOE msg=audit(1615114233.002:2): 
+type=SYSCALL msg=audit(1615114233.003:3): arch=c000003e syscall=2 success=yes exit=3 a0=7ffd2c1e5f3a a1=0 a2=0 a3=0 items=0 ppid=1 pid=4000020 auid=0 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=(none) ses=1 comm="cat" exe="/usr/bin/cat" key=65786563016E6574
+type=EOE msg=audit(1615114233.003:3): 
+"#)?;
+        let events = events.borrow();
+        assert_eq!(events.len(), 2);
+        let output = event_to_json(&events[1]);
+        assert!(output.contains(r#""key":["exec","net"]"#), "{output}");
+        Ok(())
+    }
+
+    #[test]
+    fn filter_key() -> Result<(), Box<dyn Error>> {
+        let events: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+
+        let mut c = Coalesce::new(mk_emit_vec(&events));
+        c.settings
+            .filter_keys
+            .insert(Vec::from(&b"filter-this"[..]));
+        c.settings.filter_keys.insert(Vec::from(&b"this-too"[..]));
+        process_record(&mut c, include_bytes!("testdata/record-syscall-key.txt"))?;
+        drop(c);
+        // fist event for process -> don't filter
+        assert!(events
+            .borrow()
+            .iter()
+            .any(|e| &e.id == "1628602815.266:2365"));
+        assert!(!events
+            .borrow()
+            .iter()
+            .any(|e| &e.id == "1628602815.266:2366"));
+        assert!(!events
+            .borrow()
+            .iter()
+            .any(|e| &e.id == "1628602815.266:2367"));
+
+        let mut c = Coalesce::new(mk_emit_vec(&events));
+        c.settings.filter_null_keys = true;
+        process_record(
+            &mut c,
+            include_bytes!("testdata/record-syscall-nullkey.txt"),
+        )?;
+        drop(c);
+
+        // not first event for process -> filter
+        assert!(!events
+            .borrow()
+            .iter()
+            .any(|e| &e.id == "1678282381.452:102337"));
+        // fist event for process -> don't filter
+        assert!(events
+            .borrow()
+            .iter()
+            .any(|e| &e.id == "1678283440.683:225"));
+
+        let mut c = Coalesce::new(mk_emit_vec(&events));
+        c.settings
+            .filter_keys
+            .insert(Vec::from(&b"random-filter"[..]));
+        process_record(&mut c, include_bytes!("testdata/record-login.txt"))?;
+        drop(c);
+        assert!(!events.borrow().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn filter_syscall() -> Result<(), Box<dyn Error>> {
+        let input = br#"type=SYSCALL msg=audit(1615114233.001:1): arch=c000003e syscall=217 success=yes exit=0 a0=3 a1=55b0f2d3e2a0 a2=8000 a3=0 items=0 ppid=1 pid=4000001 auid=0 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=pts1 ses=1 comm="ls" exe="/usr/bin/ls" key=(null)
+type=EOE msg=audit(1615114233.001:1): 
+type=SYSCALL msg=audit(1615114233.002:2): arch=c000003e syscall=257 success=yes exit=3 a0=ffffff9c a1=55b0f2d3e2a0 a2=0 a3=0 items=0 ppid=1 pid=4000001 auid=0 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=pts1 ses=1 comm="ls" exe="/usr/bin/ls" key=(null)
+type=EOE msg=audit(1615114233.002:2): 
+"#;
+        let ids = |events: &Rc<RefCell<Vec<Event>>>| -> Vec<String> {
+            events.borrow().iter().map(|e| e.id.to_string()).collect()
+        };
+
+        let events: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&events));
+        c.settings.filter_syscalls.insert("getdents64".into());
+        process_record(&mut c, input)?;
+        drop(c);
+        assert_eq!(ids(&events), vec!["1615114233.002:2"]);
+
+        let events: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&events));
+        c.settings.filter_allow_syscalls.insert("getdents64".into());
+        process_record(&mut c, input)?;
+        drop(c);
+        assert_eq!(ids(&events), vec!["1615114233.001:1"]);
+
         Ok(())
     }
 
-    /// Flush all in-flight event data, including partial events
-    pub fn flush(&mut self) {
-        self.expire_inflight(u64::MAX);
+    #[test]
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    fn env_redact() {
+        let patterns = Settings::default().execve_env_redact;
+        assert!(is_secret(b"AWS_SECRET_ACCESS_KEY", &patterns));
+        assert!(is_secret(b"github_token", &patterns));
+        assert!(is_secret(b"PGPASSWORD", &patterns));
+        assert!(!is_secret(b"LD_PRELOAD", &patterns));
+        assert!(!is_secret(b"LD_PRELOAD", &[]));
     }
-}
 
-impl Drop for Coalesce<'_, '_> {
-    fn drop(&mut self) {
-        self.flush();
+    #[test]
+    fn drop_record_types() -> Result<(), Box<dyn Error>> {
+        assert_eq!(
+            record_type(b"node=work type=CWD msg=audit(1615114232.375:15558): cwd=\"/\""),
+            Some(MessageType::CWD)
+        );
+        assert_eq!(
+            record_type(b"type=EOE msg=audit(1615114232.375:15558): "),
+            Some(MessageType::EOE)
+        );
+        assert_eq!(record_type(b"garbage"), None);
+
+        let ec: Rc<RefCell<Option<Event>>> = Rc::new(RefCell::new(None));
+        let mut c = Coalesce::new(mk_emit(&ec));
+        c.settings.drop_record_types = [MessageType::CWD, MessageType::EOE].into();
+        process_record(&mut c, include_bytes!("testdata/record-execve.txt"))?;
+        let ev = ec.borrow().as_ref().unwrap().clone();
+        assert!(!ev.body.contains_key(&MessageType::CWD));
+        assert!(ev.body.contains_key(&MessageType::PATH));
+        assert_eq!(c.dropped_records(), 1);
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use std::cell::RefCell;
-    use std::error::Error;
-    use std::io::{BufRead, BufReader};
-    use std::rc::Rc;
+    #[test]
+    fn filter_label() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Option<Event>>> = Rc::new(RefCell::new(None));
 
-    fn event_to_json(e: &Event) -> String {
-        let mut out = vec![];
-        crate::json::to_writer(&mut out, e).unwrap();
-        String::from_utf8_lossy(&out).to_string()
-    }
+        let mut c = Coalesce::new(mk_emit(&ec));
+        c.settings.filter_first_per_process = true;
+        c.settings
+            .proc_label_keys
+            .insert(Vec::from(&b"software_mgmt"[..]));
+        c.settings
+            .filter_labels
+            .insert(Vec::from(&b"software_mgmt"[..]));
+        c.settings
+            .proc_propagate_labels
+            .insert(Vec::from(&b"software_mgmt"[..]));
 
-    fn find_event<'a>(events: &'a [Event], id: &str) -> Option<Event<'a>> {
-        events.iter().find(|e| &e.id == id).cloned()
-    }
+        process_record(&mut c, include_bytes!("testdata/tree/00.txt"))?;
+        {
+            assert!(ec.borrow().as_ref().is_none());
+        }
 
-    fn strip_enriched<T>(text: T) -> Vec<u8>
-    where
-        T: AsRef<[u8]>,
-    {
-        let mut out = vec![];
-        for line in BufReader::new(text.as_ref()).lines() {
-            let line = line.unwrap().clone();
-            for c in line.as_bytes() {
-                match *c as char {
-                    '\x1d' => break,
-                    _ => out.push(*c),
-                };
-            }
-            out.push(b'\n');
+        process_record(&mut c, include_bytes!("testdata/tree/01.txt"))?;
+        {
+            assert!(ec.borrow().as_ref().is_none());
         }
-        out
-    }
 
-    fn process_record<T>(c: &mut Coalesce, text: T) -> Result<(), Box<dyn Error>>
-    where
-        T: AsRef<[u8]>,
-    {
-        for line in BufReader::new(text.as_ref())
-            .lines()
-            .filter(|line| match line {
-                Ok(l) if l.is_empty() => false,
-                Ok(l) if l.starts_with("#") => false,
-                _ => true,
-            })
+        process_record(&mut c, include_bytes!("testdata/record-login.txt"))?;
         {
-            let mut line = line.unwrap().clone();
-            line.push('\n');
-            c.process_line(line.as_bytes())?;
+            assert!(event_to_json(ec.borrow().as_ref().unwrap()).contains(r#"/usr/sbin/cron"#));
         }
+
+        drop(c);
+
         Ok(())
     }
 
     #[test]
-    fn coalesce() -> Result<(), Box<dyn Error>> {
-        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(Vec::new()));
-        let mut c = Coalesce::new(mk_emit_vec(&ec));
-
-        process_record(&mut c, include_bytes!("testdata/line-user-acct.txt"))?;
-        assert_eq!(
-            ec.borrow().last().unwrap().id,
-            EventID {
-                timestamp: 1615113648981,
-                sequence: 15220
-            }
-        );
-
-        if process_record(&mut c, include_bytes!("testdata/line-user-acct.txt")).is_ok() {
-            panic!("failed to detect duplicate entries");
-        };
+    fn filter_raw() {
+        for (name, filter) in &[
+            ("sockaddr", "^type=SOCKADDR (?:node=\\$*? )?msg=audit\\(\\S*?\\): saddr=01002F7661722F72756E2F6E7363642F736F636B657400"),
+            ("syscall", "^type=SYSCALL (?:node=\\$*? )?msg=audit\\(.*?\\): arch=c000003e syscall=42 success=no"),
+        ] {
+            let events: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+            let mut c = Coalesce::new(mk_emit_vec(&events));
+            c.settings.filter_raw_lines = regex::bytes::RegexSet::new([
+                filter
+            ])
+                .expect("failed to compile regex");
+            c.settings.filter_first_per_process = true;
+            process_record(&mut c, include_bytes!("testdata/record-nscd.txt")).unwrap();
+            assert!(
+                !events
+                    .borrow()
+                    .iter()
+                    .any(|e| &e.id == "1705071450.879:29498378"),
+                "nscd connect event should be filtered using {name}"
+            )
+        }
+    }
 
+    #[test]
+    fn filter_presets() -> Result<(), Box<dyn Error>> {
+        let events: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&events));
+        c.settings.filter_presets = [FilterPreset::QuietSystemd, FilterPreset::BuildServer]
+            .iter()
+            .map(|p| (*p, p.regex_set()))
+            .collect();
+        process_record(&mut c, br#"type=SYSCALL msg=audit(1615114232.375:15558): arch=c000003e syscall=257 success=yes exit=3 a0=ffffff9c a1=55d3ee2c2a50 a2=80000 a3=0 items=1 ppid=1 pid=412 auid=4294967295 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=(none) ses=4294967295 comm="systemd-journal" exe="/usr/lib/systemd/systemd-journald" key=(null)
+type=EOE msg=audit(1615114232.375:15558): 
+type=SYSCALL msg=audit(1615114232.376:15559): arch=c000003e syscall=257 success=yes exit=3 a0=ffffff9c a1=55d3ee2c2a50 a2=80000 a3=0 items=1 ppid=1 pid=412 auid=4294967295 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=(none) ses=4294967295 comm="systemd-journal" exe="/usr/lib/systemd/systemd-journald" key=(null)
+type=EOE msg=audit(1615114232.376:15559): 
+"#)?;
         process_record(&mut c, include_bytes!("testdata/record-execve.txt"))?;
-        assert_eq!(
-            ec.borrow().last().unwrap().id,
-            EventID {
-                timestamp: 1615114232375,
-                sequence: 15558
-            }
+        c.emit_heartbeat(&[]);
+
+        let events = events.borrow();
+        assert_eq!(events.len(), 2);
+        assert_eq!(c.preset_filtered().len(), 1);
+        assert_eq!(c.preset_filtered().get("quiet-systemd"), Some(&2));
+        let output = event_to_json(&events[1]);
+        assert!(
+            output.contains(r#""preset_filtered":{"quiet-systemd":2}"#),
+            "{output}"
         );
+        Ok(())
+    }
 
-        process_record(&mut c, include_bytes!("testdata/record-execve-long.txt"))?;
-        assert_eq!(
-            ec.borrow().last().unwrap().id,
-            EventID {
-                timestamp: 1615150974493,
-                sequence: 21028
+    #[test]
+    #[cfg(target_endian = "little")]
+    fn filter_sockaddr() {
+        for filter in &[
+            &["127.0.0.1", "::1"][..],
+            &["127.0.0.0/8", "::/64"],
+            &["127.0.0.1:11211", "[::1]:11211"],
+            &["127.0.0.0/8:11211", "[::/64]:11211"],
+            &["*:11211"],
+        ] {
+            {
+                let events: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+                let mut c = Coalesce::new(mk_emit_vec(&events));
+                c.settings.filter_first_per_process = true;
+                c.settings.filter_sockaddr = filter.iter().map(|s| s.parse().unwrap()).collect();
+                process_record(&mut c, include_bytes!("testdata/record-connect.txt")).unwrap();
+                let events = events.borrow();
+                println!("{events:?}");
+                assert!(!events.iter().any(|e| &e.id == "1723819442.459:2482681"));
+                assert!(!events.iter().any(|e| &e.id == "1723819442.459:2482682"));
             }
-        );
+        }
+    }
 
-        process_record(
-            &mut c,
-            include_bytes!("testdata/record-anom-promiscuous.txt"),
-        )?;
-        let output = event_to_json(ec.borrow().last().unwrap());
-        assert!(
-            output.contains(r#""saddr":"%10%00%00%00%00%00%00%00%00%00%00%00""#),
-            "SOCKADDR.saddr blob is encoded correctly"
+    fn gen_long_find_execve() -> Vec<u8> {
+        let mut buf = vec![];
+        let msgid = "1663143990.204:2148478";
+        let npath = 40000;
+
+        buf.extend(
+            format!(r#"type=SYSCALL msg=audit({msgid}): arch=c000003e syscall=59 success=yes exit=0 a0=1468e584be18 a1=1468e57f5078 a2=1468e584bd68 a3=7ffc3e352220 items=2 ppid=9264 pid=9279 auid=4294967295 uid=995 gid=992 euid=995 suid=995 fsuid=995 egid=992 sgid=992 fsgid=992 tty=(none) ses=4294967295 comm="find" exe="/usr/bin/find" key=(null)
+"#).bytes());
+        buf.extend(
+            format!(
+                r#"type=EXECVE msg=audit({msgid}): argc={} a0="/usr/bin/find" "#,
+                npath + 9
+            )
+            .bytes(),
         );
+        for i in 1..npath {
+            if i % 70 == 0 {
+                buf.extend(format!("\ntype=EXECVE msg=audit({msgid}): ").bytes());
+            } else {
+                buf.push(b' ');
+            }
+            buf.extend(format!(r#"a{i}="/opt/app/redacted/to/protect/the/guilty/output_processing.2022-09-06.{i:05}.garbage""#).bytes());
+        }
+        for (i, param) in [
+            "-type",
+            "f",
+            "-mtime",
+            "+7",
+            "-exec",
+            "/usr/bin/rm",
+            "-f",
+            "{}",
+            ";",
+        ]
+        .iter()
+        .enumerate()
+        {
+            buf.extend(format!(r#" a{}="{param}""#, npath + i).bytes());
+        }
+        buf.extend(format!("\ntype=EOE msg=audit({msgid}): \n").bytes());
+        buf
+    }
 
-        // recordds do not begin with SYSCALL.
-        process_record(&mut c, include_bytes!("testdata/record-login.txt"))?;
-        process_record(&mut c, include_bytes!("testdata/record-adjntpval.txt"))?;
-        process_record(&mut c, include_bytes!("testdata/record-avc-apparmor.txt"))?;
+    #[test]
+    fn strip_long_argv() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Option<Event>>> = Rc::new(RefCell::new(None));
+
+        let mut c = Coalesce::new(mk_emit(&ec));
+
+        c.settings.execve_argv_limit_bytes = Some(10000);
+        let buf = gen_long_find_execve();
+
+        process_record(&mut c, &buf)?;
+        {
+            let output = event_to_json(ec.borrow().as_ref().unwrap());
+            assert!(output.len() < 15000);
+            assert!(
+                output.contains(".00020.garbage"),
+                "Can't find start of argv"
+            );
+            assert!(output.contains(".39980.garbage"), "Can't find end of argv");
+            assert!(
+                !output.contains(".20000.garbage"),
+                "Should not see middle of argv"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn execve_argv_derived() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Option<Event>>> = Rc::new(RefCell::new(None));
+
+        let mut c = Coalesce::new(mk_emit(&ec));
+        c.settings.execve_argv0_base = true;
+        c.settings.execve_cmdline = true;
+        c.settings.execve_argv_flags = true;
 
-        let mut c = Coalesce::new(mk_emit_vec(&ec));
-        c.settings.translate_userdb = true;
-        c.settings.drop_translated = true;
         process_record(
             &mut c,
-            strip_enriched(include_bytes!("testdata/record-execve.txt")),
+            br#"type=SYSCALL msg=audit(1663143990.204:2148478): arch=c000003e syscall=59 success=yes exit=0 a0=1468e584be18 a1=1468e57f5078 a2=1468e584bd68 a3=7ffc3e352220 items=2 ppid=9264 pid=9279 auid=4294967295 uid=995 gid=992 euid=995 suid=995 fsuid=995 egid=992 sgid=992 fsgid=992 tty=(none) ses=4294967295 comm="rm" exe="/usr/bin/rm" key=(null)
+type=EXECVE msg=audit(1663143990.204:2148478): argc=6 a0="/usr/bin/rm" a1="-rf" a2="--one-file-system" a3="--interactive=never" a4="--" a5=2F746D702F6120622D6327
+type=EOE msg=audit(1663143990.204:2148478): 
+"#,
         )?;
-        let gid0name = nix::unistd::Group::from_gid(0.into())
-            .unwrap()
-            .unwrap()
-            .name;
-        let output = event_to_json(ec.borrow().last().unwrap());
+        let output = event_to_json(ec.borrow().as_ref().unwrap());
         println!("{output}");
-        assert!(
-            output.contains(r#""UID":"root","#),
-            "output contains translated UID"
-        );
-        assert!(
-            output.contains(&format!(r#""EGID":"{gid0name}","#)),
-            "output contains translated EGID"
-        );
-        assert!(
-            !output.contains(r#""uid":0,"#),
-            "output does not contain raw uid"
-        );
-        assert!(
-            !output.contains(r#""egid":0,"#),
-            "output does not contain raw egid"
-        );
-        assert!(
-            output.contains(r#"NODE":"work","#),
-            "node name is encoded correctly."
-        );
+        assert!(output.contains(r#""ARGV0_BASE":"rm""#));
+        assert!(output.contains(
+            r#""CMDLINE":"/usr/bin/rm -rf --one-file-system --interactive=never -- '/tmp/a b-c'\\'''"#
+        ));
+        assert!(output.contains(r#""ARGV_FLAGS":["-r","-f","--one-file-system","--interactive"]"#));
 
         Ok(())
     }
 
     #[test]
-    fn duplicate_uids() {
-        let ec = Rc::new(RefCell::new(None));
+    fn path_items() -> Result<(), Box<dyn Error>> {
+        let text = br#"type=SYSCALL msg=audit(1615114232.375:15558): arch=c000003e syscall=257 success=yes exit=3 a0=ffffff9c a1=7ffd4b7b1b1a a2=941 a3=1b6 items=2 ppid=10883 pid=10884 auid=1000 uid=1000 gid=1000 euid=1000 suid=1000 fsuid=1000 egid=1000 sgid=1000 fsgid=1000 tty=pts1 ses=1 comm="touch" exe="/usr/bin/touch" key=(null)
+type=CWD msg=audit(1615114232.375:15558): cwd="/home/user"
+type=PATH msg=audit(1615114232.375:15558): item=1 name="/tmp/new" inode=262147 dev=ca:03 mode=0100644 ouid=1000 ogid=1000 rdev=00:00 nametype=CREATE cap_fp=0 cap_fi=0 cap_fe=0 cap_fver=0
+type=PATH msg=audit(1615114232.375:15558): item=0 name="/tmp/" inode=262145 dev=ca:03 mode=041777 ouid=0 ogid=0 rdev=00:00 nametype=PARENT cap_fp=0 cap_fi=0 cap_fe=0 cap_fver=0
+type=EOE msg=audit(1615114232.375:15558): 
+"#;
+        for (nametypes, expected) in [
+            (None, &[&b"/tmp/"[..], b"/tmp/new"][..]),
+            (Some(vec![b"CREATE".to_vec()]), &[&b"/tmp/new"[..]][..]),
+            (Some(vec![b"DELETE".to_vec()]), &[][..]),
+        ] {
+            let ec: Rc<RefCell<Option<Event>>> = Rc::new(RefCell::new(None));
+            let mut c = Coalesce::new(mk_emit(&ec));
+            c.settings.path_nametypes = nametypes.map(|v| v.into_iter().collect());
+            process_record(&mut c, text)?;
+
+            let ev = ec.borrow();
+            let names: Vec<&[u8]> = match ev.as_ref().unwrap().body.get(&MessageType::PATH) {
+                Some(EventValues::Multi(paths)) => paths
+                    .iter()
+                    .filter_map(|p| match p.get("name") {
+                        Some(Value::Str(s, _)) => Some(*s),
+                        _ => None,
+                    })
+                    .collect(),
+                None => vec![],
+                _ => panic!("expected EventValues::Multi"),
+            };
+            assert_eq!(names, expected);
+        }
+        Ok(())
+    }
 
-        let mut c = Coalesce::new(mk_emit(&ec));
-        c.settings.enrich_uid_groups = false;
-        c.settings.enrich_pid = false;
-        c.settings.translate_userdb = true;
-        c.settings.translate_universal = true;
-        process_record(&mut c, include_bytes!("testdata/record-login.txt")).unwrap();
-        if let EventValues::Multi(records) =
-            &ec.borrow().as_ref().unwrap().body[&MessageType::LOGIN]
-        {
-            // Check for: pid uid subj old-auid auid tty old-ses ses res UID OLD-AUID AUID
-            let l = records[0].len();
-            assert!(
-                l == 12,
-                "expected 12 fields, got {l}: {:?}",
-                records[0].clone().into_iter().collect::<Vec<_>>()
-            );
-        } else {
-            panic!("expected EventValues::Multi");
+    #[test]
+    fn file_action() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&ec));
+        c.settings.enrich_file_action = true;
+        process_record(&mut c, br#"type=SYSCALL msg=audit(1615114232.375:15560): arch=c000003e syscall=82 success=yes exit=0 a0=7ffd4b7b1b1a a1=7ffd4b7b1b2a a2=0 a3=0 items=5 ppid=10883 pid=10885 auid=1000 uid=1000 gid=1000 euid=1000 suid=1000 fsuid=1000 egid=1000 sgid=1000 fsgid=1000 tty=pts1 ses=1 comm="mv" exe="/usr/bin/mv" key=(null)
+type=CWD msg=audit(1615114232.375:15560): cwd="/tmp"
+type=PATH msg=audit(1615114232.375:15560): item=0 name="/tmp/" inode=262145 dev=ca:03 mode=041777 ouid=0 ogid=0 rdev=00:00 nametype=PARENT cap_fp=0 cap_fi=0 cap_fe=0 cap_fver=0
+type=PATH msg=audit(1615114232.375:15560): item=1 name="/tmp/" inode=262145 dev=ca:03 mode=041777 ouid=0 ogid=0 rdev=00:00 nametype=PARENT cap_fp=0 cap_fi=0 cap_fe=0 cap_fver=0
+type=PATH msg=audit(1615114232.375:15560): item=2 name="a" inode=262147 dev=ca:03 mode=0100644 ouid=1000 ogid=1000 rdev=00:00 nametype=DELETE cap_fp=0 cap_fi=0 cap_fe=0 cap_fver=0
+type=PATH msg=audit(1615114232.375:15560): item=3 name="b" inode=262148 dev=ca:03 mode=0100644 ouid=1000 ogid=1000 rdev=00:00 nametype=DELETE cap_fp=0 cap_fi=0 cap_fe=0 cap_fver=0
+type=PATH msg=audit(1615114232.375:15560): item=4 name="b" inode=262147 dev=ca:03 mode=0100644 ouid=1000 ogid=1000 rdev=00:00 nametype=CREATE cap_fp=0 cap_fi=0 cap_fe=0 cap_fver=0
+type=EOE msg=audit(1615114232.375:15560): 
+type=SYSCALL msg=audit(1615114232.376:15561): arch=c000003e syscall=257 success=yes exit=3 a0=ffffff9c a1=7ffd4b7b1b1a a2=241 a3=1b6 items=2 ppid=10883 pid=10886 auid=1000 uid=1000 gid=1000 euid=1000 suid=1000 fsuid=1000 egid=1000 sgid=1000 fsgid=1000 tty=pts1 ses=1 comm="sh" exe="/usr/bin/sh" key=(null)
+type=CWD msg=audit(1615114232.376:15561): cwd="/tmp"
+type=PATH msg=audit(1615114232.376:15561): item=0 name="/tmp/" inode=262145 dev=ca:03 mode=041777 ouid=0 ogid=0 rdev=00:00 nametype=PARENT cap_fp=0 cap_fi=0 cap_fe=0 cap_fver=0
+type=PATH msg=audit(1615114232.376:15561): item=1 name="b" inode=262147 dev=ca:03 mode=0100644 ouid=1000 ogid=1000 rdev=00:00 nametype=NORMAL cap_fp=0 cap_fi=0 cap_fe=0 cap_fver=0
+type=EOE msg=audit(1615114232.376:15561): 
+"#)?;
+
+        let actions = |id: &str| -> Vec<Option<&'static str>> {
+            let events = ec.borrow();
+            let ev = find_event(&events, id).unwrap();
+            let Some(EventValues::Multi(paths)) = ev.body.get(&MessageType::PATH) else {
+                panic!("expected EventValues::Multi");
+            };
+            paths
+                .iter()
+                .map(|p| match p.get("FILE_ACTION") {
+                    Some(Value::Literal(s)) => Some(*s),
+                    _ => None,
+                })
+                .collect()
         };
+        assert_eq!(
+            actions("1615114232.375:15560"),
+            [
+                None,
+                None,
+                Some("renamed_from"),
+                Some("deleted"),
+                Some("renamed_to")
+            ]
+        );
+        assert_eq!(actions("1615114232.376:15561"), [None, Some("truncated")]);
+        Ok(())
     }
 
     #[test]
-    fn keep_enriched_syscalls() {
-        let ec = Rc::new(RefCell::new(None));
+    fn written_by() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&ec)).with_settings(Settings {
+            enrich_written_by: true,
+            ..Settings::default()
+        });
+        process_record(&mut c, br#"type=SYSCALL msg=audit(1615114232.376:15561): arch=c000003e syscall=257 success=yes exit=3 a0=ffffff9c a1=7ffd4b7b1b1a a2=241 a3=1b6 items=2 ppid=10883 pid=10886 auid=1000 uid=1000 gid=1000 euid=1000 suid=1000 fsuid=1000 egid=1000 sgid=1000 fsgid=1000 tty=pts1 ses=1 comm="curl" exe="/usr/bin/curl" key=(null)
+type=CWD msg=audit(1615114232.376:15561): cwd="/tmp"
+type=PATH msg=audit(1615114232.376:15561): item=0 name="/tmp/" inode=262145 dev=ca:03 mode=041777 ouid=0 ogid=0 rdev=00:00 nametype=PARENT cap_fp=0 cap_fi=0 cap_fe=0 cap_fver=0
+type=PATH msg=audit(1615114232.376:15561): item=1 name="x" inode=262147 dev=ca:03 mode=0100755 ouid=1000 ogid=1000 rdev=00:00 nametype=NORMAL cap_fp=0 cap_fi=0 cap_fe=0 cap_fver=0
+type=EOE msg=audit(1615114232.376:15561): 
+type=SYSCALL msg=audit(1615114233.001:15562): arch=c000003e syscall=59 success=yes exit=0 a0=55d3ee2c2a50 a1=55d3ee2c2a70 a2=55d3ee2c2a80 a3=0 items=2 ppid=10883 pid=10887 auid=1000 uid=1000 gid=1000 euid=1000 suid=1000 fsuid=1000 egid=1000 sgid=1000 fsgid=1000 tty=pts1 ses=1 comm="x" exe="/tmp/x" key=(null)
+type=EXECVE msg=audit(1615114233.001:15562): argc=1 a0="/tmp/x"
+type=CWD msg=audit(1615114233.001:15562): cwd="/tmp"
+type=PATH msg=audit(1615114233.001:15562): item=0 name="/tmp/x" inode=262147 dev=ca:03 mode=0100755 ouid=1000 ogid=1000 rdev=00:00 nametype=NORMAL cap_fp=0 cap_fi=0 cap_fe=0 cap_fver=0
+type=PATH msg=audit(1615114233.001:15562): item=1 name="/lib64/ld-linux-x86-64.so.2" inode=262146 dev=ca:03 mode=0100755 ouid=0 ogid=0 rdev=00:00 nametype=NORMAL cap_fp=0 cap_fi=0 cap_fe=0 cap_fver=0
+type=EOE msg=audit(1615114233.001:15562): 
+"#)?;
+
+        let events = ec.borrow();
+        let output = event_to_json(&find_event(&events, "1615114232.376:15561").unwrap());
+        assert!(!output.contains("WRITTEN_BY"), "{output}");
+        let output = event_to_json(&find_event(&events, "1615114233.001:15562").unwrap());
+        println!("{output}");
+        assert!(
+            output.contains(r#""name":"/tmp/x","#)
+                && output.contains(r#""WRITTEN_BY":{"#)
+                && output.contains(r#""exe":"/usr/bin/curl""#),
+            "{output}"
+        );
+        assert_eq!(output.matches("WRITTEN_BY").count(), 1, "{output}");
+        Ok(())
+    }
 
-        let mut c = Coalesce::new(mk_emit(&ec));
-        process_record(&mut c, include_bytes!("testdata/record-execve.txt")).unwrap();
-        assert!(event_to_json(ec.borrow().as_ref().unwrap()).contains(r#""ARCH":"x86_64""#));
-        assert!(event_to_json(ec.borrow().as_ref().unwrap()).contains(r#""SYSCALL":"execve""#));
+    #[test]
+    fn parent_stubs() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&ec)).with_settings(Settings {
+            enrich_parent_stubs: true,
+            ..Settings::default()
+        });
+        process_record(&mut c, br#"type=SYSCALL msg=audit(1615114233.001:15562): arch=c000003e syscall=59 success=yes exit=0 a0=55d3ee2c2a50 a1=55d3ee2c2a70 a2=55d3ee2c2a80 a3=0 items=1 ppid=1 pid=3999999 auid=1000 uid=1000 gid=1000 euid=1000 suid=1000 fsuid=1000 egid=1000 sgid=1000 fsgid=1000 tty=pts1 ses=1 comm="sh" exe="/usr/bin/sh" key=(null)
+type=EXECVE msg=audit(1615114233.001:15562): argc=1 a0="sh"
+type=EOE msg=audit(1615114233.001:15562): 
+"#)?;
+        // The parent exits and is forgotten.
+        c.state.processes.processes.clear();
+        c.state.processes.current.clear();
+        process_record(&mut c, br#"type=SYSCALL msg=audit(1615114233.101:15563): arch=c000003e syscall=59 success=yes exit=0 a0=55d3ee2c2a50 a1=55d3ee2c2a70 a2=55d3ee2c2a80 a3=0 items=1 ppid=3999999 pid=4000000 auid=1000 uid=1000 gid=1000 euid=1000 suid=1000 fsuid=1000 egid=1000 sgid=1000 fsgid=1000 tty=pts1 ses=1 comm="id" exe="/usr/bin/id" key=(null)
+type=EXECVE msg=audit(1615114233.101:15563): argc=1 a0="id"
+type=EOE msg=audit(1615114233.101:15563): 
+"#)?;
+
+        let events = ec.borrow();
+        let output = event_to_json(&find_event(&events, "1615114233.101:15563").unwrap());
+        assert!(
+            output.contains(r#""PPID":{"EVENT_ID":"1615114233.001:15562","comm":"sh","exe":"/usr/bin/sh","ppid":1,"CONFIDENCE":"low"}"#),
+            "{output}"
+        );
+        Ok(())
     }
 
     #[test]
-    fn translate_uids() {
-        let ec = Rc::new(RefCell::new(None));
+    fn analyze_suspicious_shell() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&ec));
+        c.settings.analyze_suspicious_shell = Some(SuspiciousShell {
+            service_exe: regex::bytes::RegexSet::new(["^/usr/sbin/nginx$"])?,
+            ..SuspiciousShell::default()
+        });
+        process_record(&mut c, br#"type=SYSCALL msg=audit(1615114233.001:15562): arch=c000003e syscall=59 success=yes exit=0 a0=55d3ee2c2a50 a1=55d3ee2c2a70 a2=55d3ee2c2a80 a3=0 items=1 ppid=1 pid=3999999 auid=4294967295 uid=33 gid=33 euid=33 suid=33 fsuid=33 egid=33 sgid=33 fsgid=33 tty=(none) ses=4294967295 comm="nginx" exe="/usr/sbin/nginx" key=(null)
+type=EXECVE msg=audit(1615114233.001:15562): argc=1 a0="nginx"
+type=EOE msg=audit(1615114233.001:15562): 
+type=SYSCALL msg=audit(1615114233.101:15563): arch=c000003e syscall=59 success=yes exit=0 a0=55d3ee2c2a50 a1=55d3ee2c2a70 a2=55d3ee2c2a80 a3=0 items=1 ppid=3999999 pid=4000000 auid=4294967295 uid=33 gid=33 euid=33 suid=33 fsuid=33 egid=33 sgid=33 fsgid=33 tty=(none) ses=4294967295 comm="bash" exe="/usr/bin/bash" key=(null)
+type=EXECVE msg=audit(1615114233.101:15563): argc=2 a0="bash" a1="-i"
+type=EOE msg=audit(1615114233.101:15563): 
+"#)?;
+
+        let events = ec.borrow();
+        let output = event_to_json(&find_event(&events, "1615114233.001:15562").unwrap());
+        assert!(!output.contains("suspicious_shell"), "{output}");
+        let output = event_to_json(&find_event(&events, "1615114233.101:15563").unwrap());
+        assert!(
+            output.contains(r#""LABELS":["suspicious_shell"]"#),
+            "{output}"
+        );
+        Ok(())
+    }
 
-        let gid0name = nix::unistd::Group::from_gid(0.into())
-            .unwrap()
-            .unwrap()
-            .name;
+    #[test]
+    fn analyze_exec_from_tmp() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&ec));
+        c.settings.analyze_exec_from_tmp = Some(ExecFromTmp::default());
+        process_record(&mut c, br#"type=SYSCALL msg=audit(1615114233.001:15562): arch=c000003e syscall=59 success=yes exit=0 a0=55d3ee2c2a50 a1=55d3ee2c2a70 a2=55d3ee2c2a80 a3=0 items=2 ppid=1 pid=3999999 auid=1000 uid=1000 gid=1000 euid=1000 suid=1000 fsuid=1000 egid=1000 sgid=1000 fsgid=1000 tty=pts1 ses=1 comm="x" exe="/dev/shm/x" key=(null)
+type=EXECVE msg=audit(1615114233.001:15562): argc=1 a0="./x"
+type=PATH msg=audit(1615114233.001:15562): item=0 name="./x" inode=262147 dev=00:1a mode=0100755 ouid=1000 ogid=1000 rdev=00:00 nametype=NORMAL cap_fp=0 cap_fi=0 cap_fe=0 cap_fver=0
+type=PATH msg=audit(1615114233.001:15562): item=1 name="/lib64/ld-linux-x86-64.so.2" inode=262146 dev=ca:03 mode=0100755 ouid=0 ogid=0 rdev=00:00 nametype=NORMAL cap_fp=0 cap_fi=0 cap_fe=0 cap_fver=0
+type=EOE msg=audit(1615114233.001:15562): 
+type=SYSCALL msg=audit(1615114233.101:15563): arch=c000003e syscall=59 success=yes exit=0 a0=55d3ee2c2a50 a1=55d3ee2c2a70 a2=55d3ee2c2a80 a3=0 items=2 ppid=1 pid=4000000 auid=1000 uid=1000 gid=1000 euid=1000 suid=1000 fsuid=1000 egid=1000 sgid=1000 fsgid=1000 tty=pts1 ses=1 comm="id" exe="/usr/bin/id" key=(null)
+type=EXECVE msg=audit(1615114233.101:15563): argc=1 a0="id"
+type=PATH msg=audit(1615114233.101:15563): item=0 name="/usr/bin/id" inode=262148 dev=ca:03 mode=0100755 ouid=0 ogid=0 rdev=00:00 nametype=NORMAL cap_fp=0 cap_fi=0 cap_fe=0 cap_fver=0
+type=PATH msg=audit(1615114233.101:15563): item=1 name="/lib64/ld-linux-x86-64.so.2" inode=262146 dev=ca:03 mode=0100755 ouid=0 ogid=0 rdev=00:00 nametype=NORMAL cap_fp=0 cap_fi=0 cap_fe=0 cap_fver=0
+type=EOE msg=audit(1615114233.101:15563): 
+"#)?;
+
+        let events = ec.borrow();
+        let output = event_to_json(&find_event(&events, "1615114233.001:15562").unwrap());
+        assert!(output.contains(r#""LABELS":["exec_from_tmp"]"#), "{output}");
+        let output = event_to_json(&find_event(&events, "1615114233.101:15563").unwrap());
+        assert!(!output.contains("exec_from_tmp"), "{output}");
+        assert_eq!(c.analyzer_hits(), 1);
+        Ok(())
+    }
 
-        let mut c = Coalesce::new(|e: &Event| *ec.borrow_mut() = Some(e.clone()));
-        c.settings.translate_userdb = true;
-        c.settings.translate_universal = true;
-        process_record(
-            &mut c,
-            strip_enriched(include_bytes!("testdata/record-login.txt")),
-        )
-        .unwrap();
+    #[test]
+    fn analyze_attack() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Option<Event>>> = Rc::new(RefCell::new(None));
+        let mut c = Coalesce::new(mk_emit(&ec));
+        c.settings.analyze_attack = Some(crate::analyze::Attack::default().rules());
+        process_record(&mut c, include_bytes!("testdata/record-execve.txt"))?;
+        let output = event_to_json(ec.borrow().as_ref().unwrap());
+        assert!(output.contains(r#""TAGS":["T1033"]"#), "{output}");
+        assert_eq!(c.analyzer_hits(), 1);
 
-        if let EventValues::Single(record) =
-            &ec.borrow().as_ref().unwrap().body[&MessageType::SYSCALL]
-        {
-            let mut uids = 0;
-            let mut gids = 0;
-            for (k, v) in record {
-                if k.to_string().ends_with("UID") {
-                    uids += 1;
-                    assert!(v == "root", "Got {k}={v:?}, expected root");
-                }
-                if k.to_string().ends_with("GID") {
-                    gids += 1;
-                    assert!(v == gid0name.as_str(), "Got {k}={v:?}, expected root");
-                }
-            }
-            assert!(
-                uids == 5 && gids == 4,
-                "Got {uids} uids/{gids} gids, expected 5/4",
-            );
-        }
+        process_record(&mut c, include_bytes!("testdata/record-login.txt"))?;
+        let output = event_to_json(ec.borrow().as_ref().unwrap());
+        assert!(!output.contains("TAGS"), "{output}");
+        Ok(())
+    }
 
-        if let EventValues::Multi(records) =
-            &ec.borrow().as_ref().unwrap().body[&MessageType::LOGIN]
-        {
-            let mut uid = false;
-            let mut old_auid = false;
-            let mut auid = false;
-            // UID="root" OLD-AUID="unset" AUID="root"
-            for (k, v) in &records[0] {
-                if k == "UID" && v == "root" {
-                    uid = true;
-                }
-                if k == "OLD-AUID" && v == "unset" {
-                    old_auid = true;
-                }
-                if k == "AUID" && v == "root" {
-                    auid = true;
-                }
-            }
-            assert!(
-                uid,
-                "missing UID: {:?}",
-                records[0].clone().into_iter().collect::<Vec<_>>()
-            );
-            assert!(
-                old_auid,
-                "missing OLD-AUID: {:?}",
-                records[0].clone().into_iter().collect::<Vec<_>>()
-            );
-            assert!(
-                auid,
-                "missing AUID: {:?}",
-                records[0].clone().into_iter().collect::<Vec<_>>()
-            );
-        } else {
-            panic!("expected EventValues::Multi");
-        };
+    #[test]
+    fn emit_rules() {
+        use crate::rules::{Field, FieldValue};
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&ec));
+        c.emit_rules(&[Rule {
+            list: 4,
+            action: 2,
+            syscalls: (0..2048).collect(),
+            fields: vec![Field {
+                field: 210,
+                op: 0x40000000,
+                value: FieldValue::Str(b"all".to_vec()),
+            }],
+        }]);
+        let events = ec.borrow();
+        let output = event_to_json(&events[0]);
+        assert!(
+            output.contains(
+                r#""LAUREL_RULES":[{"rule":"-a always,exit -S all -F key=all","key":"all"}]"#
+            ),
+            "{output}"
+        );
     }
 
     #[test]
-    fn translate_userdb_execve() {
-        let ec = Rc::new(RefCell::new(None));
+    fn emit_rules_check() {
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&ec));
+        c.emit_rules_check(&[
+            (
+                "label-process.label-argv",
+                Requirement::Syscalls(&["execve", "execveat"]),
+            ),
+            ("filter.filter-keys", Requirement::Key("noise".into())),
+        ]);
+        let output = event_to_json(&ec.borrow()[0]);
+        assert!(
+            output.contains(
+                r#""LAUREL_RULES_CHECK":[{"feature":"label-process.label-argv","missing":"rules for execve, execveat"},{"feature":"filter.filter-keys","missing":"rules with key \"noise\""}]"#
+            ),
+            "{output}"
+        );
+    }
 
-        let gid0name = nix::unistd::Group::from_gid(0.into())
-            .unwrap()
-            .unwrap()
-            .name;
+    #[test]
+    fn emit_heartbeat() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&ec));
+        c.emit_heartbeat(&[("lines", 0)]);
+        process_record(&mut c, br#"type=SYSCALL msg=audit(1615114233.001:15562): arch=c000003e syscall=59 success=yes exit=0 a0=55d3ee2c2a50 a1=55d3ee2c2a70 a2=55d3ee2c2a80 a3=0 items=1 ppid=1 pid=3999999 auid=1000 uid=1000 gid=1000 euid=1000 suid=1000 fsuid=1000 egid=1000 sgid=1000 fsgid=1000 tty=pts1 ses=1 comm="id" exe="/usr/bin/id" key=(null)
+type=EOE msg=audit(1615114233.001:15562): 
+"#)?;
+        c.emit_heartbeat(&[("lines", 2)]);
+
+        let events = ec.borrow();
+        let output = event_to_json(&events[0]);
+        assert!(
+            output.contains(r#""LAUREL_HEARTBEAT":{"version":""#),
+            "{output}"
+        );
+        assert!(!output.contains("last_event"), "{output}");
+        let output = event_to_json(&events[2]);
+        assert!(output.contains(r#""lines":2,"processes":"#), "{output}");
+        assert!(
+            output.contains(r#""last_event":"1615114233.001:15562"}"#),
+            "{output}"
+        );
+        Ok(())
+    }
 
-        let mut c = Coalesce::new(|e: &Event| *ec.borrow_mut() = Some(e.clone()));
-        c.settings.translate_userdb = true;
-        c.settings.translate_universal = true;
+    #[test]
+    fn host_metadata() -> Result<(), Box<dyn Error>> {
+        for (mode, in_events) in [(HostMetadata::Heartbeat, false), (HostMetadata::All, true)] {
+            let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+            let mut c = Coalesce::new(mk_emit_vec(&ec)).with_settings(Settings {
+                enrich_host_metadata: mode,
+                ..Settings::default()
+            });
+            c.emit_heartbeat(&[("lines", 0)]);
+            process_record(&mut c, include_bytes!("testdata/record-execve.txt"))?;
+
+            let events = ec.borrow();
+            let output = event_to_json(&events[0]);
+            assert!(output.contains(r#""LAUREL_HOST":{"#), "{output}");
+            assert!(output.contains(r#""version":""#), "{output}");
+            let output = event_to_json(&events[1]);
+            assert_eq!(output.contains("LAUREL_HOST"), in_events, "{output}");
+        }
 
+        // Host metadata describes the local host only.
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&ec)).with_settings(Settings {
+            enrich_host_metadata: HostMetadata::All,
+            local_node: Some(b"local".to_vec()),
+            ..Settings::default()
+        });
         process_record(
             &mut c,
-            strip_enriched(include_bytes!("testdata/record-execve.txt")),
-        )
-        .unwrap();
-
-        let j = event_to_json(ec.borrow().as_ref().unwrap());
-        println!("{j}");
-        assert!(j.contains(r#""OUID":"root""#));
-        assert!(j.contains(&format!(r#""OGID":"{gid0name}""#)));
+            r#"node=remote type=SYSCALL msg=audit(1615114232.375:1): arch=c000003e syscall=59 success=yes exit=0 a0=63b29337fd18 a1=63b293387d58 a2=63b293375640 a3=fffffffffffff000 items=0 ppid=10883 pid=10884 auid=1000 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=pts1 ses=1 comm="true" exe="/bin/true" key=(null)
+node=remote type=EOE msg=audit(1615114232.375:1): 
+node=local type=SYSCALL msg=audit(1615114232.375:2): arch=c000003e syscall=59 success=yes exit=0 a0=63b29337fd18 a1=63b293387d58 a2=63b293375640 a3=fffffffffffff000 items=0 ppid=10883 pid=10884 auid=1000 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=pts1 ses=1 comm="true" exe="/bin/true" key=(null)
+node=local type=EOE msg=audit(1615114232.375:2): 
+"#,
+        )?;
+        let events = ec.borrow();
+        let output = event_to_json(&events[0]);
+        assert!(!output.contains("LAUREL_HOST"), "{output}");
+        let output = event_to_json(&events[1]);
+        assert!(output.contains("LAUREL_HOST"), "{output}");
+        Ok(())
     }
 
     #[test]
-    fn translate_userdb_ptrace() {
-        let ec = Rc::new(RefCell::new(None));
-
-        let mut c = Coalesce::new(|e: &Event| *ec.borrow_mut() = Some(e.clone()));
-        c.settings.translate_userdb = true;
-        c.settings.translate_universal = true;
+    #[cfg(target_os = "linux")]
+    fn enrich_login_session() -> Result<(), Box<dyn Error>> {
+        use crate::utmp::{record, USER_PROCESS};
+        let td = nix::unistd::mkdtemp(&std::env::temp_dir().join("laurel-test-XXXXXXXX"))?;
+        let path = td.join("utmp");
+        let mut buf = record(USER_PROCESS, 4000, "pts/1", "alice", "192.0.2.1");
+        buf.extend(record(USER_PROCESS, 4100, "tty1", "root", ""));
+        std::fs::write(&path, buf)?;
 
+        let ec: Rc<RefCell<Option<Event>>> = Rc::new(RefCell::new(None));
+        let mut c = Coalesce::new(mk_emit(&ec));
+        c.login_sessions = Some(SessionTable::new(&path));
         process_record(
             &mut c,
-            strip_enriched(include_bytes!("testdata/record-ptrace.txt")),
-        )
-        .unwrap();
+            r#"type=SYSCALL msg=audit(1615114232.375:1): arch=c000003e syscall=59 success=yes exit=0 a0=63b29337fd18 a1=63b293387d58 a2=63b293375640 a3=fffffffffffff000 items=0 ppid=10883 pid=10884 auid=1000 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=pts1 ses=1 comm="true" exe="/bin/true" key=(null)
+type=EOE msg=audit(1615114232.375:1): 
+"#,
+        )?;
+        std::fs::remove_dir_all(td)?;
 
-        let j = event_to_json(ec.borrow().as_ref().unwrap());
-        println!("{j}");
-        for u in &[
-            "AUID", "UID", "UID", "EUID", "SUID", "FSUID", "EUID", "SUID", "FSUID", "OAUID", "OUID",
-        ] {
-            assert!(
-                j.contains(&format!(r#""{u}":"root"#)),
-                "record does not contain {u}"
-            );
-        }
+        let output = event_to_json(ec.borrow().as_ref().unwrap());
+        assert!(
+            output.contains(r#""LOGIN_SESSION":{"USER":"alice","HOST":"192.0.2.1","ORIGIN":"remote","PID":4000}"#),
+            "{output}"
+        );
+        Ok(())
     }
 
     #[test]
-    fn enrich_uid_groups() {
-        let ec: Rc<RefCell<Option<Event>>> = Rc::new(RefCell::new(None));
+    #[cfg(feature = "sigma")]
+    fn analyze_sigma() -> Result<(), Box<dyn Error>> {
+        let td = nix::unistd::mkdtemp(&std::env::temp_dir().join("laurel-test-XXXXXXXX"))?;
+        std::fs::write(
+            td.join("whoami.yml"),
+            "title: whoami\nid: test-whoami\ntags: [attack.discovery, attack.t1033]\nlogsource:\n  product: linux\n  category: process_creation\ndetection:\n  selection:\n    Image|endswith: /whoami\n    CurrentDirectory|startswith: /home/\n  condition: selection\n",
+        )?;
+        std::fs::write(
+            td.join("windows.yml"),
+            "title: x\nlogsource:\n  product: windows\n  category: process_creation\n",
+        )?;
+        std::fs::write(
+            td.join("hashes.yaml"),
+            "title: x\nlogsource:\n  product: linux\n  category: process_creation\ndetection:\n  selection:\n    Hashes: x\n  condition: selection\n",
+        )?;
+        std::fs::write(td.join("README"), "")?;
+        let (rules, errors) = sigma::Sigma {
+            rules: vec![td.clone()],
+        }
+        .load()?;
+        std::fs::remove_dir_all(td)?;
+        assert_eq!(rules.len(), 1);
+        assert_eq!(errors.len(), 1);
 
+        let ec: Rc<RefCell<Option<Event>>> = Rc::new(RefCell::new(None));
         let mut c = Coalesce::new(mk_emit(&ec));
-        c.settings.translate_userdb = false;
-        c.settings.enrich_uid_groups = true;
-
-        process_record(&mut c, include_bytes!("testdata/record-execve.txt")).unwrap();
-
+        c.settings.analyze_sigma = rules;
+        process_record(&mut c, include_bytes!("testdata/record-execve.txt"))?;
+        let output = event_to_json(ec.borrow().as_ref().unwrap());
         assert!(
-            event_to_json(ec.borrow().as_ref().unwrap()).contains(r#""UID_GROUPS":["#),
-            "enrich.uid_groups is performed regardless of translate.userdb"
+            output.contains(r#""TAGS":["T1033"],"SIGMA":["test-whoami"]"#),
+            "{output}"
         );
+        assert_eq!(c.analyzer_hits(), 2);
+        Ok(())
     }
 
     #[test]
-    fn enrich_uringop() {
-        let ec: Rc<RefCell<Option<Event>>> = Rc::new(RefCell::new(None));
-
-        let mut c = Coalesce::new(mk_emit(&ec));
-        c.settings.translate_userdb = true;
-        c.settings.translate_universal = true;
-
-        process_record(&mut c, include_bytes!("testdata/record-uringop.txt")).unwrap();
+    fn analyze_audit_config() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&ec));
+        c.settings.analyze_audit_config = Some(AuditConfig::default());
+        process_record(&mut c, br#"type=SYSCALL msg=audit(1615114233.001:1): arch=c000003e syscall=59 success=yes exit=0 a0=55d3ee2c2a50 a1=55d3ee2c2a70 a2=55d3ee2c2a80 a3=0 items=0 ppid=1 pid=4000010 auid=1000 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=pts1 ses=1 comm="auditctl" exe="/usr/sbin/auditctl" key=(null)
+type=EOE msg=audit(1615114233.001:1): 
+type=CONFIG_CHANGE msg=audit(1615114233.101:2): auid=1000 ses=1 subj=unconfined op=add_rule key="watch" list=4 res=1
+type=SYSCALL msg=audit(1615114233.101:2): arch=c000003e syscall=44 success=yes exit=1084 a0=4 a1=7ffc5ed7f970 a2=43c a3=0 items=0 ppid=1 pid=4000010 auid=1000 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=pts1 ses=1 comm="auditctl" exe="/usr/sbin/auditctl" key=(null)
+type=EOE msg=audit(1615114233.101:2): 
+type=CONFIG_CHANGE msg=audit(1615114233.201:3): op=set audit_enabled=2 old=1 auid=1000 ses=1 subj=unconfined res=1
+type=SYSCALL msg=audit(1615114233.201:3): arch=c000003e syscall=44 success=yes exit=60 a0=4 a1=7ffc5ed7f970 a2=3c a3=0 items=0 ppid=1 pid=4000010 auid=1000 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=pts1 ses=1 comm="auditctl" exe="/usr/sbin/auditctl" key=(null)
+type=EOE msg=audit(1615114233.201:3): 
+"#)?;
+        let events = ec.borrow();
+        let output = event_to_json(&events[0]);
+        assert!(!output.contains("LAUREL_AUDIT_CONFIG"), "{output}");
+        let output = event_to_json(&events[1]);
+        assert!(
+            output.contains(r#""LAUREL_AUDIT_CONFIG":{"rules_added":1,"rules_removed":0}"#),
+            "{output}"
+        );
+        let output = event_to_json(&events[2]);
+        assert!(
+            output.contains(r#""LAUREL_AUDIT_CONFIG":{"audit_enabled":2,"rules_added":1,"#),
+            "{output}"
+        );
+        drop(events);
 
-        let output = event_to_json(ec.borrow().as_ref().unwrap());
-        println!("{output}");
+        assert_eq!(c.analyzer_hits(), 1);
+        assert_eq!(c.state.audit_config.settings.get("audit_enabled"), Some(&2));
+        Ok(())
+    }
 
+    #[test]
+    fn target_process() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&ec));
+        c.settings.enrich_target_process = true;
+        c.settings.label_exe = Some(LabelMatcher::new(&[("sshd", "sshd")])?);
+        process_record(&mut c, br#"type=SYSCALL msg=audit(1615114233.001:1): arch=c000003e syscall=59 success=yes exit=0 a0=55d3ee2c2a50 a1=55d3ee2c2a70 a2=55d3ee2c2a80 a3=0 items=0 ppid=1 pid=4000001 auid=0 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=(none) ses=1 comm="sshd" exe="/usr/sbin/sshd" key=(null)
+type=EOE msg=audit(1615114233.001:1): 
+type=SYSCALL msg=audit(1615114234.001:2): arch=c000003e syscall=101 success=yes exit=0 a0=10 a1=3d0901 a2=0 a3=0 items=0 ppid=1 pid=4000002 auid=0 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=pts1 ses=1 comm="gdb" exe="/usr/bin/gdb" key=(null)
+type=EOE msg=audit(1615114234.001:2): 
+type=SYSCALL msg=audit(1615114235.001:3): arch=c000003e syscall=101 success=yes exit=0 a0=0 a1=3d0901 a2=0 a3=0 items=0 ppid=1 pid=4000003 auid=0 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=pts1 ses=1 comm="x" exe="/usr/bin/x" key=(null)
+type=EOE msg=audit(1615114235.001:3): 
+"#)?;
+        let events = ec.borrow();
+        let output = event_to_json(&events[1]);
         assert!(
-            output.contains(r#""URING_OP":"openat""#),
-            "uring operations should be translated."
+            output.contains(r#""TARGET_PROCESS":{"pid":4000001,"EVENT_ID":"1615114233.001:1","comm":"sshd","exe":"/usr/sbin/sshd","ppid":1,"LABELS":["sshd"]}"#),
+            "{output}"
         );
+        // PTRACE_TRACEME
+        let output = event_to_json(&events[2]);
+        assert!(!output.contains("TARGET_PROCESS"), "{output}");
+        drop(events);
+
+        process_record(&mut c, br#"type=SYSCALL msg=audit(1615114236.001:4): arch=c000003e syscall=62 success=yes exit=0 a0=3d0901 a1=9 a2=0 a3=0 items=0 ppid=1 pid=4000004 auid=0 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=pts1 ses=1 comm="kill" exe="/usr/bin/kill" key=(null)
+type=OBJ_PID msg=audit(1615114236.001:4): opid=4000001 oauid=0 ouid=0 oses=1 obj=unconfined ocomm="sshd"
+type=EOE msg=audit(1615114236.001:4): 
+"#)?;
+        let output = event_to_json(&ec.borrow()[3]);
         assert!(
-            output.contains(r#""UID":"root""#)
-                && output.contains(r#""GID":"root""#)
-                && output.contains(r#""EUID":"root""#)
-                && output.contains(r#""SUID":"root""#)
-                && output.contains(r#""FSUID":"root""#)
-                && output.contains(r#""EGID":"root""#)
-                && output.contains(r#""SGID":"root""#)
-                && output.contains(r#""FSGID":"root""#),
-            "*uid, *gid should be translated"
+            output.contains(r#""SIGNAL":"SIGKILL","TARGET_PROCESS":{"pid":4000001,"#),
+            "{output}"
         );
-
-        // todo: pid, ppid
+        assert!(
+            output.contains(r#""OPID":{"pid":4000001,"EVENT_ID":"1615114233.001:1","comm":"sshd","exe":"/usr/sbin/sshd","ppid":1,"LABELS":["sshd"]}"#),
+            "{output}"
+        );
+        Ok(())
     }
 
     #[test]
-    fn key_label() -> Result<(), Box<dyn Error>> {
-        let ec: Rc<RefCell<Option<Event>>> = Rc::new(RefCell::new(None));
+    fn selinux() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&ec));
+        c.settings.translate_selinux = true;
+        process_record(&mut c, br#"type=SYSCALL msg=audit(1615114233.001:1): arch=c000003e syscall=188 success=yes exit=0 a0=7ffd0c6b1f20 a1=55b0f2d3e2a0 a2=55b0f2d3f0c0 a3=20 items=1 ppid=1 pid=4000001 auid=0 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=pts1 ses=1 comm="chcon" exe="/usr/bin/chcon" subj=unconfined_u:unconfined_r:unconfined_t:s0-s0:c0.c1023 key=(null)
+type=PATH msg=audit(1615114233.001:1): item=0 name="/etc/shadow" inode=1 dev=fd:00 mode=0100000 ouid=0 ogid=0 rdev=00:00 obj=system_u:object_r:shadow_t:s0 nametype=NORMAL cap_fp=0 cap_fi=0 cap_fe=0 cap_fver=0 cap_frootid=0
+type=EOE msg=audit(1615114233.001:1): 
+"#)?;
+        let output = event_to_json(&ec.borrow()[0]);
+        assert!(
+            output.contains(r#""SUBJ":{"user":"unconfined_u","role":"unconfined_r","type":"unconfined_t","level":"s0-s0:c0.c1023"}"#),
+            "{output}"
+        );
+        assert!(output.contains(r#""XATTR":{"size":32}"#), "{output}");
+        assert!(
+            output.contains(
+                r#""OBJ":{"user":"system_u","role":"object_r","type":"shadow_t","level":"s0"}"#
+            ),
+            "{output}"
+        );
+        Ok(())
+    }
 
-        let mut c = Coalesce::new(mk_emit(&ec));
-        c.settings
-            .proc_label_keys
-            .insert(Vec::from(&b"software_mgmt"[..]));
-        c.settings
-            .proc_propagate_labels
-            .insert(Vec::from(&b"software_mgmt"[..]));
-        process_record(&mut c, include_bytes!("testdata/tree/00.txt"))?;
-        {
-            assert!(
-                event_to_json(ec.borrow().as_ref().unwrap())
-                    .contains(r#""LABELS":["software_mgmt"]"#),
-                "process gets 'software_mgmt' label from key"
-            );
-        }
+    #[test]
+    fn socketcall() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&ec));
+        c.settings.translate_universal = true;
+        process_record(&mut c, br#"type=SYSCALL msg=audit(1615114233.001:1): arch=40000003 syscall=102 success=yes exit=0 a0=3 a1=ffd1c2a0 a2=0 a3=0 items=0 ppid=1 pid=4000001 auid=0 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=pts1 ses=1 comm="curl" exe="/usr/bin/curl" key=(null)
+type=EOE msg=audit(1615114233.001:1): 
+"#)?;
+        process_record(&mut c, br#"type=SYSCALL msg=audit(1615114233.002:2): arch=40000003 syscall=117 success=yes exit=0 a0=17 a1=0 a2=1000 a3=380 items=0 ppid=1 pid=4000001 auid=0 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=pts1 ses=1 comm="ipcmk" exe="/usr/bin/ipcmk" key=(null)
+type=EOE msg=audit(1615114233.002:2): 
+"#)?;
+        let output = event_to_json(&ec.borrow()[0]);
+        assert!(output.contains(r#""SYSCALL":"connect""#), "{output}");
+        let output = event_to_json(&ec.borrow()[1]);
+        assert!(output.contains(r#""SYSCALL":"shmget""#), "{output}");
+        Ok(())
+    }
 
-        process_record(&mut c, include_bytes!("testdata/tree/01.txt"))?;
-        {
-            assert!(
-                event_to_json(ec.borrow().as_ref().unwrap())
-                    .contains(r#""LABELS":["software_mgmt"]"#),
-                "child process inherits 'software_mgmt' label"
-            );
+    #[test]
+    fn summary() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&ec));
+        c.settings.summary_period = Some(60_000);
+        for (ts, seq, exe) in [
+            ("1615114233.001", 1, "/usr/bin/id"),
+            ("1615114234.001", 2, "/usr/bin/id"),
+            ("1615114235.001", 3, "/usr/bin/ls"),
+            ("1615114300.001", 4, "/usr/bin/ls"),
+        ] {
+            process_record(&mut c, format!(r#"type=SYSCALL msg=audit({ts}:{seq}): arch=c000003e syscall=59 success=yes exit=0 a0=55d3ee2c2a50 a1=55d3ee2c2a70 a2=55d3ee2c2a80 a3=0 items=0 ppid=1 pid=400000{seq} auid=1000 uid=1000 gid=1000 euid=1000 suid=1000 fsuid=1000 egid=1000 sgid=1000 fsgid=1000 tty=pts1 ses=1 comm="x" exe="{exe}" key="exec"
+type=EOE msg=audit({ts}:{seq}): 
+"#).as_bytes())?;
         }
 
+        let events = ec.borrow();
+        let summaries: Vec<String> = events
+            .iter()
+            .filter(|ev| ev.laurel.contains_key("LAUREL_SUMMARY"))
+            .map(event_to_json)
+            .collect();
+        assert_eq!(summaries.len(), 1);
+        assert!(
+            summaries[0].contains(r#""LAUREL_SUMMARY":{"start":"1615114233.001","end":"1615114300.001","events":3,"bytes":1044,"auid":[{"value":1000,"events":3}],"#),
+            "{}",
+            summaries[0]
+        );
+        assert!(
+            summaries[0].contains(r#""exe":[{"value":"/usr/bin/id","events":2},{"value":"/usr/bin/ls","events":1}],"key":[{"value":"exec","events":3,"bytes":1044}]}"#),
+            "{}",
+            summaries[0]
+        );
         Ok(())
     }
 
     #[test]
-    fn label_exe() -> Result<(), Box<dyn Error>> {
-        let ec: Rc<RefCell<Option<Event>>> = Rc::new(RefCell::new(None));
-        let lm = LabelMatcher::new(&[("whoami", "recon")])?;
-
-        let mut c = Coalesce::new(mk_emit(&ec));
-        c.settings.label_exe = Some(lm.clone());
-        process_record(&mut c, include_bytes!("testdata/record-execve.txt"))?;
-        drop(c);
-        assert!(event_to_json(ec.borrow().as_ref().unwrap()).contains(r#"LABELS":["recon"]"#));
+    fn shutdown() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&ec));
+        c.process_line(br#"type=SYSCALL msg=audit(1615114233.001:15562): arch=c000003e syscall=59 success=yes exit=0 a0=55d3ee2c2a50 a1=55d3ee2c2a70 a2=55d3ee2c2a80 a3=0 items=1 ppid=1 pid=3999999 auid=1000 uid=1000 gid=1000 euid=1000 suid=1000 fsuid=1000 egid=1000 sgid=1000 fsgid=1000 tty=pts1 ses=1 comm="id" exe="/usr/bin/id" key=(null)
+"#)?;
+        assert!(ec.borrow().is_empty());
+        c.shutdown(&[("lines", 1)]);
+
+        let events = ec.borrow();
+        assert_eq!(events.len(), 2);
+        let output = event_to_json(&events[0]);
+        assert!(
+            output.contains(r#""LAUREL_FORCED_FLUSH":{"reason":"shutdown"}"#),
+            "{output}"
+        );
+        let output = event_to_json(&events[1]);
+        assert!(output.contains(r#""lines":1,"flushed":1,"#), "{output}");
+        assert!(
+            output.contains(r#""last_event":"1615114233.001:15562"}"#),
+            "{output}"
+        );
+        Ok(())
+    }
 
-        let mut c = Coalesce::new(mk_emit(&ec));
-        c.settings.label_exe = Some(lm);
+    #[test]
+    fn shutdown_reorder_window() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&ec));
+        c.settings.reorder_window = 500;
         process_record(
             &mut c,
-            strip_enriched(include_bytes!("testdata/record-execve.txt")),
+            r#"type=SYSCALL msg=audit(1615114233.001:15562): arch=c000003e syscall=59 success=yes exit=0 a0=55d3ee2c2a50 a1=55d3ee2c2a70 a2=55d3ee2c2a80 a3=0 items=0 ppid=1 pid=3999999 auid=1000 uid=1000 gid=1000 euid=1000 suid=1000 fsuid=1000 egid=1000 sgid=1000 fsgid=1000 tty=pts1 ses=1 comm="id" exe="/usr/bin/id" key=(null)
+type=EOE msg=audit(1615114233.001:15562): 
+"#,
         )?;
-        drop(c);
-        assert!(event_to_json(ec.borrow().as_ref().unwrap()).contains(r#"LABELS":["recon"]"#));
-
+        assert!(ec.borrow().is_empty());
+        c.shutdown(&[]);
+
+        let events = ec.borrow();
+        assert_eq!(events.len(), 2);
+        let output = event_to_json(&events[0]);
+        assert!(!output.contains("LAUREL_FORCED_FLUSH"), "{output}");
+        let output = event_to_json(&events[1]);
+        assert!(output.contains(r#""flushed":0,"#), "{output}");
         Ok(())
     }
 
     #[test]
-    fn label_argv() -> Result<(), Box<dyn Error>> {
-        let ec: Rc<RefCell<Option<Event>>> = Rc::new(RefCell::new(None));
-
-        let mut c = Coalesce::new(mk_emit(&ec));
-        c.settings.label_argv = Some(LabelMatcher::new(&[(
-            r#"^\S*java .* -Dweblogic"#,
-            "weblogic",
-        )])?);
+    fn command_groups() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&ec));
+        c.settings.command_groups = Some(CommandGroups::default());
+        process_record(&mut c, br#"type=SYSCALL msg=audit(1615114233.001:1): arch=c000003e syscall=59 success=yes exit=0 a0=55d3ee2c2a50 a1=55d3ee2c2a70 a2=55d3ee2c2a80 a3=0 items=1 ppid=1 pid=4000000 auid=1000 uid=1000 gid=1000 euid=1000 suid=1000 fsuid=1000 egid=1000 sgid=1000 fsgid=1000 tty=(none) ses=1 comm="sh" exe="/bin/sh" key=(null)
+type=EXECVE msg=audit(1615114233.001:1): argc=1 a0="sh"
+type=EOE msg=audit(1615114233.001:1): 
+type=SYSCALL msg=audit(1615114233.101:2): arch=c000003e syscall=59 success=yes exit=0 a0=55d3ee2c2a50 a1=55d3ee2c2a70 a2=55d3ee2c2a80 a3=0 items=1 ppid=4000000 pid=4000001 auid=1000 uid=1000 gid=1000 euid=1000 suid=1000 fsuid=1000 egid=1000 sgid=1000 fsgid=1000 tty=(none) ses=1 comm="id" exe="/usr/bin/id" key=(null)
+type=EXECVE msg=audit(1615114233.101:2): argc=1 a0="id"
+type=EOE msg=audit(1615114233.101:2): 
+type=SYSCALL msg=audit(1615114233.201:3): arch=c000003e syscall=59 success=yes exit=0 a0=55d3ee2c2a50 a1=55d3ee2c2a70 a2=55d3ee2c2a80 a3=0 items=1 ppid=4000000 pid=4000002 auid=1000 uid=1000 gid=1000 euid=1000 suid=1000 fsuid=1000 egid=1000 sgid=1000 fsgid=1000 tty=(none) ses=1 comm="wc" exe="/usr/bin/wc" key=(null)
+type=EXECVE msg=audit(1615114233.201:3): argc=1 a0="wc"
+type=EOE msg=audit(1615114233.201:3): 
+type=SYSCALL msg=audit(1615114233.301:4): arch=c000003e syscall=59 success=yes exit=0 a0=55d3ee2c2a50 a1=55d3ee2c2a70 a2=55d3ee2c2a80 a3=0 items=1 ppid=1 pid=4000003 auid=1000 uid=1000 gid=1000 euid=1000 suid=1000 fsuid=1000 egid=1000 sgid=1000 fsgid=1000 tty=(none) ses=1 comm="sh" exe="/bin/sh" key=(null)
+type=EXECVE msg=audit(1615114233.301:4): argc=1 a0="sh"
+type=EOE msg=audit(1615114233.301:4): 
+type=SYSCALL msg=audit(1615114233.401:5): arch=c000003e syscall=59 success=yes exit=0 a0=55d3ee2c2a50 a1=55d3ee2c2a70 a2=55d3ee2c2a80 a3=0 items=1 ppid=4000003 pid=4000004 auid=1000 uid=1000 gid=1000 euid=1000 suid=1000 fsuid=1000 egid=1000 sgid=1000 fsgid=1000 tty=(none) ses=1 comm="id" exe="/usr/bin/id" key=(null)
+type=EXECVE msg=audit(1615114233.401:5): argc=1 a0="id"
+type=EOE msg=audit(1615114233.401:5): 
+"#)?;
+        c.flush();
+
+        let events = ec.borrow();
+        let ids: Vec<String> = events.iter().map(|e| e.id.to_string()).collect();
+        assert_eq!(
+            ids,
+            [
+                "1615114233.001:1",
+                "1615114233.301:4",
+                "1615114233.101:2",
+                "1615114233.401:5"
+            ]
+        );
+        let output = event_to_json(&events[2]);
+        assert!(
+            output.contains(r#""LAUREL_COMMAND":{"SHELL":{"EVENT_ID":"1615114233.001:1","comm":"sh","exe":"/bin/sh","ppid":1},"COMMANDS":[{"EVENT_ID":"1615114233.101:2","exe":"/usr/bin/id","ARGV":["id"]},{"EVENT_ID":"1615114233.201:3","exe":"/usr/bin/wc","ARGV":["wc"]}]}"#),
+            "{output}"
+        );
+        let output = event_to_json(&events[3]);
+        assert!(
+            output.contains(r#""EXECVE":{"argc":1,"ARGV":["id"]}"#),
+            "{output}"
+        );
+        Ok(())
+    }
 
-        process_record(&mut c, include_bytes!("testdata/record-weblogic.txt"))?;
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    #[test]
+    fn proc_events() {
+        let (tx, rx) = std::sync::mpsc::sync_channel(16);
+        let mut c = Coalesce::new(|_: &Event| {}).with_proc_events(rx);
+        let parent = ProcessKey::Observed {
+            time: 1700000000000,
+            pid: 4000000,
+        };
+        c.state.processes.insert(Process {
+            key: parent,
+            pid: 4000000,
+            ppid: 1,
+            exe: Some(b"/bin/sh"[..].into()),
+            labels: [b"shell".to_vec()].into(),
+            ..Process::default()
+        });
+        c.settings.proc_propagate_labels = [b"shell".to_vec()].into();
 
-        assert!(event_to_json(ec.borrow().as_ref().unwrap()).contains(r#"LABELS":["weblogic"]"#));
+        tx.send(ProcEvent::Fork {
+            pid: 4000001,
+            ppid: 4000000,
+            time: 1700000001000,
+        })
+        .unwrap();
+        c.handle_proc_events();
+        let child = c.state.processes.get_pid(4000001).expect("forked process");
+        assert_eq!(child.parent, Some(parent));
+        assert_eq!(child.exe.as_deref(), Some(&b"/bin/sh"[..]));
+        assert!(child.labels.contains(&b"shell"[..]));
+
+        tx.send(ProcEvent::Exit { pid: 4000001 }).unwrap();
+        c.handle_proc_events();
+        c.state.processes.expire_exited();
+        assert!(c.state.processes.get_pid(4000001).is_none());
+        assert!(c.state.processes.get_pid(4000000).is_some());
+    }
 
-        // Ensure this does not crash with long command lines
-        // TODO: check matcher behavior
-        let mut c = Coalesce::new(mk_emit(&ec));
-        c.settings.label_argv = Some(LabelMatcher::new(&[(
-            r#"/opt/app/redacted/to/protect/the/guilty/"#,
-            "protect-the-guilty",
-        )])?);
-        let buf = gen_long_find_execve();
-        process_record(&mut c, buf)?;
-        assert!(event_to_json(ec.borrow().as_ref().unwrap())
-            .contains(r#"LABELS":["protect-the-guilty"]"#));
+    #[test]
+    fn container_events() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let (tx, rx) = std::sync::mpsc::sync_channel(16);
+        let mut c = Coalesce::new(mk_emit_vec(&ec)).with_proc_events(rx);
+        c.settings.enrich_container_events = true;
+        c.state.processes.insert(Process {
+            key: ProcessKey::Observed {
+                time: 1700000000000,
+                pid: 4000000,
+            },
+            pid: 4000000,
+            ppid: 1,
+            exe: Some(b"/usr/bin/id"[..].into()),
+            container_info: Some(ContainerInfo { id: vec![0xab; 32] }),
+            ..Process::default()
+        });
+        // Exited processes are removed from the process table on the
+        // second expiry run.
+        for (seq, ts, pid) in [
+            (1, "1700000010.100", 4000000),
+            (2, "1700000012.000", 3999999),
+            (3, "1700000014.000", 3999999),
+        ] {
+            if seq == 2 {
+                tx.send(ProcEvent::Exit { pid: 4000000 })?;
+            }
+            process_record(
+                &mut c,
+                format!(
+                    r#"type=SYSCALL msg=audit({ts}:{seq}): arch=c000003e syscall=2 success=yes exit=3 a0=0 a1=0 a2=0 a3=0 items=1 ppid=1 pid={pid} auid=1000 uid=1000 gid=1000 euid=1000 suid=1000 fsuid=1000 egid=1000 sgid=1000 fsgid=1000 tty=pts1 ses=1 comm="id" exe="/usr/bin/id" key=(null)
+type=EOE msg=audit({ts}:{seq}): 
+"#
+                ),
+            )?;
+        }
+        let id = "ab".repeat(32);
+        let events: Vec<String> = ec.borrow().iter().map(event_to_json).collect();
+        let start = events
+            .iter()
+            .position(|e| e.contains(&format!(r#""CONTAINER_START":{{"id":"{id}","event":"1700000010.100:1","pid":4000000,"exe":"/usr/bin/id"}}"#)))
+            .expect("CONTAINER_START");
+        let stop = events
+            .iter()
+            .position(|e| {
+                e.contains(&format!(
+                    r#""CONTAINER_STOP":{{"id":"{id}","first_event":"1700000010.100:1"}}"#
+                ))
+            })
+            .expect("CONTAINER_STOP");
+        assert!(start < stop);
+        Ok(())
+    }
 
-        let mut c = Coalesce::new(mk_emit(&ec));
-        c.settings.label_argv = Some(LabelMatcher::new(&[
-            (r#"^/bin/echo "#, "echo"), // this should match.
-            (r#"aaaaaaaaaa"#, "aaaa"),  // this shouldn't. argv[1] is too long for the buffer.
-        ])?);
-        process_record(&mut c, include_bytes!("testdata/record-execve-long.txt"))?;
-        assert!(event_to_json(ec.borrow().as_ref().unwrap()).contains(r#"LABELS":["echo"]"#));
+    #[test]
+    fn sample() -> Result<(), Box<dyn Error>> {
+        let run = |filter_first_per_process| -> Result<Vec<(u32, String)>, Box<dyn Error>> {
+            let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+            let mut c = Coalesce::new(mk_emit_vec(&ec));
+            c.settings.filter_first_per_process = filter_first_per_process;
+            c.settings.filter_sample = vec![SampleRule {
+                key: Some("access".into()),
+                syscall: Some("openat".into()),
+                ratio: 4,
+            }];
+            for seq in 1..=200 {
+                let (syscall, key) = match seq {
+                    1..=100 => (257, r#""access""#),
+                    101..=150 => (257, "(null)"),
+                    _ => (2, r#""access""#),
+                };
+                process_record(
+                    &mut c,
+                    format!(
+                        r#"type=SYSCALL msg=audit(1615114233.001:{seq}): arch=c000003e syscall={syscall} success=yes exit=3 a0=ffffff9c a1=55d3ee2c2a70 a2=0 a3=0 items=1 ppid=1 pid=4000000 auid=1000 uid=1000 gid=1000 euid=1000 suid=1000 fsuid=1000 egid=1000 sgid=1000 fsgid=1000 tty=pts1 ses=1 comm="x" exe="/usr/bin/x" key={key}
+type=EOE msg=audit(1615114233.001:{seq}): 
+"#
+                    ),
+                )?;
+            }
+            let events = ec.borrow();
+            Ok(events
+                .iter()
+                .map(|e| (e.id.sequence, event_to_json(e)))
+                .collect())
+        };
+        let r1 = run(true)?;
+        assert_eq!(r1, run(true)?, "sampling is deterministic");
+        let sampled: Vec<_> = r1.iter().filter(|(seq, _)| *seq <= 100).collect();
+        assert!(
+            (10..=40).contains(&sampled.len()),
+            "{} events kept",
+            sampled.len()
+        );
+        for (_, output) in sampled {
+            assert!(output.contains(r#""SAMPLE_RATIO":4"#), "{output}");
+        }
+        let others: Vec<_> = r1.iter().filter(|(seq, _)| *seq > 100).collect();
+        assert_eq!(others.len(), 100);
+        for (_, output) in others {
+            assert!(!output.contains("SAMPLE_RATIO"), "{output}");
+        }
 
+        // The first event for the process is kept, without being
+        // subject to sampling.
+        let r2 = run(false)?;
+        let (seq, output) = &r2[0];
+        assert_eq!(*seq, 1);
+        assert!(!output.contains("SAMPLE_RATIO"), "{output}");
+        assert_eq!(
+            r2.len(),
+            r1.len() + (!r1.iter().any(|(seq, _)| *seq == 1)) as usize
+        );
         Ok(())
     }
 
-    // Returns an emitter function that puts the event into an Option
-    fn mk_emit<'c, 'ev: 'c>(
-        ec: &'c Rc<RefCell<Option<Event<'ev>>>>,
-    ) -> impl FnMut(&Event<'ev>) + 'c {
-        |ev: &Event| {
-            if !ev.is_filtered {
-                *ec.borrow_mut() = Some(ev.clone());
+    #[test]
+    fn error_events() {
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&ec));
+        let line = b"type=SYSCALL msg=audit(1615114233.001:1): arch=c000003e syscall=59 \x01\n";
+        let e = c.process_line(line).unwrap_err();
+        assert!(matches!(
+            e,
+            CoalesceError::Parse {
+                offset: Some(42),
+                ..
             }
+        ));
+        assert_eq!(e.code(), "malformed-body");
+        c.emit_error(&e, line);
+        let output = event_to_json(&ec.borrow()[0]);
+        assert!(
+            output.contains(
+                r#""LAUREL_ERROR":{"code":"malformed-body","message":"cannot parse body: "#
+            ),
+            "{output}"
+        );
+        assert!(
+            output.contains(r#""offset":42,"line":"type=SYSCALL msg=audit(1615114233.001:1): arch=c000003e syscall=59 %01"}"#),
+            "{output}"
+        );
+    }
+
+    #[test]
+    fn clock_skew() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let now = 1615114232375;
+        let mut c = Coalesce::new(mk_emit_vec(&ec)).with_clock(Arc::new(ManualClock::new(now)));
+        c.settings.clock_skew_threshold = Some(60_000);
+        let syscall = |seq: u32, ts: u64| {
+            let (sec, msec) = (ts / 1000, ts % 1000);
+            format!(
+                r#"type=SYSCALL msg=audit({sec}.{msec:03}:{seq}): arch=c000003e syscall=59 success=yes exit=0 a0=55d3ee2c2a50 a1=55d3ee2c2a70 a2=55d3ee2c2a80 a3=0 items=1 ppid=1 pid=3999999 auid=1000 uid=1000 gid=1000 euid=1000 suid=1000 fsuid=1000 egid=1000 sgid=1000 fsgid=1000 tty=pts1 ses=1 comm="id" exe="/usr/bin/id" key=(null)
+"#
+            )
+        };
+        let eoe = |seq: u32, ts: u64| {
+            let (sec, msec) = (ts / 1000, ts % 1000);
+            format!("type=EOE msg=audit({sec}.{msec:03}:{seq}): \n")
+        };
+        let then = now - 3_600_000;
+        // Records of the skewed event 2 are interleaved with those
+        // of event 3, which has a current timestamp.
+        for record in [
+            syscall(1, now),
+            eoe(1, now),
+            syscall(2, then),
+            syscall(3, now),
+            eoe(3, now),
+            eoe(2, then),
+        ] {
+            process_record(&mut c, record)?;
+        }
+        let events = ec.borrow();
+        for seq in [1, 3] {
+            let ev = events.iter().find(|e| e.id.sequence == seq).unwrap();
+            let output = event_to_json(ev);
+            assert!(!output.contains("LAUREL_CLOCK_SKEW"), "{output}");
         }
+        let ev = events.iter().find(|e| e.id.sequence == 2).unwrap();
+        let output = event_to_json(ev);
+        assert!(
+            output.contains(r#""LAUREL_CLOCK_SKEW":{"offset":3600000"#),
+            "{output}"
+        );
+        assert_eq!(c.clock_skew(), Some(3_600_000));
+        Ok(())
     }
 
-    // Returns an emitter function that appends the event onto a Vec
-    fn mk_emit_vec<'c, 'ev>(ec: &'c Rc<RefCell<Vec<Event<'ev>>>>) -> impl FnMut(&Event<'ev>) + 'c {
-        |ev: &Event| {
-            if !ev.is_filtered {
-                ec.borrow_mut().push(ev.clone());
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    #[derive(Debug)]
+    struct SyntheticProcs;
+
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    impl procfs::ProcSource for SyntheticProcs {
+        fn pids(&self) -> Result<Vec<u32>, procfs::ProcFSError> {
+            Ok(vec![3999996])
+        }
+        fn pid_info(&self, pid: u32) -> Result<procfs::ProcPidInfo, procfs::ProcFSError> {
+            match pid {
+                3999996 => Ok(procfs::ProcPidInfo {
+                    pid,
+                    ppid: 1,
+                    starttime: 1615114000000,
+                    exe: Some(b"/usr/bin/containerd-shim".to_vec()),
+                    ..Default::default()
+                }),
+                _ => Err(procfs::ProcFSError::Field("pid")),
             }
         }
+        fn pid_starttime(&self, pid: u32) -> Result<u64, procfs::ProcFSError> {
+            self.pid_info(pid).map(|p| p.starttime)
+        }
+        fn pid_nspid(&self, pid: u32) -> Result<Option<procfs::NsPid>, procfs::ProcFSError> {
+            Ok(match pid {
+                3999996 => Some(procfs::NsPid { ns: 1, pid: 1 }),
+                3999997 => Some(procfs::NsPid { ns: 1, pid: 7 }),
+                3999998 => Some(procfs::NsPid { ns: 2, pid: 1 }),
+                _ => None,
+            })
+        }
+        fn pid_cgroup(&self, pid: u32) -> Result<Option<Vec<u8>>, procfs::ProcFSError> {
+            Ok(Some(
+                format!("/system.slice/docker-{:064x}.scope", pid).into_bytes(),
+            ))
+        }
     }
 
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
     #[test]
-    fn filter_key() -> Result<(), Box<dyn Error>> {
-        let events: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+    fn ns_pid() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&ec))
+            .with_proc_source(Arc::new(SyntheticProcs))
+            .with_settings(Settings {
+                enrich_ns_pid: true,
+                ..Settings::default()
+            });
+        // 3999997 shares the parent's pid namespace, 3999998 runs in
+        // a nested one.
+        for (seq, pid) in [(1, 3999997), (2, 3999998)] {
+            process_record(
+                &mut c,
+                format!(
+                    r#"type=SYSCALL msg=audit(1615114232.375:{seq}): arch=c000003e syscall=59 success=yes exit=0 a0=55d3ee2c2a50 a1=55d3ee2c2a70 a2=55d3ee2c2a80 a3=0 items=1 ppid=3999996 pid={pid} auid=1000 uid=1000 gid=1000 euid=1000 suid=1000 fsuid=1000 egid=1000 sgid=1000 fsgid=1000 tty=pts1 ses=1 comm="id" exe="/usr/bin/id" key=(null)
+type=EOE msg=audit(1615114232.375:{seq}): 
+"#
+                ),
+            )?;
+        }
+        let events = ec.borrow();
+        let output = event_to_json(events.iter().find(|e| e.id.sequence == 1).unwrap());
+        assert!(output.contains(r#""NS_PID":7,"NS_PPID":1"#), "{output}");
+        let output = event_to_json(events.iter().find(|e| e.id.sequence == 2).unwrap());
+        assert!(output.contains(r#""NS_PID":1"#), "{output}");
+        assert!(!output.contains("NS_PPID"), "{output}");
+        Ok(())
+    }
 
-        let mut c = Coalesce::new(mk_emit_vec(&events));
-        c.settings
-            .filter_keys
-            .insert(Vec::from(&b"filter-this"[..]));
-        c.settings.filter_keys.insert(Vec::from(&b"this-too"[..]));
-        process_record(&mut c, include_bytes!("testdata/record-syscall-key.txt"))?;
-        drop(c);
-        // fist event for process -> don't filter
-        assert!(events
-            .borrow()
-            .iter()
-            .any(|e| &e.id == "1628602815.266:2365"));
-        assert!(!events
-            .borrow()
-            .iter()
-            .any(|e| &e.id == "1628602815.266:2366"));
-        assert!(!events
-            .borrow()
-            .iter()
-            .any(|e| &e.id == "1628602815.266:2367"));
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    #[test]
+    fn synthetic_cgroup() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&ec))
+            .with_proc_source(Arc::new(SyntheticProcs))
+            .with_settings(Settings {
+                enrich_container: true,
+                ..Settings::default()
+            });
+        process_record(
+            &mut c,
+            r#"type=SYSCALL msg=audit(1615114232.375:1): arch=c000003e syscall=59 success=yes exit=0 a0=55d3ee2c2a50 a1=55d3ee2c2a70 a2=55d3ee2c2a80 a3=0 items=1 ppid=3999996 pid=3999997 auid=1000 uid=1000 gid=1000 euid=1000 suid=1000 fsuid=1000 egid=1000 sgid=1000 fsgid=1000 tty=pts1 ses=1 comm="id" exe="/usr/bin/id" key=(null)
+type=EOE msg=audit(1615114232.375:1): 
+"#,
+        )?;
+        let proc = c.state.processes.get_pid(3999997).unwrap();
+        let id = &proc.container_info.as_ref().unwrap().id;
+        assert_eq!(hex_string(id), format!("{:064x}", 3999997));
+        Ok(())
+    }
 
-        let mut c = Coalesce::new(mk_emit_vec(&events));
-        c.settings.filter_null_keys = true;
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    #[test]
+    fn enrich_deadline() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&ec));
+        c.settings.enrich_deadline = Some(0);
         process_record(
             &mut c,
-            include_bytes!("testdata/record-syscall-nullkey.txt"),
+            r#"type=SYSCALL msg=audit(1615114232.375:1): arch=c000003e syscall=59 success=yes exit=0 a0=63b29337fd18 a1=63b293387d58 a2=63b293375640 a3=fffffffffffff000 items=1 ppid=10883 pid=10884 auid=1000 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=pts1 ses=1 comm="true" exe="/bin/true" key=(null)
+type=PATH msg=audit(1615114232.375:1): item=0 name="/bin/true" inode=261214 dev=ca:03 mode=0100755 ouid=0 ogid=0 rdev=00:00 nametype=NORMAL cap_fp=0 cap_fi=0 cap_fe=0 cap_fver=0
+type=EOE msg=audit(1615114232.375:1): 
+"#,
         )?;
-        drop(c);
-
-        // not first event for process -> filter
-        assert!(!events
-            .borrow()
-            .iter()
-            .any(|e| &e.id == "1678282381.452:102337"));
-        // fist event for process -> don't filter
-        assert!(events
-            .borrow()
-            .iter()
-            .any(|e| &e.id == "1678283440.683:225"));
-
-        let mut c = Coalesce::new(mk_emit_vec(&events));
-        c.settings
-            .filter_keys
-            .insert(Vec::from(&b"random-filter"[..]));
-        process_record(&mut c, include_bytes!("testdata/record-login.txt"))?;
-        drop(c);
-        assert!(!events.borrow().is_empty());
-
+        let output = event_to_json(&ec.borrow()[0]);
+        assert!(
+            output.contains(r#""LAUREL_ENRICHMENT_INCOMPLETE":{"skipped":["script"]}"#),
+            "{output}"
+        );
         Ok(())
     }
 
     #[test]
-    fn filter_label() -> Result<(), Box<dyn Error>> {
+    fn enrich_entity_id() {
         let ec: Rc<RefCell<Option<Event>>> = Rc::new(RefCell::new(None));
-
         let mut c = Coalesce::new(mk_emit(&ec));
-        c.settings.filter_first_per_process = true;
-        c.settings
-            .proc_label_keys
-            .insert(Vec::from(&b"software_mgmt"[..]));
-        c.settings
-            .filter_labels
-            .insert(Vec::from(&b"software_mgmt"[..]));
-        c.settings
-            .proc_propagate_labels
-            .insert(Vec::from(&b"software_mgmt"[..]));
+        c.settings.enrich_entity_id = true;
+        process_record(&mut c, include_bytes!("testdata/record-execve.txt")).unwrap();
+        let id = ProcessKey::Event(EventID::from_str("1615114232.375:15558").unwrap())
+            .entity_id(b"work");
+        let json = event_to_json(ec.borrow().as_ref().unwrap());
+        assert!(
+            json.contains(&format!(
+                r#""EVENT_ID":"1615114232.375:15558","ENTITY_ID":"{id}""#
+            )),
+            "{json}"
+        );
+    }
 
-        process_record(&mut c, include_bytes!("testdata/tree/00.txt"))?;
-        {
-            assert!(ec.borrow().as_ref().is_none());
-        }
+    #[test]
+    fn enrich_lineage() {
+        let lineage = |enabled| {
+            let ec: Rc<RefCell<Option<Event>>> = Rc::new(RefCell::new(None));
+            let mut c = Coalesce::new(mk_emit(&ec));
+            c.settings.enrich_lineage = enabled;
+            process_record(&mut c, include_bytes!("testdata/record-execve.txt")).unwrap();
+            let ev = ec.borrow();
+            let Some(EventValues::Single(rv)) =
+                ev.as_ref().unwrap().body.get(&MessageType::SYSCALL)
+            else {
+                panic!("no SYSCALL record");
+            };
+            rv.get("LINEAGE").map(|v| serde_json::to_string(v).unwrap())
+        };
+        assert_eq!(lineage(false), None);
+        let l = lineage(true).expect("no LINEAGE");
+        assert_eq!(l.len(), 66, "{l}");
+        // Same process tree instance, same hash
+        assert_eq!(lineage(true).as_ref(), Some(&l));
+    }
 
-        process_record(&mut c, include_bytes!("testdata/tree/01.txt"))?;
-        {
-            assert!(ec.borrow().as_ref().is_none());
-        }
+    #[test]
+    fn sections() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Option<Event>>> = Rc::new(RefCell::new(None));
+        let mut c = Coalesce::new(mk_emit(&ec));
+        c.settings.sections = vec![(
+            "SITE".into(),
+            vec![
+                (b"asset_id".to_vec(), b"a-1234".to_vec()),
+                (b"env".to_vec(), b"prod".to_vec()),
+            ],
+        )];
+        process_record(&mut c, include_bytes!("testdata/record-execve.txt"))?;
+        let output = event_to_json(ec.borrow().as_ref().unwrap());
+        assert!(
+            output.contains(r#""SITE":{"asset_id":"a-1234","env":"prod"}"#),
+            "{output}"
+        );
+        Ok(())
+    }
 
-        process_record(&mut c, include_bytes!("testdata/record-login.txt"))?;
+    #[test]
+    fn ordered_output() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&ec));
+        c.settings.ordered_output = true;
+        // The first event has no EOE marker and is only emitted once
+        // it has expired.
+        process_record(
+            &mut c,
+            r#"type=SYSCALL msg=audit(1615114232.375:1): arch=c000003e syscall=59 success=yes exit=0 a0=63b29337fd18 a1=63b293387d58 a2=63b293375640 a3=fffffffffffff000 items=0 ppid=10883 pid=10884 auid=1000 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=pts1 ses=1 comm="true" exe="/bin/true" key=(null)
+type=SYSCALL msg=audit(1615114232.380:2): arch=c000003e syscall=59 success=yes exit=0 a0=63b29337fd18 a1=63b293387d58 a2=63b293375640 a3=fffffffffffff000 items=0 ppid=10883 pid=10885 auid=1000 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=pts1 ses=1 comm="true" exe="/bin/true" key=(null)
+type=EOE msg=audit(1615114232.380:2): 
+"#,
+        )?;
+        assert!(ec.borrow().is_empty());
+
+        process_record(
+            &mut c,
+            r#"type=USER_AUTH msg=audit(1615114240.000:3): pid=1 uid=0 auid=4294967295 ses=4294967295 msg='op=PAM:authentication acct="root" exe="/bin/login" hostname=? addr=? terminal=tty1 res=success'
+"#,
+        )?;
         {
-            assert!(event_to_json(ec.borrow().as_ref().unwrap()).contains(r#"/usr/sbin/cron"#));
+            let events = ec.borrow();
+            assert_eq!(events.len(), 2);
+            assert!(&events[0].id == "1615114232.375:1");
+            assert!(&events[1].id == "1615114232.380:2");
         }
 
-        drop(c);
-
+        c.flush();
+        let events = ec.borrow();
+        assert_eq!(events.len(), 3);
+        assert!(&events[2].id == "1615114240.000:3");
         Ok(())
     }
 
     #[test]
-    fn filter_raw() {
-        for (name, filter) in &[
-            ("sockaddr", "^type=SOCKADDR (?:node=\\$*? )?msg=audit\\(\\S*?\\): saddr=01002F7661722F72756E2F6E7363642F736F636B657400"),
-            ("syscall", "^type=SYSCALL (?:node=\\$*? )?msg=audit\\(.*?\\): arch=c000003e syscall=42 success=no"),
-        ] {
-            let events: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
-            let mut c = Coalesce::new(mk_emit_vec(&events));
-            c.settings.filter_raw_lines = regex::bytes::RegexSet::new([
-                filter
-            ])
-                .expect("failed to compile regex");
-            c.settings.filter_first_per_process = true;
-            process_record(&mut c, include_bytes!("testdata/record-nscd.txt")).unwrap();
-            assert!(
-                !events
-                    .borrow()
-                    .iter()
-                    .any(|e| &e.id == "1705071450.879:29498378"),
-                "nscd connect event should be filtered using {name}"
-            )
-        }
+    fn ordered_output_synthetic() {
+        let clock = Arc::new(crate::clock::ManualClock::new(1_000_000));
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&ec)).with_clock(clock.clone());
+        c.settings.ordered_output = true;
+
+        // Synthetic events are held back like audit events ...
+        c.emit_gap(3);
+        assert!(ec.borrow().is_empty());
+        assert_eq!(c.idle_timeout(), Some(Duration::from_millis(5_001)));
+
+        // ... and are written once the system clock has moved on.
+        clock.advance(5_001);
+        c.expire_idle();
+        assert_eq!(ec.borrow().len(), 1);
+        assert!(ec.borrow()[0].laurel.contains_key("LAUREL_GAP"));
     }
 
     #[test]
-    #[cfg(target_endian = "little")]
-    fn filter_sockaddr() {
-        for filter in &[
-            &["127.0.0.1", "::1"][..],
-            &["127.0.0.0/8", "::/64"],
-            &["127.0.0.1:11211", "[::1]:11211"],
-            &["127.0.0.0/8:11211", "[::/64]:11211"],
-            &["*:11211"],
-        ] {
-            {
-                let events: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
-                let mut c = Coalesce::new(mk_emit_vec(&events));
-                c.settings.filter_first_per_process = true;
-                c.settings.filter_sockaddr = filter.iter().map(|s| s.parse().unwrap()).collect();
-                process_record(&mut c, include_bytes!("testdata/record-connect.txt")).unwrap();
-                let events = events.borrow();
-                println!("{events:?}");
-                assert!(!events.iter().any(|e| &e.id == "1723819442.459:2482681"));
-                assert!(!events.iter().any(|e| &e.id == "1723819442.459:2482682"));
-            }
-        }
-    }
-
-    fn gen_long_find_execve() -> Vec<u8> {
-        let mut buf = vec![];
-        let msgid = "1663143990.204:2148478";
-        let npath = 40000;
+    fn reorder_window() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&ec));
+        c.settings.reorder_window = 500;
+        process_record(
+            &mut c,
+            r#"type=SYSCALL msg=audit(1615114232.375:1): arch=c000003e syscall=59 success=yes exit=0 a0=63b29337fd18 a1=63b293387d58 a2=63b293375640 a3=fffffffffffff000 items=1 ppid=10883 pid=10884 auid=1000 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=pts1 ses=1 comm="true" exe="/bin/true" key=(null)
+type=EOE msg=audit(1615114232.375:1): 
+type=SYSCALL msg=audit(1615114232.380:2): arch=c000003e syscall=59 success=yes exit=0 a0=63b29337fd18 a1=63b293387d58 a2=63b293375640 a3=fffffffffffff000 items=0 ppid=10883 pid=10885 auid=1000 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=pts1 ses=1 comm="true" exe="/bin/true" key=(null)
+type=PATH msg=audit(1615114232.375:1): item=0 name="/bin/true" inode=261214 dev=ca:03 mode=0100755 ouid=0 ogid=0 rdev=00:00 nametype=NORMAL cap_fp=0 cap_fi=0 cap_fe=0 cap_fver=0
+type=EOE msg=audit(1615114232.380:2): 
+"#,
+        )?;
+        assert!(ec.borrow().is_empty());
+        assert!(matches!(
+            process_record(&mut c, "type=EOE msg=audit(1615114232.375:1): \n"),
+            Err(e) if e.downcast_ref::<CoalesceError>().is_some_and(|e| e.code() == "duplicate-event")
+        ));
 
-        buf.extend(
-            format!(r#"type=SYSCALL msg=audit({msgid}): arch=c000003e syscall=59 success=yes exit=0 a0=1468e584be18 a1=1468e57f5078 a2=1468e584bd68 a3=7ffc3e352220 items=2 ppid=9264 pid=9279 auid=4294967295 uid=995 gid=992 euid=995 suid=995 fsuid=995 egid=992 sgid=992 fsgid=992 tty=(none) ses=4294967295 comm="find" exe="/usr/bin/find" key=(null)
-"#).bytes());
-        buf.extend(
-            format!(
-                r#"type=EXECVE msg=audit({msgid}): argc={} a0="/usr/bin/find" "#,
-                npath + 9
-            )
-            .bytes(),
-        );
-        for i in 1..npath {
-            if i % 70 == 0 {
-                buf.extend(format!("\ntype=EXECVE msg=audit({msgid}): ").bytes());
-            } else {
-                buf.push(b' ');
-            }
-            buf.extend(format!(r#"a{i}="/opt/app/redacted/to/protect/the/guilty/output_processing.2022-09-06.{i:05}.garbage""#).bytes());
-        }
-        for (i, param) in [
-            "-type",
-            "f",
-            "-mtime",
-            "+7",
-            "-exec",
-            "/usr/bin/rm",
-            "-f",
-            "{}",
-            ";",
-        ]
-        .iter()
-        .enumerate()
-        {
-            buf.extend(format!(r#" a{}="{param}""#, npath + i).bytes());
-        }
-        buf.extend(format!("\ntype=EOE msg=audit({msgid}): \n").bytes());
-        buf
+        process_record(
+            &mut c,
+            r#"type=USER_AUTH msg=audit(1615114233.000:3): pid=1 uid=0 auid=4294967295 ses=4294967295 msg='op=PAM:authentication acct="root" exe="/bin/login" hostname=? addr=? terminal=tty1 res=success'
+"#,
+        )?;
+        let events = ec.borrow();
+        assert_eq!(events.len(), 3);
+        assert!(&events[0].id == "1615114232.375:1");
+        assert!(events[0].body.contains_key(&MessageType::PATH));
+        assert!(&events[1].id == "1615114232.380:2");
+        Ok(())
     }
 
     #[test]
-    fn strip_long_argv() -> Result<(), Box<dyn Error>> {
-        let ec: Rc<RefCell<Option<Event>>> = Rc::new(RefCell::new(None));
-
-        let mut c = Coalesce::new(mk_emit(&ec));
-
-        c.settings.execve_argv_limit_bytes = Some(10000);
-        let buf = gen_long_find_execve();
+    fn reorder_window_idle() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let clock = Arc::new(ManualClock::new(1615114232375));
+        let mut c = Coalesce::new(mk_emit_vec(&ec)).with_clock(clock.clone());
+        c.settings.reorder_window = 500;
+        process_record(
+            &mut c,
+            r#"type=SYSCALL msg=audit(1615114232.375:1): arch=c000003e syscall=59 success=yes exit=0 a0=63b29337fd18 a1=63b293387d58 a2=63b293375640 a3=fffffffffffff000 items=0 ppid=10883 pid=10884 auid=1000 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=pts1 ses=1 comm="true" exe="/bin/true" key=(null)
+type=EOE msg=audit(1615114232.375:1): 
+"#,
+        )?;
+        assert_eq!(c.idle_timeout(), Some(Duration::from_millis(501)));
+        clock.advance(500);
+        c.expire_idle();
+        assert!(ec.borrow().is_empty());
+        assert_eq!(c.idle_timeout(), Some(Duration::from_millis(1)));
+        clock.advance(1);
+        c.expire_idle();
+        assert_eq!(ec.borrow().len(), 1);
+        assert_eq!(c.idle_timeout(), None);
+        Ok(())
+    }
 
-        process_record(&mut c, &buf)?;
-        {
-            let output = event_to_json(ec.borrow().as_ref().unwrap());
-            assert!(output.len() < 15000);
-            assert!(
-                output.contains(".00020.garbage"),
-                "Can't find start of argv"
-            );
-            assert!(output.contains(".39980.garbage"), "Can't find end of argv");
+    #[test]
+    fn node_processes() -> Result<(), Box<dyn Error>> {
+        let ec: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+        let mut c = Coalesce::new(mk_emit_vec(&ec));
+        c.settings.local_node = Some(b"local".to_vec());
+        for (node, seq, pid, ppid, exe) in [
+            ("a", 1, 3999999, 1, "/usr/bin/a"),
+            ("b", 2, 3999999, 1, "/usr/bin/b"),
+            ("a", 3, 4000000, 3999999, "/usr/bin/id"),
+            ("b", 4, 4000000, 3999999, "/usr/bin/id"),
+        ] {
+            process_record(
+                &mut c,
+                format!(
+                    r#"node={node} type=SYSCALL msg=audit(1615114233.001:{seq}): arch=c000003e syscall=59 success=yes exit=0 a0=55d3ee2c2a50 a1=55d3ee2c2a70 a2=55d3ee2c2a80 a3=0 items=1 ppid={ppid} pid={pid} auid=1000 uid=1000 gid=1000 euid=1000 suid=1000 fsuid=1000 egid=1000 sgid=1000 fsgid=1000 tty=pts1 ses=1 comm="x" exe="{exe}" key=(null)
+node={node} type=EOE msg=audit(1615114233.001:{seq}): 
+"#
+                ),
+            )?;
+        }
+        assert!(c.state.processes.processes.is_empty());
+        assert_eq!(c.state.node_processes.len(), 2);
+        assert!(c.state.node_processes[&b"a".to_vec()].remote);
+
+        // Node names must be usable as keys in the JSON state file.
+        let state: State = serde_json::from_str(&serde_json::to_string(&c.state)?)?;
+        assert_eq!(state.node_processes.len(), 2);
+        assert!(state.node_processes.contains_key(b"a".as_slice()));
+
+        let events = ec.borrow();
+        for (id, parent, exe) in [
+            ("1615114233.001:3", "1615114233.001:1", "/usr/bin/a"),
+            ("1615114233.001:4", "1615114233.001:2", "/usr/bin/b"),
+        ] {
+            let output = event_to_json(&find_event(&events, id).unwrap());
             assert!(
-                !output.contains(".20000.garbage"),
-                "Should not see middle of argv"
+                output.contains(&format!(
+                    r#""PPID":{{"EVENT_ID":"{parent}","comm":"x","exe":"{exe}""#
+                )),
+                "{output}"
             );
         }
-
         Ok(())
     }
 
@@ -2267,6 +6757,54 @@ mod test {
         }
     }
 
+    /// Using the same "dpkg -l" trace: dpkg execs dpkg-query, which
+    /// spawns sh (depth 1), which spawns the pager (depth 2).
+    #[test]
+    fn propagate_policy() {
+        let ids = [
+            "1778355636.725:2322552", // dpkg
+            "1778355636.728:2322553", // dpkg-query
+            "1778355636.752:2322555", // sh
+            "1778355636.754:2322557", // pager (less)
+        ];
+        for (n, (policy, expected)) in [
+            (r#"across = ["exec"]"#, [true, true, false, false]),
+            (r#"across = ["fork"]"#, [true, false, false, false]),
+            ("max-depth = 1", [true, true, true, false]),
+            (
+                r#"stop-exe = ["^/usr/bin/less$"]"#,
+                [true, true, true, false],
+            ),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let policy: PropagatePolicy = toml::from_str(policy).unwrap();
+            let s = Settings {
+                label_exe: LabelMatcher::new(&[("^/usr/bin/dpkg$", "pkg_mgmt")]).ok(),
+                proc_propagate_labels: [b"pkg_mgmt".to_vec()].into(),
+                proc_propagate_policy: [(b"pkg_mgmt".to_vec(), policy)].into(),
+                ..Settings::default()
+            };
+
+            let events: Rc<RefCell<Vec<Event>>> = Rc::new(RefCell::new(vec![]));
+            let mut c = Coalesce::new(mk_emit_vec(&events));
+            c.settings = s;
+            process_record(&mut c, include_bytes!("testdata/proc-trace-dpkg-l.txt")).unwrap();
+            drop(c);
+
+            let events = events.borrow();
+            for (id, expected) in ids.iter().zip(expected) {
+                let event = find_event(&events, id).unwrap_or_else(|| panic!("did not find {id}"));
+                assert_eq!(
+                    event_to_json(&event).contains(r#""LABELS":["pkg_mgmt"]"#),
+                    *expected,
+                    "policy #{n}: unexpected label state for {id}"
+                );
+            }
+        }
+    }
+
     #[test]
     fn shell_proc_trace_confusion() {
         let s1 = Settings {
@@ -2343,6 +6881,7 @@ mod test {
                         )]
                         .into()
                     },
+                    ..ProcTable::default()
                 },
                 ..State::default()
             });
@@ -2418,6 +6957,7 @@ type=EOE msg=audit(1740992884.191:7058722):
                         )]
                         .into()
                     },
+                    ..ProcTable::default()
                 },
                 ..State::default()
             });
@@ -2479,59 +7019,94 @@ type=EOE msg=audit(1615114232.375:99999):
 "#
         );
 
-        let ec = Rc::new(RefCell::new(None));
+        for workers in [0, 2] {
+            let ec = Rc::new(RefCell::new(None));
+            let mut c = Coalesce::new(mk_emit(&ec)).with_settings(Settings {
+                enrich_exe_hash: true,
+                enrich_exe_hash_size_limit: 20_000_000,
+                enrich_workers: workers,
+                enrich_uid_groups: false,
+                enrich_pid: false,
+                ..Settings::default()
+            });
+            // Great. Ubuntu's uutils comes out as a fat binary of > 10 MB.
+            process_record(&mut c, record.as_bytes())?;
+
+            let output = event_to_json(ec.borrow().as_ref().expect("no event emitted"));
+            println!("{output}");
+            assert!(
+                output.contains(&format!(r#""EXE_HASH":"{expected_hash}""#)),
+                "output should contain EXE_HASH with correct SHA256 ({workers} workers)"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    fn enrich_kernel_module() -> Result<(), Box<dyn Error>> {
+        use sha2::{Digest, Sha256};
+
+        // The module file is opened as seen by the loading process.
+        let pid = std::process::id();
+        let record = format!(
+            r#"type=SYSCALL msg=audit(1615114232.375:1): arch=c000003e syscall=313 success=yes exit=0 a0=3 a1=55d3ee2c2a50 a2=0 a3=0 items=1 ppid=1 pid={pid} auid=0 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=pts0 ses=1 comm="insmod" exe="/usr/bin/kmod" key=(null)
+type=CWD msg=audit(1615114232.375:1): cwd="/bin"
+type=PATH msg=audit(1615114232.375:1): item=0 name="true" inode=261214 dev=ca:03 mode=0100755 ouid=0 ogid=0 rdev=00:00 nametype=NORMAL cap_fp=0 cap_fi=0 cap_fe=0 cap_fver=0
+type=EOE msg=audit(1615114232.375:1): 
+"#
+        );
+        let expected_hash = hex_string(Sha256::digest(std::fs::read("/bin/true")?).as_ref());
+
+        for workers in [0, 2] {
+            let ec: Rc<RefCell<Option<Event>>> = Rc::new(RefCell::new(None));
+            let mut c = Coalesce::new(mk_emit(&ec)).with_settings(Settings {
+                enrich_kernel_module: true,
+                enrich_kernel_module_hash: true,
+                enrich_workers: workers,
+                ..Settings::default()
+            });
+            process_record(&mut c, record.as_bytes())?;
+
+            let output = event_to_json(ec.borrow().as_ref().unwrap());
+            println!("{output}");
+            assert!(
+                output.contains(&format!(
+                    r#""MODULE_FILE":"/bin/true","MODULE_HASH":"{expected_hash}","KERNEL_TAINTED":"#
+                )),
+                "{output} ({workers} workers)"
+            );
+        }
+
+        // Files above the size limit are not hashed.
+        let ec: Rc<RefCell<Option<Event>>> = Rc::new(RefCell::new(None));
         let mut c = Coalesce::new(mk_emit(&ec)).with_settings(Settings {
-            enrich_exe_hash: true,
-            enrich_exe_hash_size_limit: 20_000_000,
-            enrich_uid_groups: false,
-            enrich_pid: false,
+            enrich_kernel_module: true,
+            enrich_kernel_module_hash: true,
+            enrich_kernel_module_hash_size_limit: 1,
             ..Settings::default()
         });
-        // Great. Ubuntu's uutils comes out as a fat binary of > 10 MB.
         process_record(&mut c, record.as_bytes())?;
-
-        let output = event_to_json(ec.borrow().as_ref().expect("no event emitted"));
-        println!("{output}");
-        assert!(
-            output.contains(&format!(r#""EXE_HASH":"{expected_hash}""#)),
-            "output should contain EXE_HASH with correct SHA256"
-        );
+        let output = event_to_json(ec.borrow().as_ref().unwrap());
+        assert!(!output.contains("MODULE_HASH"), "{output}");
         Ok(())
     }
 
     #[test]
-    #[cfg(all(feature = "procfs", target_os = "linux"))]
-    fn exe_hash_cache() {
-        let mut cache = ExeHashCache::new(3);
-        assert_eq!(cache.entries.len(), 0);
-        cache.insert((0, 0, 0), [0; 32]);
-        assert_eq!(cache.entries.len(), 1);
-        cache.insert((1, 1, 1), [1; 32]);
-        assert_eq!(cache.entries.len(), 2);
-        cache.insert((2, 2, 2), [2; 32]);
-        assert_eq!(cache.entries.len(), 3);
-        // Overflow the table,
-        cache.insert((3, 3, 3), [3; 32]);
-        assert_eq!(cache.entries.len(), 3);
+    fn id_names() {
+        let mut userdb = UserDB::default();
+        let mut names = IdNames::default();
+        assert_eq!(names.get(&mut userdb, b"auid", 0xffffffff), b"unset");
+        assert_eq!(names.get(&mut userdb, b"uid", 0), b"root");
+        assert_eq!(names.get(&mut userdb, b"gid", 0), b"root");
         assert_eq!(
-            cache.get(&(0, 0, 0)),
-            None,
-            "(0,0,0) should have been evicted"
-        );
-        assert_ne!(
-            cache.get(&(1, 1, 1)),
-            None,
-            "(1,1,1) should NOT have been evicted"
-        );
-        // insert another, evict another, testing LRU mechanism.
-        cache.insert((4, 4, 4), [4; 32]);
-        assert_ne!(
-            cache.get(&(1, 1, 1)),
-            None,
-            "(1,1,1) should still NOT have been evicted"
+            names.get(&mut userdb, b"ogid", 0xfffffff0),
+            b"unknown(4294967280)"
         );
-        assert_ne!(cache.get(&(4, 4, 4)), None);
-        assert_ne!(cache.get(&(3, 3, 3)), None);
-        assert_eq!(cache.get(&(2, 2, 2)), None);
+        assert_eq!(names.0.len(), 4);
+        names.get(&mut userdb, b"euid", 0);
+        assert_eq!(names.0.len(), 4, "uid and euid should share an entry");
+        names.clear();
+        assert!(names.0.is_empty());
     }
 }
@@ -1,15 +1,35 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
 
 use serde::{
     de::{self, Deserializer, Visitor},
     Deserialize, Serialize,
 };
+use serde_with::SerializeDisplay;
 
-use crate::coalesce::Settings;
+use thiserror::Error;
+
+use indexmap::IndexMap;
+
+use linux_audit_parser::MessageType;
+
+use crate::analyze::{default_shells, Attack, AuditConfig, ExecFromTmp, SuspiciousShell};
+use crate::coalesce::{Settings, ENV_REDACT_DEFAULT};
+use crate::constants::KEY_SEPARATOR;
+use crate::forward::Destination;
+use crate::json::{Escape, Framing, InvalidUtf8, SpecialFormatter};
 use crate::label_matcher::LabelMatcher;
+use crate::presets::FilterPreset;
+use crate::projection::FieldPath;
+use crate::rules::Requirement;
+#[cfg(feature = "sigma")]
+use crate::sigma::Sigma;
 use crate::sockaddr::SocketAddrMatcher;
+use crate::translation::TranslatedValues;
+use crate::userdb;
 
 fn default_state_file() -> Option<PathBuf> {
     Some(Path::new("state").into())
@@ -43,6 +63,13 @@ pub struct Statefile {
     pub max_age: u64,
     #[serde(rename = "write-state-period")]
     pub write_state_period: Option<u64>,
+    /// Position in the input file, if `input` is a file
+    #[serde(default = "default_input_checkpoint", rename = "input-checkpoint")]
+    pub input_checkpoint: PathBuf,
+}
+
+fn default_input_checkpoint() -> PathBuf {
+    "input-checkpoint".into()
 }
 
 impl Default for Statefile {
@@ -52,6 +79,7 @@ impl Default for Statefile {
             generations: 0,
             max_age: default_state_max_age(),
             write_state_period: None,
+            input_checkpoint: default_input_checkpoint(),
         }
     }
 }
@@ -70,6 +98,77 @@ pub struct Logfile {
     pub generations: Option<u64>,
     #[serde(rename = "line-prefix")]
     pub line_prefix: Option<String>,
+    #[serde(default)]
+    pub escape: Escape,
+    #[serde(rename = "b64-max-bytes")]
+    pub b64_max_bytes: Option<usize>,
+    #[serde(default, rename = "invalid-utf8")]
+    pub invalid_utf8: InvalidUtf8,
+    #[serde(default)]
+    pub framing: Framing,
+    #[serde(default, rename = "translated-values")]
+    pub translated_values: TranslatedValues,
+    #[serde(rename = "spool-size")]
+    pub spool_size: Option<usize>,
+    #[serde(rename = "sign-key")]
+    pub sign_key: Option<PathBuf>,
+    #[serde(rename = "encrypt-to")]
+    pub encrypt_to: Option<String>,
+    #[serde(default)]
+    pub format: OutputFormat,
+    #[serde(rename = "sd-enterprise-id")]
+    pub sd_enterprise_id: Option<u32>,
+    pub fields: Option<Vec<FieldPath>>,
+    #[serde(rename = "string-table")]
+    pub string_table: Option<Vec<FieldPath>>,
+    #[serde(rename = "string-table-size")]
+    pub string_table_size: Option<usize>,
+    #[serde(default)]
+    pub lookup: Vec<Lookup>,
+    #[serde(default)]
+    pub rewrite: Vec<RewriteRule>,
+    #[serde(default)]
+    pub rename: IndexMap<FieldPath, String>,
+}
+
+/// Rule that replaces matches of `regex` in values of `field` on
+/// output
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub struct RewriteRule {
+    pub field: FieldPath,
+    pub regex: String,
+    pub replace: String,
+}
+
+/// Lookup table whose values are added to events on output
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub struct Lookup {
+    pub file: PathBuf,
+    pub field: FieldPath,
+    pub name: String,
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Json,
+    Rfc5424,
+    Text,
+    Ausearch,
+    #[serde(rename = "go-audit")]
+    GoAudit,
+    Otlp,
+}
+
+impl From<&Logfile> for SpecialFormatter {
+    fn from(def: &Logfile) -> Self {
+        SpecialFormatter {
+            escape: def.escape,
+            b64_max_bytes: def.b64_max_bytes,
+            invalid_utf8: def.invalid_utf8,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize, Eq, PartialEq)]
@@ -77,6 +176,8 @@ pub struct Debug {
     pub inputlog: Option<Logfile>,
     #[serde(rename = "parse-error-log")]
     pub parse_error_log: Option<Logfile>,
+    #[serde(default, rename = "error-events")]
+    pub error_events: bool,
 }
 
 #[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Hash)]
@@ -90,12 +191,44 @@ fn execve_argv_default() -> HashSet<ArrayOrString> {
     [ArrayOrString::Array].into()
 }
 
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArgvDerived {
+    Argv0Base,
+    Cmdline,
+    Flags,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HostMetadata {
+    #[default]
+    Off,
+    Heartbeat,
+    All,
+}
+
+/// Fields from the user database that can be added for the audit uid
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PasswdField {
+    Shell,
+    Home,
+    Gecos,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Transform {
     #[serde(default = "execve_argv_default", rename = "execve-argv")]
     pub execve_argv: HashSet<ArrayOrString>,
     #[serde(default, rename = "execve-argv-limit-bytes")]
     pub execve_argv_limit_bytes: Option<usize>,
+    #[serde(default, rename = "execve-argv-derived")]
+    pub execve_argv_derived: HashSet<ArgvDerived>,
+    #[serde(default, rename = "path-nametypes")]
+    pub path_nametypes: Option<Vec<String>>,
+    #[serde(default, rename = "key-list")]
+    pub key_list: bool,
 }
 
 impl Default for Transform {
@@ -103,6 +236,9 @@ impl Default for Transform {
         Transform {
             execve_argv: execve_argv_default(),
             execve_argv_limit_bytes: None,
+            execve_argv_derived: HashSet::new(),
+            path_nametypes: None,
+            key_list: false,
         }
     }
 }
@@ -115,6 +251,14 @@ pub struct Translate {
     pub userdb: bool,
     #[serde(default, rename = "drop-raw")]
     pub drop_raw: bool,
+    #[serde(default, rename = "skip-universal")]
+    pub skip_universal: HashSet<MessageType>,
+    #[serde(default, rename = "skip-user-db")]
+    pub skip_userdb: HashSet<MessageType>,
+    #[serde(rename = "user-db-refresh")]
+    pub userdb_refresh: Option<u64>,
+    #[serde(default)]
+    pub selinux: bool,
 }
 
 fn execve_env_default() -> Vec<String> {
@@ -124,7 +268,11 @@ fn execve_env_default() -> Vec<String> {
         .collect()
 }
 
-fn true_value() -> bool {
+fn execve_env_redact_default() -> Vec<String> {
+    ENV_REDACT_DEFAULT.iter().map(|s| s.to_string()).collect()
+}
+
+pub(crate) fn true_value() -> bool {
     true
 }
 
@@ -132,18 +280,64 @@ fn true_value() -> bool {
 pub struct Enrich {
     #[serde(default = "execve_env_default", rename = "execve-env")]
     pub execve_env: Vec<String>,
+    #[serde(default = "execve_env_redact_default", rename = "execve-env-redact")]
+    pub execve_env_redact: Vec<String>,
     #[serde(default = "true_value")]
     pub container: bool,
     #[serde(default)]
     pub container_info: bool,
     #[serde(default = "true_value")]
     pub systemd: bool,
+    #[serde(default, rename = "systemd-unit")]
+    pub systemd_unit: bool,
+    #[serde(default, rename = "ns-pid")]
+    pub ns_pid: bool,
+    #[serde(default, rename = "file-action")]
+    pub file_action: bool,
+    #[serde(default, rename = "written-by")]
+    pub written_by: bool,
+    #[serde(
+        default = "default_written_by_cache_entries",
+        rename = "written-by-cache-entries"
+    )]
+    pub written_by_cache_entries: usize,
+    #[serde(default, rename = "parent-stubs")]
+    pub parent_stubs: bool,
+    #[serde(default, rename = "target-process")]
+    pub target_process: bool,
+    #[serde(default, rename = "host-path")]
+    pub host_path: bool,
+    #[serde(default)]
+    pub mount: bool,
+    #[serde(default, rename = "kernel-module")]
+    pub kernel_module: bool,
+    #[serde(default, rename = "kernel-module-hash")]
+    pub kernel_module_hash: bool,
+    #[serde(
+        default = "default_kernel_module_hash_size_limit",
+        rename = "kernel-module-hash-size-limit"
+    )]
+    pub kernel_module_hash_size_limit: u64,
+    #[serde(default, rename = "host-metadata")]
+    pub host_metadata: HostMetadata,
+    #[serde(default, rename = "login-session")]
+    pub login_session: bool,
     #[serde(default = "true_value")]
     pub pid: bool,
+    #[serde(default)]
+    pub ancestors: bool,
+    #[serde(default, rename = "entity-id")]
+    pub entity_id: bool,
+    #[serde(default)]
+    pub lineage: bool,
     #[serde(default = "true_value")]
     pub script: bool,
     #[serde(default = "true_value", rename = "uid-groups")]
     pub uid_groups: bool,
+    #[serde(default, rename = "uid-groups-privileged")]
+    pub uid_groups_privileged: bool,
+    #[serde(default, rename = "auid-passwd")]
+    pub auid_passwd: Vec<PasswdField>,
     #[serde(default)]
     pub prefix: Option<String>,
     #[serde(default, rename = "exe-hash")]
@@ -158,30 +352,73 @@ pub struct Enrich {
         rename = "exe-hash-cache-entries"
     )]
     pub exe_hash_cache_entries: usize,
+    #[serde(default, rename = "cgroup-prefetch")]
+    pub cgroup_prefetch: bool,
+    #[serde(default, rename = "process-events")]
+    pub process_events: bool,
+    #[serde(default, rename = "container-events")]
+    pub container_events: bool,
+    #[serde(default)]
+    pub deadline: Option<u64>,
+    #[serde(default)]
+    pub workers: usize,
 }
 
 fn default_exe_hash_size_limit() -> u64 {
     10_000_000
 }
 
+fn default_kernel_module_hash_size_limit() -> u64 {
+    100_000_000
+}
+
 fn default_exe_hash_cache_entries() -> usize {
     1024
 }
 
+fn default_written_by_cache_entries() -> usize {
+    1024
+}
+
 impl Default for Enrich {
     fn default() -> Self {
         Enrich {
             execve_env: execve_env_default(),
+            execve_env_redact: execve_env_redact_default(),
             container: true,
             container_info: false,
             systemd: true,
+            systemd_unit: false,
+            ns_pid: false,
+            file_action: false,
+            written_by: false,
+            written_by_cache_entries: default_written_by_cache_entries(),
+            parent_stubs: false,
+            target_process: false,
+            host_path: false,
+            mount: false,
+            kernel_module: false,
+            kernel_module_hash: false,
+            kernel_module_hash_size_limit: default_kernel_module_hash_size_limit(),
+            host_metadata: HostMetadata::Off,
+            login_session: false,
             pid: true,
+            ancestors: false,
+            entity_id: false,
+            lineage: false,
             script: true,
             uid_groups: true,
+            uid_groups_privileged: false,
+            auid_passwd: vec![],
             prefix: None,
             exe_hash: false,
             exe_hash_size_limit: default_exe_hash_size_limit(),
             exe_hash_cache_entries: default_exe_hash_cache_entries(),
+            cgroup_prefetch: false,
+            process_events: false,
+            container_events: false,
+            deadline: None,
+            workers: 0,
         }
     }
 }
@@ -193,6 +430,32 @@ fn default_4096() -> usize {
     4096
 }
 
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Propagation {
+    Fork,
+    Exec,
+}
+
+fn propagate_across_default() -> HashSet<Propagation> {
+    [Propagation::Fork, Propagation::Exec].into()
+}
+
+/// Restrictions on how a single label is propagated to other processes
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PropagatePolicy {
+    /// Maximum number of generations of child processes
+    #[serde(default, rename = "max-depth")]
+    pub max_depth: Option<u32>,
+    /// Propagate from parent to child processes (fork) and/or from a
+    /// process to its replacement (exec)
+    #[serde(default = "propagate_across_default")]
+    pub across: HashSet<Propagation>,
+    /// Don't propagate into processes running these executables
+    #[serde(default, rename = "stop-exe", with = "regex_set")]
+    pub stop_exe: regex::bytes::RegexSet,
+}
+
 #[derive(Default, Debug, Serialize, Deserialize)]
 pub struct LabelProcess {
     #[serde(default, rename = "label-keys")]
@@ -213,8 +476,246 @@ pub struct LabelProcess {
     pub label_script: Option<LabelMatcher>,
     #[serde(default, rename = "unlabel-script")]
     pub unlabel_script: Option<LabelMatcher>,
+    #[serde(default, rename = "label-unit")]
+    pub label_unit: Option<LabelMatcher>,
+    #[serde(default, rename = "label-cgroup")]
+    pub label_cgroup: Option<LabelMatcher>,
     #[serde(default, rename = "propagate-labels")]
     pub propagate_labels: HashSet<String>,
+    #[serde(default, rename = "propagate-policy")]
+    pub propagate_policy: HashMap<String, PropagatePolicy>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub struct AuditRules {
+    #[serde(default)]
+    pub report: bool,
+    #[serde(default = "true_value", rename = "check-exec")]
+    pub check_exec: bool,
+    #[serde(default = "true_value", rename = "check-features")]
+    pub check_features: bool,
+}
+
+impl Default for AuditRules {
+    fn default() -> Self {
+        AuditRules {
+            report: false,
+            check_exec: true,
+            check_features: true,
+        }
+    }
+}
+
+/// Group commands that are run by the same shell within a short
+/// time window into a single event.
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub struct CommandGroups {
+    /// Names of shell executables
+    #[serde(default = "default_shells")]
+    pub shells: HashSet<String>,
+    /// Time window in milliseconds
+    #[serde(default = "default_command_group_window")]
+    pub window: u64,
+}
+
+fn default_command_group_window() -> u64 {
+    1000
+}
+
+impl Default for CommandGroups {
+    fn default() -> Self {
+        CommandGroups {
+            shells: default_shells(),
+            window: default_command_group_window(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct Correlate {
+    #[serde(default, rename = "command-groups")]
+    pub command_groups: Option<CommandGroups>,
+}
+
+fn default_entity_idle_timeout() -> u64 {
+    60
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub struct Entities {
+    pub processes: Option<Logfile>,
+    pub files: Option<Logfile>,
+    #[serde(default = "default_entity_idle_timeout", rename = "idle-timeout")]
+    pub idle_timeout: u64,
+}
+
+impl Default for Entities {
+    fn default() -> Self {
+        Entities {
+            processes: None,
+            files: None,
+            idle_timeout: default_entity_idle_timeout(),
+        }
+    }
+}
+
+fn default_summary_max_entries() -> usize {
+    10
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub struct Summary {
+    #[serde(default)]
+    pub period: Option<u64>,
+    #[serde(default = "default_summary_max_entries", rename = "max-entries")]
+    pub max_entries: usize,
+    #[serde(default, rename = "key-share-warning")]
+    pub key_share_warning: Option<u64>,
+}
+
+impl Default for Summary {
+    fn default() -> Self {
+        Summary {
+            period: None,
+            max_entries: default_summary_max_entries(),
+            key_share_warning: None,
+        }
+    }
+}
+
+/// Value of a field in a user-defined section
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(untagged)]
+pub enum SectionValue {
+    Literal(String),
+    Env { env: String },
+    File { file: PathBuf },
+}
+
+impl SectionValue {
+    /// Returns the value, or `None` if the environment variable or
+    /// file is not available
+    pub fn resolve(&self) -> Option<String> {
+        match self {
+            SectionValue::Literal(s) => Some(s.clone()),
+            SectionValue::Env { env } => std::env::var(env).ok(),
+            SectionValue::File { file } => fs::read_to_string(file)
+                .ok()
+                .map(|s| s.trim_end().to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct Daemon {
+    #[serde(default)]
+    pub pidfile: Option<PathBuf>,
+    #[serde(default)]
+    pub chroot: bool,
+}
+
+/// Low-volume channel for events that match alert rules
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct Alerts {
+    /// Log for alerts, e.g. a webhook or syslog
+    pub output: Option<Logfile>,
+    #[serde(default)]
+    pub rules: Vec<AlertRule>,
+}
+
+/// Rule for events that are sent as alerts, see `crate::alert`
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub struct AlertRule {
+    pub name: String,
+    /// Process labels (`SYSCALL.LABELS`)
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// ATT&CK techniques (`SYSCALL.TAGS`) or Sigma rule IDs
+    /// (`SYSCALL.SIGMA`)
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Length of the deduplication and burst window in seconds
+    #[serde(default = "default_alert_window")]
+    pub window: u64,
+    /// Maximum number of alerts per window
+    #[serde(default = "default_alert_burst")]
+    pub burst: u64,
+    /// Fields whose values identify duplicate alerts
+    #[serde(default, rename = "dedup-fields")]
+    pub dedup_fields: Vec<FieldPath>,
+}
+
+fn default_alert_window() -> u64 {
+    300
+}
+
+fn default_alert_burst() -> u64 {
+    10
+}
+
+/// Consumers that receive a copy of the raw audit stream
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub struct Forward {
+    #[serde(default)]
+    pub destinations: Vec<Destination>,
+    /// Number of records that are queued per destination
+    #[serde(default = "default_forward_queue_size", rename = "queue-size")]
+    pub queue_size: usize,
+}
+
+fn default_forward_queue_size() -> usize {
+    10000
+}
+
+impl Default for Forward {
+    fn default() -> Self {
+        Forward {
+            destinations: vec![],
+            queue_size: default_forward_queue_size(),
+        }
+    }
+}
+
+/// Socket for querying recent events, see `laurel query`
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub struct Control {
+    #[serde(default)]
+    pub socket: Option<PathBuf>,
+    /// Number of recent events that are kept
+    #[serde(default = "default_control_events")]
+    pub events: usize,
+}
+
+fn default_control_events() -> usize {
+    1000
+}
+
+impl Default for Control {
+    fn default() -> Self {
+        Control {
+            socket: None,
+            events: default_control_events(),
+        }
+    }
+}
+
+/// Sigma configuration that is rejected by [`Config::validate`] if
+/// laurel has been built without the `sigma` feature
+#[cfg(not(feature = "sigma"))]
+type Sigma = toml::Table;
+
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub struct Analyze {
+    #[serde(default, rename = "suspicious-shell")]
+    pub suspicious_shell: Option<SuspiciousShell>,
+    #[serde(default, rename = "exec-from-tmp")]
+    pub exec_from_tmp: Option<ExecFromTmp>,
+    #[serde(default, rename = "audit-config")]
+    pub audit_config: Option<AuditConfig>,
+    #[serde(default)]
+    pub attack: Option<Attack>,
+    #[serde(default)]
+    pub sigma: Option<Sigma>,
 }
 
 #[derive(Default, Debug, Serialize, Deserialize)]
@@ -254,21 +755,57 @@ pub struct Filter {
     pub filter_labels: HashSet<String>,
     #[serde(default, rename = "filter-raw-lines", with = "regex_set")]
     pub filter_raw_lines: regex::bytes::RegexSet,
+    #[serde(default)]
+    pub presets: Vec<FilterPreset>,
     #[serde(default, rename = "filter-null-keys")]
     pub filter_null_keys: bool,
+    #[serde(default)]
+    pub syscalls: HashSet<String>,
+    #[serde(default, rename = "allow-syscalls")]
+    pub allow_syscalls: HashSet<String>,
     #[serde(default, rename = "filter-sockaddr")]
     pub filter_sockaddr: Vec<SocketAddrMatcher>,
     #[serde(default, rename = "filter-action")]
     pub filter_action: FilterAction,
     #[serde(default = "true_value", rename = "keep-first-per-process")]
     pub keep_first_per_process: bool,
+    #[serde(default)]
+    pub sample: Vec<SampleRule>,
+    #[serde(default, rename = "drop-record-types")]
+    pub drop_record_types: HashSet<MessageType>,
+}
+
+/// Keep only one in `ratio` events that match `key` and `syscall`
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub struct SampleRule {
+    #[serde(default)]
+    pub key: Option<String>,
+    #[serde(default)]
+    pub syscall: Option<String>,
+    pub ratio: u32,
 }
 
-#[derive(Debug, Serialize, Default)]
+impl SampleRule {
+    /// Returns true if the rule matches `syscall` and any of the keys
+    /// in `key`
+    pub fn matches(&self, key: Option<&[u8]>, syscall: Option<&str>) -> bool {
+        let mut keys = key
+            .into_iter()
+            .flat_map(|key| key.split(|c| *c == KEY_SEPARATOR));
+        self.key
+            .as_ref()
+            .map_or(true, |k| keys.any(|p| p == k.as_bytes()))
+            && self.syscall.as_deref().map_or(true, |s| Some(s) == syscall)
+    }
+}
+
+#[derive(Debug, SerializeDisplay, Default)]
 pub enum Input {
     #[default]
     Stdin,
     Unix(PathBuf),
+    /// Audit log file that is followed across rotations
+    File(PathBuf),
 }
 
 impl std::fmt::Display for Input {
@@ -276,6 +813,7 @@ impl std::fmt::Display for Input {
         match self {
             Input::Stdin => write!(fmt, "stdin"),
             Input::Unix(p) => write!(fmt, "unix:{}", p.to_string_lossy()),
+            Input::File(p) => write!(fmt, "file:{}", p.to_string_lossy()),
         }
     }
 }
@@ -304,12 +842,18 @@ impl Visitor<'_> for InputVisitor {
         } else if let Some(s) = s.strip_prefix("unix:") {
             let p = Path::new(s).to_path_buf();
             Ok(Input::Unix(p))
+        } else if let Some(s) = s.strip_prefix("file:") {
+            Ok(Input::File(Path::new(s).to_path_buf()))
         } else {
             Err(de::Error::custom("unrecognized input specification"))
         }
     }
 }
 
+fn default_shutdown_grace_period() -> u64 {
+    5
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
     pub user: Option<String>,
@@ -318,8 +862,31 @@ pub struct Config {
     pub input: Input,
     #[serde(default, rename = "statusreport-period")]
     pub statusreport_period: Option<u64>,
+    #[serde(default, rename = "heartbeat-period")]
+    pub heartbeat_period: Option<u64>,
+    #[serde(default, rename = "clock-skew-threshold")]
+    pub clock_skew_threshold: Option<u64>,
+    #[serde(
+        default = "default_shutdown_grace_period",
+        rename = "shutdown-grace-period"
+    )]
+    pub shutdown_grace_period: u64,
+    #[serde(default, rename = "reorder-window")]
+    pub reorder_window: u64,
+    #[serde(default, rename = "ordered-output")]
+    pub ordered_output: bool,
     #[serde(default)]
     pub marker: Option<String>,
+    #[serde(default, rename = "local-node")]
+    pub local_node: Option<String>,
+    #[serde(default)]
+    pub daemon: Daemon,
+    #[serde(default)]
+    pub control: Control,
+    #[serde(default)]
+    pub forward: Forward,
+    #[serde(default, rename = "audit-rules")]
+    pub audit_rules: AuditRules,
     #[serde(default)]
     pub state: Statefile,
     #[serde(default)]
@@ -327,6 +894,10 @@ pub struct Config {
     #[serde(default)]
     pub filterlog: Logfile,
     #[serde(default)]
+    pub slimlog: Option<Logfile>,
+    #[serde(default)]
+    pub alerts: Alerts,
+    #[serde(default)]
     pub debug: Debug,
     #[serde(default)]
     pub transform: Transform,
@@ -337,7 +908,17 @@ pub struct Config {
     #[serde(default, rename = "label-process")]
     pub label_process: LabelProcess,
     #[serde(default)]
+    pub analyze: Analyze,
+    #[serde(default)]
+    pub correlate: Correlate,
+    #[serde(default)]
+    pub entities: Entities,
+    #[serde(default)]
+    pub summary: Summary,
+    #[serde(default)]
     pub filter: Filter,
+    #[serde(default)]
+    pub sections: IndexMap<String, IndexMap<String, SectionValue>>,
 }
 
 impl Default for Config {
@@ -347,12 +928,23 @@ impl Default for Config {
             directory: Some(".".into()),
             input: Input::Stdin,
             statusreport_period: None,
+            heartbeat_period: None,
+            clock_skew_threshold: None,
+            shutdown_grace_period: default_shutdown_grace_period(),
+            reorder_window: 0,
+            ordered_output: false,
             marker: None,
+            local_node: None,
+            daemon: Daemon::default(),
+            control: Control::default(),
+            forward: Forward::default(),
+            audit_rules: AuditRules::default(),
             state: Statefile {
                 file: Some("state".into()),
                 generations: 3,
                 max_age: 60,
                 write_state_period: None,
+                input_checkpoint: default_input_checkpoint(),
             },
             auditlog: Logfile {
                 file: "audit.log".into(),
@@ -366,12 +958,19 @@ impl Default for Config {
                 generations: Some(5),
                 ..Logfile::default()
             },
+            slimlog: None,
+            alerts: Alerts::default(),
             debug: Debug::default(),
             transform: Transform::default(),
             translate: Translate::default(),
             enrich: Enrich::default(),
             label_process: LabelProcess::default(),
+            analyze: Analyze::default(),
+            correlate: Correlate::default(),
+            entities: Entities::default(),
+            summary: Summary::default(),
             filter: Filter::default(),
+            sections: IndexMap::new(),
         }
     }
 }
@@ -400,7 +999,330 @@ impl std::fmt::Display for Config {
     }
 }
 
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Error reading {0}: {1}")]
+    Read(PathBuf, std::io::Error),
+    #[error("Config file {0} must not be world-writable")]
+    WorldWritable(PathBuf),
+    /// Syntax or type error in a configuration file, with the key
+    /// whose value is invalid if it could be determined
+    #[error("Error parsing {0}: {2}")]
+    Parse(PathBuf, Option<String>, Box<toml::de::Error>),
+    #[error("Invalid override {0}")]
+    Override(String),
+    #[error("Error applying overrides: {1}")]
+    ApplyOverrides(Option<String>, Box<toml::de::Error>),
+    #[error("Error serializing default configuration: {0}")]
+    Defaults(toml::ser::Error),
+    #[error("{0}: {1}")]
+    Invalid(&'static str, &'static str),
+}
+
+impl ConfigError {
+    /// Returns a short identifier for the class of error
+    pub fn code(&self) -> &'static str {
+        match self {
+            ConfigError::Read(..) => "read",
+            ConfigError::WorldWritable(_) => "world-writable",
+            ConfigError::Parse(..) => "parse",
+            ConfigError::Override(_) => "override",
+            ConfigError::ApplyOverrides(..) => "apply-overrides",
+            ConfigError::Defaults(_) => "defaults",
+            ConfigError::Invalid(..) => "invalid",
+        }
+    }
+
+    /// Returns the `.`-separated path of the configuration key that
+    /// the error refers to, if known
+    pub fn key(&self) -> Option<&str> {
+        match self {
+            ConfigError::Parse(_, key, _) | ConfigError::ApplyOverrides(key, _) => key.as_deref(),
+            ConfigError::Override(o) => o.split_once('=').map(|(k, _)| k),
+            ConfigError::Invalid(key, _) => Some(key),
+            _ => None,
+        }
+    }
+}
+
+/// Read a single TOML file
+fn read_table(path: &Path) -> Result<toml::Table, ConfigError> {
+    let err = |e| ConfigError::Read(path.into(), e);
+    if fs::metadata(path).map_err(err)?.permissions().mode() & 0o002 != 0 {
+        return Err(ConfigError::WorldWritable(path.into()));
+    }
+    let text = fs::read_to_string(path).map_err(err)?;
+    toml::from_str(&text).map_err(|e| ConfigError::Parse(path.into(), None, e.into()))
+}
+
+/// Insert `value` at `path`, creating tables as needed. Returns
+/// `None` if one of the parents is not a table.
+fn insert_path(table: &mut toml::Table, path: &[&str], value: toml::Value) -> Option<()> {
+    let (last, parents) = path.split_last()?;
+    let mut table = table;
+    for k in parents {
+        table = match table
+            .entry(k.to_string())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+        {
+            toml::Value::Table(t) => t,
+            _ => return None,
+        };
+    }
+    table.insert(last.to_string(), value);
+    Some(())
+}
+
+/// Collect `.`-separated paths and values of all non-table values
+fn leaves<'a>(
+    table: &'a toml::Table,
+    prefix: &[&'a str],
+    out: &mut Vec<(Vec<&'a str>, &'a toml::Value)>,
+) {
+    for (k, v) in table {
+        let mut path = prefix.to_vec();
+        path.push(k);
+        match v {
+            toml::Value::Table(t) => leaves(t, &path, out),
+            v => out.push((path, v)),
+        }
+    }
+}
+
+/// Determine the key in `table` that cannot be deserialized by
+/// applying the values one by one to the default configuration.
+fn find_invalid_key(table: &toml::Table) -> Option<String> {
+    let defaults = toml::Table::try_from(Config::default()).ok()?;
+    let mut values = vec![];
+    leaves(table, &[], &mut values);
+    values.into_iter().find_map(|(path, value)| {
+        let mut t = defaults.clone();
+        insert_path(&mut t, &path, value.clone())?;
+        t.try_into::<Config>().is_err().then(|| path.join("."))
+    })
+}
+
+/// Merge tables recursively. All other values in `base`, including
+/// arrays, are replaced.
+fn merge_table(base: &mut toml::Table, other: toml::Table) {
+    for (k, v) in other {
+        match (base.get_mut(&k), v) {
+            (Some(toml::Value::Table(b)), toml::Value::Table(o)) => merge_table(b, o),
+            (_, v) => {
+                base.insert(k, v);
+            }
+        }
+    }
+}
+
+/// Set value for a `.`-separated key, creating tables as needed.
+/// Values that cannot be parsed as TOML are treated as strings.
+fn set_value(table: &mut toml::Table, key: &str, value: &str) -> Result<(), ConfigError> {
+    let err = || ConfigError::Override(format!("{key}={value}"));
+    let value = toml::from_str::<toml::Table>(&format!("v = {value}"))
+        .ok()
+        .and_then(|mut t| t.remove("v"))
+        .unwrap_or_else(|| toml::Value::String(value.into()));
+    let path: Vec<&str> = key.split('.').collect();
+    if path.iter().any(|k| k.is_empty()) {
+        return Err(err());
+    }
+    insert_path(table, &path, value).ok_or_else(err)
+}
+
+/// Turn `LAUREL_*` environment variables into `key=value` overrides:
+/// The prefix is stripped, `__` separates sections and keys, `_` is
+/// replaced by `-`, e.g. `LAUREL_AUDITLOG__READ_USERS` becomes
+/// `auditlog.read-users`.
+pub fn env_overrides<I>(vars: I) -> Vec<(String, String)>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let mut overrides: Vec<_> = vars
+        .into_iter()
+        .filter_map(|(k, v)| {
+            let key = k.strip_prefix("LAUREL_")?;
+            let key = key
+                .split("__")
+                .map(|s| s.to_ascii_lowercase().replace('_', "-"))
+                .collect::<Vec<_>>()
+                .join(".");
+            Some((key, v))
+        })
+        .collect();
+    overrides.sort();
+    overrides
+}
+
 impl Config {
+    /// Read configuration from `path`. If a `config.d` directory
+    /// exists next to it, the `*.toml` files contained therein are
+    /// merged in lexical order.
+    pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        Self::load(Some(path), &[])
+    }
+
+    /// Read configuration from `path` (see [`Config::from_file`]) or
+    /// start with the default configuration, then apply `key=value`
+    /// overrides.
+    pub fn load(
+        path: Option<&Path>,
+        overrides: &[(String, String)],
+    ) -> Result<Config, ConfigError> {
+        let config = Self::parse(path, overrides)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn parse(path: Option<&Path>, overrides: &[(String, String)]) -> Result<Config, ConfigError> {
+        let Some(path) = path else {
+            if overrides.is_empty() {
+                return Ok(Config::default());
+            }
+            let mut table =
+                toml::Table::try_from(Config::default()).map_err(ConfigError::Defaults)?;
+            for (k, v) in overrides {
+                set_value(&mut table, k, v)?;
+            }
+            return table
+                .clone()
+                .try_into()
+                .map_err(|e| ConfigError::ApplyOverrides(find_invalid_key(&table), e.into()));
+        };
+        let mut table = read_table(path)?;
+        let dir = path.with_file_name("config.d");
+        if dir.is_dir() {
+            let mut fragments = fs::read_dir(&dir)
+                .map_err(|e| ConfigError::Read(dir.clone(), e))?
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| p.extension().is_some_and(|e| e == "toml") && p.is_file())
+                .collect::<Vec<_>>();
+            fragments.sort();
+            for fragment in fragments {
+                merge_table(&mut table, read_table(&fragment)?);
+            }
+        }
+        for (k, v) in overrides {
+            set_value(&mut table, k, v)?;
+        }
+        table.clone().try_into().map_err(|e| {
+            let key = find_invalid_key(&table);
+            match overrides.is_empty() {
+                true => ConfigError::Parse(path.into(), key, e.into()),
+                false => ConfigError::ApplyOverrides(key, e.into()),
+            }
+        })
+    }
+
+    /// Returns all configured output logs with their section names
+    fn logfiles(&self) -> Vec<(&'static str, &Logfile)> {
+        let mut rv = vec![("auditlog", &self.auditlog), ("filterlog", &self.filterlog)];
+        for (name, log) in [
+            ("slimlog", &self.slimlog),
+            ("alerts.output", &self.alerts.output),
+            ("debug.inputlog", &self.debug.inputlog),
+            ("debug.parse-error-log", &self.debug.parse_error_log),
+            ("entities.processes", &self.entities.processes),
+            ("entities.files", &self.entities.files),
+        ] {
+            if let Some(log) = log {
+                rv.push((name, log));
+            }
+        }
+        rv
+    }
+
+    /// Checks for settings that can't be combined or that need
+    /// features laurel has been built without
+    fn validate(&self) -> Result<(), ConfigError> {
+        if !cfg!(feature = "sigma") && self.analyze.sigma.is_some() {
+            return Err(ConfigError::Invalid(
+                "analyze.sigma",
+                "built without Sigma support",
+            ));
+        }
+        for (name, log) in self.logfiles() {
+            if !cfg!(feature = "signing") && log.sign_key.is_some() {
+                return Err(ConfigError::Invalid(name, "built without signing support"));
+            }
+            if !cfg!(feature = "encryption") && log.encrypt_to.is_some() {
+                return Err(ConfigError::Invalid(
+                    name,
+                    "built without encryption support",
+                ));
+            }
+            if log.framing != Framing::Lf && log.format != OutputFormat::Json {
+                return Err(ConfigError::Invalid(
+                    name,
+                    "framing requires format = \"json\"",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the configured features that only work if certain
+    /// audit rules are loaded
+    pub fn rule_requirements(&self) -> Vec<(&'static str, Requirement)> {
+        const EXEC: &[&str] = &["execve", "execveat"];
+        let exec = Requirement::Syscalls(EXEC);
+        let mut reqs = vec![];
+        let (lp, an) = (&self.label_process, &self.analyze);
+        for (name, configured) in [
+            ("label-process.label-argv", lp.label_argv.is_some()),
+            ("label-process.unlabel-argv", lp.unlabel_argv.is_some()),
+            ("label-process.label-script", lp.label_script.is_some()),
+            ("label-process.unlabel-script", lp.unlabel_script.is_some()),
+            ("analyze.suspicious-shell", an.suspicious_shell.is_some()),
+            ("analyze.exec-from-tmp", an.exec_from_tmp.is_some()),
+            ("analyze.sigma", an.sigma.is_some()),
+            (
+                "correlate.command-groups",
+                self.correlate.command_groups.is_some(),
+            ),
+        ] {
+            if configured {
+                reqs.push((name, exec.clone()));
+            }
+        }
+        if !self.filter.filter_sockaddr.is_empty() {
+            reqs.push((
+                "filter.filter-sockaddr",
+                Requirement::Syscalls(&[
+                    "connect", "bind", "accept", "accept4", "sendto", "sendmsg", "recvfrom",
+                    "recvmsg",
+                ]),
+            ));
+        }
+        if self.enrich.target_process {
+            reqs.push((
+                "enrich.target-process",
+                Requirement::Syscalls(&[
+                    "ptrace",
+                    "process_vm_readv",
+                    "process_vm_writev",
+                    "pidfd_getfd",
+                    "kill",
+                    "tkill",
+                    "tgkill",
+                ]),
+            ));
+        }
+        if self.enrich.kernel_module {
+            reqs.push((
+                "enrich.kernel-module",
+                Requirement::Syscalls(&["init_module", "finit_module", "delete_module"]),
+            ));
+        }
+        for key in &self.label_process.label_keys {
+            reqs.push(("label-process.label-keys", Requirement::Key(key.clone())));
+        }
+        for key in &self.filter.filter_keys {
+            reqs.push(("filter.filter-keys", Requirement::Key(key.clone())));
+        }
+        reqs
+    }
+
     pub fn make_coalesce_settings(&self) -> Settings {
         let (mut execve_prefix, execve_exact) = self
             .enrich
@@ -415,18 +1337,64 @@ impl Config {
             execve_argv_list: self.transform.execve_argv.contains(&ArrayOrString::Array),
             execve_argv_string: self.transform.execve_argv.contains(&ArrayOrString::String),
             execve_argv_limit_bytes: self.transform.execve_argv_limit_bytes,
+            execve_argv0_base: self
+                .transform
+                .execve_argv_derived
+                .contains(&ArgvDerived::Argv0Base),
+            execve_cmdline: self
+                .transform
+                .execve_argv_derived
+                .contains(&ArgvDerived::Cmdline),
+            execve_argv_flags: self
+                .transform
+                .execve_argv_derived
+                .contains(&ArgvDerived::Flags),
+            path_nametypes: self
+                .transform
+                .path_nametypes
+                .as_ref()
+                .map(|v| v.iter().map(|s| s.as_bytes().to_vec()).collect()),
+            key_list: self.transform.key_list,
             execve_env_exact: execve_exact.into_iter().collect(),
             execve_env_prefix: execve_prefix,
+            execve_env_redact: self
+                .enrich
+                .execve_env_redact
+                .iter()
+                .map(|s| s.to_ascii_uppercase().into_bytes())
+                .collect(),
             enrich_container: self.enrich.container,
             enrich_container_info: self.enrich.container_info,
+            enrich_container_events: self.enrich.container_events,
             enrich_systemd: self.enrich.systemd,
+            enrich_systemd_unit: self.enrich.systemd_unit,
+            enrich_ns_pid: self.enrich.ns_pid,
+            enrich_file_action: self.enrich.file_action,
+            enrich_written_by: self.enrich.written_by,
+            enrich_written_by_cache_entries: self.enrich.written_by_cache_entries,
+            enrich_parent_stubs: self.enrich.parent_stubs,
+            enrich_target_process: self.enrich.target_process,
+            enrich_host_path: self.enrich.host_path,
+            enrich_mount: self.enrich.mount,
+            enrich_kernel_module: self.enrich.kernel_module,
+            enrich_kernel_module_hash: self.enrich.kernel_module_hash,
+            enrich_kernel_module_hash_size_limit: self.enrich.kernel_module_hash_size_limit,
+            enrich_host_metadata: self.enrich.host_metadata,
+            enrich_login_session: self.enrich.login_session,
             enrich_pid: self.enrich.pid,
+            enrich_ancestors: self.enrich.ancestors,
+            enrich_entity_id: self.enrich.entity_id,
+            enrich_lineage: self.enrich.lineage,
             enrich_script: self.enrich.script,
             enrich_uid_groups: self.enrich.uid_groups,
+            enrich_uid_groups_privileged: self.enrich.uid_groups_privileged,
+            enrich_auid_passwd: self.enrich.auid_passwd.clone(),
             enrich_exe_hash: self.enrich.exe_hash,
             enrich_exe_hash_size_limit: self.enrich.exe_hash_size_limit,
             enrich_exe_hash_cache_entries: self.enrich.exe_hash_cache_entries,
+            enrich_workers: self.enrich.workers,
             enrich_prefix: self.enrich.prefix.clone(),
+            enrich_deadline: self.enrich.deadline.filter(|d| *d > 0),
             proc_label_keys: self
                 .label_process
                 .label_keys
@@ -437,11 +1405,25 @@ impl Config {
                 .label_process
                 .propagate_labels
                 .iter()
+                .chain(self.label_process.propagate_policy.keys())
                 .map(|s| s.as_bytes().to_vec())
                 .collect(),
+            proc_propagate_policy: self
+                .label_process
+                .propagate_policy
+                .iter()
+                .map(|(k, v)| (k.as_bytes().to_vec(), v.clone()))
+                .collect(),
             translate_universal: self.translate.universal,
             translate_userdb: self.translate.userdb,
+            translate_userdb_refresh: self
+                .translate
+                .userdb_refresh
+                .unwrap_or(userdb::DEFAULT_REFRESH),
             drop_translated: self.translate.drop_raw,
+            translate_skip_universal: self.translate.skip_universal.clone(),
+            translate_skip_userdb: self.translate.skip_userdb.clone(),
+            translate_selinux: self.translate.selinux,
             label_exe: self.label_process.label_exe.clone(),
             unlabel_exe: self.label_process.unlabel_exe.clone(),
             label_argv: self.label_process.label_argv.clone(),
@@ -450,6 +1432,8 @@ impl Config {
             label_argv_count: self.label_process.label_argv_count,
             label_script: self.label_process.label_script.clone(),
             unlabel_script: self.label_process.unlabel_script.clone(),
+            label_unit: self.label_process.label_unit.clone(),
+            label_cgroup: self.label_process.label_cgroup.clone(),
             filter_keys: self
                 .filter
                 .filter_keys
@@ -463,9 +1447,50 @@ impl Config {
                 .map(|s| s.as_bytes().to_vec())
                 .collect(),
             filter_null_keys: self.filter.filter_null_keys,
+            filter_syscalls: self.filter.syscalls.clone(),
+            filter_allow_syscalls: self.filter.allow_syscalls.clone(),
             filter_sockaddr: self.filter.filter_sockaddr.clone(),
             filter_raw_lines: self.filter.filter_raw_lines.clone(),
+            filter_presets: self
+                .filter
+                .presets
+                .iter()
+                .map(|p| (*p, p.regex_set()))
+                .collect(),
             filter_first_per_process: !self.filter.keep_first_per_process,
+            filter_sample: self.filter.sample.clone(),
+            drop_record_types: self.filter.drop_record_types.clone(),
+            analyze_suspicious_shell: self.analyze.suspicious_shell.clone(),
+            analyze_exec_from_tmp: self.analyze.exec_from_tmp.clone(),
+            analyze_audit_config: self.analyze.audit_config.clone(),
+            analyze_attack: self.analyze.attack.as_ref().map(Attack::rules),
+            // Rules are loaded by the caller, see `Sigma::load`
+            #[cfg(feature = "sigma")]
+            analyze_sigma: vec![],
+            command_groups: self.correlate.command_groups.clone(),
+            local_node: self.local_node.clone().map(String::into_bytes),
+            clock_skew_threshold: self.clock_skew_threshold.map(|t| t * 1000),
+            reorder_window: self.reorder_window,
+            ordered_output: self.ordered_output,
+            entity_processes: self.entities.processes.is_some(),
+            entity_files: self.entities.files.is_some(),
+            entity_idle_timeout: self.entities.idle_timeout * 1000,
+            summary_period: self.summary.period.filter(|p| *p > 0).map(|p| p * 1000),
+            summary_max_entries: self.summary.max_entries,
+            summary_key_share_warning: self.summary.key_share_warning,
+            sections: self
+                .sections
+                .iter()
+                .map(|(name, fields)| {
+                    let fields = fields
+                        .iter()
+                        .filter_map(|(k, v)| {
+                            Some((k.clone().into_bytes(), v.resolve()?.into_bytes()))
+                        })
+                        .collect();
+                    (name.clone(), fields)
+                })
+                .collect(),
         }
     }
 }
@@ -540,6 +1565,139 @@ read-users = ["splunk"]
         // assert!(toml::to_string(&cfg_default) == toml::to_string(&cfg_empty_sections));
     }
 
+    #[test]
+    fn sections() {
+        let c: Config = toml::de::from_str(
+            r#"
+[sections.SITE]
+asset_id = "a-1234"
+home = { env = "HOME" }
+missing = { file = "/nonexistent/laurel-asset-id" }
+"#,
+        )
+        .unwrap();
+        let s = c.make_coalesce_settings();
+        assert_eq!(s.sections.len(), 1);
+        let (name, fields) = &s.sections[0];
+        assert_eq!(name, "SITE");
+        let keys: Vec<_> = fields.iter().map(|(k, _)| k.as_slice()).collect();
+        assert_eq!(keys, [&b"asset_id"[..], b"home"]);
+        assert_eq!(fields[0].1, b"a-1234");
+    }
+
+    #[test]
+    fn config_d() {
+        use nix::unistd::mkdtemp;
+        let td = mkdtemp(&std::env::temp_dir().join("laurel-test-XXXXXXXX"))
+            .expect("can't create temp dir");
+        let path = td.join("config.toml");
+        fs::write(
+            &path,
+            r#"
+directory = "/var/log/laurel"
+[auditlog]
+file = "audit.log"
+size = 1000
+[filter]
+filter-keys = ["a"]
+"#,
+        )
+        .unwrap();
+        fs::create_dir(td.join("config.d")).unwrap();
+        fs::write(
+            td.join("config.d/20-role.toml"),
+            "[filter]\nfilter-keys = [\"c\"]\n",
+        )
+        .unwrap();
+        fs::write(
+            td.join("config.d/10-fleet.toml"),
+            "[auditlog]\nsize = 2000\n[filter]\nfilter-keys = [\"b\"]\nfilter-null-keys = true\n",
+        )
+        .unwrap();
+        fs::write(td.join("config.d/README"), "not a config file").unwrap();
+
+        let c = Config::from_file(&path).unwrap();
+        assert_eq!(c.directory, Some("/var/log/laurel".into()));
+        assert_eq!(c.auditlog.file, Path::new("audit.log"));
+        assert_eq!(c.auditlog.size, Some(2000));
+        assert_eq!(c.filter.filter_keys, ["c".to_string()].into());
+        assert!(c.filter.filter_null_keys);
+
+        fs::write(td.join("config.d/30-broken.toml"), "[filter\n").unwrap();
+        assert!(matches!(
+            Config::from_file(&path),
+            Err(ConfigError::Parse(p, None, _)) if p.ends_with("30-broken.toml")
+        ));
+
+        fs::write(
+            td.join("config.d/30-broken.toml"),
+            "[auditlog]\nsize = \"large\"\n",
+        )
+        .unwrap();
+        let e = Config::from_file(&path).unwrap_err();
+        assert_eq!(e.code(), "parse");
+        assert_eq!(e.key(), Some("auditlog.size"));
+
+        fs::remove_dir_all(td).expect("remove_dir_all");
+    }
+
+    #[test]
+    fn overrides() {
+        let overrides = env_overrides([
+            ("HOME".to_string(), "/root".to_string()),
+            ("LAUREL_DIRECTORY".to_string(), "/tmp/laurel".to_string()),
+            (
+                "LAUREL_AUDITLOG__READ_USERS".to_string(),
+                r#"["a", "b"]"#.to_string(),
+            ),
+            ("LAUREL_STATUSREPORT_PERIOD".to_string(), "60".to_string()),
+        ]);
+        assert_eq!(
+            overrides,
+            [
+                (
+                    "auditlog.read-users".to_string(),
+                    r#"["a", "b"]"#.to_string()
+                ),
+                ("directory".to_string(), "/tmp/laurel".to_string()),
+                ("statusreport-period".to_string(), "60".to_string()),
+            ]
+        );
+        let c = Config::load(None, &[]).unwrap();
+        assert!(matches!(c.input, Input::Stdin));
+
+        let mut overrides = overrides;
+        overrides.push(("input".into(), "unix:/run/laurel.sock".into()));
+        overrides.push(("enrich.pid".into(), "false".into()));
+
+        let c = Config::load(None, &overrides).unwrap();
+        assert_eq!(c.directory, Some("/tmp/laurel".into()));
+        assert_eq!(c.statusreport_period, Some(60));
+        assert_eq!(c.auditlog.users, Some(vec!["a".into(), "b".into()]));
+        assert_eq!(c.auditlog.file, Path::new("audit.log"));
+        assert!(matches!(c.input, Input::Unix(ref p) if p == Path::new("/run/laurel.sock")));
+        assert!(!c.enrich.pid);
+
+        assert!(matches!(
+            Config::load(None, &[("directory.foo".into(), "bar".into())]),
+            Err(ConfigError::Override(_))
+        ));
+        assert!(matches!(
+            Config::load(None, &[("statusreport-period".into(), "soon".into())]),
+            Err(ConfigError::ApplyOverrides(Some(k), _)) if k == "statusreport-period"
+        ));
+        assert!(matches!(
+            Config::load(
+                None,
+                &[
+                    ("auditlog.format".into(), "text".into()),
+                    ("auditlog.framing".into(), "json-seq".into())
+                ]
+            ),
+            Err(ConfigError::Invalid("auditlog", _))
+        ));
+    }
+
     #[test]
     fn statefile() {
         let cfg: Config = toml::de::from_str(
@@ -552,6 +1710,38 @@ file = ""
         assert_eq!(cfg.state.file, None);
     }
 
+    #[test]
+    fn rule_requirements() {
+        let cfg: Config = toml::de::from_str(
+            r#"
+[label-process]
+label-argv."^curl " = "download"
+label-keys = [ "software_mgmt" ]
+[enrich]
+kernel-module = true
+"#,
+        )
+        .expect("toml parse error");
+        assert_eq!(
+            cfg.rule_requirements(),
+            [
+                (
+                    "label-process.label-argv",
+                    Requirement::Syscalls(&["execve", "execveat"])
+                ),
+                (
+                    "enrich.kernel-module",
+                    Requirement::Syscalls(&["init_module", "finit_module", "delete_module"])
+                ),
+                (
+                    "label-process.label-keys",
+                    Requirement::Key("software_mgmt".into())
+                ),
+            ]
+        );
+        assert!(Config::default().rule_requirements().is_empty());
+    }
+
     #[test]
     fn env_prefix() {
         let cfg: Config = toml::de::from_str(
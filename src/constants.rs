@@ -114,6 +114,139 @@ lazy_static! {
     };
 }
 
+/// Operations of the socketcall(2) multiplexer, see
+/// linux/include/uapi/linux/net.h
+const SOCKETCALL_OPS: &[&str] = &[
+    "socket",
+    "bind",
+    "connect",
+    "listen",
+    "accept",
+    "getsockname",
+    "getpeername",
+    "socketpair",
+    "send",
+    "recv",
+    "sendto",
+    "recvfrom",
+    "shutdown",
+    "setsockopt",
+    "getsockopt",
+    "sendmsg",
+    "recvmsg",
+    "accept4",
+    "recvmmsg",
+    "sendmmsg",
+];
+
+/// Operations of the ipc(2) multiplexer, see
+/// linux/include/uapi/linux/ipc.h
+const IPC_OPS: &[(u64, &str)] = &[
+    (1, "semop"),
+    (2, "semget"),
+    (3, "semctl"),
+    (4, "semtimedop"),
+    (11, "msgsnd"),
+    (12, "msgrcv"),
+    (13, "msgget"),
+    (14, "msgctl"),
+    (21, "shmat"),
+    (22, "shmdt"),
+    (23, "shmget"),
+    (24, "shmctl"),
+];
+
+/// Returns the operation performed by a multiplexed syscall
+/// (`socketcall`, `ipc`), based on its first argument.
+pub fn demux_syscall(name: &str, a0: u64) -> Option<&'static str> {
+    match name {
+        "socketcall" => SOCKETCALL_OPS.get((a0 as usize).checked_sub(1)?).copied(),
+        // The upper 16 bits contain the interface version.
+        "ipc" => IPC_OPS
+            .iter()
+            .find(|(n, _)| *n == a0 & 0xffff)
+            .map(|(_, op)| *op),
+        _ => None,
+    }
+}
+
+/// Signal names for architectures that use the generic numbering,
+/// see linux/include/uapi/asm-generic/signal.h
+const SIGNALS: &[&str] = &[
+    "SIGHUP",
+    "SIGINT",
+    "SIGQUIT",
+    "SIGILL",
+    "SIGTRAP",
+    "SIGABRT",
+    "SIGBUS",
+    "SIGFPE",
+    "SIGKILL",
+    "SIGUSR1",
+    "SIGSEGV",
+    "SIGUSR2",
+    "SIGPIPE",
+    "SIGALRM",
+    "SIGTERM",
+    "SIGSTKFLT",
+    "SIGCHLD",
+    "SIGCONT",
+    "SIGSTOP",
+    "SIGTSTP",
+    "SIGTTIN",
+    "SIGTTOU",
+    "SIGURG",
+    "SIGXCPU",
+    "SIGXFSZ",
+    "SIGVTALRM",
+    "SIGPROF",
+    "SIGWINCH",
+    "SIGIO",
+    "SIGPWR",
+    "SIGSYS",
+];
+
+/// Returns the name of `signal` as numbered on architecture `arch`
+/// (see [`ARCH_NAMES`]). Alpha, MIPS, PA-RISC, and SPARC, which use
+/// their own numbering, are not supported.
+pub fn signal_name(arch: &str, signal: u64) -> Option<&'static str> {
+    if ["alpha", "mips", "parisc", "sparc"]
+        .iter()
+        .any(|a| arch.starts_with(a))
+    {
+        return None;
+    }
+    SIGNALS.get((signal as usize).checked_sub(1)?).copied()
+}
+
+/// Bits of the NTP status word, see linux/include/uapi/linux/timex.h
+pub const NTP_STATUS_FLAGS: &[(i64, &str)] = &[
+    (0x0001, "PLL"),
+    (0x0002, "PPSFREQ"),
+    (0x0004, "PPSTIME"),
+    (0x0008, "FLL"),
+    (0x0010, "INS"),
+    (0x0020, "DEL"),
+    (0x0040, "UNSYNC"),
+    (0x0080, "FREQHOLD"),
+    (0x0100, "PPSSIGNAL"),
+    (0x0200, "PPSJITTER"),
+    (0x0400, "PPSWANDER"),
+    (0x0800, "PPSERROR"),
+    (0x1000, "CLOCKERR"),
+    (0x2000, "NANO"),
+    (0x4000, "MODE"),
+    (0x8000, "CLK"),
+];
+
+/// Letters for the bits of the kernel taint mask, see
+/// linux/Documentation/admin-guide/tainted-kernels.rst
+pub const KERNEL_TAINT_FLAGS: &[u8] = b"PFSRMBUDAWCIOELKXTN";
+
+/// Separator between multiple keys in the `key` field of SYSCALL
+/// records, see AUDIT_KEY_SEPARATOR in linux/include/uapi/linux/audit.h
+pub const KEY_SEPARATOR: u8 = 0x01;
+
 pub fn initialize() {
     lazy_static::initialize(&SYSCALL_NAMES);
     lazy_static::initialize(&ARCH_IDS);
@@ -0,0 +1,448 @@
+//! Control socket for looking back at recent events, see `laurel query`
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::os::fd::AsFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use nix::poll::{PollFd, PollFlags};
+use serde_json::{json, Value};
+
+use crate::projection::FieldPath;
+
+/// Time after which clients are disconnected
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The most recent events, serialized as JSON
+#[derive(Clone, Debug)]
+pub struct EventRing {
+    size: usize,
+    events: Arc<Mutex<VecDeque<Vec<u8>>>>,
+}
+
+impl EventRing {
+    pub fn new(size: usize) -> Self {
+        EventRing {
+            size,
+            events: Arc::new(Mutex::new(VecDeque::with_capacity(size))),
+        }
+    }
+
+    /// Adds serialized event, removes the oldest event if the ring
+    /// is full.
+    pub fn push(&self, event: Vec<u8>) {
+        if self.size == 0 {
+            return;
+        }
+        let mut events = self.events.lock().unwrap();
+        if events.len() >= self.size {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    /// Returns events that match `query`, oldest first
+    pub fn query(&self, query: &Query) -> Vec<Vec<u8>> {
+        // Serialized events are copied so that the lock is not held
+        // while they are parsed.
+        let events: Vec<Vec<u8>> = self.events.lock().unwrap().iter().cloned().collect();
+        events
+            .into_iter()
+            .filter(|e| {
+                query.is_empty()
+                    || serde_json::from_slice(e).is_ok_and(|v: Value| query.matches(&v))
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Op {
+    /// `=`: Equality
+    Equals,
+    /// `~`: Substring
+    Contains,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Condition {
+    path: FieldPath,
+    op: Op,
+    value: String,
+}
+
+/// Whitespace-separated list of conditions, all of which must match,
+/// e.g. `exe=/usr/bin/curl SYSCALL.UID=root ARGV~http`. `*` matches
+/// all events; an empty query is rejected.
+///
+/// Field paths are matched at any depth, so that `exe` matches
+/// `SYSCALL.exe` as well as `PPID.exe`. Lists match if any of their
+/// elements match.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Query(Vec<Condition>);
+
+impl Query {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn matches(&self, event: &Value) -> bool {
+        self.0.iter().all(|c| {
+            let mut found = vec![];
+            lookup(event, c.path.elements(), &mut found);
+            found.into_iter().any(|v| compare(v, &c.op, &c.value))
+        })
+    }
+}
+
+impl FromStr for Query {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().is_empty() {
+            return Err("empty query".into());
+        }
+        s.split_whitespace()
+            .filter(|term| *term != "*")
+            .map(|term| {
+                let (path, op, value) = match term.find(['=', '~']) {
+                    Some(n) if term[n..].starts_with('=') => {
+                        (&term[..n], Op::Equals, &term[n + 1..])
+                    }
+                    Some(n) => (&term[..n], Op::Contains, &term[n + 1..]),
+                    None => return Err(format!("invalid condition {term:?}")),
+                };
+                Ok(Condition {
+                    path: path.parse()?,
+                    op,
+                    value: value.into(),
+                })
+            })
+            .collect::<Result<_, _>>()
+            .map(Query)
+    }
+}
+
+/// Collects values found at `path` below `value` or any of its
+/// descendants.
+fn lookup<'a>(value: &'a Value, path: &[String], found: &mut Vec<&'a Value>) {
+    let (first, rest) = path.split_first().unwrap();
+    match value {
+        Value::Object(map) => {
+            match (map.get(first), rest.is_empty()) {
+                (Some(v), true) => found.push(v),
+                (Some(v), false) => follow(v, rest, found),
+                _ => {}
+            }
+            map.values().for_each(|v| lookup(v, path, found));
+        }
+        Value::Array(vs) => vs.iter().for_each(|v| lookup(v, path, found)),
+        _ => {}
+    }
+}
+
+/// Collects values found at exactly `path` below `value`
+fn follow<'a>(value: &'a Value, path: &[String], found: &mut Vec<&'a Value>) {
+    match (value, path.split_first()) {
+        (v, None) => found.push(v),
+        (Value::Object(map), Some((first, rest))) => {
+            if let Some(v) = map.get(first) {
+                follow(v, rest, found);
+            }
+        }
+        (Value::Array(vs), Some(_)) => vs.iter().for_each(|v| follow(v, path, found)),
+        _ => {}
+    }
+}
+
+fn compare(value: &Value, op: &Op, want: &str) -> bool {
+    let s = match value {
+        Value::String(s) => s.clone(),
+        Value::Number(n) => n.to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Null => "null".into(),
+        Value::Array(vs) => return vs.iter().any(|v| compare(v, op, want)),
+        Value::Object(_) => return false,
+    };
+    match op {
+        Op::Equals => s == want,
+        Op::Contains => s.contains(want),
+    }
+}
+
+/// Maximum length of a query line
+const MAX_REQUEST: usize = 4096;
+
+/// Maximum number of clients that are served at the same time
+const MAX_CLIENTS: usize = 16;
+
+/// Answers a single request: The client sends a query on a single
+/// line, matching events are returned as JSON lines, followed by an
+/// empty line. Errors are returned as a single `{"error":...}` line.
+///
+/// Clients can tell from the missing empty line that a response has
+/// been truncated.
+fn respond(request: &[u8], ring: &EventRing) -> Vec<u8> {
+    let mut response = vec![];
+    let query = std::str::from_utf8(request)
+        .map_err(|_| "query is not valid UTF-8".to_string())
+        .and_then(str::parse::<Query>);
+    match query {
+        Ok(query) => {
+            for event in ring.query(&query) {
+                response.extend(event);
+                response.push(b'\n');
+            }
+            response.push(b'\n');
+        }
+        Err(e) => {
+            response.extend(json!({ "error": e }).to_string().bytes());
+            response.push(b'\n');
+        }
+    }
+    response
+}
+
+/// State of a connected client
+struct Client {
+    stream: UnixStream,
+    /// Query line as received so far
+    request: Vec<u8>,
+    /// Response, once the query has been received
+    response: Option<Vec<u8>>,
+    /// Number of response bytes that have been written
+    written: usize,
+    /// Point in time at which the client is disconnected
+    deadline: Instant,
+}
+
+impl Client {
+    /// Reads or writes as much as possible without blocking. Returns
+    /// true once the client has been served.
+    fn advance(&mut self, ring: &EventRing) -> io::Result<bool> {
+        while self.response.is_none() {
+            let mut buf = [0u8; 1024];
+            match self.stream.read(&mut buf) {
+                Ok(n) => {
+                    self.request.extend(&buf[..n]);
+                    if let Some(end) = self.request.iter().position(|c| *c == b'\n') {
+                        self.request.truncate(end);
+                    } else if n > 0 && self.request.len() <= MAX_REQUEST {
+                        continue;
+                    } else if n > 0 {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "query too long"));
+                    }
+                    // A query without newline at EOF is answered
+                    // as well.
+                    self.response = Some(respond(&self.request, ring));
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let response = self.response.as_ref().unwrap();
+        while self.written < response.len() {
+            match self.stream.write(&response[self.written..]) {
+                Ok(n) => self.written += n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(true)
+    }
+
+    fn events(&self) -> PollFlags {
+        match self.response {
+            None => PollFlags::POLLIN,
+            Some(_) => PollFlags::POLLOUT,
+        }
+    }
+}
+
+/// Control socket that is served from the main loop without
+/// blocking: The listener and connected clients are polled along
+/// with the input, [`Server::serve`] accepts new clients and reads
+/// queries or writes responses as far as possible. Clients that have
+/// not been served within one second are disconnected.
+pub struct Server {
+    listener: UnixListener,
+    ring: EventRing,
+    clients: Vec<Client>,
+}
+
+impl Server {
+    pub fn new(listener: UnixListener, ring: EventRing) -> io::Result<Self> {
+        listener.set_nonblocking(true)?;
+        Ok(Server {
+            listener,
+            ring,
+            clients: vec![],
+        })
+    }
+
+    /// Returns file descriptors and events that [`Server::serve`]
+    /// should be called for. The listener is not included while
+    /// the maximum number of clients is connected.
+    pub fn poll_fds(&self) -> Vec<PollFd<'_>> {
+        let listener = (self.clients.len() < MAX_CLIENTS)
+            .then(|| PollFd::new(self.listener.as_fd(), PollFlags::POLLIN));
+        listener
+            .into_iter()
+            .chain(
+                self.clients
+                    .iter()
+                    .map(|c| PollFd::new(c.stream.as_fd(), c.events())),
+            )
+            .collect()
+    }
+
+    /// Returns the time until the next client times out
+    pub fn timeout(&self) -> Option<Duration> {
+        let now = Instant::now();
+        self.clients
+            .iter()
+            .map(|c| c.deadline.saturating_duration_since(now))
+            .min()
+    }
+
+    /// Accepts pending connections and makes progress on all
+    /// clients without blocking.
+    pub fn serve(&mut self) {
+        while self.clients.len() < MAX_CLIENTS {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    if let Err(e) = stream.set_nonblocking(true) {
+                        log::warn!("control socket: {e}");
+                        continue;
+                    }
+                    self.clients.push(Client {
+                        stream,
+                        request: vec![],
+                        response: None,
+                        written: 0,
+                        deadline: Instant::now() + CLIENT_TIMEOUT,
+                    });
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::warn!("control socket: {e}");
+                    break;
+                }
+            }
+        }
+        let now = Instant::now();
+        let ring = &self.ring;
+        self.clients.retain_mut(|c| match c.advance(ring) {
+            Ok(true) => false,
+            Ok(false) if c.deadline <= now => {
+                match c.response {
+                    Some(_) => log::warn!("control socket: client timed out, response truncated"),
+                    None => log::warn!("control socket: client timed out"),
+                }
+                false
+            }
+            Ok(false) => true,
+            Err(e) => {
+                log::warn!("control socket: {e}");
+                false
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn query() {
+        let ring = EventRing::new(2);
+        for (id, exe, argv) in [
+            ("1:1", "/usr/bin/id", vec!["id"]),
+            ("1:2", "/usr/bin/curl", vec!["curl", "http://example.com/"]),
+            ("1:3", "/usr/bin/wget", vec!["wget", "http://example.com/"]),
+        ] {
+            ring.push(
+                serde_json::to_vec(&json!({
+                    "ID": id,
+                    "SYSCALL": {"exe": exe, "PPID": {"exe": "/bin/sh"}},
+                    "EXECVE": {"argc": argv.len(), "ARGV": argv},
+                }))
+                .unwrap(),
+            );
+        }
+        let ids = |q: &str| -> Vec<String> {
+            ring.query(&q.parse().unwrap())
+                .iter()
+                .map(|e| serde_json::from_slice::<Value>(e).unwrap()["ID"].to_string())
+                .collect()
+        };
+        assert_eq!(ids("*"), [r#""1:2""#, r#""1:3""#]);
+        assert_eq!(ids("* exe=/usr/bin/curl"), [r#""1:2""#]);
+        assert_eq!(ids("exe=/usr/bin/curl"), [r#""1:2""#]);
+        assert_eq!(ids("SYSCALL.exe~bin/"), [r#""1:2""#, r#""1:3""#]);
+        assert_eq!(ids("exe=/bin/sh"), [r#""1:2""#, r#""1:3""#]);
+        assert_eq!(ids("SYSCALL.exe=/bin/sh").len(), 0);
+        assert_eq!(ids("PPID.exe=/bin/sh argc=2 ARGV=wget"), [r#""1:3""#]);
+        assert_eq!(ids("ARGV~example.com"), [r#""1:2""#, r#""1:3""#]);
+        assert!("".parse::<Query>().is_err());
+        assert!(" ".parse::<Query>().is_err());
+        assert!("exe".parse::<Query>().is_err());
+        assert!("a..b=c".parse::<Query>().is_err());
+
+        let ring = EventRing::new(0);
+        ring.push(br#"{"ID":"1:1"}"#.to_vec());
+        assert!(ring.query(&Query::default()).is_empty());
+    }
+
+    #[test]
+    fn server() {
+        let dir = nix::unistd::mkdtemp(&std::env::temp_dir().join("laurel-test-XXXXXXXX")).unwrap();
+        let path = dir.join("control.sock");
+        let ring = EventRing::new(10);
+        ring.push(br#"{"ID":"1:1"}"#.to_vec());
+        let mut server = Server::new(UnixListener::bind(&path).unwrap(), ring).unwrap();
+        // Nothing pending
+        server.serve();
+        assert_eq!(server.timeout(), None);
+
+        // A client that does not send its query does not block
+        // other clients.
+        let _silent = UnixStream::connect(&path).unwrap();
+        let mut client = UnixStream::connect(&path).unwrap();
+        server.serve();
+        assert_eq!(server.clients.len(), 2);
+        assert_eq!(server.poll_fds().len(), 3);
+        client.write_all(b"ID=1:").unwrap();
+        server.serve();
+        client.write_all(b"1\n").unwrap();
+        server.serve();
+        assert_eq!(server.clients.len(), 1);
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert_eq!(response, "{\"ID\":\"1:1\"}\n\n");
+
+        for (request, error) in [
+            (&b"\n"[..], "empty query"),
+            (&b"ID\n"[..], "invalid condition \\\"ID\\\""),
+            (&b"ID=\xff\n"[..], "query is not valid UTF-8"),
+        ] {
+            let mut client = UnixStream::connect(&path).unwrap();
+            client.write_all(request).unwrap();
+            server.serve();
+            let mut response = String::new();
+            client.read_to_string(&mut response).unwrap();
+            assert_eq!(response, format!("{{\"error\":\"{error}\"}}\n"));
+        }
+
+        std::thread::sleep(CLIENT_TIMEOUT);
+        server.serve();
+        assert!(server.clients.is_empty());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,359 @@
+//! Encryption of output files
+//!
+//! Encrypted files consist of segments. Each segment is encrypted to
+//! the recipient's X25519 public key using an ephemeral key pair,
+//! every write operation is stored as a separate record. A new
+//! segment is started whenever a file is opened, so that appending to
+//! existing files does not require any state. When the file is
+//! closed, a final record ends the segment.
+//!
+//! - Segment header: `MAGIC` (8 bytes), ephemeral public key (32 bytes)
+//! - Record: `R`, flags (1 byte, `FINAL`), length (32-bit
+//!   big-endian), AES-256-GCM ciphertext. The first six bytes are
+//!   authenticated as associated data.
+//!
+//! The key for a segment is derived from the shared secret using
+//! HKDF-SHA256 with the ephemeral and the recipient public keys as
+//! salt, the nonce is the number of the record within the segment.
+//!
+//! Records that can't be decrypted are skipped using their length. If
+//! the framing itself is damaged, e.g. by a partial write, decryption
+//! continues at the next segment header.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::Aes256Gcm;
+use faster_hex::{hex_decode, hex_string};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{x25519, X25519_BASEPOINT_BYTES};
+
+use thiserror::Error;
+
+const MAGIC: &[u8; 8] = b"\x00LRLENC\x02";
+const RECORD: u8 = b'R';
+/// Flag for the record that ends a segment
+const FINAL: u8 = 1;
+const INFO: &[u8] = b"laurel file encryption v2";
+/// Records are never this large, longer lengths indicate corruption.
+const MAX_RECORD: usize = 1 << 30;
+
+#[derive(Debug, Error)]
+pub enum EncryptError {
+    #[error("invalid public key: expected 64 hex digits")]
+    PublicKey,
+    #[error("{0}: {1}")]
+    KeyFile(PathBuf, io::Error),
+    #[error("{0}: expected 64 hex digits")]
+    KeyFormat(PathBuf),
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[error("unexpected data at offset {0}")]
+    Format(u64),
+    #[error("record at offset {0} can't be decrypted")]
+    Decrypt(u64),
+    #[error("segment at offset {0} has not been finished")]
+    Unfinished(u64),
+}
+
+/// Returns the public key for a secret key
+pub fn public_key(secret: &[u8; 32]) -> [u8; 32] {
+    x25519(*secret, X25519_BASEPOINT_BYTES)
+}
+
+/// Returns a random secret key
+fn random_key() -> io::Result<[u8; 32]> {
+    let mut key = [0u8; 32];
+    fs::File::open("/dev/urandom")?.read_exact(&mut key)?;
+    Ok(key)
+}
+
+/// Creates a new secret key in `path` and returns the public key as
+/// hex digits. An existing file is not overwritten.
+pub fn generate_key(path: &Path) -> Result<String, EncryptError> {
+    let err = |e| EncryptError::KeyFile(path.to_path_buf(), e);
+    let secret = random_key().map_err(|e| EncryptError::KeyFile("/dev/urandom".into(), e))?;
+    let mut f = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(err)?;
+    writeln!(f, "{}", hex_string(&secret)).map_err(err)?;
+    Ok(hex_string(&public_key(&secret)))
+}
+
+/// Reads a secret key that is stored as hex digits from `path`
+pub fn read_secret_key(path: &Path) -> Result<[u8; 32], EncryptError> {
+    let buf = fs::read_to_string(path).map_err(|e| EncryptError::KeyFile(path.to_path_buf(), e))?;
+    let buf = buf.trim().as_bytes();
+    let mut key = [0u8; 32];
+    if buf.len() != 64 || hex_decode(buf, &mut key).is_err() {
+        return Err(EncryptError::KeyFormat(path.to_path_buf()));
+    }
+    Ok(key)
+}
+
+/// Parses a public key given as hex digits
+pub fn parse_public_key(s: &str) -> Result<[u8; 32], EncryptError> {
+    let mut key = [0u8; 32];
+    if s.len() != 64 || hex_decode(s.as_bytes(), &mut key).is_err() {
+        return Err(EncryptError::PublicKey);
+    }
+    Ok(key)
+}
+
+/// Cipher and record counter of the current segment
+struct Segment {
+    cipher: Aes256Gcm,
+    counter: u64,
+}
+
+impl Segment {
+    fn new(shared: &[u8; 32], ephemeral: &[u8; 32], recipient: &[u8; 32]) -> Self {
+        let mut salt = [0u8; 64];
+        salt[..32].copy_from_slice(ephemeral);
+        salt[32..].copy_from_slice(recipient);
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::new(Some(&salt[..]), shared)
+            .expand(INFO, &mut key)
+            .expect("HKDF output length");
+        Segment {
+            cipher: Aes256Gcm::new(&key.into()),
+            counter: 0,
+        }
+    }
+
+    fn nonce(&mut self) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&self.counter.to_be_bytes());
+        self.counter += 1;
+        nonce
+    }
+}
+
+/// Encrypts data written to a file for a recipient
+pub struct FileEncryptor {
+    recipient: [u8; 32],
+    segment: Option<Segment>,
+}
+
+impl FileEncryptor {
+    pub fn new(recipient: [u8; 32]) -> Self {
+        FileEncryptor {
+            recipient,
+            segment: None,
+        }
+    }
+
+    /// Starts a new segment and returns its header
+    pub fn begin(&mut self) -> io::Result<Vec<u8>> {
+        let secret = random_key()?;
+        let ephemeral = public_key(&secret);
+        let shared = x25519(secret, self.recipient);
+        self.segment = Some(Segment::new(&shared, &ephemeral, &self.recipient));
+        let mut header = MAGIC.to_vec();
+        header.extend(ephemeral);
+        Ok(header)
+    }
+
+    /// Encrypts `buf` and returns it as a record of the current
+    /// segment
+    pub fn seal(&mut self, buf: &[u8]) -> io::Result<Vec<u8>> {
+        self.record(buf, 0)
+    }
+
+    /// Ends the current segment and returns the final record. Returns
+    /// nothing if no segment has been started.
+    pub fn finish(&mut self) -> io::Result<Vec<u8>> {
+        if self.segment.is_none() {
+            return Ok(vec![]);
+        }
+        let record = self.record(&[], FINAL);
+        self.segment = None;
+        record
+    }
+
+    fn record(&mut self, buf: &[u8], flags: u8) -> io::Result<Vec<u8>> {
+        let segment = self
+            .segment
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "no segment"))?;
+        // The ciphertext is as long as the plaintext plus the tag.
+        let len = buf.len() + 16;
+        let mut record = Vec::with_capacity(6 + len);
+        record.extend([RECORD, flags]);
+        record.extend((len as u32).to_be_bytes());
+        let nonce = segment.nonce();
+        let ciphertext = segment
+            .cipher
+            .encrypt(
+                &nonce.into(),
+                Payload {
+                    msg: buf,
+                    aad: &record,
+                },
+            )
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "encryption failed"))?;
+        record.extend(ciphertext);
+        Ok(record)
+    }
+}
+
+/// Returns the offset of the next segment header at or after `from`
+fn next_header(buf: &[u8], from: usize) -> usize {
+    buf.get(from..)
+        .and_then(|b| b.windows(MAGIC.len()).position(|w| w == MAGIC))
+        .map_or(buf.len(), |p| from + p)
+}
+
+/// Decrypts a file that has been read from `r` using the recipient's
+/// secret key and writes the contents to `w`.
+///
+/// Damaged parts of the file are skipped, the problems that have been
+/// found are returned. Segments that have not been finished, e.g.
+/// because the file is still being written, are reported as
+/// [`EncryptError::Unfinished`].
+pub fn decrypt<R: Read, W: Write>(
+    secret: &[u8; 32],
+    mut r: R,
+    w: &mut W,
+) -> Result<Vec<EncryptError>, EncryptError> {
+    let recipient = public_key(secret);
+    let mut buf = vec![];
+    r.read_to_end(&mut buf)?;
+
+    let mut errors = vec![];
+    // Current segment and the offset of its header
+    let mut segment: Option<(Segment, usize)> = None;
+    let mut pos = 0;
+    while pos < buf.len() {
+        let rest = &buf[pos..];
+        if rest.len() >= MAGIC.len() + 32 && rest.starts_with(MAGIC) {
+            if let Some((_, start)) = segment.take() {
+                errors.push(EncryptError::Unfinished(start as u64));
+            }
+            let ephemeral: [u8; 32] = rest[MAGIC.len()..][..32].try_into().unwrap();
+            let shared = x25519(*secret, ephemeral);
+            segment = Some((Segment::new(&shared, &ephemeral, &recipient), pos));
+            pos += MAGIC.len() + 32;
+            continue;
+        }
+        let len = match rest {
+            [RECORD, flags, l @ ..] if segment.is_some() && flags & !FINAL == 0 && l.len() >= 4 => {
+                Some(u32::from_be_bytes([l[0], l[1], l[2], l[3]]) as usize)
+                    .filter(|len| *len <= MAX_RECORD && 6 + len <= rest.len())
+            }
+            _ => None,
+        };
+        let (Some(len), Some((seg, _))) = (len, segment.as_mut()) else {
+            // The framing is damaged. The remainder of the segment
+            // can't be read, continue with the next one.
+            errors.push(EncryptError::Format(pos as u64));
+            segment = None;
+            pos = next_header(&buf, pos + 1);
+            continue;
+        };
+        let nonce = seg.nonce();
+        let payload = Payload {
+            msg: &rest[6..6 + len],
+            aad: &rest[..6],
+        };
+        match seg.cipher.decrypt(&nonce.into(), payload) {
+            Ok(plaintext) => w.write_all(&plaintext)?,
+            Err(_) => {
+                errors.push(EncryptError::Decrypt(pos as u64));
+                // A partially written record may be followed by the
+                // header of a segment that has been started later.
+                let next = next_header(&buf, pos + 1);
+                if next < pos + 6 + len {
+                    pos = next;
+                    continue;
+                }
+            }
+        }
+        if rest[1] & FINAL != 0 {
+            segment = None;
+        }
+        pos += 6 + len;
+    }
+    if let Some((_, start)) = segment {
+        errors.push(EncryptError::Unfinished(start as u64));
+    }
+    Ok(errors)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let secret = [9u8; 32];
+        let mut enc = FileEncryptor::new(public_key(&secret));
+        let mut file = enc.begin().unwrap();
+        file.extend(enc.seal(b"line 1\n").unwrap());
+        file.extend(enc.seal(b"line 2\n").unwrap());
+        file.extend(enc.finish().unwrap());
+        // appending to an existing file
+        let mut enc = FileEncryptor::new(public_key(&secret));
+        file.extend(enc.begin().unwrap());
+        file.extend(enc.seal(b"line 3\n").unwrap());
+        file.extend(enc.finish().unwrap());
+        assert!(!file.windows(4).any(|w| w == b"line"));
+
+        let mut out = vec![];
+        let errors = decrypt(&secret, file.as_slice(), &mut out).unwrap();
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(out, b"line 1\nline 2\nline 3\n");
+
+        let errors = decrypt(&[10u8; 32], file.as_slice(), &mut vec![]).unwrap();
+        assert!(matches!(errors[0], EncryptError::Decrypt(40)));
+
+        // Damaged ciphertext: only the affected record is lost.
+        let mut tampered = file.clone();
+        tampered[50] ^= 1;
+        let mut out = vec![];
+        let errors = decrypt(&secret, tampered.as_slice(), &mut out).unwrap();
+        assert!(matches!(errors[..], [EncryptError::Decrypt(40)]));
+        assert_eq!(out, b"line 2\nline 3\n");
+
+        // The final flag is authenticated.
+        let mut tampered = file.clone();
+        tampered[41] = FINAL;
+        let errors = decrypt(&secret, tampered.as_slice(), &mut vec![]).unwrap();
+        assert!(matches!(errors[0], EncryptError::Decrypt(40)));
+
+        // Damaged framing: decryption continues with the next segment.
+        let second = next_header(&file, 1);
+        let mut tampered = file.clone();
+        tampered[40] = b'X';
+        let mut out = vec![];
+        let errors = decrypt(&secret, tampered.as_slice(), &mut out).unwrap();
+        assert!(matches!(errors[..], [EncryptError::Format(40)]));
+        assert_eq!(out, b"line 3\n");
+
+        // Partial write followed by a new segment
+        let mut truncated = file[..second - 3].to_vec();
+        truncated.extend(&file[second..]);
+        let mut out = vec![];
+        let errors = decrypt(&secret, truncated.as_slice(), &mut out).unwrap();
+        assert!(matches!(
+            errors[..],
+            [EncryptError::Decrypt(_), EncryptError::Unfinished(0)]
+        ));
+        assert_eq!(out, b"line 1\nline 2\nline 3\n");
+
+        // Segment that is still being written
+        let mut out = vec![];
+        let errors = decrypt(&secret, &file[..second], &mut out).unwrap();
+        assert!(errors.is_empty());
+        let last = file.len() - 22;
+        let errors = decrypt(&secret, &file[..last], &mut out).unwrap();
+        assert!(matches!(errors[..], [EncryptError::Unfinished(s)] if s == second as u64));
+    }
+}
@@ -0,0 +1,240 @@
+//! Compact records for processes and files that have been seen in
+//! events
+//!
+//! Instead of deriving nodes for e.g. graph databases from the event
+//! stream, consumers can use one record per process and per file
+//! path. Records are written once the process or file has not been
+//! seen in an event for some time.
+//!
+//! Paths and other strings are kept as raw bytes, so that they are
+//! escaped like the values in events and names that are not valid
+//! UTF-8 remain distinct.
+
+use indexmap::IndexMap;
+
+use serde::Serialize;
+use serde_bytes::ByteBuf;
+
+use linux_audit_parser::EventID;
+
+use crate::proc::{Process, ProcessKey};
+
+/// Maximum number of processes and files that are tracked; the
+/// oldest entries are written early if this is exceeded.
+const ENTITIES_MAX: usize = 65536;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ProcessEntity {
+    #[serde(
+        rename = "NODE",
+        skip_serializing_if = "Option::is_none",
+        with = "serde_bytes"
+    )]
+    pub node: Option<Vec<u8>>,
+    #[serde(rename = "PROCESS")]
+    pub key: ProcessKey,
+    #[serde(rename = "PARENT", skip_serializing_if = "Option::is_none")]
+    pub parent: Option<ProcessKey>,
+    pub pid: u32,
+    pub ppid: u32,
+    #[serde(skip_serializing_if = "Option::is_none", with = "serde_bytes")]
+    pub exe: Option<Vec<u8>>,
+    #[serde(skip_serializing_if = "Option::is_none", with = "serde_bytes")]
+    pub comm: Option<Vec<u8>>,
+    #[serde(rename = "LABELS", skip_serializing_if = "Vec::is_empty")]
+    pub labels: Vec<ByteBuf>,
+    pub first_event: EventID,
+    pub last_event: EventID,
+    pub events: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FileEntity {
+    #[serde(
+        rename = "NODE",
+        skip_serializing_if = "Option::is_none",
+        with = "serde_bytes"
+    )]
+    pub node: Option<Vec<u8>>,
+    #[serde(rename = "FILE", with = "serde_bytes")]
+    pub path: Vec<u8>,
+    pub first_event: EventID,
+    pub last_event: EventID,
+    pub events: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(untagged)]
+pub enum Entity {
+    Process(ProcessEntity),
+    File(FileEntity),
+}
+
+impl Entity {
+    fn last_event(&self) -> EventID {
+        match self {
+            Entity::Process(p) => p.last_event,
+            Entity::File(f) => f.last_event,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum EntityKey {
+    Process(Option<Vec<u8>>, ProcessKey),
+    File(Option<Vec<u8>>, Vec<u8>),
+}
+
+/// Processes and files that have been seen recently
+#[derive(Debug, Default)]
+pub struct EntityTracker {
+    entities: IndexMap<EntityKey, Entity>,
+}
+
+impl EntityTracker {
+    /// Records that `proc` has been seen in event `id`.
+    pub fn process(&mut self, node: Option<&[u8]>, proc: &Process, id: EventID) {
+        let key = EntityKey::Process(node.map(Vec::from), proc.key);
+        match self.entities.get_mut(&key) {
+            Some(Entity::Process(p)) => {
+                p.last_event = id;
+                p.events += 1;
+                // exe, comm may have changed since the process was
+                // first seen.
+                p.exe = proc.exe.as_deref().map(Vec::from);
+                p.comm = proc.comm.as_deref().map(Vec::from);
+            }
+            _ => {
+                let mut labels: Vec<ByteBuf> =
+                    proc.labels.iter().cloned().map(ByteBuf::from).collect();
+                labels.sort();
+                self.entities.insert(
+                    key,
+                    Entity::Process(ProcessEntity {
+                        node: node.map(Vec::from),
+                        key: proc.key,
+                        parent: proc.parent,
+                        pid: proc.pid,
+                        ppid: proc.ppid,
+                        exe: proc.exe.as_deref().map(Vec::from),
+                        comm: proc.comm.as_deref().map(Vec::from),
+                        labels,
+                        first_event: id,
+                        last_event: id,
+                        events: 1,
+                    }),
+                );
+            }
+        }
+    }
+
+    /// Records that `path` has been touched in event `id`.
+    pub fn file(&mut self, node: Option<&[u8]>, path: &[u8], id: EventID) {
+        let key = EntityKey::File(node.map(Vec::from), path.to_vec());
+        match self.entities.get_mut(&key) {
+            Some(Entity::File(f)) => {
+                f.last_event = id;
+                f.events += 1;
+            }
+            _ => {
+                self.entities.insert(
+                    key,
+                    Entity::File(FileEntity {
+                        node: node.map(Vec::from),
+                        path: path.to_vec(),
+                        first_event: id,
+                        last_event: id,
+                        events: 1,
+                    }),
+                );
+            }
+        }
+    }
+
+    /// Removes and returns entities that have not been seen since
+    /// `before` (milliseconds since epoch). If too many entities are
+    /// tracked, the oldest ones are removed as well.
+    pub fn expire(&mut self, before: u64) -> Vec<Entity> {
+        let mut expired = vec![];
+        let excess = self.entities.len().saturating_sub(ENTITIES_MAX);
+        let mut n = 0;
+        self.entities.retain(|_, e| {
+            n += 1;
+            if n <= excess || e.last_event().timestamp < before {
+                expired.push(e.clone());
+                false
+            } else {
+                true
+            }
+        });
+        expired
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn to_json(e: &Entity) -> String {
+        let mut out = vec![];
+        crate::json::to_writer(&mut out, e).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn expire() {
+        let mut t = EntityTracker::default();
+        let id = |timestamp| EventID {
+            timestamp,
+            sequence: 1,
+        };
+        let proc = Process {
+            key: ProcessKey::Event(id(1000)),
+            pid: 100,
+            ppid: 1,
+            exe: Some(b"/usr/bin/id"[..].into()),
+            ..Process::default()
+        };
+        t.process(None, &proc, id(1000));
+        t.process(None, &proc, id(5000));
+        t.file(None, b"/etc/passwd", id(1000));
+        t.file(Some(b"node"), b"/etc/passwd", id(1000));
+        assert_eq!(t.len(), 3);
+
+        let expired = t.expire(2000);
+        assert_eq!(expired.len(), 2);
+        assert_eq!(
+            to_json(&expired[1]),
+            r#"{"NODE":"node","FILE":"/etc/passwd","first_event":"1.000:1","last_event":"1.000:1","events":1}"#
+        );
+        let expired = t.expire(u64::MAX);
+        assert_eq!(
+            to_json(&expired[0]),
+            r#"{"PROCESS":"id[1.000:1]","pid":100,"ppid":1,"exe":"/usr/bin/id","first_event":"1.000:1","last_event":"5.000:1","events":2}"#
+        );
+        assert!(t.is_empty());
+    }
+
+    #[test]
+    fn raw_paths() {
+        let mut t = EntityTracker::default();
+        let id = EventID {
+            timestamp: 1000,
+            sequence: 1,
+        };
+        // Both are replaced by the same string in a lossy conversion.
+        t.file(None, b"/tmp/\xff", id);
+        t.file(None, b"/tmp/\xfe", id);
+        let expired: Vec<_> = t.expire(u64::MAX).iter().map(to_json).collect();
+        assert_eq!(expired.len(), 2);
+        assert_ne!(expired[0], expired[1]);
+    }
+}
@@ -0,0 +1,216 @@
+//! Reading audit log files while they are written and rotated
+
+use std::cell::Cell;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Time to wait for new data at the end of the file
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Position within a file, identified by its inode
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub ino: u64,
+    pub offset: u64,
+}
+
+impl Checkpoint {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    pub fn store(&self, path: &Path) -> io::Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let mut f = File::create(&tmp)?;
+        serde_json::to_writer(&mut f, self)?;
+        f.write_all(b"\n")?;
+        f.sync_all()?;
+        fs::rename(&tmp, path)
+    }
+}
+
+/// Reader that follows a file across rotations, as done by
+/// `auditd(8)`: Once the end of the current file has been reached and
+/// a different file has been created in its place, reading continues
+/// with the new file.
+///
+/// At the end of the file, reads fail with
+/// [`io::ErrorKind::WouldBlock`] after waiting for new data for a
+/// short time.
+#[derive(Debug)]
+pub struct FollowFile {
+    path: PathBuf,
+    file: File,
+    position: Rc<Cell<Checkpoint>>,
+}
+
+impl FollowFile {
+    /// Opens `path` and continues reading at `checkpoint`, which may
+    /// refer to a file that has already been rotated to `path.1`. If
+    /// the file from the checkpoint can't be found, reading starts at
+    /// the beginning of the current file. Without a checkpoint,
+    /// reading starts at the end of the current file.
+    pub fn open(path: &Path, checkpoint: Option<Checkpoint>) -> io::Result<Self> {
+        let mut rotated = path.as_os_str().to_owned();
+        rotated.push(".1");
+        let found = checkpoint.and_then(|c| {
+            [path, Path::new(&rotated)]
+                .into_iter()
+                .filter_map(|p| File::open(p).ok())
+                .find(|f| {
+                    f.metadata()
+                        .is_ok_and(|m| m.ino() == c.ino && m.len() >= c.offset)
+                })
+                .map(|f| (f, c))
+        });
+        let (mut file, position) = match found {
+            Some(found) => found,
+            None => {
+                let f = File::open(path)?;
+                let meta = f.metadata()?;
+                let offset = match checkpoint {
+                    Some(_) => {
+                        log::warn!(
+                            "File from input checkpoint not found, reading {} from start",
+                            path.display()
+                        );
+                        0
+                    }
+                    None => meta.len(),
+                };
+                let ino = meta.ino();
+                (f, Checkpoint { ino, offset })
+            }
+        };
+        file.seek(SeekFrom::Start(position.offset))?;
+        Ok(FollowFile {
+            path: path.into(),
+            file,
+            position: Rc::new(Cell::new(position)),
+        })
+    }
+
+    /// Handle to the position after the data that has been read so
+    /// far
+    pub fn position(&self) -> Rc<Cell<Checkpoint>> {
+        Rc::clone(&self.position)
+    }
+
+    fn advance(&self, n: usize) {
+        let mut c = self.position.get();
+        c.offset += n as u64;
+        self.position.set(c);
+    }
+}
+
+impl Read for FollowFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut waited = false;
+        loop {
+            let n = self.file.read(buf)?;
+            if n > 0 {
+                self.advance(n);
+                return Ok(n);
+            }
+            let Checkpoint { ino, offset } = self.position.get();
+            match fs::metadata(&self.path) {
+                Ok(meta) if meta.ino() != ino => {
+                    // Data may have been appended just before the
+                    // file was rotated.
+                    let n = self.file.read(buf)?;
+                    if n > 0 {
+                        self.advance(n);
+                        return Ok(n);
+                    }
+                    self.file = File::open(&self.path)?;
+                    let ino = self.file.metadata()?.ino();
+                    self.position.set(Checkpoint { ino, offset: 0 });
+                    continue;
+                }
+                Ok(meta) if meta.len() < offset => {
+                    self.file.seek(SeekFrom::Start(0))?;
+                    self.position.set(Checkpoint { ino, offset: 0 });
+                    continue;
+                }
+                _ => {}
+            }
+            if waited {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            std::thread::sleep(POLL_INTERVAL);
+            waited = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use nix::unistd::mkdtemp;
+    use std::env::temp_dir;
+
+    fn read_available(r: &mut FollowFile) -> Vec<u8> {
+        let mut out = vec![];
+        let mut buf = [0u8; 4];
+        loop {
+            match r.read(&mut buf) {
+                Ok(n) => out.extend(&buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return out,
+                Err(e) => panic!("{e}"),
+            }
+        }
+    }
+
+    #[test]
+    fn follow() {
+        let td = mkdtemp(&temp_dir().join("laurel-test-XXXXXXXX")).expect("can't create temp dir");
+        let path = td.join("audit.log");
+        let append = |s: &str| {
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .unwrap()
+                .write_all(s.as_bytes())
+                .unwrap()
+        };
+        append("old\n");
+
+        // Without checkpoint, start at the end.
+        let mut r = FollowFile::open(&path, None).unwrap();
+        assert_eq!(read_available(&mut r), b"");
+        append("one\n");
+        assert_eq!(read_available(&mut r), b"one\n");
+        let c = r.position().get();
+        assert_eq!(c.offset, 8);
+
+        // Rotation, with data written just before
+        append("two\n");
+        fs::rename(&path, td.join("audit.log.1")).unwrap();
+        append("three\n");
+        assert_eq!(read_available(&mut r), b"two\nthree\n");
+
+        // Continue at checkpoint in rotated file
+        let mut r = FollowFile::open(&path, Some(c)).unwrap();
+        assert_eq!(read_available(&mut r), b"two\nthree\n");
+        assert_eq!(r.position().get().offset, 6);
+
+        // Unknown checkpoint
+        let c = Checkpoint { ino: 0, offset: 1 };
+        let mut r = FollowFile::open(&path, Some(c)).unwrap();
+        assert_eq!(read_available(&mut r), b"three\n");
+
+        let c = r.position().get();
+        c.store(&td.join("checkpoint")).unwrap();
+        assert_eq!(Checkpoint::load(&td.join("checkpoint")).unwrap(), c);
+
+        std::fs::remove_dir_all(td).expect("remove_dir_all");
+    }
+}
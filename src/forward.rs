@@ -0,0 +1,369 @@
+//! Pass raw audit records on to other consumers
+
+use std::fmt::{self, Display};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Time between attempts to reopen a destination that has failed
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Time that is spent writing out queued records on shutdown
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time that a program is given to exit after its input has been
+/// closed, before it is killed
+const EXIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Consumer of the raw audit stream
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Destination {
+    /// `["program", args…]`: Program that reads records from its
+    /// standard input, such as an audispd plugin
+    Command(Vec<String>),
+    /// `unix:path`: Unix stream socket
+    Unix(PathBuf),
+    /// Named pipe or other file that exists
+    File(PathBuf),
+}
+
+impl Display for Destination {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Destination::Command(argv) => write!(f, "{argv:?}"),
+            Destination::Unix(path) => write!(f, "unix:{}", path.display()),
+            Destination::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl FromStr for Destination {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            Ok(Destination::Unix(path.into()))
+        } else if s.starts_with('/') {
+            Ok(Destination::File(s.into()))
+        } else if s.starts_with('|') {
+            Err(format!(
+                "{s:?}: programs must be given as list of program and arguments"
+            ))
+        } else {
+            Err(format!("{s:?} is not a unix socket or absolute path"))
+        }
+    }
+}
+
+/// Programs are given as list of program and arguments, sockets and
+/// files as strings.
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum DestinationRepr {
+    Command(Vec<String>),
+    Path(String),
+}
+
+impl<'de> Deserialize<'de> for Destination {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        match DestinationRepr::deserialize(d)? {
+            DestinationRepr::Command(argv) if argv.is_empty() => {
+                Err(serde::de::Error::custom("empty command"))
+            }
+            DestinationRepr::Command(argv) => Ok(Destination::Command(argv)),
+            DestinationRepr::Path(s) => s.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+impl Serialize for Destination {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Destination::Command(argv) => DestinationRepr::Command(argv.clone()),
+            _ => DestinationRepr::Path(self.to_string()),
+        }
+        .serialize(s)
+    }
+}
+
+/// Output that has been opened for a destination
+struct Sink {
+    child: Option<Child>,
+    output: Box<dyn Write + Send>,
+}
+
+impl Drop for Sink {
+    fn drop(&mut self) {
+        // Close the pipe before waiting for the program to exit.
+        self.output = Box::new(io::sink());
+        if let Some(mut child) = self.child.take() {
+            let deadline = Instant::now() + EXIT_TIMEOUT;
+            loop {
+                match child.try_wait() {
+                    Ok(None) if Instant::now() < deadline => {
+                        std::thread::sleep(Duration::from_millis(10))
+                    }
+                    Ok(None) => {
+                        log::warn!("Killing forwarding program {}", child.id());
+                        _ = child.kill();
+                        _ = child.wait();
+                        break;
+                    }
+                    _ => break,
+                }
+            }
+        }
+    }
+}
+
+impl Destination {
+    fn open(&self) -> io::Result<Sink> {
+        match self {
+            Destination::Command(argv) => {
+                let mut child = Command::new(&argv[0])
+                    .args(&argv[1..])
+                    .stdin(Stdio::piped())
+                    .spawn()?;
+                let stdin = child.stdin.take().unwrap();
+                Ok(Sink {
+                    child: Some(child),
+                    output: Box::new(stdin),
+                })
+            }
+            Destination::Unix(path) => Ok(Sink {
+                child: None,
+                output: Box::new(UnixStream::connect(path)?),
+            }),
+            Destination::File(path) => {
+                // Opening a FIFO that has no reader fails instead of
+                // blocking.
+                let file: File = OpenOptions::new()
+                    .append(true)
+                    .custom_flags(libc::O_NONBLOCK)
+                    .open(path)?;
+                // Writes block if the reader is slow.
+                if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETFL, libc::O_APPEND) } == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(Sink {
+                    child: None,
+                    output: Box::new(file),
+                })
+            }
+        }
+    }
+}
+
+/// Copies records to destinations, each of which is served by a
+/// separate thread. Records are dropped if a destination can't keep
+/// up or can't be written to.
+///
+/// Programs are started when the forwarder is created, so it should
+/// be created before privileges are dropped. When the forwarder is
+/// dropped, queued records are written out for a few seconds.
+pub struct Forwarder {
+    senders: Vec<SyncSender<Arc<[u8]>>>,
+    threads: Vec<JoinHandle<()>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Forwarder {
+    /// Creates forwarder that queues up to `queue_size` records per
+    /// destination.
+    pub fn new(destinations: &[Destination], queue_size: usize) -> Self {
+        let dropped = Arc::new(AtomicU64::new(0));
+        let (senders, threads) = destinations
+            .iter()
+            .map(|dest| {
+                let (tx, rx) = sync_channel::<Arc<[u8]>>(queue_size);
+                let dest = dest.clone();
+                let dropped = Arc::clone(&dropped);
+                let mut next_try = Instant::now();
+                let mut sink = match &dest {
+                    Destination::Command(_) => match dest.open() {
+                        Ok(s) => {
+                            log::info!("Forwarding to {dest}");
+                            Some(s)
+                        }
+                        Err(e) => {
+                            log::warn!("Can't forward to {dest}: {e}");
+                            next_try += RETRY_INTERVAL;
+                            None
+                        }
+                    },
+                    _ => None,
+                };
+                let thread = std::thread::spawn(move || {
+                    for line in rx {
+                        if sink.is_none() && Instant::now() >= next_try {
+                            match dest.open() {
+                                Ok(s) => {
+                                    log::info!("Forwarding to {dest}");
+                                    sink = Some(s);
+                                }
+                                Err(e) => {
+                                    log::warn!("Can't forward to {dest}: {e}");
+                                    next_try = Instant::now() + RETRY_INTERVAL;
+                                }
+                            }
+                        }
+                        let Some(s) = &mut sink else {
+                            dropped.fetch_add(1, Ordering::Relaxed);
+                            continue;
+                        };
+                        if let Err(e) = s.output.write_all(&line).and_then(|_| s.output.flush()) {
+                            log::warn!("Error forwarding to {dest}: {e}");
+                            dropped.fetch_add(1, Ordering::Relaxed);
+                            sink = None;
+                            next_try = Instant::now() + RETRY_INTERVAL;
+                        }
+                    }
+                });
+                (tx, thread)
+            })
+            .unzip();
+        Forwarder {
+            senders,
+            threads,
+            dropped,
+        }
+    }
+
+    pub fn forward(&self, line: &[u8]) {
+        let line: Arc<[u8]> = line.into();
+        for tx in &self.senders {
+            if tx.try_send(Arc::clone(&line)).is_err() {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Number of records that could not be forwarded, counted per
+    /// destination
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Forwarder {
+    fn drop(&mut self) {
+        // Closing the queues lets the threads finish once they have
+        // written the remaining records. Destinations that are stuck
+        // are abandoned after SHUTDOWN_TIMEOUT.
+        self.senders.clear();
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+        for thread in self.threads.drain(..) {
+            while !thread.is_finished() && Instant::now() < deadline {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            if thread.is_finished() {
+                _ = thread.join();
+            } else {
+                log::warn!("Giving up forwarding remaining records");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use nix::unistd::mkdtemp;
+    use std::env::temp_dir;
+    use std::io::Read;
+
+    #[test]
+    fn destination() {
+        #[derive(Deserialize)]
+        struct Forward {
+            destinations: Vec<Destination>,
+        }
+        let f: Forward = toml::from_str(
+            r#"destinations = [ ["/sbin/audisp-remote", "--foo bar"], "unix:/run/x.sock" ]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            f.destinations,
+            [
+                Destination::Command(vec!["/sbin/audisp-remote".into(), "--foo bar".into()]),
+                Destination::Unix("/run/x.sock".into())
+            ]
+        );
+        assert!("relative".parse::<Destination>().is_err());
+        assert!("|/sbin/audisp-remote".parse::<Destination>().is_err());
+        assert!(toml::from_str::<Forward>("destinations = [[]]").is_err());
+    }
+
+    #[test]
+    fn flush_on_drop() {
+        let td = mkdtemp(&temp_dir().join("laurel-test-XXXXXXXX")).expect("can't create temp dir");
+        let path = td.join("out");
+        let f = Forwarder::new(
+            &[Destination::Command(vec![
+                "/bin/sh".into(),
+                "-c".into(),
+                format!("sleep 0.2; cat > {}", path.display()),
+            ])],
+            10,
+        );
+        f.forward(b"a\n");
+        f.forward(b"b\n");
+        drop(f);
+        assert_eq!(std::fs::read(&path).unwrap(), b"a\nb\n");
+
+        // Programs that don't exit after their input has been closed
+        // are killed.
+        let f = Forwarder::new(
+            &[Destination::Command(vec![
+                "/bin/sh".into(),
+                "-c".into(),
+                "exec sleep 10".into(),
+            ])],
+            10,
+        );
+        f.forward(b"a\n");
+        let start = Instant::now();
+        drop(f);
+        assert!(start.elapsed() < SHUTDOWN_TIMEOUT);
+        std::fs::remove_dir_all(td).expect("remove_dir_all");
+    }
+
+    #[test]
+    fn forward() {
+        let td = mkdtemp(&temp_dir().join("laurel-test-XXXXXXXX")).expect("can't create temp dir");
+        let path = td.join("fifo");
+        nix::unistd::mkfifo(&path, nix::sys::stat::Mode::S_IRWXU).unwrap();
+        let f = Forwarder::new(&[Destination::File(path.clone())], 10);
+        // The FIFO has no reader yet.
+        f.forward(b"lost\n");
+        // Dropping the forwarder waits for the record to be handled.
+        let dropped = Arc::clone(&f.dropped);
+        drop(f);
+        assert_eq!(dropped.load(Ordering::Relaxed), 1);
+        let mut reader = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&path)
+            .unwrap();
+        let f = Forwarder::new(&[Destination::File(path.clone())], 10);
+        f.forward(b"type=EOE msg=audit(1:1): \n");
+        let dropped = Arc::clone(&f.dropped);
+        drop(f);
+        let mut buf = vec![];
+        _ = reader.read_to_end(&mut buf);
+        assert_eq!(buf, b"type=EOE msg=audit(1:1): \n");
+        assert_eq!(dropped.load(Ordering::Relaxed), 0);
+        std::fs::remove_dir_all(td).expect("remove_dir_all");
+    }
+}
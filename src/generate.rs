@@ -0,0 +1,354 @@
+//! Synthetic audit streams for load testing
+//!
+//! Events consist of the records that the kernel writes for execve,
+//! connect, and openat syscalls, terminated by EOE records. Processes
+//! that have been started by exec events are used for later events.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use faster_hex::hex_string;
+
+/// Relative frequencies of event types
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mix {
+    pub exec: u32,
+    pub network: u32,
+    pub file: u32,
+}
+
+impl Default for Mix {
+    fn default() -> Self {
+        Mix {
+            exec: 1,
+            network: 1,
+            file: 1,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseMixError(String);
+
+impl fmt::Display for ParseMixError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid event mix: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseMixError {}
+
+impl FromStr for Mix {
+    type Err = ParseMixError;
+    /// Parses lists such as `exec=5,network=2,file=3`. Types that
+    /// are not listed are not generated.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut mix = Mix {
+            exec: 0,
+            network: 0,
+            file: 0,
+        };
+        for item in s.split(',') {
+            let (name, n) = item
+                .split_once('=')
+                .ok_or_else(|| ParseMixError(item.into()))?;
+            let n = n.parse().map_err(|_| ParseMixError(item.into()))?;
+            match name.trim() {
+                "exec" => mix.exec = n,
+                "network" => mix.network = n,
+                "file" => mix.file = n,
+                _ => return Err(ParseMixError(format!("unknown event type {name}"))),
+            }
+        }
+        if mix.exec + mix.network + mix.file == 0 {
+            return Err(ParseMixError(s.into()));
+        }
+        Ok(mix)
+    }
+}
+
+/// xorshift64* generator, so that streams can be reproduced from a
+/// seed
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // The state must not be zero.
+        Rng(seed | 1)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+const COMMANDS: &[&[&str]] = &[
+    &["/usr/bin/ls", "ls", "-la"],
+    &["/usr/bin/cat", "cat", "/etc/hostname"],
+    &["/usr/bin/curl", "curl", "-s", "https://example.com/"],
+    &["/usr/bin/python3.11", "python3", "report.py"],
+    &["/usr/bin/git", "git", "status"],
+    &["/usr/bin/ps", "ps", "aux"],
+];
+
+const FILES: &[&str] = &[
+    "/etc/passwd",
+    "/etc/hosts",
+    "/etc/ld.so.cache",
+    "/var/log/syslog",
+    "/home/user/.bashrc",
+    "/tmp/output.txt",
+];
+
+const DESTINATIONS: &[([u8; 4], u16)] = &[
+    ([10, 0, 0, 5], 443),
+    ([192, 168, 1, 10], 22),
+    ([93, 184, 216, 34], 80),
+    ([127, 0, 0, 1], 5432),
+];
+
+/// Login sessions: uid, auid, session ID
+const SESSIONS: &[(u32, u32, u32)] = &[(1000, 1000, 1), (0, 1000, 2), (48, 0xffffffff, 0xffffffff)];
+
+/// Maximum number of processes that events are attributed to
+const MAX_PROCS: usize = 64;
+
+#[derive(Clone)]
+struct Proc {
+    pid: u32,
+    ppid: u32,
+    uid: u32,
+    auid: u32,
+    ses: u32,
+    exe: String,
+    argv: Vec<String>,
+}
+
+impl Proc {
+    fn comm(&self) -> &str {
+        let name = self.exe.rsplit('/').next().unwrap_or_default();
+        &name[..name.len().min(15)]
+    }
+}
+
+/// Produces raw audit events
+pub struct Generator {
+    mix: Mix,
+    rng: Rng,
+    sequence: u64,
+    next_pid: u32,
+    /// The first entries are shells that start new processes.
+    procs: Vec<Proc>,
+}
+
+impl Generator {
+    pub fn new(mix: Mix, seed: u64) -> Self {
+        let procs = SESSIONS
+            .iter()
+            .enumerate()
+            .map(|(n, (uid, auid, ses))| Proc {
+                pid: 2000 + n as u32,
+                ppid: 1000,
+                uid: *uid,
+                auid: *auid,
+                ses: *ses,
+                exe: "/usr/bin/bash".into(),
+                argv: vec!["-bash".into()],
+            })
+            .collect();
+        Generator {
+            mix,
+            rng: Rng::new(seed),
+            sequence: 1,
+            next_pid: 3000,
+            procs,
+        }
+    }
+
+    /// Writes the records of one event with the timestamp `time`
+    /// (milliseconds since the epoch)
+    pub fn write_event(&mut self, w: &mut dyn Write, time: u64) -> io::Result<()> {
+        let id = format!("{}.{:03}:{}", time / 1000, time % 1000, self.sequence);
+        self.sequence += 1;
+        let total = self.mix.exec + self.mix.network + self.mix.file;
+        let n = (self.rng.next_u64() % total as u64) as u32;
+        if n < self.mix.exec {
+            self.write_exec(w, &id)
+        } else if n < self.mix.exec + self.mix.network {
+            self.write_connect(w, &id)
+        } else {
+            self.write_open(w, &id)
+        }
+    }
+
+    fn write_exec(&mut self, w: &mut dyn Write, id: &str) -> io::Result<()> {
+        let parent = self.rng.pick(&self.procs[..SESSIONS.len()]).clone();
+        let cmd = self.rng.pick(COMMANDS);
+        let proc = Proc {
+            pid: self.next_pid,
+            ppid: parent.pid,
+            exe: cmd[0].into(),
+            argv: cmd[1..].iter().map(|s| s.to_string()).collect(),
+            ..parent
+        };
+        self.next_pid += 1;
+        if self.procs.len() >= MAX_PROCS {
+            self.procs.remove(SESSIONS.len());
+        }
+        self.procs.push(proc.clone());
+
+        syscall(w, id, &proc, "59 success=yes exit=0", 2)?;
+        write!(w, "type=EXECVE msg=audit({id}): argc={}", proc.argv.len())?;
+        for (n, arg) in proc.argv.iter().enumerate() {
+            write!(w, r#" a{n}="{arg}""#)?;
+        }
+        writeln!(w)?;
+        writeln!(w, r#"type=CWD msg=audit({id}): cwd="/home/user""#)?;
+        path(w, id, 0, &proc.exe, 0o100755)?;
+        path(w, id, 1, "/lib64/ld-linux-x86-64.so.2", 0o100755)?;
+        proctitle(w, id, &proc)?;
+        writeln!(w, "type=EOE msg=audit({id}): ")
+    }
+
+    fn write_connect(&mut self, w: &mut dyn Write, id: &str) -> io::Result<()> {
+        let proc = self.rng.pick(&self.procs).clone();
+        let (addr, port) = self.rng.pick(DESTINATIONS);
+        syscall(w, id, &proc, "42 success=yes exit=0", 0)?;
+        writeln!(
+            w,
+            "type=SOCKADDR msg=audit({id}): saddr=0200{port:04X}{}0000000000000000",
+            hex_string(addr).to_uppercase()
+        )?;
+        proctitle(w, id, &proc)?;
+        writeln!(w, "type=EOE msg=audit({id}): ")
+    }
+
+    fn write_open(&mut self, w: &mut dyn Write, id: &str) -> io::Result<()> {
+        let proc = self.rng.pick(&self.procs).clone();
+        let file = self.rng.pick(FILES);
+        syscall(w, id, &proc, "257 success=yes exit=3", 1)?;
+        writeln!(w, r#"type=CWD msg=audit({id}): cwd="/home/user""#)?;
+        path(w, id, 0, file, 0o100644)?;
+        proctitle(w, id, &proc)?;
+        writeln!(w, "type=EOE msg=audit({id}): ")
+    }
+}
+
+fn syscall(w: &mut dyn Write, id: &str, p: &Proc, result: &str, items: u32) -> io::Result<()> {
+    let tty = match p.ses {
+        0xffffffff => "(none)".to_string(),
+        ses => format!("pts{ses}"),
+    };
+    writeln!(
+        w,
+        r#"type=SYSCALL msg=audit({id}): arch=c000003e syscall={result} a0=3 a1=7ffd2c1e5f3a a2=10 a3=0 items={items} ppid={} pid={} auid={} uid={uid} gid={uid} euid={uid} suid={uid} fsuid={uid} egid={uid} sgid={uid} fsgid={uid} tty={tty} ses={} comm="{}" exe="{}" key=(null)"#,
+        p.ppid,
+        p.pid,
+        p.auid,
+        p.ses,
+        p.comm(),
+        p.exe,
+        uid = p.uid,
+    )
+}
+
+fn path(w: &mut dyn Write, id: &str, item: u32, name: &str, mode: u32) -> io::Result<()> {
+    // Derive a stable inode number from the path.
+    let inode = name
+        .bytes()
+        .fold(261000u32, |h, c| h.wrapping_mul(31).wrapping_add(c as u32));
+    writeln!(
+        w,
+        r#"type=PATH msg=audit({id}): item={item} name="{name}" inode={inode} dev=fd:01 mode=0{mode:o} ouid=0 ogid=0 rdev=00:00 nametype=NORMAL cap_fp=0 cap_fi=0 cap_fe=0 cap_fver=0"#
+    )
+}
+
+fn proctitle(w: &mut dyn Write, id: &str, p: &Proc) -> io::Result<()> {
+    let title = p.argv.join("\0");
+    writeln!(
+        w,
+        "type=PROCTITLE msg=audit({id}): proctitle={}",
+        hex_string(title.as_bytes()).to_uppercase()
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::coalesce::Coalesce;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn mix() {
+        assert_eq!(
+            "exec=5, network=0,file=2".parse::<Mix>().unwrap(),
+            Mix {
+                exec: 5,
+                network: 0,
+                file: 2
+            }
+        );
+        assert_eq!(
+            "network=1".parse::<Mix>().unwrap(),
+            Mix {
+                exec: 0,
+                network: 1,
+                file: 0
+            }
+        );
+        for s in ["", "exec=0", "exec=x", "dns=1"] {
+            assert!(s.parse::<Mix>().is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn generate() {
+        let mut buf = vec![];
+        let mut gen = Generator::new(Mix::default(), 42);
+        for n in 0..300 {
+            gen.write_event(&mut buf, 1700000000000 + n).unwrap();
+        }
+
+        let mut again = vec![];
+        let mut gen = Generator::new(Mix::default(), 42);
+        for n in 0..300 {
+            gen.write_event(&mut again, 1700000000000 + n).unwrap();
+        }
+        assert_eq!(buf, again, "output should be reproducible");
+
+        let events = Rc::new(RefCell::new(vec![]));
+        {
+            let events = events.clone();
+            let mut c = Coalesce::new(move |ev: &crate::types::Event| {
+                events
+                    .borrow_mut()
+                    .push(crate::json::to_value(ev, Default::default()).unwrap())
+            })
+            .offline();
+            for line in buf.split_inclusive(|c| *c == b'\n') {
+                c.process_line(line).unwrap();
+            }
+        }
+        let events = events.borrow();
+        assert_eq!(events.len(), 300);
+        for ty in ["EXECVE", "SOCKADDR", "PATH"] {
+            assert!(
+                events.iter().any(|ev| ev.get(ty).is_some()),
+                "no {ty} events"
+            );
+        }
+    }
+}
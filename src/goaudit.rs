@@ -0,0 +1,191 @@
+//! Output of events in a format compatible with go-audit
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use linux_audit_parser::MessageType;
+
+use crate::json::{to_value, SpecialFormatter};
+
+/// Serialize message using formatter, write in go-audit format.
+pub fn write<W, T>(w: &mut W, message: &T, formatter: SpecialFormatter) -> io::Result<()>
+where
+    W: ?Sized + Write,
+    T: ?Sized + Serialize,
+{
+    write_value(w, &to_value(message, formatter)?)
+}
+
+/// Writes already-serialized message as a JSON document that mimics
+/// the output of go-audit:
+///
+/// - `sequence` and `timestamp` are taken from the event ID.
+/// - `messages` contains one `{"type":…,"data":…}` object per
+///   record, `type` being the numeric record type and `data` the
+///   record's fields in `key=value` form, using raw values.
+/// - `uid_map` maps the numeric UIDs found in `*uid` fields to user
+///   names, if they have been translated (see `translate.userdb`).
+///
+/// Records and fields added by _laurel_ are left out.
+pub fn write_value<W: ?Sized + Write>(w: &mut W, value: &Value) -> io::Result<()> {
+    let Value::Object(map) = value else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "message is not an object",
+        ));
+    };
+    let id = map.get("ID").and_then(Value::as_str).unwrap_or_default();
+    let (timestamp, sequence) = id.split_once(':').unwrap_or((id, "0"));
+    let mut messages = vec![];
+    let mut uid_map = BTreeMap::new();
+    for (ty, v) in map {
+        let Ok(MessageType(num)) = ty.parse::<MessageType>() else {
+            continue;
+        };
+        let records = match v {
+            Value::Object(rec) => vec![rec],
+            Value::Array(vs) => vs.iter().filter_map(Value::as_object).collect(),
+            _ => continue,
+        };
+        for rec in records {
+            let mut data = vec![];
+            write_fields(&mut data, ty, rec)?;
+            messages.push(json!({
+                "type": num,
+                "data": String::from_utf8_lossy(data.strip_prefix(b" ").unwrap_or(&data)),
+            }));
+            for (k, v) in rec {
+                if let (true, Some(Value::String(name))) =
+                    (k.ends_with("uid"), rec.get(&k.to_ascii_uppercase()))
+                {
+                    uid_map.insert(scalar(v), name.clone());
+                }
+            }
+        }
+    }
+    serde_json::to_writer(
+        w,
+        &json!({
+            "sequence": sequence.parse::<u64>().unwrap_or_default(),
+            "timestamp": timestamp,
+            "messages": messages,
+            "uid_map": uid_map,
+        }),
+    )?;
+    Ok(())
+}
+
+fn write_fields<W: ?Sized + Write>(
+    w: &mut W,
+    ty: &str,
+    rec: &Map<String, Value>,
+) -> io::Result<()> {
+    for (k, v) in rec {
+        match (k.as_str(), v) {
+            ("ARGV", Value::Array(argv)) if ty == "PROCTITLE" => {
+                let args: Vec<String> = argv.iter().map(scalar).collect();
+                write!(w, " proctitle=")?;
+                for c in args.join("\0").bytes() {
+                    write!(w, "{c:02X}")?;
+                }
+            }
+            // Syscall arguments follow the exit code.
+            ("ARGV", _) if ty == "SYSCALL" => {}
+            ("ARGV", Value::Array(argv)) => write_args(w, argv)?,
+            (k, _) if k.chars().any(|c| c.is_ascii_uppercase()) => {}
+            (k, Value::Object(nested)) => {
+                write!(w, " {k}='")?;
+                let mut buf = vec![];
+                write_fields(&mut buf, ty, nested)?;
+                w.write_all(buf.strip_prefix(b" ").unwrap_or(&buf))?;
+                write!(w, "'")?;
+            }
+            (k, v) => {
+                write!(w, " {k}={}", raw(v))?;
+                if let ("SYSCALL", "exit", Some(Value::Array(argv))) = (ty, k, rec.get("ARGV")) {
+                    write_args(w, argv)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_args<W: ?Sized + Write>(w: &mut W, args: &[Value]) -> io::Result<()> {
+    for (n, arg) in args.iter().enumerate() {
+        write!(w, " a{n}={}", raw(arg))?;
+    }
+    Ok(())
+}
+
+/// Approximates the representation of a value in the original
+/// record: Hexadecimal and octal numbers lose their `0x`, `0o`
+/// prefixes, strings that contain characters other than
+/// alphanumerics and `_-.:,` are quoted.
+fn raw(value: &Value) -> String {
+    match value {
+        Value::String(s) => {
+            if let Some(hex) = s.strip_prefix("0x") {
+                hex.into()
+            } else if let Some(oct) = s.strip_prefix("0o") {
+                format!("0{oct}")
+            } else if !s.is_empty()
+                && s.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "_-.:,".contains(c))
+            {
+                s.clone()
+            } else {
+                format!("{s:?}")
+            }
+        }
+        v => scalar(v),
+    }
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => "(null)".into(),
+        Value::Array(vs) => vs.iter().map(scalar).collect::<Vec<_>>().join(","),
+        v => v.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn goaudit() {
+        let event: Value =
+            serde_json::from_slice(include_bytes!("testdata/record-execve.json")).unwrap();
+        let mut buf = vec![];
+        write_value(&mut buf, &event).unwrap();
+        let output: Value = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(output["sequence"], 15558);
+        assert_eq!(output["timestamp"], "1615114232.375");
+        assert_eq!(output["uid_map"], json!({"0": "root", "1000": "user"}));
+        let messages = output["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 6);
+        assert_eq!(messages[0]["type"], 1300);
+        assert_eq!(
+            messages[0]["data"],
+            r#"arch=c000003e syscall=59 success=yes exit=0 a0=63b29337fd18 a1=63b293387d58 a2=63b293375640 a3=fffffffffffff000 items=2 ppid=10883 pid=10884 auid=1000 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=pts1 ses=1 comm=whoami exe="/usr/bin/whoami" key=(null)"#
+        );
+        assert_eq!(messages[1]["data"], "argc=1 a0=whoami");
+        assert_eq!(messages[2]["data"], r#"cwd="/home/user/tmp""#);
+        assert_eq!(messages[3]["type"], 1302);
+        assert!(
+            messages[3]["data"].as_str().unwrap().starts_with(
+                r#"item=0 name="/usr/bin/whoami" inode=261214 dev=ca:03 mode=0100755 "#
+            ),
+            "{}",
+            messages[3]["data"]
+        );
+        assert_eq!(messages[5]["type"], 1327);
+        assert_eq!(messages[5]["data"], "proctitle=77686F616D69");
+    }
+}
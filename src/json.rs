@@ -3,24 +3,148 @@ use std::{
     io::{self, Read, Write},
 };
 
-use serde::de;
+use serde::{de, ser, Deserialize, Serialize};
 
 use crate::quote::*;
 
+/// Escaping profile for byte buffers that are not (entirely)
+/// printable UTF-8 strings
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Escape {
+    /// URI-encoding (`%XX`) for non-printable bytes, `%`, `+`
+    #[default]
+    Uri,
+    /// JSON-native `\u00XX` escapes for non-UTF-8 bytes
+    Json,
+    /// `\xXX` escapes for non-UTF-8 bytes, `\\` for backslashes
+    Hex,
+    /// Base64 encoding with `base64:` prefix for values that are not
+    /// valid UTF-8
+    Base64,
+    /// `{"b64":"…","truncated":false}` objects for values that are
+    /// not valid UTF-8
+    Base64Object,
+}
+
+const BASE64_PREFIX: &[u8] = b"base64:";
+
+/// Handling of byte buffers that are not valid UTF-8. Unless the
+/// mode is [`InvalidUtf8::Keep`], this takes precedence over
+/// [`Escape`] for such buffers.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum InvalidUtf8 {
+    /// Buffers are escaped according to [`Escape`]
+    #[default]
+    Keep,
+    /// Invalid sequences are replaced with U+FFFD
+    Replace,
+    /// Invalid bytes are written as `\xXX`, backslashes are doubled
+    Hex,
+}
+
+/// Framing of output lines
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Framing {
+    /// Lines end with `\n` (JSON Lines)
+    #[default]
+    Lf,
+    /// Lines end with `\r\n`
+    Crlf,
+    /// Lines start with an ASCII record separator (0x1e) and end with
+    /// `\n` (RFC 7464 JSON text sequences)
+    JsonSeq,
+}
+
+impl Framing {
+    /// Returns the `\n`-terminated lines in `buf`, framed according
+    /// to the selected mode
+    pub fn apply(self, buf: Vec<u8>) -> Vec<u8> {
+        if self == Framing::Lf {
+            return buf;
+        }
+        let mut out = Vec::with_capacity(buf.len() + 8);
+        for line in buf.split_inclusive(|c| *c == b'\n') {
+            match (self, line.strip_suffix(b"\n")) {
+                (Framing::Crlf, Some(line)) => {
+                    out.extend(line);
+                    out.extend(b"\r\n");
+                }
+                (Framing::JsonSeq, _) => {
+                    out.push(0x1e);
+                    out.extend(line);
+                }
+                _ => out.extend(line),
+            }
+        }
+        out
+    }
+}
+
 /// A Formatter for serde_josn that outputs byte buffers as
-/// URI-encodeed strings.
-#[derive(Clone, Debug)]
-pub struct SpecialFormatter;
+/// strings, escaped according to the selected profile (default:
+/// URI-encoded).
+#[derive(Clone, Debug, Default)]
+pub struct SpecialFormatter {
+    pub escape: Escape,
+    /// Maximum number of bytes encoded for [`Escape::Base64Object`]
+    pub b64_max_bytes: Option<usize>,
+    pub invalid_utf8: InvalidUtf8,
+}
+
+impl From<Escape> for SpecialFormatter {
+    fn from(escape: Escape) -> Self {
+        SpecialFormatter {
+            escape,
+            ..Self::default()
+        }
+    }
+}
 
 impl serde_json::ser::Formatter for SpecialFormatter {
     fn write_byte_array<W>(&mut self, writer: &mut W, value: &[u8]) -> io::Result<()>
     where
         W: ?Sized + Write,
     {
+        if self.invalid_utf8 != InvalidUtf8::Keep && std::str::from_utf8(value).is_err() {
+            self.begin_string(writer)?;
+            match self.invalid_utf8 {
+                InvalidUtf8::Hex => {
+                    HexEscapeWriter(&mut BackslashEscapeWriter(writer)).write_all(value)?
+                }
+                _ => BackslashEscapeWriter(writer)
+                    .write_all(String::from_utf8_lossy(value).as_bytes())?,
+            }
+            return self.end_string(writer);
+        }
+        if self.escape == Escape::Base64Object && std::str::from_utf8(value).is_err() {
+            let (value, truncated) = match self.b64_max_bytes {
+                Some(max) if value.len() > max => (&value[..max], true),
+                _ => (value, false),
+            };
+            writer.write_all(br#"{"b64":""#)?;
+            write_base64(writer, value)?;
+            return write!(writer, r#"","truncated":{truncated}}}"#);
+        }
         self.begin_string(writer)?;
-        URIEscapeWriter(&mut BackslashEscapeWriter(writer))
-            .write(value)
-            .map(|_| ())?;
+        match self.escape {
+            Escape::Uri => {
+                URIEscapeWriter(&mut BackslashEscapeWriter(writer)).write_all(value)?;
+            }
+            Escape::Json => JSONEscapeWriter(writer).write_all(value)?,
+            Escape::Hex => HexEscapeWriter(&mut BackslashEscapeWriter(writer)).write_all(value)?,
+            Escape::Base64
+                if value.starts_with(BASE64_PREFIX) || std::str::from_utf8(value).is_err() =>
+            {
+                writer.write_all(BASE64_PREFIX)?;
+                write_base64(writer, value)?;
+            }
+            Escape::Base64 | Escape::Base64Object => {
+                BackslashEscapeWriter(writer).write_all(value)?
+            }
+        }
         self.end_string(writer)
     }
 }
@@ -30,10 +154,304 @@ where
     W: Write,
     T: ?Sized + serde::Serialize,
 {
-    let mut ser = serde_json::Serializer::with_formatter(writer, SpecialFormatter);
+    to_writer_escaped(writer, value, SpecialFormatter::default())
+}
+
+/// Like [`to_writer`], using the specified escaping profile
+pub fn to_writer_escaped<W, T>(
+    writer: W,
+    value: &T,
+    formatter: SpecialFormatter,
+) -> serde_json::Result<()>
+where
+    W: Write,
+    T: ?Sized + serde::Serialize,
+{
+    let mut ser = serde_json::Serializer::with_formatter(writer, formatter);
     value.serialize(&mut ser)
 }
 
+/// Serialize value to a [`serde_json::Value`] using the specified
+/// escaping profile for byte buffers.
+pub fn to_value<T>(value: &T, formatter: SpecialFormatter) -> serde_json::Result<serde_json::Value>
+where
+    T: ?Sized + serde::Serialize,
+{
+    value.serialize(ValueSerializer(&formatter))
+}
+
+/// Builds a [`serde_json::Value`] directly. Only byte buffers are
+/// rendered through the [`SpecialFormatter`].
+struct ValueSerializer<'f>(&'f SpecialFormatter);
+
+type JValue = serde_json::Value;
+
+/// Converts a serialized map key to a string, like serde_json does
+/// for its output
+fn map_key<T: ?Sized + Serialize>(key: &T) -> serde_json::Result<String> {
+    match key.serialize(serde_json::value::Serializer)? {
+        JValue::String(s) => Ok(s),
+        JValue::Number(n) => Ok(n.to_string()),
+        JValue::Bool(b) => Ok(b.to_string()),
+        _ => Err(ser::Error::custom("key must be a string")),
+    }
+}
+
+impl<'f> ser::Serializer for ValueSerializer<'f> {
+    type Ok = JValue;
+    type Error = serde_json::Error;
+    type SerializeSeq = SerializeVec<'f>;
+    type SerializeTuple = SerializeVec<'f>;
+    type SerializeTupleStruct = SerializeVec<'f>;
+    type SerializeTupleVariant = SerializeVariant<SerializeVec<'f>>;
+    type SerializeMap = SerializeMap<'f>;
+    type SerializeStruct = SerializeMap<'f>;
+    type SerializeStructVariant = SerializeVariant<SerializeMap<'f>>;
+
+    fn serialize_bool(self, v: bool) -> serde_json::Result<JValue> {
+        Ok(JValue::Bool(v))
+    }
+    fn serialize_i8(self, v: i8) -> serde_json::Result<JValue> {
+        Ok(v.into())
+    }
+    fn serialize_i16(self, v: i16) -> serde_json::Result<JValue> {
+        Ok(v.into())
+    }
+    fn serialize_i32(self, v: i32) -> serde_json::Result<JValue> {
+        Ok(v.into())
+    }
+    fn serialize_i64(self, v: i64) -> serde_json::Result<JValue> {
+        Ok(v.into())
+    }
+    fn serialize_i128(self, v: i128) -> serde_json::Result<JValue> {
+        serde_json::value::Serializer.serialize_i128(v)
+    }
+    fn serialize_u8(self, v: u8) -> serde_json::Result<JValue> {
+        Ok(v.into())
+    }
+    fn serialize_u16(self, v: u16) -> serde_json::Result<JValue> {
+        Ok(v.into())
+    }
+    fn serialize_u32(self, v: u32) -> serde_json::Result<JValue> {
+        Ok(v.into())
+    }
+    fn serialize_u64(self, v: u64) -> serde_json::Result<JValue> {
+        Ok(v.into())
+    }
+    fn serialize_u128(self, v: u128) -> serde_json::Result<JValue> {
+        serde_json::value::Serializer.serialize_u128(v)
+    }
+    fn serialize_f32(self, v: f32) -> serde_json::Result<JValue> {
+        Ok(v.into())
+    }
+    fn serialize_f64(self, v: f64) -> serde_json::Result<JValue> {
+        Ok(v.into())
+    }
+    fn serialize_char(self, v: char) -> serde_json::Result<JValue> {
+        Ok(JValue::String(v.into()))
+    }
+    fn serialize_str(self, v: &str) -> serde_json::Result<JValue> {
+        Ok(JValue::String(v.into()))
+    }
+    fn serialize_bytes(self, v: &[u8]) -> serde_json::Result<JValue> {
+        // Only the escaped string (or base64 object) is parsed, not
+        // the whole message.
+        let mut buf = Vec::with_capacity(v.len() + 2);
+        serde_json::ser::Formatter::write_byte_array(&mut self.0.clone(), &mut buf, v)
+            .map_err(serde_json::Error::io)?;
+        serde_json::from_slice(&buf)
+    }
+    fn serialize_none(self) -> serde_json::Result<JValue> {
+        Ok(JValue::Null)
+    }
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> serde_json::Result<JValue> {
+        value.serialize(self)
+    }
+    fn serialize_unit(self) -> serde_json::Result<JValue> {
+        Ok(JValue::Null)
+    }
+    fn serialize_unit_struct(self, _name: &'static str) -> serde_json::Result<JValue> {
+        Ok(JValue::Null)
+    }
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> serde_json::Result<JValue> {
+        Ok(JValue::String(variant.into()))
+    }
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> serde_json::Result<JValue> {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> serde_json::Result<JValue> {
+        let mut map = serde_json::Map::new();
+        map.insert(variant.into(), value.serialize(self)?);
+        Ok(JValue::Object(map))
+    }
+    fn serialize_seq(self, len: Option<usize>) -> serde_json::Result<SerializeVec<'f>> {
+        Ok(SerializeVec(self.0, Vec::with_capacity(len.unwrap_or(0))))
+    }
+    fn serialize_tuple(self, len: usize) -> serde_json::Result<SerializeVec<'f>> {
+        self.serialize_seq(Some(len))
+    }
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> serde_json::Result<SerializeVec<'f>> {
+        self.serialize_seq(Some(len))
+    }
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> serde_json::Result<Self::SerializeTupleVariant> {
+        Ok(SerializeVariant(variant, self.serialize_seq(Some(len))?))
+    }
+    fn serialize_map(self, _len: Option<usize>) -> serde_json::Result<SerializeMap<'f>> {
+        Ok(SerializeMap(self.0, serde_json::Map::new(), None))
+    }
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> serde_json::Result<SerializeMap<'f>> {
+        self.serialize_map(Some(len))
+    }
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> serde_json::Result<Self::SerializeStructVariant> {
+        Ok(SerializeVariant(variant, self.serialize_map(Some(len))?))
+    }
+}
+
+struct SerializeVec<'f>(&'f SpecialFormatter, Vec<JValue>);
+
+impl ser::SerializeSeq for SerializeVec<'_> {
+    type Ok = JValue;
+    type Error = serde_json::Error;
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> serde_json::Result<()> {
+        self.1.push(value.serialize(ValueSerializer(self.0))?);
+        Ok(())
+    }
+    fn end(self) -> serde_json::Result<JValue> {
+        Ok(JValue::Array(self.1))
+    }
+}
+
+impl ser::SerializeTuple for SerializeVec<'_> {
+    type Ok = JValue;
+    type Error = serde_json::Error;
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> serde_json::Result<()> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+    fn end(self) -> serde_json::Result<JValue> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeVec<'_> {
+    type Ok = JValue;
+    type Error = serde_json::Error;
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> serde_json::Result<()> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+    fn end(self) -> serde_json::Result<JValue> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+struct SerializeMap<'f>(
+    &'f SpecialFormatter,
+    serde_json::Map<String, JValue>,
+    Option<String>,
+);
+
+impl ser::SerializeMap for SerializeMap<'_> {
+    type Ok = JValue;
+    type Error = serde_json::Error;
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> serde_json::Result<()> {
+        self.2 = Some(map_key(key)?);
+        Ok(())
+    }
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> serde_json::Result<()> {
+        let key = self.2.take().unwrap_or_default();
+        self.1
+            .insert(key, value.serialize(ValueSerializer(self.0))?);
+        Ok(())
+    }
+    fn end(self) -> serde_json::Result<JValue> {
+        Ok(JValue::Object(self.1))
+    }
+}
+
+impl ser::SerializeStruct for SerializeMap<'_> {
+    type Ok = JValue;
+    type Error = serde_json::Error;
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> serde_json::Result<()> {
+        self.1
+            .insert(key.into(), value.serialize(ValueSerializer(self.0))?);
+        Ok(())
+    }
+    fn end(self) -> serde_json::Result<JValue> {
+        Ok(JValue::Object(self.1))
+    }
+}
+
+/// Enum variant with data, written as `{"variant": data}`
+struct SerializeVariant<S>(&'static str, S);
+
+impl ser::SerializeTupleVariant for SerializeVariant<SerializeVec<'_>> {
+    type Ok = JValue;
+    type Error = serde_json::Error;
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> serde_json::Result<()> {
+        ser::SerializeSeq::serialize_element(&mut self.1, value)
+    }
+    fn end(self) -> serde_json::Result<JValue> {
+        let mut map = serde_json::Map::new();
+        map.insert(self.0.into(), ser::SerializeSeq::end(self.1)?);
+        Ok(JValue::Object(map))
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeVariant<SerializeMap<'_>> {
+    type Ok = JValue;
+    type Error = serde_json::Error;
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> serde_json::Result<()> {
+        ser::SerializeStruct::serialize_field(&mut self.1, key, value)
+    }
+    fn end(self) -> serde_json::Result<JValue> {
+        let mut map = serde_json::Map::new();
+        map.insert(self.0.into(), ser::SerializeStruct::end(self.1)?);
+        Ok(JValue::Object(map))
+    }
+}
+
 struct Deserializer<R: Read>(serde_json::Deserializer<serde_json::de::IoRead<R>>);
 
 impl<R: Read> Deserializer<R> {
@@ -154,7 +572,10 @@ where
 
 #[cfg(test)]
 mod test {
-    use super::{from_reader, to_writer};
+    use super::{
+        from_reader, to_value, to_writer, to_writer_escaped, Escape, Framing, InvalidUtf8,
+        SpecialFormatter,
+    };
 
     fn ser(value: &[u8]) -> String {
         let mut buf = vec![];
@@ -195,4 +616,171 @@ mod test {
             assert_eq!(*buf, *de(serialized));
         }
     }
+
+    #[test]
+    fn json_serialize_escaped() {
+        let ser = |value: &[u8], escape: Escape| {
+            let mut buf = vec![];
+            to_writer_escaped(&mut buf, serde_bytes::Bytes::new(value), escape.into()).unwrap();
+            String::from_utf8(buf).unwrap()
+        };
+        for (buf, uri, json, hex, base64) in &[
+            (
+                &b"asdf"[..],
+                r#""asdf""#,
+                r#""asdf""#,
+                r#""asdf""#,
+                r#""asdf""#,
+            ),
+            (b"%+", r#""%25%2b""#, r#""%+""#, r#""%+""#, r#""%+""#),
+            (
+                b"a\\b",
+                r#""a\\b""#,
+                r#""a\\b""#,
+                r#""a\\\\b""#,
+                r#""a\\b""#,
+            ),
+            (
+                b"\t\n",
+                r#""%09%0a""#,
+                r#""\t\n""#,
+                r#""\t\n""#,
+                r#""\t\n""#,
+            ),
+            (b"\xc3\xa4", r#""ä""#, r#""ä""#, r#""ä""#, r#""ä""#),
+            (
+                b"\xc3\xc3\xa4",
+                r#""%c3ä""#,
+                r#""\u00c3ä""#,
+                r#""\\xc3ä""#,
+                r#""base64:w8Ok""#,
+            ),
+            (
+                b"base64:Zm9v",
+                r#""base64:Zm9v""#,
+                r#""base64:Zm9v""#,
+                r#""base64:Zm9v""#,
+                r#""base64:YmFzZTY0OlptOXY=""#,
+            ),
+        ] {
+            assert_eq!(ser(buf, Escape::Uri), *uri);
+            assert_eq!(ser(buf, Escape::Json), *json);
+            assert_eq!(ser(buf, Escape::Hex), *hex);
+            assert_eq!(ser(buf, Escape::Base64), *base64);
+        }
+    }
+
+    #[test]
+    fn value() {
+        use serde_bytes::Bytes;
+        use std::collections::BTreeMap;
+
+        #[derive(serde::Serialize)]
+        enum Kind {
+            Unit,
+            Newtype(u8),
+            Struct { x: i64 },
+        }
+
+        #[derive(serde::Serialize)]
+        struct Message<'a> {
+            name: &'a Bytes,
+            args: Vec<&'a Bytes>,
+            map: BTreeMap<u32, &'a Bytes>,
+            none: Option<u32>,
+            kinds: (Kind, Kind, Kind),
+        }
+
+        let msg = Message {
+            name: Bytes::new(b"ls"),
+            args: vec![Bytes::new(b"a\\b%"), Bytes::new(b"\xc3\xc3\xa4\t")],
+            map: [(1, Bytes::new(b"\"\xff"))].into(),
+            none: None,
+            kinds: (Kind::Unit, Kind::Newtype(1), Kind::Struct { x: -1 }),
+        };
+        for escape in [
+            Escape::Uri,
+            Escape::Json,
+            Escape::Hex,
+            Escape::Base64,
+            Escape::Base64Object,
+        ] {
+            let mut buf = vec![];
+            to_writer_escaped(&mut buf, &msg, escape.into()).unwrap();
+            let expected: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+            assert_eq!(
+                to_value(&msg, escape.into()).unwrap(),
+                expected,
+                "{escape:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn json_serialize_b64_object() {
+        let ser = |value: &[u8], b64_max_bytes| {
+            let mut buf = vec![];
+            let formatter = SpecialFormatter {
+                escape: Escape::Base64Object,
+                b64_max_bytes,
+                ..SpecialFormatter::default()
+            };
+            to_writer_escaped(&mut buf, &[serde_bytes::Bytes::new(value)], formatter).unwrap();
+            String::from_utf8(buf).unwrap()
+        };
+        assert_eq!(ser(b"a\tb", None), r#"["a\tb"]"#);
+        assert_eq!(ser(b"base64:Zm9v", None), r#"["base64:Zm9v"]"#);
+        assert_eq!(
+            ser(b"\xc3\xc3\xa4", None),
+            r#"[{"b64":"w8Ok","truncated":false}]"#
+        );
+        assert_eq!(
+            ser(b"\xc3\xc3\xa4", Some(3)),
+            r#"[{"b64":"w8Ok","truncated":false}]"#
+        );
+        assert_eq!(
+            ser(b"\xc3\xc3\xa4", Some(2)),
+            r#"[{"b64":"w8M=","truncated":true}]"#
+        );
+    }
+
+    #[test]
+    fn framing() {
+        let buf = b"{\"a\":1}\n{\"b\":2}\n".to_vec();
+        assert_eq!(Framing::Lf.apply(buf.clone()), buf);
+        assert_eq!(
+            Framing::Crlf.apply(buf.clone()),
+            b"{\"a\":1}\r\n{\"b\":2}\r\n"
+        );
+        assert_eq!(
+            Framing::JsonSeq.apply(buf),
+            b"\x1e{\"a\":1}\n\x1e{\"b\":2}\n"
+        );
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let ser = |invalid_utf8, value: &[u8]| {
+            let mut buf = vec![];
+            let formatter = SpecialFormatter {
+                invalid_utf8,
+                ..SpecialFormatter::default()
+            };
+            to_writer_escaped(&mut buf, &[serde_bytes::Bytes::new(value)], formatter).unwrap();
+            String::from_utf8(buf).unwrap()
+        };
+        let value = b"cmd=\xff\\x \"a\"";
+        assert_eq!(ser(InvalidUtf8::Keep, value), r#"["cmd=%ff\\x \"a\""]"#);
+        assert_eq!(
+            ser(InvalidUtf8::Replace, value),
+            "[\"cmd=\u{fffd}\\\\x \\\"a\\\"\"]"
+        );
+        assert_eq!(ser(InvalidUtf8::Hex, value), r#"["cmd=\\xff\\\\x \"a\""]"#);
+        // Valid UTF-8 is escaped according to the profile.
+        assert_eq!(ser(InvalidUtf8::Hex, "ä%".as_bytes()), r#"["ä%25"]"#);
+        // The result can be read back.
+        let s = ser(InvalidUtf8::Hex, value);
+        let v: Vec<String> = serde_json::from_str(&s).unwrap();
+        assert_eq!(v, [r#"cmd=\xff\\x "a""#]);
+    }
 }
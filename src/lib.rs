@@ -1,20 +1,56 @@
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+pub mod alert;
+pub mod analyze;
+pub mod ausearch;
+pub mod cache;
+pub mod clock;
 pub mod coalesce;
 pub mod config;
 pub mod constants;
+pub mod control;
+#[cfg(feature = "encryption")]
+pub mod encrypt;
+pub mod entity;
+pub mod follow;
+pub mod forward;
+pub mod generate;
+pub mod goaudit;
 pub mod hash;
 pub mod json;
 pub mod label_matcher;
 pub mod logger;
+pub mod lookup;
+pub mod otlp;
+pub mod pool;
+pub mod presets;
 pub mod proc;
 #[cfg(all(feature = "procfs", target_os = "linux"))]
 pub mod procfs;
+#[cfg(all(feature = "procfs", target_os = "linux"))]
+pub mod procwatch;
+pub mod projection;
 pub(crate) mod quote;
+pub mod rename;
+pub mod rewrite;
+pub mod rfc5424;
 pub mod rotate;
+pub mod rules;
+#[cfg(feature = "sigma")]
+pub mod sigma;
+#[cfg(feature = "signing")]
+pub mod sign;
 #[cfg(target_os = "linux")]
 pub mod sockaddr;
+pub mod spool;
+pub mod strtab;
+pub mod summary;
+pub mod tail;
 #[cfg(test)]
 mod test;
+pub mod text;
+pub mod translation;
 pub mod types;
 pub mod userdb;
+#[cfg(target_os = "linux")]
+pub mod utmp;
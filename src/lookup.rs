@@ -0,0 +1,191 @@
+//! Add values from external lookup tables to events
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use serde_json::{Map, Value};
+
+use thiserror::Error;
+
+use crate::projection::FieldPath;
+
+/// Minimum time between checks whether a lookup table file has
+/// changed
+const RELOAD_CHECK_PERIOD: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Error)]
+pub enum LookupError {
+    #[error("{0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("{0}: line {1}: expected key,value")]
+    Csv(PathBuf, usize),
+    #[error("{0}: {1}")]
+    Json(PathBuf, serde_json::Error),
+}
+
+/// Table that maps values of a field to values that are added to the
+/// event
+///
+/// Tables are read from CSV files (`key,value` per line, lines
+/// starting with `#` are ignored) or, if the file name ends with
+/// `.json`, from a JSON object. The file is read again if its
+/// modification time has changed.
+#[derive(Debug)]
+pub struct LookupTable {
+    path: PathBuf,
+    field: FieldPath,
+    name: String,
+    entries: HashMap<String, Value>,
+    modified: Option<SystemTime>,
+    last_check: Instant,
+}
+
+impl LookupTable {
+    /// Reads a table from `path`. Values of `field` that are found
+    /// in the table are added next to `field` as `name`.
+    pub fn new(path: &Path, field: &FieldPath, name: &str) -> Result<Self, LookupError> {
+        let mut t = LookupTable {
+            path: path.to_path_buf(),
+            field: field.clone(),
+            name: name.into(),
+            entries: HashMap::new(),
+            modified: None,
+            last_check: Instant::now(),
+        };
+        t.load()?;
+        Ok(t)
+    }
+
+    fn load(&mut self) -> Result<(), LookupError> {
+        let err = |e| LookupError::Io(self.path.clone(), e);
+        let modified = std::fs::metadata(&self.path)
+            .and_then(|m| m.modified())
+            .map_err(err)?;
+        let buf = std::fs::read_to_string(&self.path).map_err(err)?;
+        self.entries = if self.path.extension().is_some_and(|e| e == "json") {
+            serde_json::from_str::<Map<String, Value>>(&buf)
+                .map_err(|e| LookupError::Json(self.path.clone(), e))?
+                .into_iter()
+                .collect()
+        } else {
+            parse_csv(&buf).map_err(|n| LookupError::Csv(self.path.clone(), n))?
+        };
+        self.modified = Some(modified);
+        Ok(())
+    }
+
+    /// Reads the table again if the file has been modified. Errors
+    /// are logged, the previous entries are kept.
+    fn reload(&mut self) {
+        if self.last_check.elapsed() < RELOAD_CHECK_PERIOD {
+            return;
+        }
+        self.last_check = Instant::now();
+        let modified = std::fs::metadata(&self.path).and_then(|m| m.modified());
+        if modified.ok() == self.modified {
+            return;
+        }
+        match self.load() {
+            Ok(()) => log::info!(
+                "Reloaded lookup table {} ({} entries)",
+                self.path.display(),
+                self.entries.len()
+            ),
+            Err(e) => log::warn!("Failed to reload lookup table: {e}"),
+        }
+    }
+
+    /// Adds values for all occurrences of the selected field in
+    /// `event`.
+    pub fn apply(&mut self, event: &mut Value) {
+        self.reload();
+        lookup(event, self.field.elements(), &self.name, &self.entries);
+    }
+}
+
+/// Parses `key,value` lines. Returns the line number on error.
+fn parse_csv(buf: &str) -> Result<HashMap<String, Value>, usize> {
+    let mut entries = HashMap::new();
+    for (n, line) in buf.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (k, v) = line.split_once(',').ok_or(n + 1)?;
+        entries.insert(k.trim().into(), Value::String(v.trim().into()));
+    }
+    Ok(entries)
+}
+
+fn lookup(value: &mut Value, path: &[String], name: &str, entries: &HashMap<String, Value>) {
+    match (path, value) {
+        ([last], Value::Object(map)) => {
+            let found = match map.get(last) {
+                Some(Value::String(s)) => entries.get(s),
+                Some(Value::Number(n)) => entries.get(&n.to_string()),
+                _ => None,
+            };
+            if let Some(v) = found {
+                map.insert(name.into(), v.clone());
+            }
+        }
+        ([first, rest @ ..], Value::Object(map)) => {
+            if let Some(v) = map.get_mut(first) {
+                lookup(v, rest, name, entries);
+            }
+        }
+        // Records that occur multiple times, e.g. PATH
+        (_, Value::Array(vs)) => vs.iter_mut().for_each(|v| lookup(v, path, name, entries)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lookup_table() {
+        let dir = std::env::temp_dir().join(format!("laurel-lookup-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let csv = dir.join("departments.csv");
+        std::fs::write(
+            &csv,
+            "# uid,department\n1000,Sales, EMEA\n1001,Engineering\n",
+        )
+        .unwrap();
+        let mut t =
+            LookupTable::new(&csv, &"SYSCALL.uid".parse().unwrap(), "uid_department").unwrap();
+        let mut ev: Value =
+            serde_json::from_str(r#"{"ID":"1.0:1","SYSCALL":{"uid":1000,"exe":"/usr/bin/id"}}"#)
+                .unwrap();
+        t.apply(&mut ev);
+        assert_eq!(
+            ev.to_string(),
+            r#"{"ID":"1.0:1","SYSCALL":{"uid":1000,"exe":"/usr/bin/id","uid_department":"Sales, EMEA"}}"#
+        );
+
+        let json = dir.join("software.json");
+        std::fs::write(&json, r#"{"/usr/bin/ls":{"id":42}}"#).unwrap();
+        let mut t = LookupTable::new(&json, &"PATH.name".parse().unwrap(), "SOFTWARE").unwrap();
+        let mut ev: Value = serde_json::from_str(
+            r#"{"ID":"1.0:2","PATH":[{"name":"/usr/bin/ls"},{"name":"/lib/ld.so"}]}"#,
+        )
+        .unwrap();
+        t.apply(&mut ev);
+        assert_eq!(
+            ev.to_string(),
+            r#"{"ID":"1.0:2","PATH":[{"name":"/usr/bin/ls","SOFTWARE":{"id":42}},{"name":"/lib/ld.so"}]}"#
+        );
+
+        std::fs::write(&csv, "1000\n").unwrap();
+        assert!(matches!(
+            LookupTable::new(&csv, &"SYSCALL.uid".parse().unwrap(), "x"),
+            Err(LookupError::Csv(_, 1))
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
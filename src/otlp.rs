@@ -0,0 +1,614 @@
+//! Output of events as OpenTelemetry (OTLP) log records, and a
+//! minimal OTLP/HTTP transport
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender};
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::json::{to_value, SpecialFormatter};
+
+/// severity_number for INFO, see OpenTelemetry logs data model
+const SEVERITY_INFO: u8 = 9;
+
+/// Writes events as OTLP/JSON `ExportLogsServiceRequest` documents,
+/// one per event:
+///
+/// - The resource carries `service.name`, `service.version`,
+///   `host.name` and, if present, the event's `NODE` as
+///   `audit.node`.
+/// - The log record's timestamp is taken from the event ID, the ID
+///   itself is added as `audit.event_id` attribute.
+/// - All records are written to the log record's body as a
+///   `kvlistValue`, nested values are mapped to `kvlistValue` and
+///   `arrayValue`.
+#[derive(Clone, Debug)]
+pub struct Otlp {
+    hostname: String,
+}
+
+impl Otlp {
+    pub fn new() -> Self {
+        Otlp {
+            hostname: hostname().unwrap_or_default(),
+        }
+    }
+
+    /// Serialize message using formatter, write as OTLP/JSON document.
+    pub fn write<W, T>(&self, w: &mut W, message: &T, formatter: SpecialFormatter) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+        T: ?Sized + Serialize,
+    {
+        self.write_value(w, &to_value(message, formatter)?)
+    }
+
+    /// Write already-serialized message as OTLP/JSON document.
+    pub fn write_value<W: ?Sized + Write>(&self, w: &mut W, value: &Value) -> io::Result<()> {
+        let Value::Object(map) = value else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "message is not an object",
+            ));
+        };
+        let id = map.get("ID").and_then(Value::as_str).unwrap_or_default();
+        let mut resource = vec![
+            attribute("service.name", "laurel"),
+            attribute("service.version", crate::VERSION),
+            attribute("host.name", &self.hostname),
+        ];
+        if let Some(node) = map.get("NODE").and_then(Value::as_str) {
+            resource.push(attribute("audit.node", node));
+        }
+        let body: Vec<Value> = map
+            .iter()
+            .filter(|(k, _)| *k != "ID" && *k != "NODE")
+            .map(|(k, v)| json!({"key": k, "value": any_value(v)}))
+            .collect();
+        let mut record = json!({
+            "severityNumber": SEVERITY_INFO,
+            "severityText": "INFO",
+            "body": {"kvlistValue": {"values": body}},
+            "attributes": [attribute("audit.event_id", id)],
+        });
+        if let Some(ns) = timestamp_nanos(id) {
+            record["timeUnixNano"] = Value::String(ns);
+        }
+        serde_json::to_writer(
+            w,
+            &json!({
+                "resourceLogs": [{
+                    "resource": {"attributes": resource},
+                    "scopeLogs": [{
+                        "scope": {"name": "laurel", "version": crate::VERSION},
+                        "logRecords": [record],
+                    }],
+                }],
+            }),
+        )?;
+        Ok(())
+    }
+}
+
+impl Default for Otlp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+    json!({"key": key, "value": {"stringValue": value}})
+}
+
+/// Map JSON value to OTLP/JSON `AnyValue`
+fn any_value(value: &Value) -> Value {
+    match value {
+        Value::Null => json!({}),
+        Value::Bool(b) => json!({ "boolValue": b }),
+        Value::Number(n) if n.is_f64() => json!({ "doubleValue": n }),
+        // 64 bit integers are encoded as strings.
+        Value::Number(n) => json!({ "intValue": n.to_string() }),
+        Value::String(s) => json!({ "stringValue": s }),
+        Value::Array(vs) => json!({
+            "arrayValue": {"values": vs.iter().map(any_value).collect::<Vec<_>>()}
+        }),
+        Value::Object(m) => json!({
+            "kvlistValue": {"values": m
+                .iter()
+                .map(|(k, v)| json!({"key": k, "value": any_value(v)}))
+                .collect::<Vec<_>>()}
+        }),
+    }
+}
+
+/// Convert the timestamp part of an event id to nanoseconds since
+/// the epoch
+fn timestamp_nanos(id: &str) -> Option<String> {
+    let (sec, rest) = id.split_once('.')?;
+    let (msec, _) = rest.split_once(':')?;
+    let sec: u64 = sec.parse().ok()?;
+    let msec: u64 = msec.parse().ok()?;
+    Some((sec * 1_000_000_000 + msec * 1_000_000).to_string())
+}
+
+fn hostname() -> Option<String> {
+    let name = nix::unistd::gethostname().ok()?;
+    Some(name.to_str()?.into())
+}
+
+/// Number of lines that are queued for sending
+const QUEUE_SIZE: usize = 10000;
+
+/// Maximum number of OTLP documents that are sent in one request
+const BATCH_MAX: usize = 100;
+
+/// Time that is spent sending queued lines on shutdown
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Lines that could not be sent by any [`HttpExporter`]
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the number of lines that have been dropped by
+/// [`HttpExporter`]s because the queue was full or because sending
+/// failed
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// OTLP/HTTP transport: Every complete line written is queued when
+/// flushed and sent as the body of a `POST` request by a background
+/// thread. If all lines that are waiting in the queue are OTLP
+/// documents, up to [`BATCH_MAX`] of them are merged into one
+/// request. The connection is kept open between requests if the
+/// server allows it. On connection errors, up to `retries` attempts
+/// are made, using an exponential backoff starting with 100ms.
+///
+/// Writing never fails. Lines that don't fit into the queue or that
+/// can't be sent are dropped and counted, see [`dropped`].
+pub struct HttpExporter {
+    buf: Vec<u8>,
+    queue: Option<SyncSender<Vec<u8>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl HttpExporter {
+    /// Create exporter for `http[s]://host[:port][/path]`, see
+    /// [`Client::new`].
+    pub fn new(url: &str, retries: u64) -> io::Result<Self> {
+        let mut client = Client::new(url, retries)?;
+        let (tx, rx) = sync_channel::<Vec<u8>>(QUEUE_SIZE);
+        let thread = std::thread::spawn(move || {
+            while let Ok(line) = rx.recv() {
+                let mut batch = vec![line];
+                while batch.len() < BATCH_MAX {
+                    match rx.try_recv() {
+                        Ok(line) => batch.push(line),
+                        Err(_) => break,
+                    }
+                }
+                match merge(&batch) {
+                    Some(body) if batch.len() > 1 => client.send(&body, batch.len() as u64),
+                    _ => batch.iter().for_each(|line| client.send(line, 1)),
+                }
+            }
+        });
+        Ok(HttpExporter {
+            buf: vec![],
+            queue: Some(tx),
+            thread: Some(thread),
+        })
+    }
+}
+
+/// Merges OTLP/JSON documents into one. Returns `None` if one of
+/// `lines` is not an OTLP document.
+fn merge(lines: &[Vec<u8>]) -> Option<Vec<u8>> {
+    let mut resource_logs = vec![];
+    for line in lines {
+        let mut doc: Value = serde_json::from_slice(line).ok()?;
+        match doc.get_mut("resourceLogs").map(Value::take) {
+            Some(Value::Array(rl)) => resource_logs.extend(rl),
+            _ => return None,
+        }
+    }
+    serde_json::to_vec(&json!({ "resourceLogs": resource_logs })).ok()
+}
+
+impl Write for HttpExporter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        while let Some(n) = self.buf.iter().position(|c| *c == b'\n') {
+            let mut line: Vec<u8> = self.buf.drain(..=n).collect();
+            line.pop();
+            let queued = self
+                .queue
+                .as_ref()
+                .is_some_and(|q| q.try_send(line).is_ok());
+            if !queued {
+                DROPPED.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for HttpExporter {
+    fn drop(&mut self) {
+        // Closing the queue lets the thread finish once it has sent
+        // the remaining lines.
+        self.queue = None;
+        let Some(thread) = self.thread.take() else {
+            return;
+        };
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+        while !thread.is_finished() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        if thread.is_finished() {
+            _ = thread.join();
+        } else {
+            log::warn!("Giving up sending remaining events");
+        }
+    }
+}
+
+/// Connection to an HTTP server, possibly using TLS
+enum Connection {
+    Plain(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<rustls::StreamOwned<rustls::ClientConnection, TcpStream>>),
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Plain(s) => s.read(buf),
+            #[cfg(feature = "tls")]
+            Connection::Tls(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Plain(s) => s.write(buf),
+            #[cfg(feature = "tls")]
+            Connection::Tls(s) => s.write(buf),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Plain(s) => s.flush(),
+            #[cfg(feature = "tls")]
+            Connection::Tls(s) => s.flush(),
+        }
+    }
+}
+
+/// Minimal HTTP/1.1 client that sends `POST` requests to one URL
+struct Client {
+    host: String,
+    path: String,
+    #[cfg(feature = "tls")]
+    tls: Option<(Arc<rustls::ClientConfig>, rustls::ServerName)>,
+    retries: u64,
+    stream: Option<BufReader<Connection>>,
+}
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+impl Client {
+    /// Create client for `http://host[:port][/path]` or, with the
+    /// `tls` feature, `https://host[:port][/path]`. The port defaults
+    /// to 4318, the path to `/v1/logs`. Server certificates are
+    /// verified using the system's trust store.
+    fn new(url: &str, retries: u64) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("invalid URL: {url}"));
+        let (rest, https) = match (url.strip_prefix("http://"), url.strip_prefix("https://")) {
+            (Some(rest), _) => (rest, false),
+            (_, Some(rest)) => (rest, true),
+            _ => return Err(invalid()),
+        };
+        let (host, path) = match rest.find('/') {
+            Some(n) => (&rest[..n], &rest[n..]),
+            None => (rest, "/v1/logs"),
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        let (name, host) = match host.rsplit_once(':') {
+            Some((name, port)) if !port.contains(']') => (name, host.to_string()),
+            _ => (host, format!("{host}:4318")),
+        };
+        #[cfg(feature = "tls")]
+        let tls = match https {
+            true => Some(tls_config(
+                name.trim_start_matches('[').trim_end_matches(']'),
+            )?),
+            false => None,
+        };
+        #[cfg(not(feature = "tls"))]
+        if https {
+            let _ = name;
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "https URLs are not supported in this build",
+            ));
+        }
+        Ok(Client {
+            host,
+            path: path.into(),
+            #[cfg(feature = "tls")]
+            tls,
+            retries,
+            stream: None,
+        })
+    }
+
+    fn connect(&mut self) -> io::Result<&mut BufReader<Connection>> {
+        if self.stream.is_none() {
+            let s = TcpStream::connect(&self.host)?;
+            s.set_read_timeout(Some(TIMEOUT))?;
+            s.set_write_timeout(Some(TIMEOUT))?;
+            #[cfg(feature = "tls")]
+            if let Some((config, name)) = &self.tls {
+                let conn = rustls::ClientConnection::new(Arc::clone(config), name.clone())
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                let s = Connection::Tls(Box::new(rustls::StreamOwned::new(conn, s)));
+                return Ok(self.stream.insert(BufReader::new(s)));
+            }
+            self.stream = Some(BufReader::new(Connection::Plain(s)));
+        }
+        Ok(self.stream.as_mut().unwrap())
+    }
+
+    /// Sends `body` that contains `n` events, counts them as dropped
+    /// on failure
+    fn send(&mut self, body: &[u8], n: u64) {
+        if let Err(e) = self.post(body) {
+            log::warn!("Could not send {n} events to {}: {e}", self.host);
+            DROPPED.fetch_add(n, Ordering::Relaxed);
+        }
+    }
+
+    fn post(&mut self, body: &[u8]) -> io::Result<()> {
+        let mut err = None;
+        for i in 0..=self.retries {
+            if i > 0 {
+                std::thread::sleep(Duration::from_millis(100 * (1 << (i - 1))));
+            }
+            match self.try_post(body) {
+                Ok((status, keep_alive)) => {
+                    if !keep_alive {
+                        self.stream = None;
+                    }
+                    if !(200..300).contains(&status) {
+                        return Err(io::Error::new(
+                            io::ErrorKind::Other,
+                            format!("HTTP status {status}"),
+                        ));
+                    }
+                    return Ok(());
+                }
+                Err(e) => {
+                    self.stream = None;
+                    err = Some(e);
+                }
+            }
+        }
+        Err(err.unwrap_or_else(|| io::Error::new(io::ErrorKind::Other, "post failed")))
+    }
+
+    /// Sends request, returns status code and whether the
+    /// connection can be reused
+    fn try_post(&mut self, body: &[u8]) -> io::Result<(u16, bool)> {
+        let header = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+            self.path,
+            self.host,
+            body.len()
+        );
+        let stream = self.connect()?;
+        stream.get_mut().write_all(header.as_bytes())?;
+        stream.get_mut().write_all(body)?;
+        read_response(stream)
+    }
+}
+
+/// Creates TLS configuration that verifies server certificates for
+/// `name` against the system's trust store
+#[cfg(feature = "tls")]
+fn tls_config(name: &str) -> io::Result<(Arc<rustls::ClientConfig>, rustls::ServerName)> {
+    let name = rustls::ServerName::try_from(name)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut roots = rustls::RootCertStore::empty();
+    for cert in rustls_native_certs::load_native_certs()? {
+        // Certificates that can't be parsed are skipped.
+        _ = roots.add(&rustls::Certificate(cert.0));
+    }
+    if roots.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no trusted CA certificates found",
+        ));
+    }
+    let config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok((Arc::new(config), name))
+}
+
+/// Read HTTP/1.1 response, discarding the body
+fn read_response<R: BufRead>(r: &mut R) -> io::Result<(u16, bool)> {
+    let malformed = || io::Error::new(io::ErrorKind::InvalidData, "malformed HTTP response");
+    let mut line = String::new();
+    r.read_line(&mut line)?;
+    let status: u16 = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(malformed)?;
+    let mut length = None;
+    let mut chunked = false;
+    let mut keep_alive = true;
+    loop {
+        line.clear();
+        if r.read_line(&mut line)? == 0 {
+            return Err(malformed());
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let Some((k, v)) = line.split_once(':') else {
+            continue;
+        };
+        let v = v.trim();
+        match k.to_ascii_lowercase().as_str() {
+            "content-length" => length = Some(v.parse::<u64>().map_err(|_| malformed())?),
+            "transfer-encoding" => chunked = v.eq_ignore_ascii_case("chunked"),
+            "connection" => keep_alive = !v.eq_ignore_ascii_case("close"),
+            _ => {}
+        }
+    }
+    if chunked {
+        loop {
+            line.clear();
+            r.read_line(&mut line)?;
+            let size = line.trim_end().split(';').next().unwrap_or_default();
+            let size = u64::from_str_radix(size, 16).map_err(|_| malformed())?;
+            // chunk data or trailers, followed by CRLF
+            if size == 0 {
+                loop {
+                    line.clear();
+                    if r.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+                        break;
+                    }
+                }
+                break;
+            }
+            io::copy(&mut r.take(size + 2), &mut io::sink())?;
+        }
+    } else if let Some(length) = length {
+        io::copy(&mut r.take(length), &mut io::sink())?;
+    } else {
+        // Body is delimited by end of connection.
+        keep_alive = false;
+    }
+    Ok((status, keep_alive))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn otlp() {
+        let event: Value =
+            serde_json::from_slice(include_bytes!("testdata/record-execve.json")).unwrap();
+        let mut buf = vec![];
+        Otlp::new().write_value(&mut buf, &event).unwrap();
+        let output: Value = serde_json::from_slice(&buf).unwrap();
+        let rl = &output["resourceLogs"][0];
+        assert!(rl["resource"]["attributes"]
+            .as_array()
+            .unwrap()
+            .contains(&attribute("audit.node", "work")));
+        let record = &rl["scopeLogs"][0]["logRecords"][0];
+        assert_eq!(record["timeUnixNano"], "1615114232375000000");
+        assert_eq!(
+            record["attributes"][0],
+            attribute("audit.event_id", "1615114232.375:15558")
+        );
+        let body = &record["body"]["kvlistValue"]["values"];
+        assert_eq!(body[0]["key"], "SYSCALL");
+        assert_eq!(
+            body[0]["value"]["kvlistValue"]["values"][1],
+            json!({"key": "syscall", "value": {"intValue": "59"}})
+        );
+        assert_eq!(
+            body[4]["value"],
+            json!({"kvlistValue": {"values": [
+                {"key": "ARGV", "value": {"arrayValue": {"values": [{"stringValue": "whoami"}]}}}
+            ]}})
+        );
+    }
+
+    #[test]
+    fn http_exporter() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (s, _) = listener.accept().unwrap();
+            let mut r = BufReader::new(s);
+            let mut bodies = vec![];
+            for _ in 0..2 {
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    r.read_line(&mut line).unwrap();
+                    if let Some(l) = line.strip_prefix("Content-Length: ") {
+                        length = l.trim().parse().unwrap();
+                    }
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                let mut body = vec![0; length];
+                r.read_exact(&mut body).unwrap();
+                bodies.push(String::from_utf8(body).unwrap());
+                r.get_mut()
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n{}")
+                    .unwrap();
+            }
+            bodies
+        });
+
+        let mut e = HttpExporter::new(&format!("http://{addr}"), 0).unwrap();
+        e.write_all(b"{\"a\":1}\n{\"b\"").unwrap();
+        e.flush().unwrap();
+        // Lines that are not OTLP documents are sent one by one.
+        e.write_all(b":2}\n").unwrap();
+        e.flush().unwrap();
+        drop(e);
+        assert_eq!(server.join().unwrap(), vec![r#"{"a":1}"#, r#"{"b":2}"#]);
+        assert_eq!(dropped(), 0);
+    }
+
+    #[test]
+    fn url() {
+        let c = Client::new("http://collector/otlp/v1/logs", 0).unwrap();
+        assert_eq!(c.host, "collector:4318");
+        assert_eq!(c.path, "/otlp/v1/logs");
+        let c = Client::new("http://[::1]:8080", 0).unwrap();
+        assert_eq!(c.host, "[::1]:8080");
+        assert!(Client::new("ftp://collector", 0).is_err());
+        assert!(Client::new("http:///v1/logs", 0).is_err());
+    }
+
+    #[test]
+    fn merge_batch() {
+        let lines = [
+            br#"{"resourceLogs":[{"a":1}]}"#.to_vec(),
+            br#"{"resourceLogs":[{"b":2}]}"#.to_vec(),
+        ];
+        assert_eq!(
+            merge(&lines).unwrap(),
+            br#"{"resourceLogs":[{"a":1},{"b":2}]}"#
+        );
+        assert!(merge(&[lines[0].clone(), br#"{"b":2}"#.to_vec()]).is_none());
+    }
+}
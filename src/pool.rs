@@ -0,0 +1,139 @@
+//! Bounded pool of worker threads for blocking enrichment tasks
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Flag that tells a running task that its result is no longer
+/// needed
+#[derive(Clone, Debug, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Fixed number of worker threads that take jobs from a queue of
+/// limited length
+pub struct WorkerPool {
+    queue: Option<SyncSender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    pub fn new(threads: usize, queue_len: usize) -> io::Result<Self> {
+        let (tx, rx) = mpsc::sync_channel::<Job>(queue_len);
+        let rx = Arc::new(Mutex::new(rx));
+        let mut workers = Vec::with_capacity(threads);
+        for n in 0..threads {
+            let rx = Arc::clone(&rx);
+            workers.push(
+                thread::Builder::new()
+                    .name(format!("laurel-worker-{n}"))
+                    .spawn(move || loop {
+                        let job = match rx.lock() {
+                            Ok(rx) => rx.recv(),
+                            Err(_) => return,
+                        };
+                        match job {
+                            Ok(job) => job(),
+                            Err(_) => return,
+                        }
+                    })?,
+            );
+        }
+        Ok(WorkerPool {
+            queue: Some(tx),
+            workers,
+        })
+    }
+
+    /// Queues `f` to be run by one of the workers. If the queue is
+    /// full, `None` is returned and the caller is expected to do the
+    /// work itself.
+    pub fn submit<T, F>(&self, f: F) -> Option<Task<T>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Cancel) -> T + Send + 'static,
+    {
+        let (tx, rx) = mpsc::sync_channel(1);
+        let cancel = Cancel::default();
+        let job_cancel = cancel.clone();
+        let job: Job = Box::new(move || {
+            if !job_cancel.is_cancelled() {
+                let _ = tx.send(f(&job_cancel));
+            }
+        });
+        self.queue.as_ref()?.try_send(job).ok()?;
+        Some(Task { rx, cancel })
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        self.queue.take();
+        for w in self.workers.drain(..) {
+            let _ = w.join();
+        }
+    }
+}
+
+/// Handle to the result of a job that has been submitted to a
+/// [`WorkerPool`]. Dropping it cancels the job.
+#[derive(Debug)]
+pub struct Task<T> {
+    rx: Receiver<T>,
+    cancel: Cancel,
+}
+
+impl<T> Task<T> {
+    /// Waits for the result, but not beyond `deadline`. If no result
+    /// is available by then, the job is cancelled.
+    pub fn wait(self, deadline: Option<Instant>) -> Option<T> {
+        match deadline {
+            None => self.rx.recv().ok(),
+            Some(t) => self
+                .rx
+                .recv_timeout(t.saturating_duration_since(Instant::now()))
+                .ok(),
+        }
+    }
+}
+
+impl<T> Drop for Task<T> {
+    fn drop(&mut self) {
+        self.cancel.0.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn pool() {
+        let pool = WorkerPool::new(2, 4).unwrap();
+        let tasks: Vec<_> = (0..4u32)
+            .map(|n| pool.submit(move |_| n * 2).expect("queue full"))
+            .collect();
+        let results: Vec<_> = tasks.into_iter().map(|t| t.wait(None)).collect();
+        assert_eq!(results, [Some(0), Some(2), Some(4), Some(6)]);
+
+        let task = pool
+            .submit(|cancel| {
+                while !cancel.is_cancelled() {
+                    thread::sleep(Duration::from_millis(1));
+                }
+            })
+            .unwrap();
+        assert_eq!(task.wait(Some(Instant::now())), None);
+    }
+}
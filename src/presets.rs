@@ -0,0 +1,144 @@
+//! Filter presets for well-known, benign, high-volume activity
+//!
+//! Presets are applied to raw SYSCALL lines, just like
+//! `filter-raw-lines`. Events that match a preset are filtered and
+//! counted per preset.
+//!
+//! Patterns only cover system directories that are owned by root, so
+//! that unprivileged users can't have their programs filtered by
+//! placing them in a matching location. Within `exe="…"`, wildcards
+//! must not extend beyond the closing quote.
+
+use regex::bytes::RegexSet;
+use serde::{Deserialize, Serialize};
+
+/// Start of a SYSCALL line, with optional node name
+const SYSCALL: &str = r#"^(?:node=\S+ )?type=SYSCALL msg=audit\([^)]*\): .*"#;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FilterPreset {
+    /// systemd service daemons: journald, logind, resolved, udevd etc.
+    QuietSystemd,
+    /// kubelet, container runtimes and their shims
+    KubernetesNode,
+    /// Compilers, assemblers, linkers and build tools
+    BuildServer,
+}
+
+impl FilterPreset {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FilterPreset::QuietSystemd => "quiet-systemd",
+            FilterPreset::KubernetesNode => "kubernetes-node",
+            FilterPreset::BuildServer => "build-server",
+        }
+    }
+
+    /// Patterns for the `exe` field of SYSCALL records
+    fn exe_patterns(&self) -> &'static [&'static str] {
+        match self {
+            FilterPreset::QuietSystemd => &[
+                r"/(?:usr/)?lib/systemd/systemd-(?:journald|logind|resolved|timesyncd|udevd|networkd|oomd|userdbd|userwork|homed)",
+                r"/(?:usr/)?s?bin/udevadm",
+            ],
+            FilterPreset::KubernetesNode => &[
+                r"/(?:usr/(?:local/)?)?bin/(?:kubelet|kube-proxy)",
+                r"/(?:usr/(?:local/)?)?bin/containerd(?:-shim(?:-runc-v[12])?)?",
+                r"/(?:usr/(?:local/)?)?bin/(?:crio|conmon)",
+            ],
+            FilterPreset::BuildServer => &[
+                r"/usr/(?:lib|libexec)/gcc/[^/]+/[^/]+/(?:cc1|cc1plus|collect2|lto1|lto-wrapper)",
+                r"/usr/(?:local/)?bin/(?:[\w-]+-linux-gnu-)?(?:as|ld|ld\.bfd|ld\.gold|ld\.lld|ar|ranlib|strip)",
+                r"/usr/(?:local/)?bin/(?:make|gmake|ninja|ccache|sccache|rustc|cargo)",
+                r#"/usr/local/rustup/toolchains/[^/"]+/(?:bin/(?:rustc|cargo)|lib/rustlib/[^"]*)"#,
+            ],
+        }
+    }
+
+    /// Returns the compiled regular expressions that are matched
+    /// against raw audit lines
+    pub fn regex_set(&self) -> RegexSet {
+        RegexSet::new(
+            self.exe_patterns()
+                .iter()
+                .map(|p| format!(r#"{SYSCALL} exe="{p}""#)),
+        )
+        .expect("preset patterns must compile")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn presets() {
+        for (preset, line, matched) in [
+            (
+                FilterPreset::QuietSystemd,
+                r#"type=SYSCALL msg=audit(1615114232.375:15558): arch=c000003e syscall=257 success=yes exit=3 a0=ffffff9c a1=55d3ee2c2a50 a2=80000 a3=0 items=1 ppid=1 pid=412 auid=4294967295 uid=0 gid=0 euid=0 suid=0 fsuid=0 egid=0 sgid=0 fsgid=0 tty=(none) ses=4294967295 comm="systemd-journal" exe="/usr/lib/systemd/systemd-journald" key=(null)"#,
+                true,
+            ),
+            (
+                FilterPreset::QuietSystemd,
+                r#"type=USER_START msg=audit(1615114232.375:15559): pid=1 uid=0 auid=1000 ses=1 msg='op=PAM:session_open acct="user" exe="/usr/lib/systemd/systemd-logind" hostname=? addr=? terminal=? res=success'"#,
+                false,
+            ),
+            (
+                FilterPreset::QuietSystemd,
+                r#"node=work type=SYSCALL msg=audit(1615114232.375:15560): arch=c000003e syscall=59 success=yes exit=0 items=2 ppid=1 pid=500 auid=4294967295 uid=0 comm="systemd" exe="/usr/lib/systemd/systemd" key=(null)"#,
+                false,
+            ),
+            (
+                FilterPreset::KubernetesNode,
+                r#"node=k8s-1 type=SYSCALL msg=audit(1615114232.375:15561): arch=c000003e syscall=59 success=yes exit=0 items=2 ppid=1 pid=900 auid=4294967295 uid=0 comm="containerd-shim" exe="/usr/bin/containerd-shim-runc-v2" key=(null)"#,
+                true,
+            ),
+            (
+                FilterPreset::KubernetesNode,
+                r#"type=SYSCALL msg=audit(1615114232.375:15562): arch=c000003e syscall=59 success=yes exit=0 items=2 ppid=900 pid=901 auid=4294967295 uid=0 comm="runc" exe="/usr/bin/runc" key=(null)"#,
+                false,
+            ),
+            // The path of the pause binary is chosen by the image.
+            (
+                FilterPreset::KubernetesNode,
+                r#"type=SYSCALL msg=audit(1615114232.375:15568): arch=c000003e syscall=59 success=yes exit=0 items=2 ppid=900 pid=902 auid=4294967295 uid=0 comm="pause" exe="/pause" key=(null)"#,
+                false,
+            ),
+            (
+                FilterPreset::BuildServer,
+                r#"type=SYSCALL msg=audit(1615114232.375:15563): arch=c000003e syscall=59 success=yes exit=0 items=2 ppid=20 pid=21 auid=1000 uid=1000 comm="cc1" exe="/usr/lib/gcc/x86_64-linux-gnu/12/cc1" key=(null)"#,
+                true,
+            ),
+            (
+                FilterPreset::BuildServer,
+                r#"type=SYSCALL msg=audit(1615114232.375:15564): arch=c000003e syscall=59 success=yes exit=0 items=2 ppid=20 pid=22 auid=1000 uid=1000 comm="rustc" exe="/usr/local/rustup/toolchains/stable-x86_64-unknown-linux-gnu/bin/rustc" key=(null)"#,
+                true,
+            ),
+            // Home directories are writable by their owners.
+            (
+                FilterPreset::BuildServer,
+                r#"type=SYSCALL msg=audit(1615114232.375:15566): arch=c000003e syscall=59 success=yes exit=0 items=2 ppid=20 pid=24 auid=1000 uid=1000 comm="rustc" exe="/home/user/.rustup/toolchains/stable-x86_64-unknown-linux-gnu/bin/rustc" key=(null)"#,
+                false,
+            ),
+            (
+                FilterPreset::BuildServer,
+                r#"type=SYSCALL msg=audit(1615114232.375:15567): arch=c000003e syscall=59 success=yes exit=0 items=2 ppid=20 pid=25 auid=1000 uid=1000 comm="x" exe="/tmp/x/.rustup/toolchains/a/bin/rustc" key=(null)"#,
+                false,
+            ),
+            (
+                FilterPreset::BuildServer,
+                r#"type=SYSCALL msg=audit(1615114232.375:15565): arch=c000003e syscall=59 success=yes exit=0 items=2 ppid=20 pid=23 auid=1000 uid=1000 comm="bash" exe="/usr/bin/bash" key=(null)"#,
+                false,
+            ),
+        ] {
+            assert_eq!(
+                preset.regex_set().is_match(line.as_bytes()),
+                matched,
+                "{}: {line}",
+                preset.name()
+            );
+        }
+    }
+}
@@ -1,10 +1,9 @@
 use std::cmp::Ordering;
-#[cfg(all(feature = "procfs", target_os = "linux", not(test)))]
-use std::collections::BTreeSet;
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt::{self, Display};
 use std::iter::Iterator;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::vec::Vec;
 
 #[cfg(all(feature = "procfs", target_os = "linux"))]
@@ -14,6 +13,8 @@ use serde::{Deserialize, Serialize};
 
 use serde_with::{DeserializeFromStr, SerializeDisplay};
 
+use sha2::{Digest, Sha256};
+
 use thiserror::Error;
 
 use crate::label_matcher::LabelMatcher;
@@ -29,8 +30,12 @@ pub struct ContainerInfo {
     pub id: Vec<u8>,
 }
 
+/// Number of ancestors that are fetched from procfs
+#[cfg(all(feature = "procfs", target_os = "linux"))]
+const MAX_LINEAGE_DEPTH: usize = 64;
+
 /// Host-unique identifier for processes
-#[derive(Clone, Copy, Debug, PartialEq, Eq, DeserializeFromStr, SerializeDisplay)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, DeserializeFromStr, SerializeDisplay)]
 pub enum ProcessKey {
     Event(EventID),
     Observed { time: u64, pid: u32 },
@@ -81,6 +86,42 @@ impl FromStr for ProcessKey {
     }
 }
 
+impl ProcessKey {
+    /// Time at which the process was first seen, in milliseconds
+    /// since the epoch. For event-based keys, this is the time of
+    /// the execve call, the process may have been started earlier.
+    pub fn time(&self) -> u64 {
+        match self {
+            ProcessKey::Event(id) => id.timestamp,
+            ProcessKey::Observed { time, pid: _ } => *time,
+        }
+    }
+
+    /// Returns a UUID that identifies the process across hosts,
+    /// derived from the node name and the process key. The UUID uses
+    /// the custom format (version 8) of RFC 9562.
+    pub fn entity_id(&self, node: &[u8]) -> String {
+        let hash = Sha256::new()
+            .chain_update(node)
+            .chain_update([0])
+            .chain_update(self.to_string())
+            .finalize();
+        let mut b = [0u8; 16];
+        b.copy_from_slice(&hash[..16]);
+        b[6] = (b[6] & 0x0f) | 0x80;
+        b[8] = (b[8] & 0x3f) | 0x80;
+        let h = faster_hex::hex_string(&b);
+        format!(
+            "{}-{}-{}-{}-{}",
+            &h[..8],
+            &h[8..12],
+            &h[12..16],
+            &h[16..20],
+            &h[20..]
+        )
+    }
+}
+
 impl Default for ProcessKey {
     fn default() -> Self {
         ProcessKey::Observed { time: 0, pid: 0 }
@@ -113,6 +154,54 @@ impl PartialOrd for ProcessKey {
     }
 }
 
+/// Byte string that may be shared between processes, e.g. the same
+/// executable path
+pub type SharedBytes = Arc<[u8]>;
+
+mod shared_bytes {
+    use super::SharedBytes;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &Option<SharedBytes>, s: S) -> Result<S::Ok, S::Error> {
+        serde_bytes::serialize(&value.as_deref(), s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<SharedBytes>, D::Error> {
+        Ok(Option::<serde_bytes::ByteBuf>::deserialize(d)?.map(|b| b.into_vec().into()))
+    }
+}
+
+/// Set of byte strings that are shared between entries of a
+/// [`ProcTable`]
+#[derive(Debug, Default, Clone)]
+pub struct Interner(HashSet<SharedBytes>);
+
+impl Interner {
+    /// Returns the shared copy of `s`, adding `s` if necessary.
+    pub fn intern(&mut self, s: &SharedBytes) -> SharedBytes {
+        match self.0.get(s) {
+            Some(shared) => shared.clone(),
+            None => {
+                self.0.insert(s.clone());
+                s.clone()
+            }
+        }
+    }
+
+    /// Removes strings that are not referenced outside the interner.
+    pub fn collect(&mut self) {
+        self.0.retain(|s| Arc::strong_count(s) > 1);
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Process {
     /// "primary key", unique per host
@@ -124,17 +213,29 @@ pub struct Process {
     /// parent's porocess ID
     pub ppid: u32,
     /// path to binary
-    #[serde(with = "serde_bytes")]
-    pub exe: Option<Vec<u8>>,
+    #[serde(with = "shared_bytes")]
+    pub exe: Option<SharedBytes>,
     /// process-settable argv[0]
-    #[serde(with = "serde_bytes")]
-    pub comm: Option<Vec<u8>>,
+    #[serde(with = "shared_bytes")]
+    pub comm: Option<SharedBytes>,
     /// Labels assigned to process
     pub labels: HashSet<Vec<u8>>,
     #[cfg(all(feature = "procfs", target_os = "linux"))]
     pub container_info: Option<ContainerInfo>,
     #[cfg(all(feature = "procfs", target_os = "linux"))]
     pub systemd_service: Option<Vec<Vec<u8>>>,
+    /// Innermost systemd unit (service or scope)
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    #[serde(default, with = "serde_bytes")]
+    pub systemd_unit: Option<Vec<u8>>,
+    /// Process ID in the innermost pid namespace, if the process is
+    /// not in laurel's pid namespace
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    #[serde(default)]
+    pub ns_pid: Option<procfs::NsPid>,
+    /// Lineage hash, see [`ProcTable::lineage`]. Set on insert.
+    #[serde(default)]
+    pub lineage: Option<[u8; 32]>,
 }
 
 #[cfg(all(feature = "procfs", target_os = "linux"))]
@@ -149,14 +250,17 @@ impl From<procfs::ProcPidInfo> for Process {
             pid: p.pid,
             ppid: p.ppid,
             labels: HashSet::new(),
-            exe: p.exe,
-            comm: Some(p.comm),
+            exe: p.exe.map(SharedBytes::from),
+            comm: Some(p.comm.into()),
             container_info: p
                 .cgroup
                 .as_deref()
                 .and_then(try_extract_container_id)
                 .map(|id| ContainerInfo { id }),
             systemd_service: p.cgroup.as_deref().and_then(try_extract_systemd_service),
+            systemd_unit: p.cgroup.as_deref().and_then(try_extract_systemd_unit),
+            ns_pid: None,
+            lineage: None,
         }
     }
 }
@@ -204,16 +308,64 @@ pub(crate) fn try_extract_systemd_service(path: &[u8]) -> Option<Vec<Vec<u8>>> {
     }
 }
 
-impl Process {
-    /// Generate a shadow process table entry from /proc/$PID for a given PID
-    #[cfg(all(feature = "procfs", target_os = "linux"))]
-    pub fn parse_proc(pid: u32) -> Result<Process, ProcError> {
-        procfs::parse_proc_pid(pid)
-            .map(|p| p.into())
-            .map_err(ProcError::ProcFSError)
-    }
+/// Try to extract the innermost "something.service" or
+/// "something.scope" fragment from cgroup path
+#[cfg(all(feature = "procfs", target_os = "linux"))]
+pub(crate) fn try_extract_systemd_unit(path: &[u8]) -> Option<Vec<u8>> {
+    path.rsplit(|&c| c == b'/')
+        .find(|f| f.ends_with(b".service") || f.ends_with(b".scope"))
+        .map(Vec::from)
 }
 
+/// Maximum number of threads used for the initial scan of /proc
+#[cfg(all(feature = "procfs", target_os = "linux"))]
+const SCAN_THREADS_MAX: usize = 8;
+
+/// Generate shadow process table entries for `pids` from `source`,
+/// distributing the work across several threads. Namespace pids are
+/// only read if `ns_pid` is set.
+#[cfg(all(feature = "procfs", target_os = "linux"))]
+fn scan_pids(source: &dyn procfs::ProcSource, pids: &[u32], ns_pid: bool) -> Vec<Process> {
+    let threads = std::thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(SCAN_THREADS_MAX);
+    let chunk_size = ((pids.len() + threads - 1) / threads).max(1);
+    std::thread::scope(|s| {
+        let workers: Vec<_> = pids
+            .chunks(chunk_size)
+            .map(|chunk| {
+                s.spawn(move || {
+                    // /proc/<pid> access is racy. Ignore errors here.
+                    chunk
+                        .iter()
+                        .filter_map(|&pid| {
+                            let mut proc = Process::from(source.pid_info(pid).ok()?);
+                            if ns_pid {
+                                proc.ns_pid = source.pid_nspid(pid).ok().flatten();
+                            }
+                            Some(proc)
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|w| w.join().unwrap_or_default())
+            .collect()
+    })
+}
+
+/// Tolerance in milliseconds when comparing process start times
+/// derived from /proc with event timestamps
+pub(crate) const START_TIME_SLACK: u64 = 100;
+
+/// Interval in milliseconds (event time) during which a process
+/// table entry whose start time has been checked against /proc is
+/// trusted without reading /proc/<pid>/stat again
+#[cfg(all(feature = "procfs", target_os = "linux"))]
+const START_TIME_CHECK_INTERVAL: u64 = 1_000;
+
 #[derive(Debug, Error)]
 pub enum ProcError {
     #[cfg(all(feature = "procfs", target_os = "linux"))]
@@ -221,14 +373,59 @@ pub enum ProcError {
     ProcFSError(procfs::ProcFSError),
 }
 
+impl ProcError {
+    /// Returns a short identifier for the class of error
+    pub fn code(&self) -> &'static str {
+        match *self {
+            #[cfg(all(feature = "procfs", target_os = "linux"))]
+            ProcError::ProcFSError(ref e) => e.code(),
+        }
+    }
+
+    /// Returns the process ID that the error refers to, if any
+    pub fn pid(&self) -> Option<u32> {
+        match *self {
+            #[cfg(all(feature = "procfs", target_os = "linux"))]
+            ProcError::ProcFSError(ref e) => e.pid(),
+        }
+    }
+}
+
 /// Shadow process table
 ///
 /// This process table replica can be fed with EXECVE-based events or
 /// from /proc entries.
+///
+/// The table is owned by the thread that coalesces events; enrichment
+/// workers (see [`crate::pool`]) only receive copies of the data they
+/// need. Since lookups and label updates never run concurrently, no
+/// locking is done here.
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct ProcTable {
     pub processes: BTreeMap<ProcessKey, Process>,
     pub current: BTreeMap<u32, ProcessKey>,
+    /// Processes are running on a remote node, /proc is not
+    /// consulted.
+    #[serde(default)]
+    pub remote: bool,
+    /// Executable paths and comm values shared by processes
+    #[serde(skip)]
+    pub strings: Interner,
+    /// Processes that have been reported as exited, but that have
+    /// not been removed yet
+    #[serde(skip)]
+    pub exited: HashSet<u32>,
+    /// Table entries whose start time has been checked against
+    /// /proc, and the latest event time for which this was done
+    #[serde(skip)]
+    pub starttime_checked: HashMap<u32, (ProcessKey, u64)>,
+    /// Source of process information; /proc if unset
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    #[serde(skip)]
+    pub source: Option<Arc<dyn procfs::ProcSource>>,
+    /// Retrieve process IDs from the innermost pid namespace
+    #[serde(skip)]
+    pub ns_pid: bool,
 }
 
 impl ProcTable {
@@ -236,36 +433,52 @@ impl ProcTable {
     ///
     /// If label_exe and propagate_labels are supplied, Process labels
     /// based on executable are applied and propagated to children.
+    /// If ns_pid is set, process IDs from the innermost pid namespace
+    /// are retrieved.
     pub fn from_proc(
         label_exe: Option<LabelMatcher>,
         propagate_labels: &HashSet<Vec<u8>>,
+        ns_pid: bool,
     ) -> Result<ProcTable, ProcError> {
         let mut pt = ProcTable {
-            processes: BTreeMap::new(),
-            current: BTreeMap::new(),
+            ns_pid,
+            ..ProcTable::default()
         };
+        pt.populate(label_exe, propagate_labels)?;
+        Ok(pt)
+    }
 
+    /// Adds entries for all processes from the table's source, see
+    /// [`ProcTable::from_proc`]
+    pub fn populate(
+        &mut self,
+        label_exe: Option<LabelMatcher>,
+        propagate_labels: &HashSet<Vec<u8>>,
+    ) -> Result<(), ProcError> {
         #[cfg(all(feature = "procfs", target_os = "linux"))]
         {
-            for pid in procfs::get_pids().map_err(ProcError::ProcFSError)? {
-                // /proc/<pid> access is racy. Ignore errors here.
-                if let Ok(mut proc) = Process::parse_proc(pid) {
-                    if let (Some(label_exe), Some(exe)) = (&label_exe, &proc.exe) {
-                        proc.labels.extend(label_exe.matches(exe).map(Vec::from));
-                    }
-                    pt.insert(proc);
+            let pids = self.source().pids().map_err(ProcError::ProcFSError)?;
+            for mut proc in scan_pids(self.source(), &pids, self.ns_pid) {
+                if let (Some(label_exe), Some(exe)) = (&label_exe, &proc.exe) {
+                    proc.labels.extend(label_exe.matches(exe).map(Vec::from));
                 }
+                self.insert(proc);
             }
             // build parent/child relationships
-            for proc in pt.processes.values_mut() {
+            for proc in self.processes.values_mut() {
                 if proc.parent.is_none() {
-                    proc.parent = pt.current.get(&proc.pid).cloned();
+                    proc.parent = self.current.get(&proc.ppid).cloned();
                 }
             }
+            // Parents are started before their children.
+            let keys: Vec<_> = self.processes.keys().cloned().collect();
+            for key in &keys {
+                self.update_lineage(key);
+            }
         }
 
         if let Some(label_exe) = &label_exe {
-            for proc in pt.processes.values_mut() {
+            for proc in self.processes.values_mut() {
                 if let Some(exe) = &proc.exe {
                     proc.labels.extend(label_exe.matches(exe).map(Vec::from))
                 }
@@ -273,13 +486,76 @@ impl ProcTable {
             if !propagate_labels.is_empty() { /* TODO */ }
         }
 
-        Ok(pt)
+        Ok(())
+    }
+
+    /// Constructs empty process table for a remote node
+    pub fn remote() -> ProcTable {
+        ProcTable {
+            remote: true,
+            ..ProcTable::default()
+        }
     }
 
-    pub fn insert(&mut self, proc: Process) {
+    /// Constructs an empty process table that retrieves process
+    /// information from `source` instead of /proc
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    pub fn with_source(source: Arc<dyn procfs::ProcSource>) -> ProcTable {
+        ProcTable {
+            source: Some(source),
+            ..ProcTable::default()
+        }
+    }
+
+    /// Returns the source of process information
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    pub fn source(&self) -> &dyn procfs::ProcSource {
+        self.source.as_deref().unwrap_or(&procfs::LiveProcSource)
+    }
+
+    pub fn insert(&mut self, mut proc: Process) {
+        self.intern(&mut proc);
         let (pid, key) = (proc.pid, proc.key);
         self.processes.insert(proc.key, proc);
         self.current.insert(pid, key);
+        self.update_lineage(&key);
+    }
+
+    /// Derives the lineage hash of the process from the parent's
+    /// stored lineage hash
+    fn update_lineage(&mut self, key: &ProcessKey) {
+        let Some(proc) = self.processes.get(key) else {
+            return;
+        };
+        let parent = proc
+            .parent
+            .and_then(|k| self.processes.get(&k))
+            .and_then(|p| p.lineage);
+        let mut h = Sha256::new();
+        h.update(parent.unwrap_or_default());
+        h.update(proc.key.time().to_be_bytes());
+        h.update(proc.exe.as_deref().unwrap_or_default());
+        let lineage = h.finalize().into();
+        if let Some(proc) = self.processes.get_mut(key) {
+            proc.lineage = Some(lineage);
+        }
+    }
+
+    fn intern(&mut self, proc: &mut Process) {
+        if let Some(exe) = &mut proc.exe {
+            *exe = self.strings.intern(exe);
+        }
+        if let Some(comm) = &mut proc.comm {
+            *comm = self.strings.intern(comm);
+        }
+    }
+
+    /// Replaces strings in all processes by shared copies, e.g.
+    /// after the process table has been deserialized.
+    pub fn intern_all(&mut self) {
+        let mut processes = std::mem::take(&mut self.processes);
+        processes.values_mut().for_each(|p| self.intern(p));
+        self.processes = processes;
     }
 
     /// Retrieves a process by key.
@@ -292,6 +568,17 @@ impl ProcTable {
         self.processes.get_mut(key)
     }
 
+    /// Returns the lineage hash for the process: SHA-256 over the
+    /// parent's lineage hash, start time, and exe. Processes whose
+    /// parent is unknown start a new lineage. Unlike process keys,
+    /// lineage hashes identify the whole process tree instance.
+    ///
+    /// The hash is computed when the process is inserted or linked
+    /// to its parent.
+    pub fn lineage(&self, key: &ProcessKey) -> Option<[u8; 32]> {
+        self.get_key(key)?.lineage
+    }
+
     /// Retrieves a process by pid.
     pub fn get_pid(&self, pid: u32) -> Option<&Process> {
         self.current.get(&pid).and_then(|pk| self.get_key(pk))
@@ -302,12 +589,63 @@ impl ProcTable {
     /// information from another source, i.e. /proc.
     pub fn get_or_retrieve(&mut self, pid: u32) -> Option<&Process> {
         #[cfg(all(feature = "procfs", target_os = "linux"))]
-        if self.get_pid(pid).is_none() {
+        if !self.remote && self.get_pid(pid).is_none() {
             self.insert_from_procfs(pid);
         }
         self.get_pid(pid)
     }
 
+    /// Retrieves the process that was running as `pid` at `time`
+    /// (milliseconds since the epoch).
+    ///
+    /// Unlike [`ProcTable::get_or_retrieve`], the start time of the
+    /// process that is currently running as `pid` is checked against
+    /// the shadow process table entry and `time`, so that a process
+    /// that has reused the pid is not mistaken for an earlier one.
+    /// The second element of the result is set if pid reuse has been
+    /// detected, but it could not be determined which process was
+    /// meant.
+    pub fn get_or_retrieve_at(&mut self, pid: u32, time: u64) -> (Option<&Process>, bool) {
+        let known = self.current.get(&pid).map(ProcessKey::time);
+        // The table entry belongs to a process that was seen
+        // after `time`.
+        #[cfg_attr(not(all(feature = "procfs", target_os = "linux")), allow(unused_mut))]
+        let mut reused = known.is_some_and(|t| t > time + START_TIME_SLACK);
+        #[cfg(all(feature = "procfs", target_os = "linux"))]
+        if !self.remote && !reused && !self.starttime_recently_checked(pid, time) {
+            match (known, self.source().pid_starttime(pid)) {
+                // Still the same process
+                (Some(known), Ok(start)) if start <= known + START_TIME_SLACK => {
+                    self.starttime_checked
+                        .insert(pid, (self.current[&pid], time));
+                }
+                // A process we have not seen yet that was already
+                // running at `time`
+                (_, Ok(start)) if start <= time + START_TIME_SLACK => {
+                    reused = self.insert_from_procfs(pid).is_none();
+                }
+                // The running process was started after `time` and
+                // the process that was meant is unknown.
+                (None, Ok(_)) => reused = true,
+                _ => {}
+            }
+        }
+        (self.get_pid(pid), reused)
+    }
+
+    /// Returns whether the start time of the table entry for `pid`
+    /// has been checked against /proc within
+    /// [`START_TIME_CHECK_INTERVAL`] before `time`
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    fn starttime_recently_checked(&self, pid: u32, time: u64) -> bool {
+        match (self.current.get(&pid), self.starttime_checked.get(&pid)) {
+            (Some(key), Some((checked_key, checked))) => {
+                key == checked_key && time <= checked + START_TIME_CHECK_INTERVAL
+            }
+            _ => false,
+        }
+    }
+
     pub fn keys(&self) -> std::collections::btree_map::Keys<'_, ProcessKey, Process> {
         self.processes.keys()
     }
@@ -316,7 +654,8 @@ impl ProcTable {
     /// process table.
     #[cfg(all(feature = "procfs", target_os = "linux"))]
     pub fn insert_from_procfs(&mut self, pid: u32) -> Option<&Process> {
-        if let Ok(p) = Process::parse_proc(pid) {
+        if let Ok(mut p) = self.source().pid_info(pid).map(Process::from) {
+            p.ns_pid = self.retrieve_ns_pid(pid);
             let key = p.key;
             self.insert(p);
             self.processes.get(&key)
@@ -325,6 +664,54 @@ impl ProcTable {
         }
     }
 
+    /// Fetch the process ID of `pid` within its innermost pid
+    /// namespace if that has been requested
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    pub fn retrieve_ns_pid(&self, pid: u32) -> Option<procfs::NsPid> {
+        if self.remote || !self.ns_pid {
+            return None;
+        }
+        self.source().pid_nspid(pid).ok().flatten()
+    }
+
+    /// Links the process to its ancestors, fetching ancestors that
+    /// have not been seen in audit events from procfs. Without this,
+    /// parent chains end at the first process that has not been
+    /// seen, e.g. if there are no audit rules for execve.
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    pub fn link_ancestors(&mut self, key: &ProcessKey) {
+        if self.remote {
+            return;
+        }
+        let mut key = *key;
+        // Processes that have been linked to their parents, their
+        // lineage hashes are updated starting with the topmost one.
+        let mut linked = vec![];
+        for _ in 0..MAX_LINEAGE_DEPTH {
+            let Some(proc) = self.processes.get(&key) else {
+                break;
+            };
+            if proc.parent.is_some() || proc.ppid == 0 {
+                break;
+            }
+            let (ppid, time) = (proc.ppid, proc.key.time());
+            // If the ppid has been reused, the table entry belongs to
+            // a different process.
+            let (Some(parent), false) = self.get_or_retrieve_at(ppid, time) else {
+                break;
+            };
+            let parent = parent.key;
+            if let Some(proc) = self.processes.get_mut(&key) {
+                proc.parent = Some(parent);
+            }
+            linked.push(key);
+            key = parent;
+        }
+        for key in linked.iter().rev() {
+            self.update_lineage(key);
+        }
+    }
+
     /// Remove processes that are no longer running and that were not
     /// parents of currently running processes.
     ///
@@ -332,13 +719,48 @@ impl ProcTable {
     /// incurring load.
     #[cfg(all(feature = "procfs", target_os = "linux", not(test)))]
     pub fn expire(&mut self) {
-        let mut proc_prune: BTreeSet<ProcessKey> = self.processes.keys().cloned().collect();
-        let mut pid_prune: Vec<u32> = vec![];
-
-        let live_processes = match procfs::get_pids() {
+        let live_processes = match self.source().pids() {
             Ok(p) => p,
             Err(_) => return,
         };
+        self.exited.clear();
+        self.prune(live_processes);
+    }
+
+    /// Record that a process has exited. It is removed on the next
+    /// call to [`ProcTable::expire_exited`].
+    pub fn exit(&mut self, pid: u32) {
+        self.exited.insert(pid);
+    }
+
+    /// Like [`ProcTable::expire`], but only processes that have been
+    /// reported using [`ProcTable::exit`] are considered gone; /proc
+    /// is not consulted.
+    pub fn expire_exited(&mut self) {
+        if self.exited.is_empty() {
+            return;
+        }
+        let exited = std::mem::take(&mut self.exited);
+        let live_processes: Vec<u32> = self
+            .current
+            .keys()
+            .filter(|pid| !exited.contains(pid))
+            .cloned()
+            .collect();
+        self.prune(live_processes);
+        // Parents of running processes have been kept.
+        self.exited = exited
+            .into_iter()
+            .filter(|pid| self.current.contains_key(pid))
+            .collect();
+    }
+
+    /// Remove processes that are not in `live_processes` and that are
+    /// not parents of processes in `live_processes`.
+    fn prune(&mut self, live_processes: impl IntoIterator<Item = u32>) {
+        let mut proc_prune: BTreeSet<ProcessKey> = self.processes.keys().cloned().collect();
+        let mut pid_prune: Vec<u32> = vec![];
+
         // unmark latest instance in by_pids and all its parents
         for seed_pid in live_processes {
             let mut key = match self.current.get(&seed_pid) {
@@ -375,7 +797,26 @@ impl ProcTable {
         }
         for pid in pid_prune {
             self.current.remove(&pid);
+            self.starttime_checked.remove(&pid);
+        }
+        if !proc_prune.is_empty() {
+            self.strings.collect();
+        }
+    }
+
+    /// Remove the oldest processes until at most max_entries are
+    /// left. This is used where the set of running processes is not
+    /// known, e.g. for remote nodes.
+    pub fn truncate(&mut self, max_entries: usize) {
+        while self.processes.len() > max_entries {
+            let Some((key, proc)) = self.processes.pop_first() else {
+                break;
+            };
+            if self.current.get(&proc.pid) == Some(&key) {
+                self.current.remove(&proc.pid);
+            }
         }
+        self.strings.collect();
     }
 
     /// No expire mechanism has been implemented for the case where
@@ -390,13 +831,292 @@ mod tests {
     use std::error::Error;
     #[test]
     fn show_processes() -> Result<(), Box<dyn Error>> {
-        let pt = ProcTable::from_proc(None, &HashSet::new())?;
+        let pt = ProcTable::from_proc(None, &HashSet::new(), false)?;
         for p in pt.processes {
             println!("{:?}", &p);
         }
         Ok(())
     }
 
+    #[test]
+    fn truncate() {
+        let mut pt = ProcTable::remote();
+        for (seq, pid) in [(1, 100), (2, 101), (3, 100), (4, 102)] {
+            pt.insert(Process {
+                key: ProcessKey::Event(EventID {
+                    timestamp: 1700000000000,
+                    sequence: seq,
+                }),
+                pid,
+                ..Process::default()
+            });
+        }
+        pt.truncate(2);
+        assert_eq!(pt.processes.len(), 2);
+        assert!(pt.get_pid(100).is_some());
+        assert!(pt.get_pid(101).is_none());
+        assert!(pt.get_pid(102).is_some());
+    }
+
+    #[test]
+    fn entity_id() {
+        let key = ProcessKey::Event(EventID {
+            timestamp: 1700000000000,
+            sequence: 1,
+        });
+        let id = key.entity_id(b"work");
+        assert_eq!(id.len(), 36);
+        assert_eq!(&id[14..15], "8", "version: {id}");
+        assert!("89ab".contains(&id[19..20]), "variant: {id}");
+        assert_eq!(id, key.entity_id(b"work"));
+        assert_ne!(id, key.entity_id(b"other"));
+        let observed = ProcessKey::Observed {
+            time: 1700000000000,
+            pid: 1,
+        };
+        assert_ne!(id, observed.entity_id(b"work"));
+    }
+
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    #[test]
+    fn link_ancestors() {
+        let mut pt = ProcTable::default();
+        let key = pt.insert_from_procfs(std::process::id()).unwrap().key;
+        assert!(pt.get_key(&key).unwrap().parent.is_none());
+        pt.link_ancestors(&key);
+        let proc = pt.get_key(&key).unwrap();
+        let parent = pt.get_key(&proc.parent.unwrap()).unwrap();
+        assert_eq!(parent.pid, proc.ppid);
+        if parent.ppid != 0 {
+            assert!(parent.parent.is_some());
+        }
+    }
+
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    #[derive(Debug)]
+    struct SyntheticProcs(Vec<procfs::ProcPidInfo>);
+
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    impl procfs::ProcSource for SyntheticProcs {
+        fn pids(&self) -> Result<Vec<u32>, procfs::ProcFSError> {
+            Ok(self.0.iter().map(|p| p.pid).collect())
+        }
+        fn pid_info(&self, pid: u32) -> Result<procfs::ProcPidInfo, procfs::ProcFSError> {
+            self.0
+                .iter()
+                .find(|p| p.pid == pid)
+                .cloned()
+                .ok_or(procfs::ProcFSError::Field("pid"))
+        }
+        fn pid_starttime(&self, pid: u32) -> Result<u64, procfs::ProcFSError> {
+            self.pid_info(pid).map(|p| p.starttime)
+        }
+        fn pid_nspid(&self, _: u32) -> Result<Option<procfs::NsPid>, procfs::ProcFSError> {
+            Ok(None)
+        }
+    }
+
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    #[test]
+    fn synthetic_source() {
+        let procs = [
+            (1, 0, 1000, "/usr/lib/systemd/systemd"),
+            (100, 1, 2000, "/usr/sbin/sshd"),
+            (200, 100, 3000, "/usr/bin/bash"),
+        ]
+        .map(|(pid, ppid, starttime, exe)| procfs::ProcPidInfo {
+            pid,
+            ppid,
+            starttime,
+            exe: Some(exe.into()),
+            ..Default::default()
+        });
+        let mut pt = ProcTable::with_source(Arc::new(SyntheticProcs(procs.to_vec())));
+
+        let key = pt.get_or_retrieve(200).unwrap().key;
+        pt.link_ancestors(&key);
+        let mut pids = vec![];
+        let mut next = Some(key);
+        while let Some(key) = next {
+            let proc = pt.get_key(&key).unwrap();
+            pids.push(proc.pid);
+            next = proc.parent;
+        }
+        assert_eq!(pids, [200, 100, 1]);
+        // Lineage hashes have been updated after linking.
+        let lineage = procs.iter().fold([0u8; 32], |hash, p| {
+            Sha256::new()
+                .chain_update(hash)
+                .chain_update(p.starttime.to_be_bytes())
+                .chain_update(p.exe.as_deref().unwrap())
+                .finalize()
+                .into()
+        });
+        assert_eq!(pt.lineage(&key), Some(lineage));
+
+        // A process that was started later has reused the pid.
+        assert!(pt.get_or_retrieve_at(100, 1500).1);
+        assert!(pt.get_or_retrieve(300).is_none());
+
+        // The parent of a process that has been started before the
+        // process that is now running as ppid is not linked.
+        pt.insert(Process {
+            key: ProcessKey::Observed {
+                time: 1500,
+                pid: 150,
+            },
+            pid: 150,
+            ppid: 100,
+            ..Process::default()
+        });
+        let key = ProcessKey::Observed {
+            time: 1500,
+            pid: 150,
+        };
+        pt.link_ancestors(&key);
+        assert!(pt.get_key(&key).unwrap().parent.is_none());
+    }
+
+    #[test]
+    fn lineage() {
+        let key = |sequence| {
+            ProcessKey::Event(EventID {
+                timestamp: 1700000000000,
+                sequence,
+            })
+        };
+        let mut pt = ProcTable::remote();
+        for (seq, parent, exe) in [
+            (1, None, "/usr/sbin/slurmd"),
+            (2, Some(1), "/usr/bin/bash"),
+            (3, Some(2), "/usr/bin/python3"),
+            (4, None, "/usr/bin/bash"),
+            (5, Some(4), "/usr/bin/python3"),
+        ] {
+            pt.insert(Process {
+                key: key(seq),
+                parent: parent.map(key),
+                pid: seq,
+                exe: Some(exe.as_bytes().into()),
+                ..Process::default()
+            });
+        }
+
+        let root = Sha256::new()
+            .chain_update([0u8; 32])
+            .chain_update(1700000000000u64.to_be_bytes())
+            .chain_update(b"/usr/sbin/slurmd")
+            .finalize();
+        assert_eq!(pt.lineage(&key(1)).unwrap()[..], root[..]);
+        let child = Sha256::new()
+            .chain_update(root)
+            .chain_update(1700000000000u64.to_be_bytes())
+            .chain_update(b"/usr/bin/bash")
+            .finalize();
+        assert_eq!(pt.lineage(&key(2)).unwrap()[..], child[..]);
+
+        // Same exe and start time, different ancestors
+        assert_ne!(pt.lineage(&key(3)), pt.lineage(&key(5)));
+        assert!(pt.lineage(&key(6)).is_none());
+    }
+
+    #[test]
+    fn pid_reuse() {
+        let mut pt = ProcTable::remote();
+        pt.insert(Process {
+            key: ProcessKey::Observed {
+                time: 1700000005000,
+                pid: 100,
+            },
+            pid: 100,
+            ..Process::default()
+        });
+        let (proc, reused) = pt.get_or_retrieve_at(100, 1700000010000);
+        assert!(proc.is_some() && !reused);
+        let (_, reused) = pt.get_or_retrieve_at(100, 1700000000000);
+        assert!(reused);
+
+        #[cfg(all(feature = "procfs", target_os = "linux"))]
+        {
+            let pid = std::process::id();
+            let mut pt = ProcTable::default();
+            pt.insert(Process {
+                key: ProcessKey::Observed { time: 1000, pid },
+                pid,
+                ..Process::default()
+            });
+            // The running process has replaced the stale entry.
+            let (proc, reused) = pt.get_or_retrieve_at(pid, u64::MAX / 2);
+            assert!(proc.unwrap().key.time() > 1000 && !reused);
+            // The running process did not exist at that time.
+            let mut pt = ProcTable::default();
+            let (proc, reused) = pt.get_or_retrieve_at(pid, 1000);
+            assert!(proc.is_none() && reused);
+        }
+    }
+
+    #[test]
+    fn expire_exited() {
+        let mut pt = ProcTable::default();
+        for (seq, pid, ppid) in [(1, 100, 1), (2, 101, 100), (3, 102, 100)] {
+            pt.insert(Process {
+                key: ProcessKey::Event(EventID {
+                    timestamp: 1700000000000,
+                    sequence: seq,
+                }),
+                parent: pt.current.get(&ppid).cloned(),
+                pid,
+                ppid,
+                ..Process::default()
+            });
+        }
+        // Parent of a running process is kept
+        pt.exit(100);
+        pt.exit(101);
+        pt.expire_exited();
+        assert_eq!(pt.processes.len(), 2);
+        assert!(pt.get_pid(100).is_some());
+        assert!(pt.get_pid(101).is_none());
+
+        pt.exit(102);
+        pt.expire_exited();
+        assert!(pt.processes.is_empty());
+    }
+
+    #[test]
+    fn interned_strings() {
+        let mut pt = ProcTable::remote();
+        for (seq, pid, exe) in [
+            (1, 100, "/bin/sh"),
+            (2, 101, "/bin/sh"),
+            (3, 102, "/bin/ls"),
+        ] {
+            pt.insert(Process {
+                key: ProcessKey::Event(EventID {
+                    timestamp: 1700000000000,
+                    sequence: seq,
+                }),
+                pid,
+                exe: Some(exe.as_bytes().into()),
+                comm: Some(b"sh"[..].into()),
+                ..Process::default()
+            });
+        }
+        assert_eq!(pt.strings.len(), 3);
+        let (a, b) = (pt.get_pid(100).unwrap(), pt.get_pid(101).unwrap());
+        assert!(Arc::ptr_eq(
+            a.exe.as_ref().unwrap(),
+            b.exe.as_ref().unwrap()
+        ));
+        assert!(Arc::ptr_eq(
+            a.comm.as_ref().unwrap(),
+            b.comm.as_ref().unwrap()
+        ));
+
+        pt.truncate(1);
+        assert_eq!(pt.strings.len(), 2);
+    }
+
     #[test]
     fn proc_key_ord() {
         let e1 = ProcessKey::Event(EventID {
@@ -487,4 +1207,28 @@ mod tests {
             assert_eq!(*expected, got);
         }
     }
+
+    #[test]
+    #[cfg(all(feature = "procfs", target_os = "linux"))]
+    fn extract_systemd_unit() {
+        for (raw, expected) in &[
+            (&b""[..], None),
+            (&b"0::/init.scope"[..], Some(b"init.scope".to_vec())),
+            (
+                &b"0::/system.slice/nginx.service"[..],
+                Some(b"nginx.service".to_vec()),
+            ),
+            (
+                &b"0::/user.slice/user-1000.slice/user@1000.service/app.slice/emacs.service"[..],
+                Some(b"emacs.service".to_vec()),
+            ),
+            (
+                &b"0::/user.slice/user-1000.slice/session-2.scope"[..],
+                Some(b"session-2.scope".to_vec()),
+            ),
+        ] {
+            let got = try_extract_systemd_unit(raw);
+            assert_eq!(*expected, got);
+        }
+    }
 }
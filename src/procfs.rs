@@ -3,16 +3,23 @@ use std::fs::Metadata;
 use std::fs::{read_dir, read_link, File};
 use std::io::{BufRead, BufReader, Read, Write};
 use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
 use lazy_static::lazy_static;
 use nix::sys::time::TimeSpec;
 use nix::time::{clock_gettime, ClockId};
 use nix::unistd::{sysconf, SysconfVar};
 
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::cache::LruCache;
+use crate::clock::{Clock, SystemClock};
+
 lazy_static! {
     /// kernel clock ticks per second
     pub static ref CLK_TCK: u64
@@ -21,7 +28,7 @@ lazy_static! {
 
 #[derive(Debug, Error)]
 pub enum ProcFSError {
-    #[error("can't read /proc/{pid}/(obj): {err}")]
+    #[error("can't read /proc/{pid}/{obj}: {err}")]
     PidFile {
         pid: u32,
         obj: &'static str,
@@ -35,6 +42,26 @@ pub enum ProcFSError {
     Errno(&'static str, nix::errno::Errno),
 }
 
+impl ProcFSError {
+    /// Returns a short identifier for the class of error
+    pub fn code(&self) -> &'static str {
+        match self {
+            ProcFSError::PidFile { .. } => "pid-file",
+            ProcFSError::Enum(_) => "enumerate",
+            ProcFSError::Field(_) => "field",
+            ProcFSError::Errno(..) => "errno",
+        }
+    }
+
+    /// Returns the process ID that the error refers to, if any
+    pub fn pid(&self) -> Option<u32> {
+        match self {
+            ProcFSError::PidFile { pid, .. } => Some(*pid),
+            _ => None,
+        }
+    }
+}
+
 pub fn slurp_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, std::io::Error> {
     let f = File::open(path)?;
     let mut r = BufReader::with_capacity(1 << 16, f);
@@ -58,7 +85,29 @@ pub fn get_pid_exe_link(pid: u32) -> std::io::Result<PathBuf> {
     std::fs::read_link(format!("/proc/{pid}/exe"))
 }
 
-type Environment = Vec<(Vec<u8>, Vec<u8>)>;
+/// Returns the kernel's taint mask
+pub fn get_kernel_tainted() -> std::io::Result<u64> {
+    std::fs::read_to_string("/proc/sys/kernel/tainted")?
+        .trim()
+        .parse()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+/// Returns the ID of the current boot
+pub fn get_boot_id() -> std::io::Result<String> {
+    Ok(std::fs::read_to_string("/proc/sys/kernel/random/boot_id")?
+        .trim()
+        .to_string())
+}
+
+/// Returns the kernel release, as in `uname -r`
+pub fn get_kernel_release() -> std::io::Result<String> {
+    Ok(std::fs::read_to_string("/proc/sys/kernel/osrelease")?
+        .trim()
+        .to_string())
+}
+
+pub type Environment = Vec<(Vec<u8>, Vec<u8>)>;
 
 /// Returns set of environment variables that match pred for a given process
 pub fn get_environ<F>(pid: u32, pred: F) -> Result<Environment, ProcFSError>
@@ -114,6 +163,19 @@ pub fn pid_path_read(pid: u32, path: &[u8]) -> Result<Vec<u8>, std::io::Error> {
     std::fs::read(OsStr::from_bytes(&proc_path))
 }
 
+/// Opens a file for a path from a process' perspective
+///
+/// Like [`pid_path_read`], this goes through /proc/{pid}/root/.
+pub fn pid_path_open(pid: u32, path: &[u8]) -> Result<File, std::io::Error> {
+    if path.is_empty() || path[0] != b'/' {
+        return Err(std::io::ErrorKind::NotFound.into());
+    }
+    let mut proc_path = Vec::with_capacity(20 + path.len());
+    write!(proc_path, "/proc/{pid}/root").unwrap();
+    proc_path.extend(path);
+    File::open(OsStr::from_bytes(&proc_path))
+}
+
 pub struct ProcStat<'a> {
     pub pid: u32,
     pub ppid: u32,
@@ -165,8 +227,8 @@ pub fn parse_proc_pid_stat(buf: &[u8]) -> Result<ProcStat<'_>, ProcFSError> {
     })
 }
 
-#[derive(Debug)]
-pub(crate) struct ProcPidInfo {
+#[derive(Clone, Debug, Default)]
+pub struct ProcPidInfo {
     /// /proc/<pid>/stat field 1
     pub pid: u32,
     /// /proc/<pid>/stat field 4
@@ -197,6 +259,164 @@ pub(crate) fn parse_proc_pid(pid: u32) -> Result<ProcPidInfo, ProcFSError> {
         .map(|p| Vec::from(p.as_os_str().as_bytes()))
         .ok();
 
+    let starttime = starttime_to_epoch_ms(starttime)?;
+
+    let cgroup = parse_proc_pid_cgroup(pid)?;
+
+    Ok(ProcPidInfo {
+        pid,
+        ppid,
+        starttime,
+        comm: comm.to_vec(),
+        exe,
+        cgroup,
+    })
+}
+
+/// Returns the start time of process pid in milliseconds since epoch
+pub(crate) fn get_pid_starttime(pid: u32) -> Result<u64, ProcFSError> {
+    let buf = slurp_pid_obj(pid, "stat")?;
+    starttime_to_epoch_ms(parse_proc_pid_stat(&buf)?.starttime)
+}
+
+/// Source of process information for the shadow process table and
+/// for enrichment
+///
+/// This is /proc ([`LiveProcSource`]) except in tests that use
+/// synthetic process hierarchies. Synthetic sources need not provide
+/// the information that is only used for enrichment; by default, it
+/// is reported as missing.
+pub trait ProcSource: std::fmt::Debug + Send + Sync {
+    /// Returns all currently valid process IDs
+    fn pids(&self) -> Result<Vec<u32>, ProcFSError>;
+    /// Returns information about process `pid`
+    fn pid_info(&self, pid: u32) -> Result<ProcPidInfo, ProcFSError>;
+    /// Returns the start time of process `pid` in milliseconds since
+    /// epoch
+    fn pid_starttime(&self, pid: u32) -> Result<u64, ProcFSError>;
+    /// Returns the process ID of process `pid` within its innermost
+    /// pid namespace, see [`parse_proc_pid_nspid`]
+    fn pid_nspid(&self, pid: u32) -> Result<Option<NsPid>, ProcFSError>;
+    /// Returns the ID of the process that thread `tid` belongs to.
+    /// Synthetic sources that don't provide threads return `tid`.
+    fn tid_tgid(&self, tid: u32) -> Result<u32, ProcFSError> {
+        Ok(tid)
+    }
+    /// Returns the environment variables of process `pid` whose
+    /// names match `pred`
+    fn pid_environ(
+        &self,
+        pid: u32,
+        _pred: &dyn Fn(&[u8]) -> bool,
+    ) -> Result<Environment, ProcFSError> {
+        Err(missing(pid, "environ"))
+    }
+    /// Returns the cgroup path of process `pid`
+    fn pid_cgroup(&self, pid: u32) -> Result<Option<Vec<u8>>, ProcFSError> {
+        Err(missing(pid, "cgroup"))
+    }
+    /// Returns the ID of the process that pidfd `fd` of process
+    /// `pid` refers to
+    fn pidfd_pid(&self, pid: u32, _fd: u32) -> Result<u32, ProcFSError> {
+        Err(missing(pid, "fdinfo"))
+    }
+    /// Opens the executable of process `pid` and returns it along
+    /// with its path
+    fn pid_exe(&self, _pid: u32) -> std::io::Result<(File, PathBuf)> {
+        Err(std::io::ErrorKind::NotFound.into())
+    }
+    /// Opens `path` as seen by process `pid`
+    fn pid_path_open(&self, _pid: u32, _path: &[u8]) -> std::io::Result<File> {
+        Err(std::io::ErrorKind::NotFound.into())
+    }
+    /// Returns the mount namespace of process `pid`
+    fn pid_mount_ns(&self, pid: u32) -> Result<PathBuf, ProcFSError> {
+        Err(missing(pid, "ns/mnt"))
+    }
+    /// Returns the mount table of process `pid` in
+    /// /proc/<pid>/mountinfo format
+    fn pid_mountinfo(&self, pid: u32) -> Result<Vec<u8>, ProcFSError> {
+        Err(missing(pid, "mountinfo"))
+    }
+}
+
+fn missing(pid: u32, obj: &'static str) -> ProcFSError {
+    ProcFSError::PidFile {
+        pid,
+        obj,
+        err: std::io::ErrorKind::NotFound.into(),
+    }
+}
+
+/// Reads process information from /proc
+#[derive(Debug, Default)]
+pub struct LiveProcSource;
+
+impl ProcSource for LiveProcSource {
+    fn pids(&self) -> Result<Vec<u32>, ProcFSError> {
+        get_pids()
+    }
+    fn pid_info(&self, pid: u32) -> Result<ProcPidInfo, ProcFSError> {
+        parse_proc_pid(pid)
+    }
+    fn pid_starttime(&self, pid: u32) -> Result<u64, ProcFSError> {
+        get_pid_starttime(pid)
+    }
+    fn pid_nspid(&self, pid: u32) -> Result<Option<NsPid>, ProcFSError> {
+        parse_proc_pid_nspid(pid)
+    }
+    fn tid_tgid(&self, tid: u32) -> Result<u32, ProcFSError> {
+        get_tid_tgid(tid)
+    }
+    fn pid_environ(
+        &self,
+        pid: u32,
+        pred: &dyn Fn(&[u8]) -> bool,
+    ) -> Result<Environment, ProcFSError> {
+        get_environ(pid, pred)
+    }
+    fn pid_cgroup(&self, pid: u32) -> Result<Option<Vec<u8>>, ProcFSError> {
+        parse_proc_pid_cgroup(pid)
+    }
+    fn pidfd_pid(&self, pid: u32, fd: u32) -> Result<u32, ProcFSError> {
+        get_pidfd_pid(pid, fd)
+    }
+    fn pid_exe(&self, pid: u32) -> std::io::Result<(File, PathBuf)> {
+        Ok((open_pid_exe_meta(pid)?, get_pid_exe_link(pid)?))
+    }
+    fn pid_path_open(&self, pid: u32, path: &[u8]) -> std::io::Result<File> {
+        pid_path_open(pid, path)
+    }
+    fn pid_mount_ns(&self, pid: u32) -> Result<PathBuf, ProcFSError> {
+        read_link(format!("/proc/{pid}/ns/mnt")).map_err(|err| ProcFSError::PidFile {
+            pid,
+            obj: "ns/mnt",
+            err,
+        })
+    }
+    fn pid_mountinfo(&self, pid: u32) -> Result<Vec<u8>, ProcFSError> {
+        slurp_pid_obj(pid, "mountinfo")
+    }
+}
+
+/// Returns the ID of the process that pidfd `fd` of process `pid`
+/// refers to.
+pub(crate) fn get_pidfd_pid(pid: u32, fd: u32) -> Result<u32, ProcFSError> {
+    let buf =
+        slurp_file(format!("/proc/{pid}/fdinfo/{fd}")).map_err(|err| ProcFSError::PidFile {
+            pid,
+            obj: "fdinfo",
+            err,
+        })?;
+    buf.split(|c| *c == b'\n')
+        .find_map(|line| line.strip_prefix(b"Pid:"))
+        .and_then(|v| std::str::from_utf8(v).ok()?.trim().parse().ok())
+        .ok_or(ProcFSError::Field("Pid"))
+}
+
+/// Converts starttime from /proc/<pid>/stat (clock ticks since boot)
+/// to milliseconds since epoch
+fn starttime_to_epoch_ms(starttime: u64) -> Result<u64, ProcFSError> {
     // Use the boottime-based clock to calculate process start
     // time, convert to Unix-epoch-based-time.
     let proc_boottime = TimeSpec::from(libc::timespec {
@@ -209,23 +429,10 @@ pub(crate) fn parse_proc_pid(pid: u32) -> Result<ProcPidInfo, ProcFSError> {
     let proc_age = clock_gettime(ClockId::CLOCK_BOOTTIME)
         .map_err(|e| ProcFSError::Errno("clock_gettime(CLOCK_BOOTTIME)", e))?
         - proc_boottime;
-    let starttime = {
-        let lt = clock_gettime(ClockId::CLOCK_REALTIME)
-            .map_err(|e| ProcFSError::Errno("clock_gettime(CLOCK_REALTIME)", e))?
-            - proc_age;
-        (lt.tv_sec() as u64) * 1000 + (lt.tv_nsec() as u64) / 1_000_000
-    };
-
-    let cgroup = parse_proc_pid_cgroup(pid)?;
-
-    Ok(ProcPidInfo {
-        pid,
-        ppid,
-        starttime,
-        comm: comm.to_vec(),
-        exe,
-        cgroup,
-    })
+    let lt = clock_gettime(ClockId::CLOCK_REALTIME)
+        .map_err(|e| ProcFSError::Errno("clock_gettime(CLOCK_REALTIME)", e))?
+        - proc_age;
+    Ok((lt.tv_sec() as u64) * 1000 + (lt.tv_nsec() as u64) / 1_000_000)
 }
 
 /// Parses path (third field) /proc/pid/cgroup
@@ -243,6 +450,229 @@ fn parse_cgroup_buf(buf: &[u8]) -> Result<Option<Vec<u8>>, ProcFSError> {
     Ok(None)
 }
 
+/// Returns the thread group ID, i.e. the process ID, of thread `tid`
+pub(crate) fn get_tid_tgid(tid: u32) -> Result<u32, ProcFSError> {
+    slurp_pid_obj(tid, "status")?
+        .split(|c| *c == b'\n')
+        .find_map(|line| line.strip_prefix(b"Tgid:"))
+        .and_then(|v| std::str::from_utf8(v).ok()?.trim().parse().ok())
+        .ok_or(ProcFSError::Field("Tgid"))
+}
+
+/// Process ID within a pid namespace
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NsPid {
+    /// Inode number of the pid namespace
+    pub ns: u64,
+    pub pid: u32,
+}
+
+/// Returns the process ID of process pid within its innermost pid
+/// namespace if that is not the pid namespace of /proc
+pub(crate) fn parse_proc_pid_nspid(pid: u32) -> Result<Option<NsPid>, ProcFSError> {
+    let Some(ns_pid) = parse_nspid_buf(&slurp_pid_obj(pid, "status")?) else {
+        return Ok(None);
+    };
+    let ns = std::fs::metadata(format!("/proc/{pid}/ns/pid"))
+        .map_err(|err| ProcFSError::PidFile {
+            pid,
+            obj: "ns/pid",
+            err,
+        })?
+        .ino();
+    Ok(Some(NsPid { ns, pid: ns_pid }))
+}
+
+/// Parses the NSpid line of /proc/pid/status. It lists the process
+/// ID in every pid namespace, starting with the outermost one.
+fn parse_nspid_buf(buf: &[u8]) -> Option<u32> {
+    let line = buf
+        .split(|c| *c == b'\n')
+        .find_map(|line| line.strip_prefix(b"NSpid:"))?;
+    let pids: Vec<&[u8]> = line
+        .split(|c| c.is_ascii_whitespace())
+        .filter(|s| !s.is_empty())
+        .collect();
+    match pids[..] {
+        [_, .., innermost] => std::str::from_utf8(innermost).ok()?.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Entry from /proc/pid/mountinfo
+#[derive(Debug)]
+pub(crate) struct MountInfo {
+    /// major:minor
+    dev: Vec<u8>,
+    /// Directory within the filesystem that is mounted
+    root: Vec<u8>,
+    mount_point: Vec<u8>,
+    /// Per-mount options, e.g. `rw,nosuid,noexec`
+    pub options: Vec<u8>,
+    pub fstype: Vec<u8>,
+    /// Filesystem-specific source, e.g. device or network share
+    pub source: Vec<u8>,
+}
+
+fn parse_mountinfo(buf: &[u8]) -> Vec<MountInfo> {
+    buf.split(|c| *c == b'\n')
+        .filter_map(|line| {
+            let mut fields = line.split(|c| *c == b' ').skip(2);
+            let dev = fields.next()?.to_vec();
+            let root = unescape_octal(fields.next()?);
+            let mount_point = unescape_octal(fields.next()?);
+            let options = fields.next()?.to_vec();
+            // Optional fields are terminated by a single hyphen.
+            let mut fields = fields.skip_while(|f| f != b"-").skip(1);
+            Some(MountInfo {
+                dev,
+                root,
+                mount_point,
+                options,
+                fstype: fields.next().unwrap_or_default().to_vec(),
+                source: unescape_octal(fields.next().unwrap_or_default()),
+            })
+        })
+        .collect()
+}
+
+/// Decodes `\ooo` escape sequences used for whitespace and backslashes
+fn unescape_octal(s: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut i = 0;
+    while i < s.len() {
+        match s.get(i..i + 4) {
+            Some([b'\\', a @ b'0'..=b'3', b @ b'0'..=b'7', c @ b'0'..=b'7']) => {
+                out.push((a - b'0') << 6 | (b - b'0') << 3 | (c - b'0'));
+                i += 4;
+            }
+            _ => {
+                out.push(s[i]);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+/// Returns the remainder of `path` below `dir`, starting with `/`,
+/// or empty if `path` equals `dir`
+fn strip_dir<'a>(path: &'a [u8], dir: &[u8]) -> Option<&'a [u8]> {
+    match (dir, path) {
+        (b"/", b"/") => return Some(b""),
+        (b"/", path) => return path.starts_with(b"/").then_some(path),
+        _ => {}
+    }
+    match path.strip_prefix(dir)? {
+        rest if rest.is_empty() || rest.starts_with(b"/") => Some(rest),
+        _ => None,
+    }
+}
+
+fn join_dir(dir: &[u8], rest: &[u8]) -> Vec<u8> {
+    match (dir, rest) {
+        (b"/", b"") => b"/".to_vec(),
+        (b"/", rest) => rest.to_vec(),
+        (dir, rest) => [dir, rest].concat(),
+    }
+}
+
+/// Translates absolute `path` from the mount namespace described by
+/// `theirs` to the one described by `ours`, based on a mount of the
+/// same filesystem.
+fn translate_path(theirs: &[MountInfo], ours: &[MountInfo], path: &[u8]) -> Option<Vec<u8>> {
+    // Later entries are mounted on top of earlier ones.
+    let (mount, rest) = theirs
+        .iter()
+        .filter_map(|m| Some((m, strip_dir(path, &m.mount_point)?)))
+        .max_by_key(|(m, _)| m.mount_point.len())?;
+    let fs_path = join_dir(&mount.root, rest);
+    let (mount, rest) = ours
+        .iter()
+        .filter(|m| m.dev == mount.dev)
+        .filter_map(|m| Some((m, strip_dir(&fs_path, &m.root)?)))
+        .max_by_key(|(m, _)| m.root.len())?;
+    Some(join_dir(&mount.mount_point, rest))
+}
+
+/// Returns the mount that absolute `path` resides on. Later entries
+/// are mounted on top of earlier ones.
+fn find_mount<'a>(mounts: &'a [MountInfo], path: &[u8]) -> Option<&'a MountInfo> {
+    mounts
+        .iter()
+        .filter(|m| strip_dir(path, &m.mount_point).is_some())
+        .max_by_key(|m| m.mount_point.len())
+}
+
+/// Time after which mount tables are read again
+const MOUNT_CACHE_TTL: Duration = Duration::from_secs(10);
+
+/// Number of mount namespaces whose mount tables are kept
+const MOUNT_CACHE_ENTRIES: usize = 64;
+
+/// Mount tables, keyed by mount namespace
+#[derive(Debug)]
+pub(crate) struct MountCache(LruCache<PathBuf, Vec<MountInfo>>);
+
+impl Default for MountCache {
+    fn default() -> Self {
+        MountCache::new(Arc::new(SystemClock))
+    }
+}
+
+impl MountCache {
+    pub(crate) fn new(clock: Arc<dyn Clock>) -> Self {
+        MountCache(
+            LruCache::new(MOUNT_CACHE_ENTRIES)
+                .with_ttl(MOUNT_CACHE_TTL)
+                .with_clock(clock),
+        )
+    }
+
+    /// Makes sure that the mount table of process `pid` is cached and
+    /// returns its mount namespace.
+    fn load(&mut self, source: &dyn ProcSource, pid: u32) -> Result<PathBuf, ProcFSError> {
+        let ns = source.pid_mount_ns(pid)?;
+        if self.0.get_ref(&ns).is_none() {
+            let mounts = parse_mountinfo(&source.pid_mountinfo(pid)?);
+            self.0.insert(ns.clone(), mounts);
+        }
+        Ok(ns)
+    }
+
+    /// Returns the mount that absolute `path`, as seen by process
+    /// `pid`, resides on.
+    pub(crate) fn lookup(
+        &mut self,
+        source: &dyn ProcSource,
+        pid: u32,
+        path: &[u8],
+    ) -> Result<Option<&MountInfo>, ProcFSError> {
+        let ns = self.load(source, pid)?;
+        Ok(self.0.peek(&ns).and_then(|m| find_mount(m, path)))
+    }
+
+    /// Translates absolute `path` as seen by process `pid` to
+    /// laurel's mount namespace. Returns `None` if both are in the
+    /// same mount namespace or no mount of the filesystem is visible.
+    pub(crate) fn host_path(
+        &mut self,
+        source: &dyn ProcSource,
+        pid: u32,
+        path: &[u8],
+    ) -> Result<Option<Vec<u8>>, ProcFSError> {
+        let theirs = self.load(source, pid)?;
+        let ours = self.load(source, std::process::id())?;
+        if theirs == ours {
+            return Ok(None);
+        }
+        match (self.0.peek(&theirs), self.0.peek(&ours)) {
+            (Some(theirs), Some(ours)) => Ok(translate_path(theirs, ours, path)),
+            _ => Ok(None),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -253,6 +683,13 @@ mod tests {
         println!("{proc:?}");
     }
 
+    #[test]
+    fn pid_error() {
+        let e = parse_proc_pid(u32::MAX).unwrap_err();
+        assert_eq!(e.code(), "pid-file");
+        assert_eq!(e.pid(), Some(u32::MAX));
+    }
+
     #[test]
     fn parse_stat() {
         let ProcStat { pid, ppid, comm, starttime, utime, stime } = parse_proc_pid_stat(
@@ -265,4 +702,95 @@ mod tests {
         assert_eq!(utime, 183731);
         assert_eq!(stime, 6453);
     }
+
+    #[test]
+    fn tgid() {
+        let pid = std::process::id();
+        // Tests run in threads other than the main thread.
+        let tid = nix::unistd::gettid().as_raw() as u32;
+        assert_eq!(get_tid_tgid(tid).unwrap(), pid);
+        assert_eq!(get_tid_tgid(pid).unwrap(), pid);
+    }
+
+    #[test]
+    fn nspid() {
+        let status = |nspid: &str| {
+            format!("Name:\tnginx\nTgid:\t52318\nNgid:\t0\nPid:\t52318\nPPid:\t52297\n{nspid}Uid:\t0\t0\t0\t0\n")
+        };
+        assert_eq!(
+            parse_nspid_buf(status("NSpid:\t52318\t7\n").as_bytes()),
+            Some(7)
+        );
+        assert_eq!(
+            parse_nspid_buf(status("NSpid:\t52318\t310\t1\n").as_bytes()),
+            Some(1)
+        );
+        assert_eq!(parse_nspid_buf(status("NSpid:\t52318\n").as_bytes()), None);
+        assert_eq!(parse_nspid_buf(status("").as_bytes()), None);
+    }
+
+    #[test]
+    fn host_path() {
+        let ours = parse_mountinfo(
+            br#"22 1 253:0 / / rw,relatime shared:1 - ext4 /dev/mapper/root rw
+61 22 0:52 / /var/lib/docker/overlay2/abc/merged rw,relatime shared:30 - overlay overlay rw
+62 22 253:1 / /srv rw,relatime shared:31 - ext4 /dev/vdb rw
+"#,
+        );
+        let theirs = parse_mountinfo(
+            br#"700 650 0:52 / / rw,relatime master:30 - overlay overlay rw
+701 700 253:1 /www/my\040site /var/www rw,relatime - ext4 /dev/vdb rw
+702 700 0:60 / /tmp rw - tmpfs tmpfs rw
+"#,
+        );
+        assert_eq!(theirs[1].root, b"/www/my site");
+        for (path, host) in [
+            (
+                &b"/etc/passwd"[..],
+                Some(&b"/var/lib/docker/overlay2/abc/merged/etc/passwd"[..]),
+            ),
+            (b"/", Some(b"/var/lib/docker/overlay2/abc/merged")),
+            (b"/var/www/index.html", Some(b"/srv/www/my site/index.html")),
+            (
+                b"/var/wwwx",
+                Some(b"/var/lib/docker/overlay2/abc/merged/var/wwwx"),
+            ),
+            (b"/tmp/x", None),
+        ] {
+            assert_eq!(
+                translate_path(&theirs, &ours, path).as_deref(),
+                host,
+                "{}",
+                String::from_utf8_lossy(path)
+            );
+        }
+        let mut cache = MountCache::default();
+        assert_eq!(
+            cache
+                .host_path(&LiveProcSource, std::process::id(), b"/etc/passwd")
+                .unwrap(),
+            None,
+            "no translation within our own namespace"
+        );
+        assert_eq!(cache.0.len(), 1);
+    }
+
+    #[test]
+    fn mount() {
+        let mounts = parse_mountinfo(
+            br#"22 1 253:0 / / rw,relatime shared:1 - ext4 /dev/mapper/root rw,errors=remount-ro
+40 22 0:35 / /tmp rw,nosuid,nodev,noexec shared:20 - tmpfs tmpfs rw,size=8G
+41 22 0:36 / /mnt/share rw,relatime - cifs //fileserver/my\040share rw,vers=3.1.1
+"#,
+        );
+        let m = find_mount(&mounts, b"/tmp/x").unwrap();
+        assert_eq!(m.fstype, b"tmpfs");
+        assert_eq!(m.options, b"rw,nosuid,nodev,noexec");
+        let m = find_mount(&mounts, b"/mnt/share/x.exe").unwrap();
+        assert_eq!(m.fstype, b"cifs");
+        assert_eq!(m.source, b"//fileserver/my share");
+        let m = find_mount(&mounts, b"/tmpx").unwrap();
+        assert_eq!(m.fstype, b"ext4");
+        assert_eq!(m.source, b"/dev/mapper/root");
+    }
 }
@@ -0,0 +1,296 @@
+//! Process events from the kernel's process events connector
+//!
+//! Reading `/proc/<pid>/cgroup` while an event is being processed
+//! often fails for short-lived processes because they have already
+//! exited. The kernel's process events connector reports fork, exec,
+//! and exit events as they happen, so the information can be captured
+//! before the corresponding audit event arrives. Fork and exit events
+//! can also be used to keep the shadow process table up to date.
+
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::mpsc::SyncSender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use nix::sys::socket::{bind, recv, send, MsgFlags, NetlinkAddr};
+
+use thiserror::Error;
+
+use crate::cache::LruCache;
+use crate::proc::START_TIME_SLACK;
+use crate::procfs;
+
+const NETLINK_CONNECTOR: libc::c_int = 11;
+const CN_IDX_PROC: u32 = 1;
+const CN_VAL_PROC: u32 = 1;
+const PROC_CN_MCAST_LISTEN: u32 = 1;
+const PROC_EVENT_FORK: u32 = 0x1;
+const PROC_EVENT_EXEC: u32 = 0x2;
+const PROC_EVENT_EXIT: u32 = 0x80000000;
+const NLMSG_DONE: u16 = 3;
+const NLMSG_HDRLEN: usize = 16;
+/// Size of struct cn_msg without the trailing data
+const CN_MSG_LEN: usize = 20;
+
+/// Suggested capacity for the queue of process events
+pub const EVENT_QUEUE_SIZE: usize = 65536;
+
+/// Cache entries are removed after this time
+const MAX_AGE: Duration = Duration::from_secs(60);
+
+/// Maximum number of cached cgroup paths
+const MAX_ENTRIES: usize = 65536;
+
+#[derive(Debug, Error)]
+pub enum ProcWatchError {
+    #[error("netlink: {0}")]
+    Netlink(nix::Error),
+}
+
+/// Process lifecycle event
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProcEvent {
+    /// A new process has been created. `time` is the time of the
+    /// fork in milliseconds since the epoch.
+    Fork {
+        pid: u32,
+        ppid: u32,
+        time: u64,
+    },
+    Exec {
+        pid: u32,
+    },
+    Exit {
+        pid: u32,
+    },
+}
+
+impl ProcEvent {
+    pub fn pid(&self) -> u32 {
+        match self {
+            ProcEvent::Fork { pid, .. } | ProcEvent::Exec { pid } | ProcEvent::Exit { pid } => *pid,
+        }
+    }
+}
+
+/// Start times and cgroup paths, keyed by pid
+type CgroupMap = LruCache<u32, (u64, Vec<u8>)>;
+
+/// Cgroup paths of recently started processes
+#[derive(Clone, Debug)]
+pub struct CgroupCache(Arc<Mutex<CgroupMap>>);
+
+impl Default for CgroupCache {
+    fn default() -> Self {
+        CgroupCache(Arc::new(Mutex::new(
+            LruCache::new(MAX_ENTRIES).with_ttl(MAX_AGE),
+        )))
+    }
+}
+
+impl CgroupCache {
+    /// Records the cgroup path of process `pid` that has been
+    /// started at `starttime` (milliseconds since the epoch)
+    pub fn insert(&self, pid: u32, starttime: u64, cgroup: Vec<u8>) {
+        self.0.lock().unwrap().insert(pid, (starttime, cgroup));
+    }
+
+    /// Returns the cgroup path of the process that was running as
+    /// `pid` at `time`. Entries for processes that have been started
+    /// later, i.e. that have reused the pid, are ignored.
+    pub fn get(&self, pid: u32, time: u64) -> Option<Vec<u8>> {
+        self.0
+            .lock()
+            .unwrap()
+            .get_ref(&pid)
+            .filter(|(starttime, _)| *starttime <= time + START_TIME_SLACK)
+            .map(|(_, cgroup)| cgroup.clone())
+    }
+
+    /// Removes entries older than [`MAX_AGE`]
+    pub fn expire(&self) {
+        self.0.lock().unwrap().purge_expired();
+    }
+}
+
+/// Subscription to process events
+pub struct ProcWatcher {
+    sock: OwnedFd,
+}
+
+impl ProcWatcher {
+    /// Subscribes to process events. This requires CAP_NET_ADMIN.
+    pub fn new() -> Result<Self, ProcWatchError> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+                NETLINK_CONNECTOR,
+            )
+        };
+        if fd < 0 {
+            return Err(ProcWatchError::Netlink(nix::Error::last()));
+        }
+        let sock = unsafe { OwnedFd::from_raw_fd(fd) };
+        bind(sock.as_raw_fd(), &NetlinkAddr::new(0, CN_IDX_PROC))
+            .map_err(ProcWatchError::Netlink)?;
+
+        let mut req = Vec::with_capacity(NLMSG_HDRLEN + CN_MSG_LEN + 4);
+        req.extend(((NLMSG_HDRLEN + CN_MSG_LEN + 4) as u32).to_ne_bytes());
+        req.extend(NLMSG_DONE.to_ne_bytes());
+        req.extend(0u16.to_ne_bytes());
+        req.extend(0u32.to_ne_bytes());
+        req.extend(std::process::id().to_ne_bytes());
+        req.extend(CN_IDX_PROC.to_ne_bytes());
+        req.extend(CN_VAL_PROC.to_ne_bytes());
+        req.extend(0u32.to_ne_bytes());
+        req.extend(0u32.to_ne_bytes());
+        req.extend(4u16.to_ne_bytes());
+        req.extend(0u16.to_ne_bytes());
+        req.extend(PROC_CN_MCAST_LISTEN.to_ne_bytes());
+        send(sock.as_raw_fd(), &req, MsgFlags::empty()).map_err(ProcWatchError::Netlink)?;
+
+        Ok(ProcWatcher { sock })
+    }
+
+    /// Starts a thread that records cgroup paths of new processes in
+    /// `cgroups` and passes process events on to `events`. Events are
+    /// dropped if the receiver does not keep up.
+    pub fn spawn(self, cgroups: Option<CgroupCache>, events: Option<SyncSender<ProcEvent>>) {
+        thread::spawn(move || self.run(cgroups, events));
+    }
+
+    fn run(self, cgroups: Option<CgroupCache>, events: Option<SyncSender<ProcEvent>>) {
+        let mut buf = vec![0u8; 4096];
+        let mut last_expire = Instant::now();
+        loop {
+            let len = match recv(self.sock.as_raw_fd(), &mut buf, MsgFlags::empty()) {
+                Ok(len) => len,
+                // ENOBUFS: Events have been lost, nothing to be done.
+                Err(nix::Error::EINTR) | Err(nix::Error::ENOBUFS) => continue,
+                Err(e) => {
+                    log::warn!("Stopped receiving process events: {e}");
+                    return;
+                }
+            };
+            let Some(event) = parse_event(&buf[..len]) else {
+                continue;
+            };
+            if let Some(cache) = &cgroups {
+                if let ProcEvent::Fork { .. } | ProcEvent::Exec { .. } = event {
+                    let pid = event.pid();
+                    if let (Ok(starttime), Ok(Some(cgroup))) = (
+                        procfs::get_pid_starttime(pid),
+                        procfs::parse_proc_pid_cgroup(pid),
+                    ) {
+                        cache.insert(pid, starttime, cgroup);
+                    }
+                }
+                if last_expire.elapsed() > MAX_AGE {
+                    cache.expire();
+                    last_expire = Instant::now();
+                }
+            }
+            if let Some(events) = &events {
+                // Only fails if the buffer is full or the receiver
+                // is gone.
+                _ = events.try_send(event);
+            }
+        }
+    }
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    buf.get(offset..offset + 4)
+        .map(|b| u32::from_ne_bytes(b.try_into().unwrap()))
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Parses fork, exec, and exit events. Thread creation and exit of
+/// threads other than the thread group leader are ignored.
+fn parse_event(buf: &[u8]) -> Option<ProcEvent> {
+    // struct proc_event: what, cpu, timestamp_ns, event_data
+    let ev = buf.get(NLMSG_HDRLEN + CN_MSG_LEN..)?;
+    match read_u32(ev, 0)? {
+        PROC_EVENT_FORK => {
+            let ppid = read_u32(ev, 20)?;
+            let (pid, tgid) = (read_u32(ev, 24)?, read_u32(ev, 28)?);
+            (pid == tgid).then(|| ProcEvent::Fork {
+                pid,
+                ppid,
+                time: now_ms(),
+            })
+        }
+        PROC_EVENT_EXEC => Some(ProcEvent::Exec {
+            pid: read_u32(ev, 20)?,
+        }),
+        PROC_EVENT_EXIT => {
+            let (pid, tgid) = (read_u32(ev, 16)?, read_u32(ev, 20)?);
+            (pid == tgid).then_some(ProcEvent::Exit { pid })
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn event(what: u32, data: &[u32]) -> Vec<u8> {
+        let mut buf = vec![0u8; NLMSG_HDRLEN + CN_MSG_LEN];
+        buf.extend(what.to_ne_bytes());
+        buf.extend(0u32.to_ne_bytes());
+        buf.extend(0u64.to_ne_bytes());
+        data.iter().for_each(|n| buf.extend(n.to_ne_bytes()));
+        buf
+    }
+
+    #[test]
+    fn parse() {
+        assert!(matches!(
+            parse_event(&event(PROC_EVENT_FORK, &[1, 1, 100, 100])),
+            Some(ProcEvent::Fork {
+                pid: 100,
+                ppid: 1,
+                ..
+            })
+        ));
+        assert_eq!(
+            parse_event(&event(PROC_EVENT_FORK, &[1, 1, 101, 100])),
+            None
+        );
+        assert_eq!(
+            parse_event(&event(PROC_EVENT_EXEC, &[102, 102])),
+            Some(ProcEvent::Exec { pid: 102 })
+        );
+        assert_eq!(
+            parse_event(&event(PROC_EVENT_EXIT, &[102, 102, 0, 0, 1, 1])),
+            Some(ProcEvent::Exit { pid: 102 })
+        );
+        assert_eq!(
+            parse_event(&event(PROC_EVENT_EXIT, &[103, 102, 0, 0, 1, 1])),
+            None
+        );
+        assert_eq!(parse_event(&event(0x40000000, &[102, 102, 0])), None);
+        assert_eq!(parse_event(&event(PROC_EVENT_EXEC, &[])), None);
+    }
+
+    #[test]
+    fn cgroup_cache() {
+        let cache = CgroupCache::default();
+        cache.insert(100, 1_000_000, b"/system.slice/cron.service".to_vec());
+        assert_eq!(
+            cache.get(100, 1_000_500).as_deref(),
+            Some(&b"/system.slice/cron.service"[..])
+        );
+        // The pid has been reused by the process in the cache.
+        assert_eq!(cache.get(100, 990_000), None);
+        assert_eq!(cache.get(101, 1_000_500), None);
+    }
+}
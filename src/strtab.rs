@@ -0,0 +1,152 @@
+//! Replace repeated values by references into a string table
+
+use std::collections::{HashMap, HashSet};
+
+use serde_json::{Map, Value};
+
+use crate::projection::FieldPath;
+
+pub const DEFAULT_SIZE: usize = 10_000;
+
+/// String table for selected fields
+///
+/// Values of the selected fields are replaced by numeric references.
+/// Definitions for values that have not been seen before are returned
+/// as a separate `LAUREL_STRINGS` record that needs to be written
+/// before the event. Once `size` entries have been defined, the table
+/// is cleared and numbering starts over.
+#[derive(Clone, Debug)]
+pub struct StringTable {
+    paths: Vec<FieldPath>,
+    size: usize,
+    entries: HashMap<String, u64>,
+}
+
+impl StringTable {
+    pub fn new(paths: &[FieldPath], size: usize) -> Self {
+        StringTable {
+            paths: paths.to_vec(),
+            size: size.max(1),
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Removes all entries, so that values are defined again
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Replaces selected string values in `event` by references.
+    /// Returns a `LAUREL_STRINGS` record if new values have been
+    /// added to the table.
+    pub fn apply(&mut self, event: &mut Value) -> Option<Value> {
+        // Make room before replacing anything so that all
+        // references within the event refer to the same table.
+        let mut missing = HashSet::new();
+        for path in &self.paths {
+            replace(event, path.elements(), &mut |s| {
+                if !self.entries.contains_key(s) {
+                    missing.insert(s.clone());
+                }
+                None
+            });
+        }
+        if self.entries.len() + missing.len() > self.size {
+            self.entries.clear();
+        }
+
+        let mut new = Map::new();
+        for path in &self.paths {
+            replace(event, path.elements(), &mut |s| {
+                if let Some(id) = self.entries.get(s) {
+                    return Some(Value::from(*id));
+                }
+                let id = self.entries.len() as u64;
+                self.entries.insert(s.clone(), id);
+                new.insert(id.to_string(), Value::String(s.clone()));
+                Some(Value::from(id))
+            });
+        }
+        if new.is_empty() {
+            return None;
+        }
+        let mut record = Map::new();
+        if let Some(id) = event.get("ID") {
+            record.insert("ID".into(), id.clone());
+        }
+        record.insert("LAUREL_STRINGS".into(), Value::Object(new));
+        Some(Value::Object(record))
+    }
+}
+
+/// Calls `f` for all string values at `path`, replacing them if `f`
+/// returns a new value.
+fn replace(value: &mut Value, path: &[String], f: &mut impl FnMut(&String) -> Option<Value>) {
+    match (path.split_first(), value) {
+        (None, value) => {
+            let new = match &*value {
+                Value::String(s) => f(s),
+                _ => None,
+            };
+            if let Some(v) = new {
+                *value = v;
+            }
+        }
+        (Some((first, rest)), Value::Object(map)) => {
+            if let Some(v) = map.get_mut(first) {
+                replace(v, rest, f);
+            }
+        }
+        // Records that occur multiple times, e.g. PATH
+        (Some(_), Value::Array(vs)) => vs.iter_mut().for_each(|v| replace(v, path, f)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn string_table() {
+        let paths: Vec<FieldPath> = ["SYSCALL.exe", "SYSCALL.comm", "PATH.name"]
+            .iter()
+            .map(|p| p.parse().unwrap())
+            .collect();
+        let mut t = StringTable::new(&paths, 3);
+
+        let mut ev: Value = serde_json::from_str(r#"{"ID":"1.0:1","SYSCALL":{"exe":"/usr/bin/ls","comm":"ls","pid":1},"PATH":[{"name":"/usr/bin/ls"},{"name":"/lib/ld.so"}]}"#).unwrap();
+        let dict = t.apply(&mut ev).unwrap();
+        assert_eq!(
+            dict.to_string(),
+            r#"{"ID":"1.0:1","LAUREL_STRINGS":{"0":"/usr/bin/ls","1":"ls","2":"/lib/ld.so"}}"#
+        );
+        assert_eq!(
+            ev.to_string(),
+            r#"{"ID":"1.0:1","SYSCALL":{"exe":0,"comm":1,"pid":1},"PATH":[{"name":0},{"name":2}]}"#
+        );
+
+        let mut ev: Value =
+            serde_json::from_str(r#"{"ID":"1.0:2","SYSCALL":{"exe":"/usr/bin/ls","comm":"ls"}}"#)
+                .unwrap();
+        assert_eq!(t.apply(&mut ev), None);
+        assert_eq!(
+            ev.to_string(),
+            r#"{"ID":"1.0:2","SYSCALL":{"exe":0,"comm":1}}"#
+        );
+
+        // Table is full, numbering starts over.
+        let mut ev: Value =
+            serde_json::from_str(r#"{"ID":"1.0:3","SYSCALL":{"exe":"/usr/bin/id","comm":"id"}}"#)
+                .unwrap();
+        let dict = t.apply(&mut ev).unwrap();
+        assert_eq!(
+            dict.to_string(),
+            r#"{"ID":"1.0:3","LAUREL_STRINGS":{"0":"/usr/bin/id","1":"id"}}"#
+        );
+        assert_eq!(
+            ev.to_string(),
+            r#"{"ID":"1.0:3","SYSCALL":{"exe":0,"comm":1}}"#
+        );
+    }
+}
@@ -0,0 +1,93 @@
+//! Aggregate event counts for periodic summary records
+
+use std::collections::HashMap;
+
+/// Event counts per user, group, executable, and key over one
+/// summary period
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// Timestamp of the first event of the period
+    pub start: Option<u64>,
+    pub events: u64,
+    pub auid: HashMap<u32, u64>,
+    pub uid: HashMap<u32, u64>,
+    pub gid: HashMap<u32, u64>,
+    pub exe: HashMap<Vec<u8>, u64>,
+    /// Size of all events in bytes, as read from input
+    pub bytes: u64,
+    pub key: HashMap<Vec<u8>, u64>,
+    pub key_bytes: HashMap<Vec<u8>, u64>,
+}
+
+impl Summary {
+    /// Counts one syscall event
+    pub fn add(
+        &mut self,
+        auid: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        exe: Option<&[u8]>,
+    ) {
+        self.events += 1;
+        for (map, id) in [
+            (&mut self.auid, auid),
+            (&mut self.uid, uid),
+            (&mut self.gid, gid),
+        ] {
+            if let Some(id) = id {
+                *map.entry(id).or_default() += 1;
+            }
+        }
+        if let Some(exe) = exe {
+            count(&mut self.exe, exe, 1);
+        }
+    }
+
+    /// Counts the size of a completed event, attributed to each of
+    /// its rule keys
+    pub fn add_size(&mut self, keys: &[&[u8]], bytes: u64) {
+        self.bytes += bytes;
+        for key in keys {
+            count(&mut self.key, key, 1);
+            count(&mut self.key_bytes, key, bytes);
+        }
+    }
+}
+
+fn count(map: &mut HashMap<Vec<u8>, u64>, k: &[u8], n: u64) {
+    match map.get_mut(k) {
+        Some(v) => *v += n,
+        None => _ = map.insert(k.to_vec(), n),
+    }
+}
+
+/// Returns up to `n` entries with the highest counts, in descending
+/// order
+pub fn top<K: Ord>(map: &HashMap<K, u64>, n: usize) -> Vec<(&K, u64)> {
+    let mut entries: Vec<(&K, u64)> = map.iter().map(|(k, v)| (k, *v)).collect();
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+    entries.truncate(n);
+    entries
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn summary() {
+        let mut s = Summary::default();
+        s.add(Some(1000), Some(0), Some(0), Some(b"/usr/bin/sudo"));
+        s.add(Some(1000), Some(1000), Some(1000), Some(b"/usr/bin/id"));
+        s.add(Some(1000), Some(1000), Some(1000), Some(b"/usr/bin/id"));
+        s.add_size(&[], 100);
+        s.add_size(&[&b"exec"[..]], 200);
+        assert_eq!(s.events, 3);
+        assert_eq!(s.bytes, 300);
+        assert_eq!(top(&s.auid, 10), vec![(&1000, 3)]);
+        assert_eq!(top(&s.uid, 10), vec![(&1000, 2), (&0, 1)]);
+        assert_eq!(top(&s.exe, 1), vec![(&b"/usr/bin/id".to_vec(), 2)]);
+        assert_eq!(top(&s.key, 10), vec![(&b"exec".to_vec(), 1)]);
+        assert_eq!(s.key_bytes[&b"exec"[..]], 200);
+    }
+}
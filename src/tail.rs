@@ -0,0 +1,263 @@
+//! Human-friendly rendering of Laurel log files, see `laurel tail`
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::rfc5424::format_timestamp;
+
+const DIM: &str = "\x1b[2m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const MAGENTA: &str = "\x1b[35m";
+const CYAN: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+/// Settings for [`run`]
+#[derive(Clone, Debug)]
+pub struct Tail {
+    /// Number of existing events to show at start
+    pub lines: usize,
+    /// Wait for new events after reaching end of file
+    pub follow: bool,
+    /// Use ANSI colors
+    pub color: bool,
+}
+
+impl Default for Tail {
+    fn default() -> Self {
+        Tail {
+            lines: 10,
+            follow: true,
+            color: false,
+        }
+    }
+}
+
+struct Painter(bool);
+
+impl Painter {
+    fn paint(&self, color: &str, s: &str) -> String {
+        match self.0 {
+            true => format!("{color}{s}{RESET}"),
+            false => s.to_string(),
+        }
+    }
+}
+
+fn str_of(v: Option<&Value>) -> Option<String> {
+    match v? {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// Render a single log line (optionally prefixed, see
+/// `line-prefix`) as a concise single line: time, syscall, user, exe,
+/// argv, key, labels.
+pub fn render(line: &str, color: bool) -> Option<String> {
+    let p = Painter(color);
+    let event: Value = serde_json::from_str(&line[line.find('{')?..]).ok()?;
+    let event = event.as_object()?;
+
+    let mut out = vec![];
+    let time = event
+        .get("ID")
+        .and_then(Value::as_str)
+        .and_then(format_timestamp)
+        .unwrap_or_else(|| "-".into());
+    out.push(p.paint(DIM, &time));
+
+    let Some(syscall) = event.get("SYSCALL").and_then(Value::as_object) else {
+        let types: Vec<_> = event
+            .keys()
+            .filter(|k| !["ID", "NODE"].contains(&k.as_str()))
+            .cloned()
+            .collect();
+        out.push(p.paint(BOLD, &types.join(",")));
+        return Some(out.join(" "));
+    };
+
+    let name = str_of(syscall.get("SYSCALL"))
+        .or_else(|| str_of(syscall.get("syscall")))
+        .unwrap_or_else(|| "?".into());
+    match syscall.get("success").and_then(Value::as_str) {
+        Some("no") => out.push(p.paint(RED, &name)),
+        _ => out.push(p.paint(BOLD, &name)),
+    }
+
+    let uid = str_of(syscall.get("UID")).or_else(|| str_of(syscall.get("uid")));
+    let auid = str_of(syscall.get("AUID")).or_else(|| str_of(syscall.get("auid")));
+    let user = match (uid, auid) {
+        (Some(uid), Some(auid)) if uid != auid && auid != "unset" && auid != "4294967295" => {
+            format!("{uid}({auid})")
+        }
+        (Some(uid), _) => uid,
+        _ => "?".into(),
+    };
+    out.push(p.paint(YELLOW, &user));
+
+    if let Some(exe) = str_of(syscall.get("exe")) {
+        out.push(p.paint(GREEN, &exe));
+    }
+
+    if let Some(execve) = event.get("EXECVE").and_then(Value::as_object) {
+        let argv = match execve.get("ARGV") {
+            Some(Value::Array(args)) => Some(
+                args.iter()
+                    .filter_map(|v| str_of(Some(v)))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+            _ => str_of(execve.get("ARGV_STR")),
+        };
+        if let Some(argv) = argv {
+            out.push(format!("[{argv}]"));
+        }
+    }
+
+    let key = match syscall.get("key") {
+        Some(Value::Array(keys)) => Some(
+            keys.iter()
+                .filter_map(|v| str_of(Some(v)))
+                .collect::<Vec<_>>()
+                .join(","),
+        ),
+        v => str_of(v),
+    };
+    if let Some(key) = key {
+        out.push(p.paint(CYAN, &format!("key={key}")));
+    }
+
+    if let Some(Value::Array(labels)) = syscall.get("LABELS") {
+        let labels: Vec<_> = labels.iter().filter_map(|v| str_of(Some(v))).collect();
+        out.push(p.paint(MAGENTA, &format!("labels={}", labels.join(","))));
+    }
+
+    Some(out.join(" "))
+}
+
+/// Print the last events from the log file at `path`, then
+/// optionally follow the file across rotations.
+///
+/// Lines are read as bytes; invalid UTF-8 is only replaced for
+/// display.
+pub fn run<W: Write>(path: &Path, settings: &Tail, out: &mut W) -> io::Result<()> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut last = VecDeque::with_capacity(settings.lines);
+    let mut line = Vec::new();
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        if last.len() == settings.lines {
+            last.pop_front();
+        }
+        if settings.lines > 0 {
+            last.push_back(std::mem::take(&mut line));
+        }
+        line.clear();
+    }
+    for l in last {
+        if let Some(s) = render(&String::from_utf8_lossy(&l), settings.color) {
+            writeln!(out, "{s}")?;
+        }
+    }
+    out.flush()?;
+
+    if !settings.follow {
+        return Ok(());
+    }
+
+    let mut ino = reader.get_ref().metadata()?.ino();
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? > 0 {
+            if !line.ends_with(b"\n") {
+                // Incomplete line, wait for the rest.
+                let pos = reader.stream_position()?;
+                reader.seek(SeekFrom::Start(pos - line.len() as u64))?;
+                std::thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+            if let Some(s) = render(&String::from_utf8_lossy(&line), settings.color) {
+                writeln!(out, "{s}")?;
+                out.flush()?;
+            }
+            continue;
+        }
+        std::thread::sleep(Duration::from_millis(250));
+        // Reopen file after rotation or truncation
+        if let Ok(meta) = std::fs::metadata(path) {
+            if meta.ino() != ino || meta.len() < reader.stream_position()? {
+                reader = BufReader::new(File::open(path)?);
+                ino = meta.ino();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn render_event() {
+        let line = r#"{"ID":"1663143990.204:2148478","SYSCALL":{"arch":"0xc000003e","syscall":59,"success":"yes","uid":1000,"auid":1000,"exe":"/usr/bin/rm","key":"exec","LABELS":["software_mgmt"],"UID":"user","AUID":"user","SYSCALL":"execve"},"EXECVE":{"argc":3,"ARGV":["rm","-f","/tmp/x"]}}"#;
+        assert_eq!(
+            render(line, false).unwrap(),
+            "2022-09-14T08:26:30.204Z execve user /usr/bin/rm [rm -f /tmp/x] key=exec labels=software_mgmt"
+        );
+        assert_eq!(
+            render(&format!("@cee: {line}"), false).unwrap(),
+            render(line, false).unwrap()
+        );
+        assert!(render(line, true)
+            .unwrap()
+            .contains("\x1b[32m/usr/bin/rm\x1b[0m"));
+
+        let line = r#"{"ID":"1663143990.204:2148479","SYSCALL":{"syscall":2,"success":"no","uid":0,"auid":1000,"exe":"/usr/bin/cat","key":null}}"#;
+        assert_eq!(
+            render(line, false).unwrap(),
+            "2022-09-14T08:26:30.204Z 2 0(1000) /usr/bin/cat"
+        );
+
+        let line = r#"{"ID":"1663143990.204:2148480","USER_LOGIN":{"pid":1}}"#;
+        assert_eq!(
+            render(line, false).unwrap(),
+            "2022-09-14T08:26:30.204Z USER_LOGIN"
+        );
+
+        assert_eq!(render("garbage", false), None);
+    }
+
+    #[test]
+    fn run_invalid_utf8() {
+        let td = nix::unistd::mkdtemp(&std::env::temp_dir().join("laurel-test-XXXXXXXX"))
+            .expect("can't create temp dir");
+        let path = td.join("audit.log");
+        std::fs::write(
+            &path,
+            b"{\"ID\":\"1663143990.204:1\",\"SYSCALL\":{\"SYSCALL\":\"execve\",\"uid\":0,\"exe\":\"/tmp/\xff\"}}\n\
+              {\"ID\":\"1663143990.204:2\",\"USER_LOGIN\":{\"pid\":1}}\n",
+        )
+        .unwrap();
+        let settings = Tail {
+            follow: false,
+            ..Tail::default()
+        };
+        let mut out = vec![];
+        let result = run(&path, &settings, &mut out);
+        std::fs::remove_dir_all(td).unwrap();
+        result.unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "2022-09-14T08:26:30.204Z execve 0 /tmp/\u{fffd}\n2022-09-14T08:26:30.204Z USER_LOGIN\n"
+        );
+    }
+}
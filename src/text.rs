@@ -0,0 +1,132 @@
+//! Output of events as human-readable text blocks
+
+use std::io::{self, Write};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::json::{to_value, SpecialFormatter};
+
+/// Serialize message using formatter, write as text block.
+pub fn write<W, T>(w: &mut W, message: &T, formatter: SpecialFormatter) -> io::Result<()>
+where
+    W: ?Sized + Write,
+    T: ?Sized + Serialize,
+{
+    write_value(w, &to_value(message, formatter)?)
+}
+
+/// Writes already-serialized message as a text block:
+///
+/// - The first line contains the top-level scalar values (`ID`,
+///   `NODE`).
+/// - Every other value is written on a separate, indented line as
+///   `RECORD.field: value`, with values aligned. Nested values are
+///   flattened using `.` separated names; for records that occur
+///   multiple times (e.g. `PATH`), the index is added as `PATH[0]`.
+/// - Lists of scalar values such as `ARGV` are written on a single
+///   line, elements containing whitespace are quoted.
+///
+/// Like other formats, the block is followed by a newline, so blocks
+/// are separated by empty lines.
+pub fn write_value<W: ?Sized + Write>(w: &mut W, value: &Value) -> io::Result<()> {
+    let Value::Object(map) = value else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "message is not an object",
+        ));
+    };
+    let mut header = vec![];
+    let mut lines = vec![];
+    for (k, v) in map {
+        match (k.as_str(), v) {
+            ("ID", v) if !v.is_object() && !v.is_array() => header.insert(0, scalar(v)),
+            (_, v) if !v.is_object() && !v.is_array() => {
+                header.push(format!("{}={}", k.to_ascii_lowercase(), scalar(v)))
+            }
+            _ => flatten(&mut lines, k.clone(), v),
+        }
+    }
+    writeln!(w, "{}", header.join(" "))?;
+    let width = lines.iter().map(|(k, _)| k.len()).max().unwrap_or(0);
+    for (k, v) in lines {
+        match v.as_str() {
+            "" => writeln!(w, "  {k:width$} :")?,
+            v => writeln!(w, "  {k:width$} : {v}")?,
+        }
+    }
+    Ok(())
+}
+
+/// Collects `name`/value pairs for `value` and its children
+fn flatten(lines: &mut Vec<(String, String)>, name: String, value: &Value) {
+    match value {
+        Value::Object(map) => {
+            for (k, v) in map {
+                flatten(lines, format!("{name}.{k}"), v);
+            }
+        }
+        Value::Array(vs) if vs.iter().all(|v| !v.is_object() && !v.is_array()) => {
+            let elems: Vec<String> = vs.iter().map(|v| quote(&scalar(v))).collect();
+            lines.push((name, elems.join(" ")));
+        }
+        Value::Array(vs) => {
+            for (n, v) in vs.iter().enumerate() {
+                flatten(lines, format!("{name}[{n}]"), v);
+            }
+        }
+        v => lines.push((name, scalar(v))),
+    }
+}
+
+/// Formats a scalar value, escaping control characters so that every
+/// value fits on a single line
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => s
+            .chars()
+            .flat_map(|c| match c.is_control() {
+                true => c.escape_default().collect::<Vec<_>>(),
+                false => vec![c],
+            })
+            .collect(),
+        Value::Null => String::new(),
+        v => v.to_string(),
+    }
+}
+
+/// Quotes list elements that would otherwise be ambiguous
+fn quote(s: &str) -> String {
+    if !s.is_empty() && !s.contains(|c: char| c.is_whitespace() || c == '\'' || c == '"') {
+        return s.into();
+    }
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn text() {
+        let event: Value = serde_json::from_str(r#"{"ID":"1615114232.375:29","NODE":"host","SYSCALL":{"syscall":59,"exe":"/usr/bin/printf","PID":{"EVENT_ID":"1615114232.371:28","comm":"sh"},"LABELS":[]},"EXECVE":{"argc":3,"ARGV":["printf","%s\n","hello world"]},"PATH":[{"item":0,"name":"/usr/bin/printf"},{"item":1,"name":"/lib/ld.so"}]}"#).unwrap();
+        let mut buf = vec![];
+        write_value(&mut buf, &event).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            r#"1615114232.375:29 node=host
+  SYSCALL.syscall      : 59
+  SYSCALL.exe          : /usr/bin/printf
+  SYSCALL.PID.EVENT_ID : 1615114232.371:28
+  SYSCALL.PID.comm     : sh
+  SYSCALL.LABELS       :
+  EXECVE.argc          : 3
+  EXECVE.ARGV          : printf %s\n 'hello world'
+  PATH[0].item         : 0
+  PATH[0].name         : /usr/bin/printf
+  PATH[1].item         : 1
+  PATH[1].name         : /lib/ld.so
+"#
+        );
+    }
+}
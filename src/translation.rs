@@ -0,0 +1,105 @@
+//! Select raw or translated values for an output
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Which values are written for fields that have been translated
+///
+/// Translations are added next to the raw value, using the uppercase
+/// field name, e.g. `SYSCALL.syscall` (raw) and `SYSCALL.SYSCALL`
+/// (translated).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TranslatedValues {
+    #[default]
+    Both,
+    Numeric,
+    Symbolic,
+}
+
+/// Returns true for raw fields that are translated into an uppercase
+/// counterpart
+fn is_translatable(name: &str) -> bool {
+    name.ends_with("uid")
+        || name.ends_with("gid")
+        || matches!(
+            name,
+            "arch"
+                | "syscall"
+                | "saddr"
+                | "uring_op"
+                | "resp"
+                | "fan_type"
+                | "fan_info"
+                | "subj_trust"
+                | "obj_trust"
+                | "subj"
+                | "obj"
+                | "scontext"
+                | "tcontext"
+        )
+}
+
+impl TranslatedValues {
+    /// Removes raw or translated values from all records of `event`.
+    /// Values that have not been translated are always kept.
+    pub fn apply(self, event: &mut Value) {
+        if self == TranslatedValues::Both {
+            return;
+        }
+        let Value::Object(event) = event else {
+            return;
+        };
+        for record in event.values_mut() {
+            match record {
+                Value::Object(r) => self.apply_record(r),
+                // Records that occur multiple times, e.g. PATH
+                Value::Array(rs) => rs
+                    .iter_mut()
+                    .filter_map(Value::as_object_mut)
+                    .for_each(|r| self.apply_record(r)),
+                _ => {}
+            }
+        }
+    }
+
+    fn apply_record(self, record: &mut Map<String, Value>) {
+        let remove: Vec<String> = record
+            .keys()
+            .filter(|k| is_translatable(k))
+            .map(|k| (k, k.to_ascii_uppercase()))
+            .filter(|(_, t)| record.contains_key(t))
+            .map(|(raw, translated)| match self {
+                TranslatedValues::Numeric => translated,
+                _ => raw.clone(),
+            })
+            .collect();
+        record.retain(|k, _| !remove.contains(k));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn translated_values() {
+        let event = r#"{"ID":"1.2:3","SYSCALL":{"arch":"0xc000003e","syscall":59,"ppid":1,"auid":0,"ARCH":"x86_64","SYSCALL":"execve","AUID":"root","PPID":{"EVENT_ID":"1.1:1"}},"PATH":[{"item":0,"ouid":0,"OUID":"root"}]}"#;
+        let mut v: Value = serde_json::from_str(event).unwrap();
+        TranslatedValues::Both.apply(&mut v);
+        assert_eq!(v.to_string(), event);
+
+        TranslatedValues::Numeric.apply(&mut v);
+        assert_eq!(
+            v.to_string(),
+            r#"{"ID":"1.2:3","SYSCALL":{"arch":"0xc000003e","syscall":59,"ppid":1,"auid":0,"PPID":{"EVENT_ID":"1.1:1"}},"PATH":[{"item":0,"ouid":0}]}"#
+        );
+
+        let mut v: Value = serde_json::from_str(event).unwrap();
+        TranslatedValues::Symbolic.apply(&mut v);
+        assert_eq!(
+            v.to_string(),
+            r#"{"ID":"1.2:3","SYSCALL":{"ppid":1,"ARCH":"x86_64","SYSCALL":"execve","AUID":"root","PPID":{"EVENT_ID":"1.1:1"}},"PATH":[{"item":0,"OUID":"root"}]}"#
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use std::fmt::Debug;
+use std::time::Instant;
 
 use indexmap::IndexMap;
 
@@ -6,6 +7,7 @@ use serde::{Deserialize, Serialize, Serializer};
 
 use linux_audit_parser::*;
 
+use crate::presets::FilterPreset;
 use crate::proc::ProcessKey;
 
 /// Collect records in [`EventBody`] context as single or multiple
@@ -41,6 +43,12 @@ pub struct Event<'a> {
     pub node: Option<Vec<u8>>,
     #[serde(flatten)]
     pub body: IndexMap<MessageType, EventValues<'a>>,
+    /// Records that are generated by laurel itself, e.g. `LAUREL_RULES`
+    #[serde(flatten, skip_deserializing)]
+    pub laurel: IndexMap<&'static str, EventValues<'a>>,
+    /// User-defined sections from the configuration, e.g. `SITE`
+    #[serde(flatten, skip_deserializing)]
+    pub sections: IndexMap<String, Body<'a>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub container_info: Option<Body<'a>>,
     #[serde(skip)]
@@ -48,7 +56,30 @@ pub struct Event<'a> {
     #[serde(skip)]
     pub(crate) is_exec: bool,
     #[serde(skip)]
+    pub(crate) syscall: Option<&'static str>,
+    /// Architecture of the SYSCALL record
+    #[serde(skip)]
+    pub(crate) arch: Option<&'static str>,
+    #[serde(skip)]
     pub(crate) process_key: Option<ProcessKey>,
+    /// Filter preset that matched one of the records
+    #[serde(skip)]
+    pub(crate) filter_preset: Option<FilterPreset>,
+    /// Size of the input lines that make up the event
+    #[serde(skip)]
+    pub(crate) size: usize,
+    /// Point in time after which slow enrichments are skipped,
+    /// started when the first record of the event is seen
+    #[serde(skip)]
+    pub(crate) enrich_deadline: Option<Instant>,
+    /// Ratio of the sampling rule that matched the SYSCALL record,
+    /// applied after all filters have been evaluated
+    #[serde(skip)]
+    pub(crate) sample_ratio: Option<u32>,
+    /// Difference between the system clock and the event timestamp
+    /// when the first record was seen, if it is above the threshold
+    #[serde(skip)]
+    pub(crate) clock_skew: Option<i64>,
 }
 
 impl Event<'_> {
@@ -57,10 +88,19 @@ impl Event<'_> {
             node,
             id,
             body: IndexMap::with_capacity(5),
+            laurel: IndexMap::new(),
+            sections: IndexMap::new(),
             container_info: None,
             is_filtered: false,
             is_exec: false,
+            syscall: None,
+            arch: None,
             process_key: None,
+            filter_preset: None,
+            size: 0,
+            enrich_deadline: None,
+            sample_ratio: None,
+            clock_skew: None,
         }
     }
 }
@@ -8,11 +8,19 @@ use tinyvec::TinyVec;
 
 use nix::unistd::{getgrouplist, Gid, Group, Uid, User};
 
+use crate::config::PasswdField;
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct UserEntry {
     name: String,
     primary_gid: u32,
     secondary_gids: TinyVec<[u32; 8]>,
+    #[serde(default)]
+    shell: String,
+    #[serde(default)]
+    home: String,
+    #[serde(default)]
+    gecos: String,
 }
 
 fn get_user(uid: u32) -> Option<UserEntry> {
@@ -28,6 +36,9 @@ fn get_user(uid: u32) -> Option<UserEntry> {
             name: user.name,
             primary_gid: user.gid.into(),
             secondary_gids: gids,
+            shell: user.shell.to_string_lossy().into(),
+            home: user.dir.to_string_lossy().into(),
+            gecos: user.gecos.to_string_lossy().into(),
         }
     })
 }
@@ -74,15 +85,57 @@ impl<'de> Deserialize<'de> for EpochTime {
     }
 }
 
+/// Default time after which cached entries are looked up again, in
+/// seconds
+pub const DEFAULT_REFRESH: u64 = 1800;
+
+/// Version of the serialized cache. Entries written by older
+/// versions lack fields and are discarded, see [`UserDB::upgrade`].
+const VERSION: u32 = 1;
+
 /// Implementation of a credentials store that caches user and group
 /// lookups by uid and gid, respectively.
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct UserDB {
+    /// Format version, missing in state files written before
+    /// versioning was introduced
+    #[serde(default)]
+    version: u32,
     users: BTreeMap<u32, (Option<UserEntry>, EpochTime)>,
     groups: BTreeMap<u32, (Option<String>, EpochTime)>,
+    #[serde(skip)]
+    refresh: Option<u64>,
+}
+
+impl Default for UserDB {
+    fn default() -> Self {
+        UserDB {
+            version: VERSION,
+            users: BTreeMap::new(),
+            groups: BTreeMap::new(),
+            refresh: None,
+        }
+    }
 }
 
 impl UserDB {
+    /// Discards cached entries that have been restored from an older
+    /// state format
+    pub fn upgrade(&mut self) {
+        if self.version < VERSION {
+            *self = UserDB {
+                refresh: self.refresh,
+                ..UserDB::default()
+            };
+        }
+    }
+    /// Sets the time after which cached entries are looked up again
+    pub fn set_refresh(&mut self, seconds: u64) {
+        self.refresh = Some(seconds);
+    }
+    fn is_fresh(&self, t: &EpochTime) -> bool {
+        t.elapsed().unwrap_or(Duration::MAX).as_secs() <= self.refresh.unwrap_or(DEFAULT_REFRESH)
+    }
     pub fn populate(&mut self) {
         for id in 0..1000 {
             if let Some(user) = get_user(id) {
@@ -95,9 +148,7 @@ impl UserDB {
     }
     fn get_user_entry(&mut self, uid: u32) -> Option<UserEntry> {
         match self.users.get(&uid) {
-            Some((entry, t)) if t.elapsed().unwrap_or(Duration::MAX).as_secs() <= 1800 => {
-                entry.clone()
-            }
+            Some((entry, t)) if self.is_fresh(t) => entry.clone(),
             _ => {
                 let entry = get_user(uid);
                 self.users.insert(uid, (entry.clone(), EpochTime::now()));
@@ -108,6 +159,14 @@ impl UserDB {
     pub fn get_user(&mut self, uid: u32) -> Option<String> {
         self.get_user_entry(uid).map(|user| user.name)
     }
+    pub fn get_passwd_field(&mut self, uid: u32, field: PasswdField) -> Option<String> {
+        let user = self.get_user_entry(uid)?;
+        Some(match field {
+            PasswdField::Shell => user.shell,
+            PasswdField::Home => user.home,
+            PasswdField::Gecos => user.gecos,
+        })
+    }
     pub fn get_user_groups(&mut self, uid: u32) -> Option<Vec<String>> {
         let user = self.get_user_entry(uid)?;
         let names = Some(user.primary_gid)
@@ -119,9 +178,7 @@ impl UserDB {
     }
     pub fn get_group(&mut self, gid: u32) -> Option<String> {
         match self.groups.get(&gid) {
-            Some((entry, t)) if t.elapsed().unwrap_or(Duration::MAX).as_secs() <= 1800 => {
-                entry.clone()
-            }
+            Some((entry, t)) if self.is_fresh(t) => entry.clone(),
             _ => {
                 let group = get_group(gid);
                 self.groups.insert(gid, (group.clone(), EpochTime::now()));
@@ -149,4 +206,22 @@ mod test {
             userdb.get_user_groups(uid.into())
         );
     }
+
+    #[test]
+    fn upgrade() {
+        let mut userdb: UserDB = serde_json::from_str(
+            r#"{"users":{"0":[{"name":"root","primary_gid":0,"secondary_gids":[]},1]},"groups":{}}"#,
+        )
+        .unwrap();
+        assert_eq!(userdb.version, 0);
+        userdb.upgrade();
+        assert_eq!(userdb.version, VERSION);
+        assert!(userdb.users.is_empty());
+
+        let mut userdb: UserDB =
+            serde_json::from_str(&serde_json::to_string(&UserDB::default()).unwrap()).unwrap();
+        userdb.users.insert(0, (None, EpochTime::now()));
+        userdb.upgrade();
+        assert_eq!(userdb.users.len(), 1);
+    }
 }
@@ -0,0 +1,135 @@
+//! Login sessions from the utmp database
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+pub const UTMP_PATH: &str = "/var/run/utmp";
+
+/// Size of `struct utmp` on Linux
+const RECORD_SIZE: usize = 384;
+/// `ut_type` of records that describe user sessions
+pub(crate) const USER_PROCESS: i16 = 7;
+
+/// Login session as recorded in utmp
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Session {
+    pub pid: u32,
+    /// Terminal without slashes, as in the `tty` field of `SYSCALL`
+    /// records, e.g. `pts1`
+    pub tty: Vec<u8>,
+    pub user: Vec<u8>,
+    /// Remote host or X display
+    pub host: Vec<u8>,
+}
+
+impl Session {
+    /// Describes how the user has logged in
+    pub fn origin(&self) -> &'static str {
+        if self.host.is_empty() {
+            "console"
+        } else if self.host.starts_with(b":") {
+            "graphical"
+        } else {
+            "remote"
+        }
+    }
+}
+
+/// Returns `buf` up to the first NUL byte
+fn c_str(buf: &[u8]) -> &[u8] {
+    let end = buf.iter().position(|c| *c == 0).unwrap_or(buf.len());
+    &buf[..end]
+}
+
+/// Parses utmp records and returns the user sessions
+pub fn parse(buf: &[u8]) -> Vec<Session> {
+    buf.chunks_exact(RECORD_SIZE)
+        .filter(|r| i16::from_ne_bytes([r[0], r[1]]) == USER_PROCESS)
+        .map(|r| Session {
+            pid: u32::from_ne_bytes([r[4], r[5], r[6], r[7]]),
+            tty: c_str(&r[8..40])
+                .iter()
+                .filter(|c| **c != b'/')
+                .copied()
+                .collect(),
+            user: c_str(&r[44..76]).to_vec(),
+            host: c_str(&r[76..332]).to_vec(),
+        })
+        .collect()
+}
+
+/// Sessions from the utmp file, reread whenever the file has changed
+#[derive(Debug)]
+pub struct SessionTable {
+    path: PathBuf,
+    mtime: Option<(i64, i64)>,
+    sessions: Vec<Session>,
+}
+
+impl SessionTable {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        SessionTable {
+            path: path.as_ref().to_path_buf(),
+            mtime: None,
+            sessions: vec![],
+        }
+    }
+
+    fn refresh(&mut self) -> io::Result<()> {
+        let md = fs::metadata(&self.path)?;
+        let mtime = (md.mtime(), md.mtime_nsec());
+        if self.mtime != Some(mtime) {
+            self.sessions = parse(&fs::read(&self.path)?);
+            self.mtime = Some(mtime);
+        }
+        Ok(())
+    }
+
+    /// Returns the most recent session on `tty`
+    pub fn lookup(&mut self, tty: &[u8]) -> Option<&Session> {
+        if self.refresh().is_err() {
+            self.sessions.clear();
+            self.mtime = None;
+        }
+        self.sessions.iter().rev().find(|s| s.tty == tty)
+    }
+}
+
+/// Builds a utmp record for tests
+#[cfg(test)]
+pub(crate) fn record(ty: i16, pid: u32, line: &str, user: &str, host: &str) -> Vec<u8> {
+    let mut r = vec![0u8; RECORD_SIZE];
+    r[0..2].copy_from_slice(&ty.to_ne_bytes());
+    r[4..8].copy_from_slice(&pid.to_ne_bytes());
+    r[8..8 + line.len()].copy_from_slice(line.as_bytes());
+    r[44..44 + user.len()].copy_from_slice(user.as_bytes());
+    r[76..76 + host.len()].copy_from_slice(host.as_bytes());
+    r
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_utmp() {
+        let mut buf = record(2, 0, "~", "reboot", "6.1.0");
+        buf.extend(record(USER_PROCESS, 1234, "pts/1", "alice", "192.0.2.1"));
+        buf.extend(record(USER_PROCESS, 1300, "tty2", "bob", ":0"));
+        let sessions = parse(&buf);
+        assert_eq!(
+            sessions[0],
+            Session {
+                pid: 1234,
+                tty: b"pts1".to_vec(),
+                user: b"alice".to_vec(),
+                host: b"192.0.2.1".to_vec(),
+            }
+        );
+        assert_eq!(sessions[0].origin(), "remote");
+        assert_eq!(sessions[1].origin(), "graphical");
+        assert_eq!(sessions.len(), 2);
+    }
+}